@@ -0,0 +1,82 @@
+// Optional archive of the raw api responses so that they can be parsed again later, for example
+// when a new field becomes interesting, without crawling again. Every response is written gzip
+// compressed to its own file and `index.tsv` lists the time, url and file of every response.
+
+use crate::Result_;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const INDEX: &str = "index.tsv";
+
+lazy_static::lazy_static! {
+    static ref DIRECTORY: std::sync::Mutex<Option<PathBuf>> = std::sync::Mutex::new(None);
+}
+
+pub fn enable(directory: &Path) -> Result_<()> {
+    std::fs::create_dir_all(directory)
+        .map_err(|err| format!("could not create {}: {}", directory.display(), err))?;
+    *DIRECTORY.lock().unwrap() = Some(directory.to_path_buf());
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    DIRECTORY.lock().unwrap().is_some()
+}
+
+// Archives the body if enabled. Failing to archive does not fail the request.
+pub fn record(url: &str, body: &[u8]) {
+    let directory = DIRECTORY.lock().unwrap();
+    if let Some(directory) = directory.as_ref() {
+        if let Err(err) = write(directory, url, body, SystemTime::now()) {
+            log::warn!("failed to archive the response of {}: {}", url, err);
+        }
+    }
+}
+
+// Returns the path of the archived response.
+pub fn write(directory: &Path, url: &str, body: &[u8], time: SystemTime) -> Result_<PathBuf> {
+    let since_epoch = time.duration_since(UNIX_EPOCH)?;
+    let name = format!(
+        "{}-{:09}.json.gz",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    );
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(body)?;
+    let path = directory.join(&name);
+    std::fs::write(&path, encoder.finish()?)?;
+    let mut index = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(directory.join(INDEX))?;
+    writeln!(index, "{}\t{}\t{}", since_epoch.as_secs(), url, name)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let directory = std::env::temp_dir().join("scoresaber-crawler-test-archive");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        let time = UNIX_EPOCH + std::time::Duration::new(1_600_000_000, 5);
+        let path = write(&directory, "https://example.com/a?page=1", b"{}", time).unwrap();
+        assert_eq!(path, directory.join("1600000000-000000005.json.gz"));
+        let mut body = String::new();
+        std::io::Read::read_to_string(
+            &mut flate2::read::GzDecoder::new(std::fs::File::open(&path).unwrap()),
+            &mut body,
+        )
+        .unwrap();
+        assert_eq!(body, "{}");
+        assert_eq!(
+            std::fs::read_to_string(directory.join(INDEX)).unwrap(),
+            "1600000000\thttps://example.com/a?page=1\t1600000000-000000005.json.gz\n"
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
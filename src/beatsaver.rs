@@ -0,0 +1,442 @@
+use crate::{archive, http, profile, provenance, workers, Result_, SongHash};
+
+const BEATSAVER_API_URL: &str = "https://beatsaver.com/api";
+// The name of BeatSaver in the stale data warnings, see provenance::record_enrichment.
+const SERVICE: &str = "BeatSaver";
+// BeatSaver rejects requests without a user agent.
+const USER_AGENT: &str = concat!("scoresaber-crawler/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+struct BeatSaverMap {
+    key: String,
+    metadata: Metadata,
+    #[serde(default)]
+    uploader: Option<Uploader>,
+    #[serde(default)]
+    stats: Option<Stats>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+struct Uploader {
+    username: String,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Stats {
+    up_votes: u64,
+    down_votes: u64,
+    // Between 0 and 1.
+    rating: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+struct Metadata {
+    characteristics: Vec<Characteristic>,
+    #[serde(default)]
+    bpm: Option<f64>,
+    // Seconds, 0 if the uploader did not set it.
+    #[serde(default)]
+    duration: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+struct Characteristic {
+    name: String,
+    // Difficulties that do not exist are null.
+    difficulties: std::collections::BTreeMap<String, Option<DifficultyInfo>>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+struct DifficultyInfo {
+    notes: u64,
+    // Seconds.
+    length: f64,
+    // Note jump speed.
+    #[serde(default)]
+    njs: Option<f64>,
+}
+
+// Uses the names ScoreSaber uses in the `diff` column like "ExpertPlus" and "Standard" in
+// "_ExpertPlus_SoloStandard".
+#[derive(Clone, Debug, PartialEq)]
+struct Difficulty {
+    characteristic: String,
+    difficulty: String,
+    notes: u64,
+    max_score: u64,
+    length: f64,
+    njs: Option<f64>,
+}
+
+impl Difficulty {
+    fn nps(&self) -> Option<f64> {
+        Some(self.notes as f64 / self.length).filter(|_| self.length > 0.0)
+    }
+}
+
+// Every note is worth at most 115 points times the combo multiplier. The multiplier is 1 for the
+// first note, 2 for the next 4, 4 for the next 8 and 8 for the rest.
+fn max_score(notes: u64) -> u64 {
+    match notes {
+        0 => 0,
+        1 => 115,
+        2..=5 => 115 + (notes - 1) * 230,
+        6..=13 => 1035 + (notes - 5) * 460,
+        _ => 4715 + (notes - 13) * 920,
+    }
+}
+
+fn difficulties(map: &BeatSaverMap) -> Vec<Difficulty> {
+    let mut result = vec![];
+    for characteristic in map.metadata.characteristics.iter() {
+        for (name, info) in characteristic.difficulties.iter() {
+            let info = match info {
+                Some(info) => info,
+                None => continue,
+            };
+            // BeatSaver uses "expertPlus" where ScoreSaber uses "ExpertPlus".
+            let mut chars = name.chars();
+            let difficulty = match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => continue,
+            };
+            result.push(Difficulty {
+                characteristic: characteristic.name.clone(),
+                difficulty,
+                notes: info.notes,
+                max_score: max_score(info.notes),
+                length: info.length,
+                njs: info.njs,
+            });
+        }
+    }
+    result
+}
+
+// Returns None if BeatSaver does not know the hash.
+fn get_map(client: &reqwest::Client, hash: &str) -> Result_<Option<BeatSaverMap>> {
+    let url = reqwest::Url::parse(&format!("{}/maps/by-hash/{}", BEATSAVER_API_URL, hash))?;
+    log::info!("request: {}", url);
+    let (status, body) = http::get(url.as_str(), || {
+        client
+            .get(url.clone())
+            .header(reqwest::header::USER_AGENT, USER_AGENT)
+    })?;
+    if status == reqwest::StatusCode::NOT_FOUND {
+        Ok(None)
+    } else if status.is_success() {
+        archive::record(url.as_str(), &body);
+        let _timer = profile::timer(profile::Kind::Decode);
+        Ok(Some(serde_json::from_slice(&body)?))
+    } else {
+        Err(format!(
+            "response status code does not indiciate success: {}",
+            status
+        ))?
+    }
+}
+
+fn insert_map_into_db(
+    db: &rusqlite::Connection,
+    hash: &str,
+    map: Option<&BeatSaverMap>,
+) -> Result_<()> {
+    let _timer = profile::timer(profile::Kind::Database);
+    db.execute(
+        "REPLACE INTO beatsaver_maps (hash, key, fetched_at, bpm, uploader, duration, up_votes, down_votes, rating) VALUES (?,?,datetime('now'),?,?,?,?,?,?)",
+        rusqlite::params![
+            hash,
+            map.map(|map| map.key.as_str()),
+            map.and_then(|map| map.metadata.bpm),
+            map.and_then(|map| map.uploader.as_ref())
+                .map(|uploader| uploader.username.as_str()),
+            map.map(|map| map.metadata.duration)
+                .filter(|duration| *duration > 0.0),
+            map.and_then(|map| map.stats.as_ref())
+                .map(|stats| stats.up_votes as i64),
+            map.and_then(|map| map.stats.as_ref())
+                .map(|stats| stats.down_votes as i64),
+            map.and_then(|map| map.stats.as_ref())
+                .map(|stats| stats.rating)
+        ],
+    )?;
+    // The difficulties of a hash never change but the tags of a refreshed map replace the old ones.
+    db.execute(
+        "DELETE FROM beatsaver_tags WHERE hash = ?",
+        rusqlite::params![hash],
+    )?;
+    for tag in map.map(|map| map.tags.as_slice()).unwrap_or_default() {
+        db.execute(
+            "INSERT OR IGNORE INTO beatsaver_tags (hash, tag) VALUES (?,?)",
+            rusqlite::params![hash, tag],
+        )?;
+    }
+    if let Some(bpm) = map
+        .and_then(|map| map.metadata.bpm)
+        .filter(|bpm| crate::is_valid_bpm(*bpm))
+    {
+        db.execute(
+            "UPDATE scoresaber_songs SET bpm = ?, bpm_status = 'repaired' WHERE id = ? AND bpm_status = 'invalid'",
+            rusqlite::params![bpm, hash],
+        )?;
+    }
+    record_sources(db, hash, map)?;
+    for difficulty in map.map(difficulties).unwrap_or_default() {
+        db.execute(
+            "REPLACE INTO beatsaver_difficulties (hash, characteristic, difficulty, notes, max_score, length, njs, nps) VALUES (?,?,?,?,?,?,?,?)",
+            rusqlite::params![
+                hash,
+                difficulty.characteristic,
+                difficulty.difficulty,
+                difficulty.notes as i64,
+                difficulty.max_score as i64,
+                difficulty.length,
+                difficulty.njs,
+                difficulty.nps()
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn record_sources(
+    db: &rusqlite::Connection,
+    hash: &str,
+    map: Option<&BeatSaverMap>,
+) -> Result_<()> {
+    let mut statement = db.prepare("SELECT uid, diff FROM scoresaber_songs WHERE id = ?")?;
+    let songs = statement
+        .query_map(rusqlite::params![hash], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let difficulties = map.map(difficulties).unwrap_or_default();
+    let bpm = map
+        .and_then(|map| map.metadata.bpm)
+        .filter(|bpm| crate::is_valid_bpm(*bpm));
+    for (uid, diff) in songs {
+        provenance::record(db, uid, "bpm", "beatsaver", bpm)?;
+        let difficulty = difficulties.iter().find(|x| {
+            crate::parse_difficulty(&diff)
+                == Some((x.difficulty.as_str(), x.characteristic.as_str()))
+        });
+        for (field, value) in &[
+            ("notes", difficulty.map(|x| x.notes as f64)),
+            ("length", difficulty.map(|x| x.length)),
+            ("max_score", difficulty.map(|x| x.max_score as f64)),
+        ] {
+            provenance::record(db, uid, field, "beatsaver", *value)?;
+        }
+    }
+    Ok(())
+}
+
+// Fetches the BeatSaver data of every song that has not been fetched yet. BeatSaver is not needed
+// for the rest of the run so when it fails we only warn and try the remaining songs next time.
+// Maps fetched before their bpm was stored are fetched again if they can repair an invalid bpm.
+pub fn enrich(
+    db: &rusqlite::Connection,
+    client: &reqwest::Client,
+    refresh_days: Option<u64>,
+) -> Result_<()> {
+    let hashes = hashes_to_fetch(db, refresh_days)?;
+    let missing = fetch(db, client, hashes)?;
+    provenance::record_enrichment(db, SERVICE, missing)
+}
+
+// With `refresh_days` maps fetched longer ago are fetched again, for example for their votes.
+fn hashes_to_fetch(db: &rusqlite::Connection, refresh_days: Option<u64>) -> Result_<Vec<SongHash>> {
+    let mut statement = db.prepare(
+        "SELECT DISTINCT id FROM scoresaber_songs WHERE id NOT IN (SELECT hash FROM beatsaver_maps) OR (bpm_status = 'invalid' AND id IN (SELECT hash FROM beatsaver_maps WHERE key IS NOT NULL AND bpm IS NULL)) OR (?1 IS NOT NULL AND id IN (SELECT hash FROM beatsaver_maps WHERE fetched_at < datetime('now', '-' || ?1 || ' days'))) ORDER BY id",
+    )?;
+    let hashes = statement
+        .query_map(
+            rusqlite::params![refresh_days.map(|days| days as i64)],
+            |row| row.get(0),
+        )?
+        .collect::<rusqlite::Result<Vec<SongHash>>>()?;
+    Ok(hashes)
+}
+
+// Fetches the maps of imported playlists that are not ranked.
+pub fn enrich_imported(db: &rusqlite::Connection, client: &reqwest::Client) -> Result_<()> {
+    let mut statement = db.prepare(
+        "SELECT DISTINCT hash FROM imported_playlists WHERE hash NOT IN (SELECT hash FROM beatsaver_maps)",
+    )?;
+    let hashes = statement
+        .query_map(rusqlite::params![], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<SongHash>>>()?;
+    fetch(db, client, hashes)?;
+    Ok(())
+}
+
+// Maps that fail to be fetched are left out and tried again by the next enrichment. Returns their
+// number.
+fn fetch(
+    db: &rusqlite::Connection,
+    client: &reqwest::Client,
+    hashes: Vec<SongHash>,
+) -> Result_<usize> {
+    let total = hashes.len();
+    let client = client.clone();
+    let mut fetched = 0;
+    let summary = workers::run(
+        hashes,
+        move |hash| get_map(&client, hash),
+        |hash, map| {
+            fetched += 1;
+            println!("fetched BeatSaver data {} of {}", fetched, total);
+            insert_map_into_db(db, hash, map.as_ref())
+        },
+    )?;
+    if summary.failed > 0 {
+        log::warn!(
+            "failed to fetch the BeatSaver data of {} maps{}",
+            summary.failed,
+            if summary.stopped {
+                ", stopped early"
+            } else {
+                ""
+            }
+        );
+    }
+    // The maps after a stop were not fetched either.
+    Ok(total - summary.succeeded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_score() {
+        assert_eq!(max_score(1), 115);
+        assert_eq!(max_score(5), 1035);
+        assert_eq!(max_score(13), 4715);
+        assert_eq!(max_score(903), 823_515);
+    }
+
+    #[test]
+    fn test_difficulties() {
+        let map: BeatSaverMap =
+            serde_json::from_slice(include_bytes!("../test_data/beatsaver-map.json")).unwrap();
+        assert_eq!(map.key, "29d3");
+        let difficulties = difficulties(&map)
+            .into_iter()
+            .map(|x| (x.characteristic, x.difficulty, x.notes, x.njs))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            difficulties,
+            vec![
+                (
+                    "Standard".to_string(),
+                    "Expert".to_string(),
+                    652,
+                    Some(17.0)
+                ),
+                (
+                    "Standard".to_string(),
+                    "ExpertPlus".to_string(),
+                    903,
+                    Some(19.0)
+                ),
+                (
+                    "OneSaber".to_string(),
+                    "ExpertPlus".to_string(),
+                    4,
+                    Some(18.0)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_metadata() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::create_schema(&db).unwrap();
+        let mut map: BeatSaverMap =
+            serde_json::from_slice(include_bytes!("../test_data/beatsaver-map.json")).unwrap();
+        map.tags = vec!["tech".to_string(), "anime".to_string()];
+        let hash = "CFCA2FE00BCC418DC9ECF64D92FC01CEEC52C375";
+        insert_map_into_db(&db, hash, Some(&map)).unwrap();
+        let stored: (String, Option<f64>, i64, i64) = db
+            .query_row(
+                "SELECT uploader, duration, up_votes, down_votes FROM beatsaver_maps WHERE hash = ?",
+                rusqlite::params![hash],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(stored, ("hexagonial".to_string(), None, 1413, 52));
+        let nps: f64 = db
+            .query_row(
+                "SELECT nps FROM beatsaver_difficulties WHERE hash = ? AND characteristic = 'Standard' AND difficulty = 'ExpertPlus'",
+                rusqlite::params![hash],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(nps, 903.0 / 143.0);
+        let tags = |db: &rusqlite::Connection| -> i64 {
+            db.query_row(
+                "SELECT COUNT(*) FROM beatsaver_tags WHERE hash = ?",
+                rusqlite::params![hash],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(tags(&db), 2);
+        // Refreshing replaces the tags.
+        map.tags.pop();
+        insert_map_into_db(&db, hash, Some(&map)).unwrap();
+        assert_eq!(tags(&db), 1);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_refresh() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::create_schema(&db).unwrap();
+        let song = crate::test_songs().remove(0);
+        crate::insert_song_into_db(&db, &song).unwrap();
+        assert_eq!(hashes_to_fetch(&db, None).unwrap(), vec![song.id.clone()]);
+        insert_map_into_db(&db, &song.id, None).unwrap();
+        assert!(hashes_to_fetch(&db, None).unwrap().is_empty());
+        assert!(hashes_to_fetch(&db, Some(30)).unwrap().is_empty());
+        db.execute(
+            "UPDATE beatsaver_maps SET fetched_at = datetime('now', '-31 days')",
+            rusqlite::params![],
+        )
+        .unwrap();
+        assert!(hashes_to_fetch(&db, None).unwrap().is_empty());
+        assert_eq!(hashes_to_fetch(&db, Some(30)).unwrap(), vec![song.id]);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_bpm_repair() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::create_schema(&db).unwrap();
+        let mut song = crate::test_songs().remove(0);
+        song.beats_per_minute = 0;
+        crate::insert_song_into_db(&db, &song).unwrap();
+        let bpm = |db: &rusqlite::Connection| -> (f64, String) {
+            db.query_row(
+                "SELECT bpm, bpm_status FROM scoresaber_songs WHERE uid = ?",
+                rusqlite::params![song.uid as i64],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+        assert_eq!(bpm(&db), (0.0, "invalid".to_string()));
+        let map: BeatSaverMap =
+            serde_json::from_slice(include_bytes!("../test_data/beatsaver-map.json")).unwrap();
+        insert_map_into_db(&db, &song.id, Some(&map)).unwrap();
+        assert_eq!(bpm(&db), (255.0, "repaired".to_string()));
+        // Crawling the song again uses the stored BeatSaver bpm right away.
+        crate::insert_song_into_db(&db, &song).unwrap();
+        assert_eq!(bpm(&db), (255.0, "repaired".to_string()));
+        db.close().unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+// What a crawl changed compared to the songs stored before it. The report of every crawl is kept in
+// `change_reports` as json so that notifiers and other tools can consume it.
+
+use crate::{notify, playlist, RankingChange, Result_};
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Song {
+    pub uid: i64,
+    pub name: String,
+    pub difficulty: String,
+    pub stars: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ChangeReport {
+    // Leaderboards that were not stored or were unranked before the crawl.
+    pub ranked: Vec<Song>,
+    pub reweighted: Vec<RankingChange>,
+    // Leaderboards that were missing from the crawl.
+    pub unranked: Vec<Song>,
+}
+
+impl ChangeReport {
+    pub fn is_empty(&self) -> bool {
+        self.ranked.is_empty() && self.reweighted.is_empty() && self.unranked.is_empty()
+    }
+
+    pub fn save(&self, db: &rusqlite::Connection, crawl: i64) -> Result_<()> {
+        db.execute(
+            "INSERT INTO change_reports (crawl, report) VALUES (?, ?)",
+            rusqlite::params![crawl, serde_json::to_string(self)?],
+        )?;
+        Ok(())
+    }
+
+    // One line per change under a heading per kind. Kinds without changes are left out.
+    pub fn text(&self) -> String {
+        let mut lines = vec![];
+        if !self.ranked.is_empty() {
+            lines.push(format!("{} newly ranked:", self.ranked.len()));
+            lines.extend(self.ranked.iter().map(song_line));
+        }
+        if !self.reweighted.is_empty() {
+            lines.push(format!("{} reweighted:", self.reweighted.len()));
+            lines.extend(self.reweighted.iter().map(reweight_line));
+        }
+        if !self.unranked.is_empty() {
+            lines.push(format!("{} unranked:", self.unranked.len()));
+            lines.extend(self.unranked.iter().map(song_line));
+        }
+        lines.join("\n")
+    }
+
+    pub fn notification(&self) -> notify::Notification {
+        let mut counts = vec![];
+        for (count, kind) in &[
+            (self.ranked.len(), "newly ranked"),
+            (self.reweighted.len(), "reweighted"),
+            (self.unranked.len(), "unranked"),
+        ] {
+            if *count > 0 {
+                counts.push(format!("{} {}", count, kind));
+            }
+        }
+        notify::Notification {
+            title: format!("ScoreSaber changes: {}", counts.join(", ")),
+            body: self.text(),
+        }
+    }
+}
+
+fn song_line(song: &Song) -> String {
+    format!("{} ({}): {} stars", song.name, song.difficulty, song.stars)
+}
+
+fn reweight_line(change: &RankingChange) -> String {
+    if change.old_difficulty == change.new_difficulty {
+        format!(
+            "{} ({}): {} -> {} stars",
+            change.name, change.new_difficulty, change.old_stars, change.new_stars
+        )
+    } else {
+        format!(
+            "{} ({} -> {}): {} -> {} stars",
+            change.name,
+            change.old_difficulty,
+            change.new_difficulty,
+            change.old_stars,
+            change.new_stars
+        )
+    }
+}
+
+// The report of the latest crawl, None if there was no crawl yet.
+pub fn latest(db: &rusqlite::Connection) -> Result_<Option<ChangeReport>> {
+    use rusqlite::OptionalExtension;
+    let report: Option<String> = db
+        .query_row(
+            "SELECT report FROM change_reports ORDER BY crawl DESC LIMIT 1",
+            rusqlite::params![],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match report {
+        Some(report) => Ok(Some(serde_json::from_str(&report)?)),
+        None => Ok(None),
+    }
+}
+
+// The changes of the crawls that finished in the last `days` days together with the mappers whose
+// first ranked map was ranked in that time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Digest {
+    pub days: u64,
+    pub changes: ChangeReport,
+    // The mapper and the names of their maps, newest mapper first.
+    pub new_mappers: Vec<(String, Vec<String>)>,
+}
+
+impl Digest {
+    pub fn text(&self) -> String {
+        let mut text = self.changes.text();
+        if !self.new_mappers.is_empty() {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(&format!("{} new mappers:", self.new_mappers.len()));
+            for (mapper, songs) in &self.new_mappers {
+                text.push_str(&format!("\n{}: {}", mapper, songs.join(", ")));
+            }
+        }
+        text
+    }
+
+    pub fn notification(&self) -> notify::Notification {
+        notify::Notification {
+            title: format!(
+                "ScoreSaber digest of the last {} days: {} newly ranked, {} reweighted, {} unranked, {} new mappers",
+                self.days,
+                self.changes.ranked.len(),
+                self.changes.reweighted.len(),
+                self.changes.unranked.len(),
+                self.new_mappers.len()
+            ),
+            body: self.text(),
+        }
+    }
+}
+
+pub fn digest(db: &rusqlite::Connection, days: u64) -> Result_<Digest> {
+    let since = format!("-{} days", days);
+    let mut statement = db.prepare(
+        "SELECT r.report FROM change_reports r JOIN crawls c ON c.id = r.crawl WHERE c.finished_at >= datetime('now', ?) ORDER BY r.crawl",
+    )?;
+    let reports = statement
+        .query_map(rusqlite::params![since], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    let mut changes = ChangeReport::default();
+    for report in reports {
+        let report: ChangeReport = serde_json::from_str(&report)?;
+        changes.ranked.extend(report.ranked);
+        changes.reweighted.extend(report.reweighted);
+        changes.unranked.extend(report.unranked);
+    }
+    // Unranked maps still count as earlier ranked maps of their mapper. A mapper with a map whose
+    // ranked date is unknown could have been ranked before.
+    let mut statement = db.prepare(&format!(
+        "SELECT s.levelAuthorName, s.name FROM scoresaber_songs s JOIN (SELECT s.levelAuthorName AS mapper, MIN({0}) AS first FROM scoresaber_songs s GROUP BY s.levelAuthorName COLLATE NOCASE HAVING COUNT(*) = COUNT({0}) AND MIN({0}) >= datetime('now', ?)) m ON s.levelAuthorName = m.mapper COLLATE NOCASE ORDER BY m.first DESC, m.mapper, s.name",
+        playlist::RANKED_AT
+    ))?;
+    let rows = statement
+        .query_map(rusqlite::params![since], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut new_mappers: Vec<(String, Vec<String>)> = vec![];
+    for (mapper, song) in rows {
+        match new_mappers.last_mut() {
+            Some((last, songs)) if last.eq_ignore_ascii_case(&mapper) => {
+                if !songs.contains(&song) {
+                    songs.push(song);
+                }
+            }
+            _ => new_mappers.push((mapper, vec![song])),
+        }
+    }
+    Ok(Digest {
+        days,
+        changes,
+        new_mappers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::create_schema(&db).unwrap();
+        assert_eq!(latest(&db).unwrap(), None);
+        let report = ChangeReport {
+            ranked: vec![Song {
+                uid: 1,
+                name: "a".to_string(),
+                difficulty: "_Hard_SoloStandard".to_string(),
+                stars: 5.0,
+            }],
+            reweighted: vec![RankingChange {
+                name: "b".to_string(),
+                old_stars: 4.0,
+                new_stars: 4.5,
+                old_difficulty: "_Expert_SoloStandard".to_string(),
+                new_difficulty: "_Expert_SoloStandard".to_string(),
+            }],
+            unranked: vec![],
+        };
+        report.save(&db, 1).unwrap();
+        ChangeReport::default().save(&db, 2).unwrap();
+        assert_eq!(latest(&db).unwrap(), Some(ChangeReport::default()));
+        db.execute(
+            "DELETE FROM change_reports WHERE crawl = 2",
+            rusqlite::params![],
+        )
+        .unwrap();
+        assert_eq!(latest(&db).unwrap(), Some(report.clone()));
+        assert_eq!(
+            report.notification().title,
+            "ScoreSaber changes: 1 newly ranked, 1 reweighted"
+        );
+        assert_eq!(
+            report.text(),
+            "1 newly ranked:\na (_Hard_SoloStandard): 5 stars\n1 reweighted:\nb (_Expert_SoloStandard): 4 -> 4.5 stars"
+        );
+    }
+
+    #[test]
+    fn test_digest() {
+        let db = crate::test_db();
+        // The songs of the first crawl have no known ranked date.
+        db.execute_batch(
+            "UPDATE scoresaber_songs SET first_seen = datetime('now', '-60 days'); INSERT INTO crawls (id, category, started_at, finished_at) VALUES (1, 'date-ranked', datetime('now', '-60 days'), datetime('now', '-59 days')), (2, 'date-ranked', datetime('now', '-2 days'), datetime('now', '-2 days'));",
+        )
+        .unwrap();
+        let song = |name: &str| Song {
+            uid: 1,
+            name: name.to_string(),
+            difficulty: "_Hard_SoloStandard".to_string(),
+            stars: 5.0,
+        };
+        ChangeReport {
+            ranked: vec![song("old")],
+            ..Default::default()
+        }
+        .save(&db, 1)
+        .unwrap();
+        ChangeReport {
+            ranked: vec![song("NUCLEAR-STAR")],
+            ..Default::default()
+        }
+        .save(&db, 2)
+        .unwrap();
+        assert!(digest(&db, 7).unwrap().new_mappers.is_empty());
+        db.execute_batch(
+            "UPDATE scoresaber_songs SET levelAuthorName = 'newcomer', ranked_at = datetime('now', '-2 days') WHERE uid = 100024; UPDATE scoresaber_songs SET levelAuthorName = 'Newcomer', first_seen = datetime('now', '-1 days') WHERE uid = 109086;",
+        )
+        .unwrap();
+        let digest = digest(&db, 7).unwrap();
+        assert_eq!(digest.changes.ranked, vec![song("NUCLEAR-STAR")]);
+        assert_eq!(
+            digest.new_mappers,
+            vec![(
+                "Newcomer".to_string(),
+                vec![
+                    "Milk Crown on Sonnetica".to_string(),
+                    "NUCLEAR-STAR".to_string()
+                ]
+            )]
+        );
+        assert_eq!(
+            digest.notification().title,
+            "ScoreSaber digest of the last 7 days: 1 newly ranked, 0 reweighted, 0 unranked, 1 new mappers"
+        );
+        assert!(digest
+            .text()
+            .ends_with("1 new mappers:\nNewcomer: Milk Crown on Sonnetica, NUCLEAR-STAR"));
+        // A mapper whose first map was ranked before the window is not new.
+        db.execute_batch(
+            "UPDATE scoresaber_songs SET ranked_at = datetime('now', '-30 days') WHERE uid = 109086",
+        )
+        .unwrap();
+        assert!(super::digest(&db, 7).unwrap().new_mappers.is_empty());
+        db.close().unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{derived, notify, output, playlist, Result_};
+
+// Everything that is too involved for command line options lives in an optional toml file.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub notifiers: Vec<notify::NotifierConfig>,
+    pub derived_columns: Vec<derived::DerivedColumn>,
+    pub playlists: Vec<playlist::PlaylistProfile>,
+    // Sources in the order they are trusted when they disagree, see provenance::SOURCES.
+    pub source_precedence: Vec<String>,
+    pub output: output::OutputConfig,
+    pub poll: PollConfig,
+}
+
+// Settings of `poll` that SIGHUP reloads, like
+//
+// [poll]
+// interval = 600
+// max_requests_per_second = 5.0
+// host_requests_per_second = ["scoresaber.com=2"]
+//
+// They replace the command line options they are named after.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PollConfig {
+    pub interval: Option<u64>,
+    pub max_requests_per_second: Option<f64>,
+    pub host_requests_per_second: Vec<String>,
+}
+
+pub fn load(path: Option<&std::path::Path>) -> Result_<Config> {
+    match path {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)?;
+            Ok(toml::from_str(&text)?)
+        }
+        None => Ok(Config::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_notifiers() {
+        let config: Config = toml::from_str(
+            r#"
+            [[notifiers]]
+            type = "discord"
+            webhook_url = "https://discordapp.com/api/webhooks/1/abc"
+
+            [[notifiers]]
+            type = "stdout"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.notifiers,
+            vec![
+                notify::NotifierConfig::Discord {
+                    webhook_url: "https://discordapp.com/api/webhooks/1/abc".to_string()
+                },
+                notify::NotifierConfig::Stdout,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_poll() {
+        let config: Config = toml::from_str(
+            r#"
+            [poll]
+            interval = 600
+            host_requests_per_second = ["scoresaber.com=2"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.poll,
+            PollConfig {
+                interval: Some(600),
+                max_requests_per_second: None,
+                host_requests_per_second: vec!["scoresaber.com=2".to_string()],
+            }
+        );
+        assert!(toml::from_str::<Config>("[poll]\nschedule = 1").is_err());
+    }
+}
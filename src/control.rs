@@ -0,0 +1,107 @@
+use crate::Result_;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Pauses, resumes and aborts a running crawl at its next page boundary: SIGUSR1 pauses a running
+// crawl and resumes a paused one, SIGUSR2 aborts it. SIGTERM is left alone so that it still ends the
+// process right away. The checkpoint is saved at every boundary so that an aborted crawl continues
+// from there with `--resume`. The paused state is saved with it so that a crawl whose process was
+// ended while it was paused starts paused at its checkpoint.
+#[derive(Clone, Default)]
+pub struct Control {
+    toggle: Arc<AtomicBool>,
+    abort: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+}
+
+// How often a paused crawl looks for a signal to resume or abort.
+const PAUSED_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+impl Control {
+    pub fn register() -> Result_<Self> {
+        let control = Control::default();
+        // There are no such signals on Windows.
+        #[cfg(unix)]
+        {
+            signal_hook::flag::register(signal_hook::SIGUSR1, control.toggle.clone())?;
+            signal_hook::flag::register(signal_hook::SIGUSR2, control.abort.clone())?;
+        }
+        Ok(control)
+    }
+
+    // Starts the crawl paused, for one that was paused when its process ended.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    #[cfg(test)]
+    pub fn abort(&self) {
+        self.abort.store(true, Ordering::SeqCst);
+    }
+
+    // Called at a page boundary after `songs` songs of the crawl are stored. Waits while the crawl is
+    // paused and fails if it was aborted. `save_paused` stores every change of the paused state. An
+    // aborted crawl is not paused anymore so that `--resume` runs it.
+    pub fn page_boundary(
+        &self,
+        songs: usize,
+        save_paused: impl Fn(bool) -> Result_<()>,
+    ) -> Result_<()> {
+        loop {
+            if self.abort.load(Ordering::SeqCst) {
+                if self.paused.swap(false, Ordering::SeqCst) {
+                    save_paused(false)?;
+                }
+                return Err(format!(
+                    "aborted the crawl after {} songs, --resume continues it",
+                    songs
+                ))?;
+            }
+            if self.toggle.swap(false, Ordering::SeqCst) {
+                let paused = !self.paused.load(Ordering::SeqCst);
+                self.paused.store(paused, Ordering::SeqCst);
+                save_paused(paused)?;
+                match paused {
+                    true => println!(
+                        "paused the crawl after {} songs, SIGUSR1 resumes it and SIGUSR2 aborts it",
+                        songs
+                    ),
+                    false => println!("resuming the crawl"),
+                }
+            }
+            if !self.paused.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            std::thread::sleep(PAUSED_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control() {
+        let saved = std::cell::RefCell::new(vec![]);
+        let save_paused = |paused: bool| -> Result_<()> {
+            saved.borrow_mut().push(paused);
+            Ok(())
+        };
+        let control = Control::default();
+        control.page_boundary(0, save_paused).unwrap();
+        control.toggle.store(true, Ordering::SeqCst);
+        let toggle = control.toggle.clone();
+        let resumer = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            toggle.store(true, Ordering::SeqCst);
+        });
+        control.page_boundary(10, save_paused).unwrap();
+        resumer.join().unwrap();
+        assert_eq!(*saved.borrow(), [true, false]);
+        control.pause();
+        control.abort();
+        assert!(control.page_boundary(20, save_paused).is_err());
+        assert_eq!(*saved.borrow(), [true, false, false]);
+    }
+}
@@ -0,0 +1,209 @@
+// The responses `simulate` replays. A corpus is either a directory with files named after their
+// request, `get-leaderboards-<page>.json` and `player-<id>.json`, or a directory written by
+// `--archive-responses` whose index.tsv maps the url of every response to its file.
+
+use crate::{
+    archive, extract_ranked_songs_page, player, BoxedSongs, Category, LastPage, RankedSongsPage,
+    Result_,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+pub enum Corpus {
+    Files(PathBuf),
+    Archive {
+        directory: PathBuf,
+        // The file of every leaderboard page in the order of the songs.
+        pages: Vec<String>,
+        // The file of every player.
+        players: HashMap<String, String>,
+    },
+}
+
+fn open(path: &Path) -> Result_<std::io::BufReader<std::fs::File>> {
+    match std::fs::File::open(path) {
+        Ok(file) => Ok(std::io::BufReader::new(file)),
+        Err(err) => Err(format!("corpus file {}: {}", path.display(), err))?,
+    }
+}
+
+fn page_path(corpus: &Path, page: u64) -> PathBuf {
+    corpus.join(format!("get-leaderboards-{}.json", page))
+}
+
+impl Corpus {
+    // Only the leaderboard pages of the category are read from an archive.
+    pub fn open(directory: &Path, category: Category) -> Result_<Corpus> {
+        let index_path = directory.join(archive::INDEX);
+        if !index_path.exists() {
+            return Ok(Corpus::Files(directory.to_path_buf()));
+        }
+        let index = std::fs::read_to_string(&index_path)
+            .map_err(|err| format!("corpus file {}: {}", index_path.display(), err))?;
+        // The index is in the order the responses were received so later ones replace earlier
+        // ones. Pages are keyed by the offset of their first song because the page size changes
+        // between requests.
+        let mut pages = BTreeMap::new();
+        let mut players = HashMap::new();
+        for line in index.lines() {
+            let fields = line.split('\t').collect::<Vec<&str>>();
+            let (url, file) = match fields.as_slice() {
+                [_, url, file] => (reqwest::Url::parse(url)?, file.to_string()),
+                _ => return Err(format!("invalid line in the archive index: {}", line))?,
+            };
+            let params = url.query_pairs().into_owned().collect::<HashMap<_, _>>();
+            let query = |key: &str| params.get(key).map(|x| x.as_str());
+            if query("function") == Some("get-leaderboards") {
+                if query("cat") != Some(category.api_value().to_string().as_str()) {
+                    continue;
+                }
+                let number = |key: &str| {
+                    query(key)
+                        .and_then(|x| x.parse::<u64>().ok())
+                        .ok_or_else(|| format!("archived url without a {}: {}", key, url))
+                };
+                pages.insert(number("page")?.saturating_sub(1) * number("limit")?, file);
+            } else if let Some(segments) = url.path_segments() {
+                if let ["api", "player", id, "basic"] = segments.collect::<Vec<&str>>().as_slice() {
+                    players.insert(id.to_string(), file);
+                }
+            }
+        }
+        Ok(Corpus::Archive {
+            directory: directory.to_path_buf(),
+            pages: pages.into_iter().map(|(_, file)| file).collect(),
+            players,
+        })
+    }
+
+    // The limit the archived pages were requested with is unknown so the last page is the one
+    // without a following file.
+    pub fn get_ranked_songs_page(&self, page: u64) -> Result_<RankedSongsPage<BoxedSongs>> {
+        let (songs, last_page) = match self {
+            Corpus::Files(corpus) => {
+                let songs: BoxedSongs =
+                    Box::new(extract_ranked_songs_page(open(&page_path(corpus, page))?, 0).songs);
+                (songs, !page_path(corpus, page + 1).exists())
+            }
+            Corpus::Archive {
+                directory, pages, ..
+            } => {
+                let file = match pages.get((page as usize).wrapping_sub(1)) {
+                    Some(file) => file,
+                    None => return Err(format!("the archive has no page {}", page))?,
+                };
+                let reader = flate2::read::GzDecoder::new(open(&directory.join(file))?);
+                let songs: BoxedSongs = Box::new(extract_ranked_songs_page(reader, 0).songs);
+                (songs, page as usize == pages.len())
+            }
+        };
+        Ok(RankedSongsPage {
+            songs,
+            last_page: LastPage::Known(last_page),
+        })
+    }
+
+    pub fn get_player(&self, id: &str) -> Result_<player::Player> {
+        match self {
+            Corpus::Files(corpus) => {
+                let path = corpus.join(format!("player-{}.json", id));
+                Ok(serde_json::from_reader(open(&path)?)?)
+            }
+            Corpus::Archive {
+                directory, players, ..
+            } => {
+                let file = match players.get(id) {
+                    Some(file) => file,
+                    None => return Err(format!("the archive has no player {}", id))?,
+                };
+                let reader = flate2::read::GzDecoder::new(open(&directory.join(file))?);
+                Ok(serde_json::from_reader(reader)?)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulate(corpus: &Corpus) -> Vec<String> {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::create_schema(&db).unwrap();
+        let playlist = crate::run_crawl(
+            &db,
+            crate::CrawlOptions {
+                category: Category::DateRanked,
+                prune_unranked: true,
+                resume_offset: 0,
+                stop_when_unchanged: false,
+            },
+            &crate::control::Control::default(),
+            |page| corpus.get_ranked_songs_page(page),
+            |id| corpus.get_player(id),
+            &[],
+            &[],
+        )
+        .unwrap();
+        db.close().unwrap();
+        playlist.songs.into_iter().map(|song| song.name).collect()
+    }
+
+    #[test]
+    fn test_simulate_corpus() {
+        let directory = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test_data/corpus"));
+        let corpus = Corpus::open(directory, Category::DateRanked).unwrap();
+        assert_eq!(
+            simulate(&corpus),
+            ["Milk Crown on Sonnetica", "Happppy song", "NUCLEAR-STAR"]
+        );
+    }
+
+    #[test]
+    fn test_archive_corpus() {
+        let directory = std::env::temp_dir().join("scoresaber-crawler-test-archive-corpus");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        let page = include_bytes!("../test_data/corpus/get-leaderboards-1.json");
+        let url = |cat: u8, limit: u64, page: u64| {
+            format!(
+                "https://scoresaber.com/api.php?function=get-leaderboards&ranked=1&cat={}&limit={}&page={}",
+                cat, limit, page
+            )
+        };
+        let time = |secs: u64| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        // A later response for the same songs replaces the earlier one and other categories are
+        // left out.
+        archive::write(&directory, &url(1, 50, 1), br#"{"songs": []}"#, time(1)).unwrap();
+        archive::write(&directory, &url(1, 50, 1), page, time(2)).unwrap();
+        archive::write(&directory, &url(0, 50, 1), br#"{"songs": []}"#, time(3)).unwrap();
+        let player = br#"{"id": "1", "name": "someone", "pp": 1000, "rank": 1, "countryRank": 1}"#;
+        archive::write(
+            &directory,
+            "https://scoresaber.com/api/player/1/basic",
+            player,
+            time(4),
+        )
+        .unwrap();
+        let corpus = Corpus::open(&directory, Category::DateRanked).unwrap();
+        assert_eq!(
+            simulate(&corpus),
+            ["Milk Crown on Sonnetica", "Happppy song", "NUCLEAR-STAR"]
+        );
+        assert_eq!(corpus.get_player("1").unwrap().name, "someone");
+        assert!(corpus.get_player("2").is_err());
+        assert!(corpus.get_ranked_songs_page(2).is_err());
+        // Pages of different sizes are ordered by their first song.
+        archive::write(&directory, &url(1, 25, 3), br#"{"songs": []}"#, time(5)).unwrap();
+        let corpus = Corpus::open(&directory, Category::DateRanked).unwrap();
+        assert_eq!(
+            corpus.get_ranked_songs_page(1).unwrap().last_page,
+            LastPage::Known(false)
+        );
+        assert_eq!(
+            corpus.get_ranked_songs_page(2).unwrap().last_page,
+            LastPage::Known(true)
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
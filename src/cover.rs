@@ -0,0 +1,227 @@
+use crate::{playlist::BeatsaberPlaylist, Result_};
+
+const SIZE: u32 = 256;
+const MARGIN: u32 = 16;
+// Glyphs are 5x7 pixels scaled up and followed by one scaled pixel of space.
+const TITLE_SCALE: u32 = 3;
+const TEXT_SCALE: u32 = 2;
+
+// 5x7 pixel font. Every row is a byte whose lowest five bits are the pixels, the highest of them
+// on the left. Lower case letters use the upper case glyphs.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ' ' => [0x00; 7],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '\'' => [0x0C, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        '&' => [0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D],
+        '*' | '★' => [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+// Breaks the text into lines of at most `width` characters at spaces. Longer words are split.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word.chars().collect::<Vec<char>>();
+        if !line.is_empty() && line.chars().count() + 1 + word.len() > width {
+            lines.push(line.clone());
+            line.clear();
+        }
+        while word.len() > width {
+            let rest = word.split_off(width);
+            if !line.is_empty() {
+                lines.push(line.clone());
+                line.clear();
+            }
+            lines.push(word.into_iter().collect());
+            word = rest;
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.extend(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+// The lines of the cover: the title, the restrictions from the parentheses of generated titles like
+// "Ranked Songs (9.5 to 10 stars)" each on their own and the song count.
+fn text(playlist: &BeatsaberPlaylist) -> (String, Vec<String>) {
+    let (title, restrictions) = match playlist.title.find(" (") {
+        Some(index) if playlist.title.ends_with(')') => (
+            &playlist.title[..index],
+            playlist.title[index + 2..playlist.title.len() - 1]
+                .split(", ")
+                .map(|x| x.to_string())
+                .collect(),
+        ),
+        _ => (playlist.title.as_str(), vec![]),
+    };
+    let mut lines: Vec<String> = restrictions;
+    lines.push(format!("{} songs", playlist.songs.len()));
+    (title.to_string(), lines)
+}
+
+// Background color derived from the title so that playlists with different titles differ.
+fn background(title: &str) -> image::Rgb<u8> {
+    // FNV-1a
+    let hash = title.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    });
+    let hue = f64::from(hash % 360) / 60.0;
+    // Saturation 0.6 and value 0.45 keep white text readable.
+    let (value, chroma) = (0.45, 0.45 * 0.6);
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let channel = |c: f64| ((c + value - chroma) * 255.0).round() as u8;
+    image::Rgb([channel(r), channel(g), channel(b)])
+}
+
+fn draw_line(image: &mut image::RgbImage, line: &str, y: u32, scale: u32) {
+    let mut x = MARGIN;
+    for c in line.chars() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..5 {
+                if bits & (0x10 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let (px, py) = (x + column * scale + dx, y + row as u32 * scale + dy);
+                        if px < SIZE && py < SIZE {
+                            image.put_pixel(px, py, image::Rgb([255, 255, 255]));
+                        }
+                    }
+                }
+            }
+        }
+        x += 6 * scale;
+    }
+}
+
+// A png cover with the title, the restrictions and the song count on a colored background.
+pub fn render(playlist: &BeatsaberPlaylist) -> Result_<Vec<u8>> {
+    let mut image = image::RgbImage::from_pixel(SIZE, SIZE, background(&playlist.title));
+    let (title, lines) = text(playlist);
+    let columns = |scale: u32| ((SIZE - 2 * MARGIN) / (6 * scale)) as usize;
+    let mut y = MARGIN;
+    for line in wrap(&title, columns(TITLE_SCALE)) {
+        draw_line(&mut image, &line, y, TITLE_SCALE);
+        y += 9 * TITLE_SCALE;
+    }
+    y += 3 * TEXT_SCALE;
+    for line in lines {
+        for line in wrap(&line, columns(TEXT_SCALE)) {
+            draw_line(&mut image, &line, y, TEXT_SCALE);
+            y += 9 * TEXT_SCALE;
+        }
+    }
+    let mut bytes = vec![];
+    image::png::PNGEncoder::new(&mut bytes).encode(
+        &image.into_raw(),
+        SIZE,
+        SIZE,
+        image::ColorType::RGB(8),
+    )?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("Ranked Songs", 13), ["Ranked Songs"]);
+        assert_eq!(wrap("Ranked Songs", 8), ["Ranked", "Songs"]);
+        assert_eq!(wrap("abcdefghij k", 4), ["abcd", "efgh", "ij k"]);
+        assert!(wrap("", 4).is_empty());
+    }
+
+    #[test]
+    fn test_render() {
+        let playlist = BeatsaberPlaylist {
+            title: "Ranked Songs (9.5 to 10 stars, ExpertPlus)".to_string(),
+            author: crate::playlist::AUTHOR.to_string(),
+            description: String::new(),
+            songs: vec![],
+        };
+        assert_eq!(
+            text(&playlist),
+            (
+                "Ranked Songs".to_string(),
+                vec![
+                    "9.5 to 10 stars".to_string(),
+                    "ExpertPlus".to_string(),
+                    "0 songs".to_string()
+                ]
+            )
+        );
+        assert_ne!(background("a"), background("b"));
+        let png = render(&playlist).unwrap();
+        let image = image::load_from_memory(&png).unwrap().to_rgb();
+        assert_eq!(image.dimensions(), (SIZE, SIZE));
+        assert_eq!(*image.get_pixel(0, 0), background(&playlist.title));
+        // The first pixel of the R.
+        assert_eq!(
+            *image.get_pixel(MARGIN, MARGIN),
+            image::Rgb([255, 255, 255])
+        );
+    }
+}
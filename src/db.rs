@@ -0,0 +1,610 @@
+use crate::Result_;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub enum DbCommand {
+    /// Compares the songs and BeatSaver data with another database, for example a snapshot shared
+    /// by someone else.
+    Diff {
+        #[structopt(parse(from_os_str))]
+        other: std::path::PathBuf,
+    },
+    /// Shows the fields of a song that several sources write with the value of every source. The
+    /// value marked with * is the one in use.
+    Lookup {
+        /// Leaderboard uid or song hash.
+        song: String,
+    },
+    /// Checks the integrity of the database, updates the statistics of the query planner, reclaims
+    /// free space and reports rows that refer to songs that are not stored.
+    Maintain,
+    /// Adds the songs, scores and history of another database, for example of crawls on another
+    /// machine, together with its tracked players and their first clears. Songs and scores stored in
+    /// both keep the newer version. Crawls, BeatSaver data, imported playlists, map pools and
+    /// derived values are not merged, crawling or running the crawler once fills them in again.
+    Merge {
+        #[structopt(parse(from_os_str))]
+        other: std::path::PathBuf,
+    },
+    /// Copies the database to a new file with SQLite's online backup, which is safe while another
+    /// process like `poll` writes to it.
+    Backup {
+        #[structopt(parse(from_os_str))]
+        path: std::path::PathBuf,
+    },
+    /// Replaces the database with a backup.
+    Restore {
+        #[structopt(parse(from_os_str))]
+        path: std::path::PathBuf,
+    },
+}
+
+// Tables whose rows belong to a leaderboard uid of scoresaber_songs.
+const UID_TABLES: &[&str] = &[
+    "scoresaber_songs_history",
+    "ranked_episodes",
+    "ranking_changes",
+    "player_scores",
+    "first_clears",
+    "derived_values",
+    "field_sources",
+];
+
+pub fn run(
+    db: &mut rusqlite::Connection,
+    command: DbCommand,
+    precedence: &[String],
+) -> Result_<()> {
+    match command {
+        DbCommand::Diff { other } => {
+            let differences = differences(db, &other)?;
+            for difference in differences.iter() {
+                println!("{}", difference);
+            }
+            println!("{} differences.", differences.len());
+            Ok(())
+        }
+        DbCommand::Lookup { song } => crate::provenance::lookup(db, &song, precedence),
+        DbCommand::Maintain => maintain(db),
+        DbCommand::Merge { other } => {
+            for (table, rows) in merge(db, &other)? {
+                println!("Merged {} rows into {}.", rows, table);
+            }
+            Ok(())
+        }
+        DbCommand::Backup { path } => {
+            backup(db, &path)?;
+            println!("Backed up the database to {}.", path.display());
+            Ok(())
+        }
+        DbCommand::Restore { path } => {
+            restore(db, &path)?;
+            println!("Restored the database from {}.", path.display());
+            Ok(())
+        }
+    }
+}
+
+// Refuses to overwrite an existing file which might be an earlier backup.
+fn backup(db: &rusqlite::Connection, path: &std::path::Path) -> Result_<()> {
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()))?;
+    }
+    db.backup(
+        rusqlite::DatabaseName::Main,
+        path,
+        None::<fn(rusqlite::backup::Progress)>,
+    )?;
+    Ok(())
+}
+
+fn restore(db: &mut rusqlite::Connection, path: &std::path::Path) -> Result_<()> {
+    if !path.exists() {
+        return Err(format!("backup {} does not exist", path.display()))?;
+    }
+    db.restore(
+        rusqlite::DatabaseName::Main,
+        path,
+        None::<fn(rusqlite::backup::Progress)>,
+    )?;
+    Ok(())
+}
+
+fn maintain(db: &rusqlite::Connection) -> Result_<()> {
+    let problems = lines(db, "PRAGMA integrity_check", |row| row.get(0))?;
+    if problems != ["ok"] {
+        for problem in problems.iter() {
+            println!("{}", problem);
+        }
+        return Err("the database is corrupt, restore a backup or export what is readable")?;
+    }
+    println!("Integrity check passed.");
+    for (table, count) in orphans(db)? {
+        if count > 0 {
+            println!(
+                "{} rows of {} refer to songs that are not stored.",
+                count, table
+            );
+        }
+    }
+    db.execute_batch("ANALYZE")?;
+    let before = file_size(db)?;
+    db.execute_batch("VACUUM")?;
+    println!(
+        "Vacuumed the database from {} to {} bytes.",
+        before,
+        file_size(db)?
+    );
+    Ok(())
+}
+
+// The number of rows of every table in UID_TABLES without a song.
+fn orphans(db: &rusqlite::Connection) -> Result_<Vec<(&'static str, i64)>> {
+    let mut orphans = vec![];
+    for table in UID_TABLES {
+        let count = db.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM {} WHERE uid NOT IN (SELECT uid FROM scoresaber_songs)",
+                table
+            ),
+            rusqlite::params![],
+            |row| row.get(0),
+        )?;
+        orphans.push((*table, count));
+    }
+    Ok(orphans)
+}
+
+fn file_size(db: &rusqlite::Connection) -> Result_<i64> {
+    let pages: i64 = db.query_row("PRAGMA page_count", rusqlite::params![], |row| row.get(0))?;
+    let page_size: i64 = db.query_row("PRAGMA page_size", rusqlite::params![], |row| row.get(0))?;
+    Ok(pages * page_size)
+}
+
+// Lists the rows of a query as lines of text.
+fn lines(
+    db: &rusqlite::Connection,
+    sql: &str,
+    line: impl Fn(&rusqlite::Row) -> rusqlite::Result<String>,
+) -> Result_<Vec<String>> {
+    let mut statement = db.prepare(sql)?;
+    let lines = statement
+        .query_map(rusqlite::params![], line)?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(lines)
+}
+
+// The other database is attached as `other` while `f` runs.
+fn with_attached<T>(
+    db: &rusqlite::Connection,
+    other: &std::path::Path,
+    f: impl FnOnce(&rusqlite::Connection) -> Result_<T>,
+) -> Result_<T> {
+    let path = match other.to_str() {
+        Some(path) => path,
+        None => return Err(format!("invalid database path {}", other.display()))?,
+    };
+    if !other.exists() {
+        return Err(format!("database {} does not exist", path))?;
+    }
+    db.execute("ATTACH DATABASE ? AS other", rusqlite::params![path])?;
+    let result = f(db);
+    db.execute("DETACH DATABASE other", rusqlite::params![])?;
+    result
+}
+
+fn differences(db: &rusqlite::Connection, other: &std::path::Path) -> Result_<Vec<String>> {
+    with_attached(db, other, compare)
+}
+
+// History tables without a key with the columns that identify a row, which are indexed. Rows of the
+// other database that are not stored yet are added.
+const HISTORY_TABLES: &[(&str, &[&str])] = &[
+    ("scoresaber_songs_history", &["uid", "observed_at"]),
+    ("ranking_changes", &["uid", "changed_at"]),
+    ("player_history", &["player_id", "recorded_at"]),
+];
+
+// Returns the number of added or updated rows of every table. The other database is not changed so
+// it has to be at the same schema version, running the crawler on it once updates it.
+fn merge(
+    db: &rusqlite::Connection,
+    other: &std::path::Path,
+) -> Result_<Vec<(&'static str, usize)>> {
+    with_attached(db, other, |db| {
+        let version = |schema: &str| -> rusqlite::Result<i64> {
+            db.query_row(
+                &format!("SELECT version FROM {}.schema_version", schema),
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+        };
+        let (version, other_version) = (version("main")?, version("other")?);
+        if version != other_version {
+            return Err(format!(
+                "the other database has schema version {} instead of {}, run the crawler on it once to update it",
+                other_version, version
+            ))?;
+        }
+        db.execute_batch("BEGIN")?;
+        let result = merge_tables(db);
+        match result {
+            Ok(_) => db.execute_batch("COMMIT")?,
+            Err(_) => db.execute_batch("ROLLBACK")?,
+        }
+        result
+    })
+}
+
+fn merge_tables(db: &rusqlite::Connection) -> Result_<Vec<(&'static str, usize)>> {
+    let mut merged = vec![];
+    // Before the songs so that their stored last_updated is still the one of this database. The
+    // episodes of a song stored in both come from the database with the newer version of the song,
+    // like the song itself, and keep the earlier start.
+    merged.push((
+        "ranked_episodes",
+        db.execute(
+            "INSERT INTO main.ranked_episodes (uid, episode, started_at, ended_at) SELECT uid, episode, started_at, ended_at FROM other.ranked_episodes WHERE 1 ON CONFLICT(uid, episode) DO UPDATE SET started_at = MIN(started_at, excluded.started_at), ended_at = excluded.ended_at WHERE datetime((SELECT last_updated FROM other.scoresaber_songs WHERE uid = excluded.uid)) > IFNULL(datetime((SELECT last_updated FROM main.scoresaber_songs WHERE uid = excluded.uid)), '')",
+            rusqlite::params![],
+        )?,
+    ));
+    // A song keeps the first time either database saw it.
+    merged.push((
+        "scoresaber_songs",
+        upsert_newer(
+            db,
+            "scoresaber_songs",
+            &["uid"],
+            "last_updated",
+            Some("first_seen"),
+        )?,
+    ));
+    merged.push((
+        "player_scores",
+        upsert_newer(db, "player_scores", &["player_id", "uid"], "time_set", None)?,
+    ));
+    merged.push((
+        "full_score_crawls",
+        upsert_newer(
+            db,
+            "full_score_crawls",
+            &["player_id"],
+            "completed_at",
+            None,
+        )?,
+    ));
+    // A clear stored in both keeps the earlier one.
+    merged.push((
+        "first_clears",
+        db.execute(
+            "INSERT INTO main.first_clears (player_id, uid, accuracy, cleared_at) SELECT player_id, uid, accuracy, cleared_at FROM other.first_clears WHERE 1 ON CONFLICT(player_id, uid) DO UPDATE SET accuracy = excluded.accuracy, cleared_at = excluded.cleared_at WHERE datetime(excluded.cleared_at) < datetime(cleared_at)",
+            rusqlite::params![],
+        )?,
+    ));
+    merged.push((
+        "tracked_players",
+        db.execute(
+            "INSERT OR IGNORE INTO main.tracked_players (id) SELECT id FROM other.tracked_players",
+            rusqlite::params![],
+        )?,
+    ));
+    for (table, key) in HISTORY_TABLES {
+        let columns = columns(db, table)?;
+        let same = key
+            .iter()
+            .map(|column| format!("m.{0} = o.{0}", column))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        let rows = db.execute(
+            &format!(
+                "INSERT INTO main.{0} ({1}) SELECT {1} FROM other.{0} o WHERE NOT EXISTS (SELECT 1 FROM main.{0} m WHERE {2})",
+                table,
+                columns.join(", "),
+                same
+            ),
+            rusqlite::params![],
+        )?;
+        merged.push((*table, rows));
+    }
+    Ok(merged)
+}
+
+// Copies the rows of `table` that are missing here or whose `time` column is newer in the other
+// database. Times are compared through datetime because the API's times like
+// "2020-01-01T00:00:00Z" do not sort as text against SQLite's like "2020-01-01 00:00:00". Updated
+// rows keep the earlier value of the `keep_first` column.
+fn upsert_newer(
+    db: &rusqlite::Connection,
+    table: &str,
+    key: &[&str],
+    time: &str,
+    keep_first: Option<&str>,
+) -> Result_<usize> {
+    let columns = columns(db, table)?;
+    let assignments = columns
+        .iter()
+        .filter(|column| !key.contains(&column.as_str()))
+        .map(|column| {
+            if Some(column.as_str()) == keep_first {
+                format!(
+                    "{0} = MIN(COALESCE({0}, excluded.{0}), COALESCE(excluded.{0}, {0}))",
+                    column
+                )
+            } else {
+                format!("{0} = excluded.{0}", column)
+            }
+        })
+        .collect::<Vec<_>>();
+    // The WHERE of the SELECT is needed so that SQLite parses the ON CONFLICT clause.
+    let rows = db.execute(
+        &format!(
+            "INSERT INTO main.{0} ({1}) SELECT {1} FROM other.{0} WHERE 1 ON CONFLICT({2}) DO UPDATE SET {3} WHERE datetime(excluded.{4}) > COALESCE(datetime({4}), '')",
+            table,
+            columns.join(", "),
+            key.join(", "),
+            assignments.join(", "),
+            time
+        ),
+        rusqlite::params![],
+    )?;
+    Ok(rows)
+}
+
+// The columns of a table in the main database. Both databases have the same columns but not
+// necessarily in the same order.
+fn columns(db: &rusqlite::Connection, table: &str) -> Result_<Vec<String>> {
+    lines(db, &format!("PRAGMA main.table_info({})", table), |row| {
+        row.get(1)
+    })
+}
+
+fn compare(db: &rusqlite::Connection) -> Result_<Vec<String>> {
+    let mut differences = vec![];
+    for (sql, side) in &[
+        (
+            "SELECT uid, name, diff, stars FROM main.scoresaber_songs WHERE uid NOT IN (SELECT uid FROM other.scoresaber_songs) ORDER BY uid",
+            "only here",
+        ),
+        (
+            "SELECT uid, name, diff, stars FROM other.scoresaber_songs WHERE uid NOT IN (SELECT uid FROM main.scoresaber_songs) ORDER BY uid",
+            "only in other",
+        ),
+    ] {
+        differences.extend(lines(db, sql, |row| {
+            Ok(format!(
+                "{} {} ({}) {} stars: {}",
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, f64>(3)?,
+                side
+            ))
+        })?);
+    }
+    differences.extend(lines(
+        db,
+        "SELECT m.uid, m.name, m.diff, m.stars, o.diff, o.stars FROM main.scoresaber_songs m JOIN other.scoresaber_songs o ON o.uid = m.uid WHERE m.stars != o.stars OR m.diff != o.diff OR m.id != o.id ORDER BY m.uid",
+        |row| {
+            Ok(format!(
+                "{} {}: ({}) {} stars here, ({}) {} stars in other",
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, f64>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, f64>(5)?
+            ))
+        },
+    )?);
+    differences.extend(lines(
+        db,
+        "SELECT m.hash, m.characteristic, m.difficulty, m.notes, o.notes FROM main.beatsaver_difficulties m JOIN other.beatsaver_difficulties o ON o.hash = m.hash AND o.characteristic = m.characteristic AND o.difficulty = m.difficulty WHERE m.notes != o.notes ORDER BY m.hash",
+        |row| {
+            Ok(format!(
+                "BeatSaver {} {} {}: {} notes here, {} notes in other",
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?
+            ))
+        },
+    )?);
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_differences() {
+        let path = std::env::temp_dir().join("scoresaber-crawler-test-differences.sqlite");
+        let _ = std::fs::remove_file(&path);
+        let other = rusqlite::Connection::open(&path).unwrap();
+        crate::create_schema(&other).unwrap();
+        let db = crate::test_db();
+        for (i, mut song) in crate::test_songs().into_iter().enumerate() {
+            if i == 1 {
+                song.star_difficulty += 1.0;
+            }
+            if i != 2 {
+                crate::insert_song_into_db(&other, &song).unwrap();
+            }
+        }
+        other.close().unwrap();
+        let differences = differences(&db, &path).unwrap();
+        assert_eq!(differences.len(), 2);
+        assert!(differences[0].ends_with("only here"));
+        assert!(differences[1].contains("stars in other"));
+        db.close().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_merge() {
+        let path = std::env::temp_dir().join("scoresaber-crawler-test-merge.sqlite");
+        let _ = std::fs::remove_file(&path);
+        let other = rusqlite::Connection::open(&path).unwrap();
+        crate::create_schema(&other).unwrap();
+        let db = crate::test_db();
+        let mut songs = crate::test_songs();
+        for song in songs.iter() {
+            crate::insert_song_into_db(&other, song).unwrap();
+        }
+        songs[2].star_difficulty += 1.0;
+        crate::insert_song_into_db(&other, &songs[2]).unwrap();
+        // The last song was unranked and ranked again in the other database.
+        let seen = songs
+            .iter()
+            .map(|song| song.uid as i64)
+            .filter(|uid| *uid != songs[3].uid as i64)
+            .collect();
+        crate::prune(&other, &seen).unwrap();
+        crate::insert_song_into_db(&other, &songs[3]).unwrap();
+        other
+            .execute(
+                "UPDATE scoresaber_songs SET last_updated = '2000-01-01 00:00:00'",
+                rusqlite::params![],
+            )
+            .unwrap();
+        other
+            .execute(
+                "UPDATE scoresaber_songs SET last_updated = '2100-01-01 00:00:00', first_seen = '2000-01-01 00:00:00' WHERE uid = ?",
+                rusqlite::params![songs[2].uid as i64],
+            )
+            .unwrap();
+        other
+            .execute(
+                "INSERT INTO player_scores VALUES ('p', ?, 1, 1, 100, 100, 0.9, 200.0, 1.0, '', 0, 0, 10, 1, '2020-01-01T00:00:00Z')",
+                rusqlite::params![songs[3].uid as i64],
+            )
+            .unwrap();
+        // Older than the score stored here even though it sorts after it as text.
+        other
+            .execute(
+                "INSERT INTO player_scores VALUES ('p', ?, 2, 1, 100, 100, 0.9, 200.0, 1.0, '', 0, 0, 10, 1, '2020-01-01T00:00:00Z')",
+                rusqlite::params![songs[0].uid as i64],
+            )
+            .unwrap();
+        other
+            .execute_batch(
+                "INSERT INTO tracked_players (id) VALUES ('p'); INSERT INTO full_score_crawls VALUES ('p', '2020-01-02 00:00:00')",
+            )
+            .unwrap();
+        other
+            .execute(
+                "INSERT INTO first_clears VALUES ('p', ?, 0.9, '2020-01-01 00:00:00')",
+                rusqlite::params![songs[3].uid as i64],
+            )
+            .unwrap();
+        other.close().unwrap();
+        db.execute(
+            "INSERT INTO player_scores VALUES ('p', ?, 3, 1, 100, 100, 0.9, 200.0, 1.0, '', 0, 0, 10, 1, '2020-01-01 12:00:00')",
+            rusqlite::params![songs[0].uid as i64],
+        )
+        .unwrap();
+        for table in &[
+            "scoresaber_songs",
+            "ranked_episodes",
+            "scoresaber_songs_history",
+        ] {
+            db.execute(
+                &format!("DELETE FROM {} WHERE uid = ?", table),
+                rusqlite::params![songs[3].uid as i64],
+            )
+            .unwrap();
+        }
+
+        let merged = merge(&db, &path).unwrap();
+        assert!(merged.contains(&("scoresaber_songs", 2)));
+        assert!(merged.contains(&("player_scores", 1)));
+        assert!(merged.contains(&("tracked_players", 1)));
+        assert!(merged.contains(&("full_score_crawls", 1)));
+        assert!(merged.contains(&("first_clears", 1)));
+        let score_id: i64 = db
+            .query_row(
+                "SELECT score_id FROM player_scores WHERE uid = ?",
+                rusqlite::params![songs[0].uid as i64],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(score_id, 3);
+        // Both episodes of the last song and the episode of the newer version of the third one.
+        assert!(merged.contains(&("ranked_episodes", 3)));
+        let episodes = db
+            .prepare("SELECT e.episode, e.ended_at IS NOT NULL, (SELECT COUNT(*) FROM scoresaber_songs_history h WHERE h.uid = e.uid AND h.episode = e.episode) FROM ranked_episodes e WHERE e.uid = ? ORDER BY e.episode")
+            .unwrap()
+            .query_map(rusqlite::params![songs[3].uid as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<(i64, bool, i64)>>>()
+            .unwrap();
+        assert_eq!(episodes, [(1, true, 1), (2, false, 1)]);
+        let (stars, first_seen): (f64, String) = db
+            .query_row(
+                "SELECT stars, first_seen FROM scoresaber_songs WHERE uid = ?",
+                rusqlite::params![songs[2].uid as i64],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(stars, songs[2].star_difficulty);
+        assert_eq!(first_seen, "2000-01-01 00:00:00");
+        let history: i64 = db
+            .query_row(
+                "SELECT COUNT(*) FROM scoresaber_songs_history WHERE uid = ? AND stars = ?",
+                rusqlite::params![songs[2].uid as i64, songs[2].star_difficulty],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(history, 1);
+        // Merging again changes nothing.
+        let merged = merge(&db, &path).unwrap();
+        assert!(merged.iter().all(|(_, rows)| *rows == 0));
+        db.close().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_backup() {
+        let path = std::env::temp_dir().join("scoresaber-crawler-test-backup.sqlite");
+        let _ = std::fs::remove_file(&path);
+        let mut db = crate::test_db();
+        let songs = |db: &rusqlite::Connection| -> i64 {
+            db.query_row(
+                "SELECT COUNT(*) FROM scoresaber_songs",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        let stored = songs(&db);
+        backup(&db, &path).unwrap();
+        assert!(backup(&db, &path).is_err());
+        db.execute("DELETE FROM scoresaber_songs", rusqlite::params![])
+            .unwrap();
+        assert_eq!(songs(&db), 0);
+        restore(&mut db, &path).unwrap();
+        assert_eq!(songs(&db), stored);
+        db.close().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_maintain() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::create_schema(&db).unwrap();
+        db.execute(
+            "INSERT INTO derived_values (uid, name, value) VALUES (1, 'x', 2)",
+            rusqlite::params![],
+        )
+        .unwrap();
+        let orphans = orphans(&db).unwrap();
+        assert_eq!(orphans.len(), UID_TABLES.len());
+        assert!(orphans.contains(&("derived_values", 1)));
+        assert!(orphans.contains(&("player_scores", 0)));
+        maintain(&db).unwrap();
+        db.close().unwrap();
+    }
+}
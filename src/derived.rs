@@ -0,0 +1,399 @@
+use crate::{playlist, profile, Result_};
+
+// A column computed from the other columns of every difficulty, configured like
+//
+// [[derived_columns]]
+// name = "pp95"
+// expression = "pp(stars, 0.95)"
+//
+// The values are stored in the `derived_values` table so that they can be used in filters, sorts
+// and queries.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DerivedColumn {
+    pub name: String,
+    pub expression: String,
+}
+
+const VARIABLES: &[&str] = &[
+    "stars",
+    "bpm",
+    "plays",
+    "daily_plays",
+    "notes",
+    "length",
+    "nps",
+    "max_score",
+];
+
+// Names of derived columns end up in sql so they are restricted to identifiers.
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Identifier(String),
+    Symbol(char),
+}
+
+fn tokenize(source: &str) -> Result_<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_digit() || c == '.') {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+            let number = number
+                .parse()
+                .map_err(|_| format!("invalid number {}", number))?;
+            tokens.push(Token::Number(number));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut identifier = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                identifier.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Identifier(identifier));
+        } else if "+-*/(),".contains(c) {
+            tokens.push(Token::Symbol(c));
+            chars.next();
+        } else {
+            return Err(format!("unexpected character {}", c))?;
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expression {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Expression>),
+    Binary(char, Box<Expression>, Box<Expression>),
+    Call(String, Vec<Expression>),
+}
+
+fn arity(function: &str) -> Option<usize> {
+    match function {
+        "floor" | "ceil" | "round" | "abs" => Some(1),
+        "min" | "max" | "pp" => Some(2),
+        _ => None,
+    }
+}
+
+// Recursive descent with the usual precedence:
+// sum := product (('+' | '-') product)*
+// product := unary (('*' | '/') unary)*
+// unary := '-' unary | atom
+// atom := number | variable | function '(' arguments ')' | '(' sum ')'
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek_symbol(&self) -> Option<char> {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(c)) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if self.peek_symbol() == Some(symbol) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn sum(&mut self) -> Result_<Expression> {
+        let mut left = self.product()?;
+        while let Some(operator) = self.peek_symbol().filter(|c| *c == '+' || *c == '-') {
+            self.position += 1;
+            let right = self.product()?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn product(&mut self) -> Result_<Expression> {
+        let mut left = self.unary()?;
+        while let Some(operator) = self.peek_symbol().filter(|c| *c == '*' || *c == '/') {
+            self.position += 1;
+            let right = self.unary()?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result_<Expression> {
+        if self.eat('-') {
+            Ok(Expression::Negate(Box::new(self.unary()?)))
+        } else {
+            self.atom()
+        }
+    }
+
+    fn atom(&mut self) -> Result_<Expression> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(number)) => Ok(Expression::Number(number)),
+            Some(Token::Identifier(name)) => {
+                if !self.eat('(') {
+                    if !VARIABLES.contains(&name.as_str()) {
+                        return Err(format!(
+                            "unknown variable {}, expected one of {}",
+                            name,
+                            VARIABLES.join(", ")
+                        ))?;
+                    }
+                    return Ok(Expression::Variable(name));
+                }
+                let mut arguments = vec![];
+                if !self.eat(')') {
+                    loop {
+                        arguments.push(self.sum()?);
+                        if self.eat(')') {
+                            break;
+                        }
+                        if !self.eat(',') {
+                            return Err(format!("expected , or ) in the arguments of {}", name))?;
+                        }
+                    }
+                }
+                match arity(&name) {
+                    Some(arity) if arity == arguments.len() => {
+                        Ok(Expression::Call(name, arguments))
+                    }
+                    Some(arity) => Err(format!("{} takes {} arguments", name, arity))?,
+                    None => Err(format!("unknown function {}", name))?,
+                }
+            }
+            Some(Token::Symbol('(')) => {
+                let expression = self.sum()?;
+                if !self.eat(')') {
+                    return Err("expected )")?;
+                }
+                Ok(expression)
+            }
+            _ => Err("expected a number, variable, function call or (")?,
+        }
+    }
+}
+
+fn parse(source: &str) -> Result_<Expression> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        position: 0,
+    };
+    let expression = parser.sum()?;
+    if parser.position != parser.tokens.len() {
+        return Err(format!("unexpected input at the end of {}", source))?;
+    }
+    Ok(expression)
+}
+
+impl Expression {
+    // None if a variable used by the expression is unknown for the difficulty, for example notes
+    // without BeatSaver data, or the result is not finite.
+    fn evaluate(&self, variable: &dyn Fn(&str) -> Option<f64>) -> Option<f64> {
+        let result = match self {
+            Expression::Number(number) => *number,
+            Expression::Variable(name) => variable(name)?,
+            Expression::Negate(expression) => -expression.evaluate(variable)?,
+            Expression::Binary(operator, left, right) => {
+                let left = left.evaluate(variable)?;
+                let right = right.evaluate(variable)?;
+                match operator {
+                    '+' => left + right,
+                    '-' => left - right,
+                    '*' => left * right,
+                    _ => left / right,
+                }
+            }
+            Expression::Call(function, arguments) => {
+                let arguments = arguments
+                    .iter()
+                    .map(|x| x.evaluate(variable))
+                    .collect::<Option<Vec<f64>>>()?;
+                match function.as_str() {
+                    "floor" => arguments[0].floor(),
+                    "ceil" => arguments[0].ceil(),
+                    "round" => arguments[0].round(),
+                    "abs" => arguments[0].abs(),
+                    "min" => arguments[0].min(arguments[1]),
+                    "max" => arguments[0].max(arguments[1]),
+                    _ => crate::pp::pp(arguments[0], arguments[1]),
+                }
+            }
+        };
+        if result.is_finite() {
+            Some(result)
+        } else {
+            None
+        }
+    }
+}
+
+struct Row {
+    uid: i64,
+    stars: f64,
+    // None if the bpm is invalid.
+    bpm: Option<f64>,
+    plays: i64,
+    daily_plays: i64,
+    notes: Option<i64>,
+    length: Option<f64>,
+    max_score: Option<i64>,
+}
+
+impl Row {
+    fn variable(&self, name: &str) -> Option<f64> {
+        match name {
+            "stars" => Some(self.stars),
+            "bpm" => self.bpm,
+            "plays" => Some(self.plays as f64),
+            "daily_plays" => Some(self.daily_plays as f64),
+            "notes" => self.notes.map(|x| x as f64),
+            "length" => self.length,
+            "nps" => Some(self.notes? as f64 / self.length?),
+            _ => self.max_score.map(|x| x as f64),
+        }
+    }
+}
+
+// Recomputes the `derived_values` table from scratch.
+pub fn materialize(db: &rusqlite::Connection, columns: &[DerivedColumn]) -> Result_<()> {
+    let _timer = profile::timer(profile::Kind::Database);
+    let mut expressions = vec![];
+    for column in columns {
+        if !is_identifier(&column.name) {
+            return Err(format!("invalid derived column name {}", column.name))?;
+        }
+        let expression = parse(&column.expression)
+            .map_err(|err| format!("invalid expression of {}: {}", column.name, err))?;
+        expressions.push((column.name.as_str(), expression));
+    }
+    let mut statement = db.prepare(&format!(
+        "SELECT s.uid, s.stars, CASE WHEN s.bpm_status = 'invalid' THEN NULL ELSE s.bpm END, s.scores, s.scores_day, d.notes, d.length, d.max_score FROM {}",
+        playlist::SONGS_FROM
+    ))?;
+    let rows = statement
+        .query_map(rusqlite::params![], |row| {
+            Ok(Row {
+                uid: row.get(0)?,
+                stars: row.get(1)?,
+                bpm: row.get(2)?,
+                plays: row.get(3)?,
+                daily_plays: row.get(4)?,
+                notes: row.get(5)?,
+                length: row.get(6)?,
+                max_score: row.get(7)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<Row>>>()?;
+    // One transaction instead of one per insert.
+    db.execute_batch("BEGIN; DELETE FROM derived_values;")?;
+    let result = insert_values(db, &rows, &expressions);
+    match result {
+        Ok(()) => db.execute_batch("COMMIT")?,
+        Err(_) => db.execute_batch("ROLLBACK")?,
+    }
+    result
+}
+
+fn insert_values(
+    db: &rusqlite::Connection,
+    rows: &[Row],
+    expressions: &[(&str, Expression)],
+) -> Result_<()> {
+    for row in rows {
+        for (name, expression) in expressions {
+            let value = expression.evaluate(&|variable| row.variable(variable));
+            db.execute(
+                "INSERT INTO derived_values (uid, name, value) VALUES (?,?,?)",
+                rusqlite::params![row.uid, name, value],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(source: &str) -> Option<f64> {
+        let row = Row {
+            uid: 1,
+            stars: 10.0,
+            bpm: Some(200.0),
+            plays: 100,
+            daily_plays: 1,
+            notes: Some(900),
+            length: Some(120.0),
+            max_score: None,
+        };
+        parse(source)
+            .unwrap()
+            .evaluate(&|variable| row.variable(variable))
+    }
+
+    #[test]
+    fn test_expressions() {
+        assert_eq!(evaluate("1 + 2 * 3"), Some(7.0));
+        assert_eq!(evaluate("(1 + 2) * -3"), Some(-9.0));
+        assert_eq!(evaluate("floor(nps)"), Some(7.0));
+        assert_eq!(evaluate("max(stars, bpm) / 4"), Some(50.0));
+        assert_eq!(evaluate("max_score * 2"), None);
+        assert_eq!(evaluate("stars / 0"), None);
+        assert!((evaluate("pp(stars, 0.95)").unwrap() - 421.172_084_13).abs() < 1e-6);
+        assert!(parse("stars +").is_err());
+        assert!(parse("unknown").is_err());
+        assert!(parse("floor(1, 2)").is_err());
+        assert!(parse("1 2").is_err());
+    }
+
+    #[test]
+    fn test_materialize() {
+        let db = crate::test_db();
+        let columns = [DerivedColumn {
+            name: "double_stars".to_string(),
+            expression: "stars * 2".to_string(),
+        }];
+        materialize(&db, &columns).unwrap();
+        materialize(&db, &columns).unwrap();
+        let (count, max): (i64, f64) = db
+            .query_row(
+                "SELECT COUNT(*), MAX(value) FROM derived_values WHERE name = 'double_stars'",
+                rusqlite::params![],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(count, 4);
+        assert!((max - 20.16).abs() < 1e-9);
+        db.close().unwrap();
+    }
+}
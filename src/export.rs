@@ -0,0 +1,769 @@
+use crate::{
+    http,
+    playlist::{self, BeatsaberPlaylist},
+    workers, Result_,
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub enum ExportCommand {
+    /// Renders a playlist into a printable html setlist with covers, stars and bpm.
+    Html {
+        /// Playlist file to render instead of the playlist of all ranked songs.
+        #[structopt(long, parse(from_os_str))]
+        playlist: Option<std::path::PathBuf>,
+        #[structopt(long, parse(from_os_str), default_value = "setlist.html")]
+        output: std::path::PathBuf,
+        /// Also converts the html into a pdf at this path using `--pdf-command`.
+        #[structopt(long, parse(from_os_str))]
+        pdf: Option<std::path::PathBuf>,
+        /// Program that is called with the html and the pdf path and converts one into the other.
+        #[structopt(long, default_value = "wkhtmltopdf")]
+        pdf_command: String,
+    },
+    /// Writes a practice deck for flash card and quiz tools like Anki with one card per song
+    /// pairing its cover and title with its mapper and star difficulties.
+    Deck {
+        /// Playlist file to use instead of the playlist of all ranked songs.
+        #[structopt(long, parse(from_os_str))]
+        playlist: Option<std::path::PathBuf>,
+        #[structopt(long, parse(from_os_str), default_value = "deck.csv")]
+        output: std::path::PathBuf,
+        /// csv, whose cover column is an html image for Anki, or json.
+        #[structopt(long, default_value = "csv")]
+        format: DeckFormat,
+        /// Directory the covers are downloaded into. Covers that are already there are reused.
+        /// For Anki copy them into the collection.media directory of the profile.
+        #[structopt(long, parse(from_os_str), default_value = "covers")]
+        covers: std::path::PathBuf,
+    },
+    /// Writes the songs table or the rows of a query as csv with a header row for spreadsheets.
+    Csv {
+        /// Sql query whose rows are written instead of the songs table, for example
+        /// `SELECT * FROM per_hash_max_stars WHERE max_stars >= 10`.
+        #[structopt(long)]
+        query: Option<String>,
+        #[structopt(long, parse(from_os_str), default_value = "songs.csv")]
+        output: std::path::PathBuf,
+    },
+    /// Writes one json object per line for every song, or every score with `--scores`, for jq and
+    /// other pipelines.
+    Jsonl {
+        /// Writes the stored scores of the tracked players instead of the songs.
+        #[structopt(long, conflicts_with = "query")]
+        scores: bool,
+        /// Sql query whose rows are written instead of the songs table.
+        #[structopt(long)]
+        query: Option<String>,
+        /// Defaults to stdout.
+        #[structopt(long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Writes the songs, the scores with `--scores` or the rows of a query as a parquet file for
+    /// pandas, DuckDB and other columnar tools. Needs a build with the parquet feature.
+    Parquet {
+        /// Writes the stored scores of the tracked players instead of the songs.
+        #[structopt(long, conflicts_with = "query")]
+        scores: bool,
+        /// Sql query whose rows are written instead of the songs table.
+        #[structopt(long)]
+        query: Option<String>,
+        #[structopt(long, parse(from_os_str), default_value = "songs.parquet")]
+        output: std::path::PathBuf,
+    },
+}
+
+const SONGS_QUERY: &str = "SELECT * FROM scoresaber_songs ORDER BY uid";
+const SCORES_QUERY: &str = "SELECT * FROM player_scores ORDER BY player_id, uid";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeckFormat {
+    Csv,
+    Json,
+}
+
+impl std::str::FromStr for DeckFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(DeckFormat::Csv),
+            "json" => Ok(DeckFormat::Json),
+            _ => Err(format!("unknown deck format {}, expected csv or json", s)),
+        }
+    }
+}
+
+pub fn run(
+    db: &rusqlite::Connection,
+    client: &reqwest::Client,
+    command: ExportCommand,
+    post_process: &crate::output::PostProcess,
+) -> Result_<()> {
+    match command {
+        ExportCommand::Html {
+            playlist,
+            output,
+            pdf,
+            pdf_command,
+        } => {
+            let playlist = load_or_make_playlist(db, playlist.as_ref())?;
+            std::fs::write(
+                &output,
+                post_process.bytes(render_html(db, &playlist)?.into_bytes())?,
+            )?;
+            if let Some(pdf) = pdf {
+                convert_to_pdf(&pdf_command, &output, &pdf)?;
+            }
+            Ok(())
+        }
+        ExportCommand::Deck {
+            playlist,
+            output,
+            format,
+            covers,
+        } => {
+            let playlist = load_or_make_playlist(db, playlist.as_ref())?;
+            std::fs::create_dir_all(&covers)?;
+            cache_covers(client, &covers, &playlist)?;
+            let cards = deck(db, &playlist, &|hash| {
+                Ok(covers.join(format!("{}.png", hash)).exists())
+            })?;
+            let bytes = match format {
+                DeckFormat::Csv => post_process.bytes(render_csv(&cards).into_bytes())?,
+                DeckFormat::Json => post_process.json(&cards)?,
+            };
+            std::fs::write(&output, bytes)?;
+            println!("Wrote {} cards to {}.", cards.len(), output.display());
+            Ok(())
+        }
+        ExportCommand::Csv { query, output } => {
+            let table = Table::query(db, query.as_ref().map_or(SONGS_QUERY, |x| x.as_str()))?;
+            std::fs::write(&output, post_process.bytes(table.csv().into_bytes())?)?;
+            println!("Wrote {} rows to {}.", table.rows.len(), output.display());
+            Ok(())
+        }
+        ExportCommand::Jsonl {
+            scores,
+            query,
+            output,
+        } => {
+            let sql = match &query {
+                Some(query) => query.as_str(),
+                None if scores => SCORES_QUERY,
+                None => SONGS_QUERY,
+            };
+            let table = Table::query(db, sql)?;
+            let bytes = post_process.bytes(table.jsonl()?.into_bytes())?;
+            match output {
+                Some(output) => {
+                    std::fs::write(&output, bytes)?;
+                    println!("Wrote {} rows to {}.", table.rows.len(), output.display());
+                }
+                None => std::io::Write::write_all(&mut std::io::stdout(), &bytes)?,
+            }
+            Ok(())
+        }
+        // Parquet compresses itself and a changed newline would break the file, so the output
+        // config does not apply.
+        ExportCommand::Parquet {
+            scores,
+            query,
+            output,
+        } => {
+            let sql = match &query {
+                Some(query) => query.as_str(),
+                None if scores => SCORES_QUERY,
+                None => SONGS_QUERY,
+            };
+            let table = Table::query(db, sql)?;
+            std::fs::write(&output, table.parquet()?)?;
+            println!("Wrote {} rows to {}.", table.rows.len(), output.display());
+            Ok(())
+        }
+    }
+}
+
+// The column names and rows of an arbitrary query.
+struct Table {
+    columns: Vec<String>,
+    rows: Vec<Vec<rusqlite::types::Value>>,
+}
+
+impl Table {
+    fn query(db: &rusqlite::Connection, sql: &str) -> Result_<Table> {
+        let mut statement = db.prepare(sql)?;
+        let columns = statement
+            .column_names()
+            .into_iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        let count = columns.len();
+        let rows = statement
+            .query_map(rusqlite::params![], |row| {
+                (0..count).map(|i| row.get(i)).collect()
+            })?
+            .collect::<rusqlite::Result<Vec<Vec<rusqlite::types::Value>>>>()?;
+        Ok(Table { columns, rows })
+    }
+
+    fn csv(&self) -> String {
+        let line = |fields: Vec<String>| {
+            fields
+                .iter()
+                .map(|x| csv_field(x))
+                .collect::<Vec<String>>()
+                .join(",")
+                + "\n"
+        };
+        let mut csv = line(self.columns.clone());
+        for row in &self.rows {
+            csv.push_str(&line(row.iter().map(value_text).collect()));
+        }
+        csv
+    }
+
+    // One json object per row keyed by the column names.
+    fn jsonl(&self) -> Result_<String> {
+        let mut jsonl = String::new();
+        for row in &self.rows {
+            let object = self
+                .columns
+                .iter()
+                .cloned()
+                .zip(row.iter().map(value_json))
+                .collect::<serde_json::Map<String, serde_json::Value>>();
+            jsonl.push_str(&serde_json::to_string(&object)?);
+            jsonl.push('\n');
+        }
+        Ok(jsonl)
+    }
+
+    // One row group with an optional column per query column, typed by parquet_column.
+    #[cfg(feature = "parquet")]
+    fn parquet(&self) -> Result_<Vec<u8>> {
+        use parquet::basic::{ConvertedType, Repetition, Type as PhysicalType};
+        use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+        use parquet::schema::types::Type;
+        use rusqlite::types::Value;
+        use std::sync::Arc;
+        let columns = (0..self.columns.len())
+            .map(|i| self.rows.iter().map(|row| &row[i]).collect())
+            .collect::<Vec<Vec<&Value>>>();
+        let kinds = columns
+            .iter()
+            .map(|values| parquet_column(values))
+            .collect::<Vec<ParquetColumn>>();
+        let mut fields = Vec::new();
+        for (name, kind) in self.columns.iter().zip(&kinds) {
+            let (physical, converted) = match kind {
+                ParquetColumn::Integer => (PhysicalType::INT64, ConvertedType::NONE),
+                ParquetColumn::Real => (PhysicalType::DOUBLE, ConvertedType::NONE),
+                ParquetColumn::Text => (PhysicalType::BYTE_ARRAY, ConvertedType::UTF8),
+                ParquetColumn::Blob => (PhysicalType::BYTE_ARRAY, ConvertedType::NONE),
+            };
+            fields.push(Arc::new(
+                Type::primitive_type_builder(name, physical)
+                    .with_repetition(Repetition::OPTIONAL)
+                    .with_converted_type(converted)
+                    .build()?,
+            ));
+        }
+        let schema = Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?;
+        let properties = parquet::file::properties::WriterProperties::builder().build();
+        let mut writer = parquet::file::writer::SerializedFileWriter::new(
+            Vec::new(),
+            Arc::new(schema),
+            Arc::new(properties),
+        )?;
+        let mut row_group = writer.next_row_group()?;
+        for (values, kind) in columns.iter().zip(&kinds) {
+            let mut column = row_group
+                .next_column()?
+                .ok_or("the parquet schema has fewer columns than the query")?;
+            // Definition level 1 is a value and 0 a null. Only the values are passed.
+            let levels = values
+                .iter()
+                .map(|value| match value {
+                    Value::Null => 0,
+                    _ => 1,
+                })
+                .collect::<Vec<i16>>();
+            let values = values.iter().filter(|value| **value != &Value::Null);
+            match kind {
+                ParquetColumn::Integer => {
+                    let data = values
+                        .filter_map(|value| match value {
+                            Value::Integer(x) => Some(*x),
+                            _ => None,
+                        })
+                        .collect::<Vec<i64>>();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&data, Some(&levels[..]), None)?;
+                }
+                ParquetColumn::Real => {
+                    let data = values
+                        .filter_map(|value| match value {
+                            Value::Integer(x) => Some(*x as f64),
+                            Value::Real(x) => Some(*x),
+                            _ => None,
+                        })
+                        .collect::<Vec<f64>>();
+                    column
+                        .typed::<DoubleType>()
+                        .write_batch(&data, Some(&levels[..]), None)?;
+                }
+                ParquetColumn::Text => {
+                    let data = values
+                        .map(|value| ByteArray::from(value_text(value).into_bytes()))
+                        .collect::<Vec<ByteArray>>();
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(&data, Some(&levels[..]), None)?;
+                }
+                ParquetColumn::Blob => {
+                    let data = values
+                        .filter_map(|value| match value {
+                            Value::Blob(x) => Some(ByteArray::from(x.clone())),
+                            _ => None,
+                        })
+                        .collect::<Vec<ByteArray>>();
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(&data, Some(&levels[..]), None)?;
+                }
+            }
+            column.close()?;
+        }
+        row_group.close()?;
+        Ok(writer.into_inner()?)
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    fn parquet(&self) -> Result_<Vec<u8>> {
+        Err("export parquet needs a build with the parquet feature")?
+    }
+}
+
+// SQLite columns can mix types so a parquet column gets the narrowest type that holds all of its
+// values: integers mixed with reals are doubles and mixes with text are text like in the csv.
+#[cfg(feature = "parquet")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParquetColumn {
+    Integer,
+    Real,
+    Text,
+    Blob,
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_column(values: &[&rusqlite::types::Value]) -> ParquetColumn {
+    use rusqlite::types::Value;
+    let has = |f: fn(&Value) -> bool| values.iter().any(|value| f(value));
+    let integer = has(|value| matches!(value, Value::Integer(_)));
+    let real = has(|value| matches!(value, Value::Real(_)));
+    let text = has(|value| matches!(value, Value::Text(_)));
+    let blob = has(|value| matches!(value, Value::Blob(_)));
+    if text || (blob && (integer || real)) {
+        ParquetColumn::Text
+    } else if blob {
+        ParquetColumn::Blob
+    } else if real {
+        ParquetColumn::Real
+    } else if integer {
+        ParquetColumn::Integer
+    } else {
+        // Only nulls.
+        ParquetColumn::Text
+    }
+}
+
+// Blobs are base64 strings and reals that json cannot represent are null.
+fn value_json(value: &rusqlite::types::Value) -> serde_json::Value {
+    use rusqlite::types::Value;
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(x) => (*x).into(),
+        Value::Real(x) => serde_json::Number::from_f64(*x)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::Text(x) => x.clone().into(),
+        Value::Blob(x) => base64::encode(x).into(),
+    }
+}
+
+// Null is an empty field and blobs are base64.
+fn value_text(value: &rusqlite::types::Value) -> String {
+    use rusqlite::types::Value;
+    match value {
+        Value::Null => String::new(),
+        Value::Integer(x) => x.to_string(),
+        Value::Real(x) => x.to_string(),
+        Value::Text(x) => x.clone(),
+        Value::Blob(x) => base64::encode(x),
+    }
+}
+
+fn load_or_make_playlist(
+    db: &rusqlite::Connection,
+    path: Option<&std::path::PathBuf>,
+) -> Result_<BeatsaberPlaylist> {
+    match path {
+        Some(path) => load_playlist(path),
+        None => playlist::make_beatsaber_playlist(db, &playlist::PlaylistOptions::default()),
+    }
+}
+
+fn load_playlist(path: &std::path::Path) -> Result_<BeatsaberPlaylist> {
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    Ok(serde_json::from_reader(file)?)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const COVER_URL: &str = "https://scoresaber.com/imports/images/songs";
+
+const HTML_STYLE: &str = r#"
+body { font-family: sans-serif; }
+table { border-collapse: collapse; width: 100%; }
+th, td { border-bottom: 1px solid #ccc; padding: 4px 8px; text-align: left; vertical-align: middle; }
+tr { page-break-inside: avoid; }
+img { width: 64px; height: 64px; }
+.sub { color: #666; }
+"#;
+
+struct SetlistDifficulty {
+    sub_name: String,
+    song_author: String,
+    level_author: String,
+    beats_per_minute: f64,
+    difficulty: String,
+    star_difficulty: f64,
+}
+
+const DIFFICULTIES_SQL: &str = "SELECT songSubName, songAuthorName, levelAuthorName, bpm, diff, stars FROM scoresaber_songs WHERE id = ? ORDER BY stars DESC";
+
+fn song_difficulties(
+    statement: &mut rusqlite::Statement,
+    hash: &str,
+) -> Result_<Vec<SetlistDifficulty>> {
+    Ok(statement
+        .query_map(rusqlite::params![hash], |row| {
+            Ok(SetlistDifficulty {
+                sub_name: row.get(0)?,
+                song_author: row.get(1)?,
+                level_author: row.get(2)?,
+                beats_per_minute: row.get(3)?,
+                difficulty: row.get(4)?,
+                star_difficulty: row.get(5)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<SetlistDifficulty>>>()?)
+}
+
+// Like "ExpertPlus 9.72★" or "Lawless Expert 7.10★".
+fn stars(difficulty: &SetlistDifficulty) -> String {
+    match crate::parse_difficulty(&difficulty.difficulty) {
+        Some((name, "Standard")) => format!("{} {:.2}★", name, difficulty.star_difficulty),
+        Some((name, characteristic)) => format!(
+            "{} {} {:.2}★",
+            characteristic, name, difficulty.star_difficulty
+        ),
+        None => format!(
+            "{} {:.2}★",
+            difficulty.difficulty, difficulty.star_difficulty
+        ),
+    }
+}
+
+fn render_html(db: &rusqlite::Connection, playlist: &BeatsaberPlaylist) -> Result_<String> {
+    use std::fmt::Write;
+    let mut statement = db.prepare(DIFFICULTIES_SQL)?;
+    let mut html = String::new();
+    write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>{style}</style>\n</head>\n<body>\n<h1>{title}</h1>\n<p>{description}</p>\n<table>\n<tr><th></th><th>#</th><th>Song</th><th>Mapper</th><th>BPM</th><th>Difficulties</th></tr>\n",
+        title = escape_html(&playlist.title),
+        style = HTML_STYLE,
+        description = escape_html(&playlist.description),
+    )?;
+    for (i, song) in playlist.songs.iter().enumerate() {
+        let difficulties = song_difficulties(&mut statement, &song.hash)?;
+        // Songs that are not in the database only have what the playlist knows about them.
+        let (sub_name, song_author, level_author, bpm) = match difficulties.first() {
+            Some(first) => (
+                first.sub_name.as_str(),
+                first.song_author.as_str(),
+                first.level_author.as_str(),
+                first.beats_per_minute.to_string(),
+            ),
+            None => ("", "", "", String::new()),
+        };
+        let stars = difficulties
+            .iter()
+            .map(|x| escape_html(&stars(x)))
+            .collect::<Vec<String>>()
+            .join("<br>");
+        write!(
+            html,
+            "<tr><td><img src=\"{cover}/{hash}.png\" alt=\"\"></td><td>{number}</td><td>{name} <span class=\"sub\">{sub_name}</span><br><span class=\"sub\">{song_author}</span></td><td>{level_author}</td><td>{bpm}</td><td>{stars}</td></tr>\n",
+            cover = COVER_URL,
+            hash = escape_html(&song.hash),
+            number = i + 1,
+            name = escape_html(&song.name),
+            sub_name = escape_html(sub_name),
+            song_author = escape_html(song_author),
+            level_author = escape_html(level_author),
+            bpm = bpm,
+            stars = stars,
+        )?;
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct Card {
+    hash: String,
+    // File name of the cover in the covers directory. None if it could not be downloaded.
+    cover: Option<String>,
+    title: String,
+    song_author: String,
+    mapper: String,
+    // Hardest first.
+    stars: Vec<String>,
+}
+
+// `cover` returns whether the cover of a hash is available.
+fn deck(
+    db: &rusqlite::Connection,
+    playlist: &BeatsaberPlaylist,
+    cover: &dyn Fn(&str) -> Result_<bool>,
+) -> Result_<Vec<Card>> {
+    let mut statement = db.prepare(DIFFICULTIES_SQL)?;
+    let mut cards = vec![];
+    for song in playlist.songs.iter() {
+        let difficulties = song_difficulties(&mut statement, &song.hash)?;
+        let mut card = Card {
+            hash: song.hash.clone(),
+            cover: None,
+            title: song.name.clone(),
+            song_author: String::new(),
+            mapper: String::new(),
+            stars: difficulties.iter().map(stars).collect(),
+        };
+        if let Some(first) = difficulties.first() {
+            if !first.sub_name.is_empty() {
+                card.title = format!("{} {}", song.name, first.sub_name);
+            }
+            card.song_author = first.song_author.clone();
+            card.mapper = first.level_author.clone();
+        }
+        if cover(&song.hash)? {
+            card.cover = Some(format!("{}.png", song.hash));
+        }
+        cards.push(card);
+    }
+    Ok(cards)
+}
+
+fn cache_cover(client: &reqwest::Client, directory: &std::path::Path, hash: &str) -> Result_<bool> {
+    let path = directory.join(format!("{}.png", hash));
+    if path.exists() {
+        return Ok(true);
+    }
+    let url = format!("{}/{}.png", COVER_URL, hash);
+    log::info!("request: {}", url);
+    let (status, body) = http::get(&url, || client.get(&url))?;
+    if !status.is_success() {
+        log::warn!("no cover for {}: {}", hash, status);
+        return Ok(false);
+    }
+    std::fs::write(&path, body)?;
+    Ok(true)
+}
+
+// Downloads the covers of the playlist on the workers. Songs without a cover get a card without
+// one.
+fn cache_covers(
+    client: &reqwest::Client,
+    directory: &std::path::Path,
+    playlist: &BeatsaberPlaylist,
+) -> Result_<()> {
+    let hashes = playlist
+        .songs
+        .iter()
+        .map(|song| song.hash.clone())
+        .collect::<Vec<_>>();
+    let (client, directory) = (client.clone(), directory.to_path_buf());
+    let summary = workers::run(
+        hashes,
+        move |hash| cache_cover(&client, &directory, hash),
+        |_, _| Ok(()),
+    )?;
+    if summary.failed > 0 {
+        log::warn!("failed to download {} covers", summary.failed);
+    }
+    Ok(())
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn render_csv(cards: &[Card]) -> String {
+    let mut csv = String::from("cover,title,song_author,mapper,stars\n");
+    for card in cards {
+        let cover = match &card.cover {
+            Some(cover) => format!("<img src=\"{}\">", escape_html(cover)),
+            None => String::new(),
+        };
+        let fields = [
+            cover,
+            escape_html(&card.title),
+            escape_html(&card.song_author),
+            escape_html(&card.mapper),
+            card.stars
+                .iter()
+                .map(|x| escape_html(x))
+                .collect::<Vec<String>>()
+                .join("<br>"),
+        ];
+        csv.push_str(
+            &fields
+                .iter()
+                .map(|x| csv_field(x))
+                .collect::<Vec<String>>()
+                .join(","),
+        );
+        csv.push('\n');
+    }
+    csv
+}
+
+fn convert_to_pdf(command: &str, html: &std::path::Path, pdf: &std::path::Path) -> Result_<()> {
+    let status = std::process::Command::new(command)
+        .arg(html)
+        .arg(pdf)
+        .status()?;
+    if !status.success() {
+        return Err(format!("{} exited with {}", command, status))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html("<b>Tom & Jerry's \"song\"</b>"),
+            "&lt;b&gt;Tom &amp; Jerry&#39;s &quot;song&quot;&lt;/b&gt;"
+        );
+    }
+
+    #[test]
+    fn test_deck() {
+        let db = crate::test_db();
+        let playlist =
+            playlist::make_beatsaber_playlist(&db, &playlist::PlaylistOptions::default()).unwrap();
+        let cards = deck(&db, &playlist, &|hash| {
+            Ok(hash != "762B7BF1C06DBCC7AAB23D955A553E5420FBA6E5")
+        })
+        .unwrap();
+        assert_eq!(cards.len(), 3);
+        assert_eq!(cards[0].stars, ["ExpertPlus 10.08★"]);
+        assert!(cards[0].cover.is_some());
+        let csv = render_csv(&cards);
+        assert_eq!(csv.lines().count(), 4);
+        assert!(csv.lines().any(|line| line.starts_with(",NUCLEAR-STAR")));
+        assert_eq!(csv_field("a, \"b\""), "\"a, \"\"b\"\"\"");
+    }
+
+    #[test]
+    fn test_table_csv() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE t (name TEXT, stars REAL, notes INTEGER);
+            INSERT INTO t VALUES ('Tom, \"Jerry\"', 9.5, 1000), ('a', NULL, 2);",
+        )
+        .unwrap();
+        let table = Table::query(&db, "SELECT * FROM t").unwrap();
+        assert_eq!(
+            table.csv(),
+            "name,stars,notes\n\"Tom, \"\"Jerry\"\"\",9.5,1000\na,,2\n"
+        );
+        crate::create_schema(&db).unwrap();
+        let table = Table::query(&db, SONGS_QUERY).unwrap();
+        assert_eq!(table.csv().lines().count(), 1);
+        assert!(table.columns.contains(&"levelAuthorName".to_string()));
+    }
+
+    #[test]
+    fn test_table_jsonl() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE t (name TEXT, stars REAL, notes INTEGER);
+            INSERT INTO t VALUES ('a\nb', 9.5, 1000), ('c', NULL, 2);",
+        )
+        .unwrap();
+        let table = Table::query(&db, "SELECT * FROM t").unwrap();
+        assert_eq!(
+            table.jsonl().unwrap(),
+            "{\"name\":\"a\\nb\",\"notes\":1000,\"stars\":9.5}\n{\"name\":\"c\",\"notes\":2,\"stars\":null}\n"
+        );
+        crate::create_schema(&db).unwrap();
+        assert_eq!(Table::query(&db, SCORES_QUERY).unwrap().rows.len(), 0);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_table_parquet() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use parquet::record::RowAccessor;
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE t (notes INTEGER, stars REAL, name TEXT, nothing);
+            INSERT INTO t VALUES (1000, 9.5, 'a', NULL), (NULL, 9, NULL, NULL);",
+        )
+        .unwrap();
+        let table = Table::query(&db, "SELECT * FROM t").unwrap();
+        let path = std::env::temp_dir().join("scoresaber-crawler-test-export.parquet");
+        std::fs::write(&path, table.parquet().unwrap()).unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(rows[0].get_long(0).unwrap(), 1000);
+        assert_eq!(rows[0].get_double(1).unwrap(), 9.5);
+        assert_eq!(rows[0].get_string(2).unwrap(), "a");
+        assert!(rows[1].get_long(0).is_err());
+        assert_eq!(rows[1].get_double(1).unwrap(), 9.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,748 @@
+// Sends the requests to ScoreSaber, BeatSaver and the notifiers. Transient failures, connection
+// errors and 5xx or 429 responses, are retried with exponential backoff and jitter so that a hiccup
+// of an api does not abort a long crawl. An optional rate limit spaces out all requests, including
+// the ones of concurrent threads, so that heavy crawls do not hammer the apis. A host whose requests
+// keep failing gets none for a while so that a service that is down, like BeatSaver during an
+// outage, fails fast instead of slowing down the run with retries.
+//
+// With a cache directory the bodies of GET responses are kept together with their ETag and
+// Last-Modified headers. Later runs send them back and reuse the cached body when the api answers
+// 304 Not Modified, so that re-crawls only transfer what changed.
+
+use crate::{profile, Result_};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+static RETRIES: AtomicU64 = AtomicU64::new(3);
+// Milliseconds before the first retry. Every further retry waits about twice as long.
+static BASE_DELAY_MS: AtomicU64 = AtomicU64::new(1000);
+// Milliseconds a GET request including its retries and body may take, 0 without a deadline. No
+// retry is started that would have to wait past it. The timeouts of the client only limit single
+// reads so a slow trickle of bytes could go on forever.
+static DEADLINE_MS: AtomicU64 = AtomicU64::new(0);
+const MAX_DELAY_MS: u64 = 60_000;
+// After this many requests in a row to a host failed, including their retries, the host gets no
+// requests for BREAKER_COOLDOWN. Then one request tests whether it is back.
+const BREAKER_FAILURES: u32 = 5;
+const BREAKER_COOLDOWN: Duration = Duration::from_secs(300);
+
+lazy_static::lazy_static! {
+    static ref RATE_LIMITS: std::sync::Mutex<RateLimits> =
+        std::sync::Mutex::new(RateLimits::default());
+    static ref BREAKERS: std::sync::Mutex<std::collections::HashMap<String, Breaker>> =
+        std::sync::Mutex::new(std::collections::HashMap::new());
+    static ref CACHE_DIRECTORY: std::sync::Mutex<Option<PathBuf>> = std::sync::Mutex::new(None);
+}
+
+// The circuit breaker of a host.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl Breaker {
+    fn allows(&self, now: Instant) -> bool {
+        self.open_until.map_or(true, |until| now >= until)
+    }
+
+    fn record(&mut self, succeeded: bool, now: Instant) {
+        if succeeded {
+            *self = Breaker::default();
+            return;
+        }
+        self.failures += 1;
+        if self.failures >= BREAKER_FAILURES {
+            self.open_until = Some(now + BREAKER_COOLDOWN);
+        }
+    }
+}
+
+fn interval(requests_per_second: f64) -> Result_<Duration> {
+    if !requests_per_second.is_normal() || requests_per_second < 0.0 {
+        return Err(format!(
+            "invalid rate limit {}, it has to be more than 0 requests per second",
+            requests_per_second
+        ))?;
+    }
+    Ok(Duration::from_secs_f64(1.0 / requests_per_second))
+}
+
+// The minimum time between the start of two requests and when the next one may start, overall and
+// for the requests to single hosts.
+#[derive(Debug, Default)]
+struct RateLimits {
+    overall: Option<(Duration, Instant)>,
+    hosts: std::collections::HashMap<String, (Duration, Instant)>,
+}
+
+impl RateLimits {
+    fn limit_rate(&mut self, requests_per_second: f64) -> Result_<()> {
+        self.overall = Some((interval(requests_per_second)?, Instant::now()));
+        Ok(())
+    }
+
+    // `limit` is like `api.beatsaver.com=2` for at most 2 requests per second to that host. It
+    // applies in addition to the overall limit.
+    fn limit_host_rate(&mut self, limit: &str) -> Result_<()> {
+        let (host, requests_per_second) = match limit.rfind('=') {
+            Some(index) => (&limit[..index], &limit[index + 1..]),
+            None => {
+                return Err(format!(
+                    "expected host=requests per second but got {}",
+                    limit
+                ))?
+            }
+        };
+        let requests_per_second = requests_per_second
+            .parse::<f64>()
+            .map_err(|err| format!("invalid rate limit {}: {}", limit, err))?;
+        self.hosts.insert(
+            host.to_ascii_lowercase(),
+            (interval(requests_per_second)?, Instant::now()),
+        );
+        Ok(())
+    }
+
+    // Reserves the earliest start after `now` that the overall limit and the limit of `host`
+    // allow.
+    fn reserve_turn(&mut self, host: Option<&str>, now: Instant) -> Instant {
+        let mut start = match self.overall.as_mut() {
+            Some((interval, next)) => reserve(next, *interval, now),
+            None => now,
+        };
+        if let Some((interval, next)) = host.and_then(|host| self.hosts.get_mut(host)) {
+            start = reserve(next, *interval, start);
+        }
+        start
+    }
+}
+
+// Replaces the rate limits, for example when `poll` reloads its config. Nothing changes if one of
+// the limits is invalid.
+pub fn set_rate_limits(requests_per_second: Option<f64>, host_limits: &[String]) -> Result_<()> {
+    let mut limits = RateLimits::default();
+    if let Some(requests_per_second) = requests_per_second {
+        limits.limit_rate(requests_per_second)?;
+    }
+    for limit in host_limits {
+        limits.limit_host_rate(limit)?;
+    }
+    *RATE_LIMITS.lock().unwrap() = limits;
+    Ok(())
+}
+
+// Reserves the earliest start after `now` that keeps `interval` to the previous reservation.
+fn reserve(next: &mut Instant, interval: Duration, now: Instant) -> Instant {
+    let start = (*next).max(now);
+    *next = start + interval;
+    start
+}
+
+// Waits until the overall limit and the limit of `host` allow another request.
+fn wait_for_turn(host: Option<&str>) {
+    let start = RATE_LIMITS
+        .lock()
+        .unwrap()
+        .reserve_turn(host, Instant::now());
+    let now = Instant::now();
+    if start > now {
+        std::thread::sleep(start - now);
+    }
+}
+
+pub fn configure_retries(retries: u64, base_delay: Duration) {
+    RETRIES.store(retries, Ordering::Relaxed);
+    BASE_DELAY_MS.store(base_delay.as_millis() as u64, Ordering::Relaxed);
+}
+
+pub fn set_deadline(deadline: Option<Duration>) {
+    DEADLINE_MS.store(
+        deadline.map(|x| x.as_millis() as u64).unwrap_or(0),
+        Ordering::Relaxed,
+    );
+}
+
+fn is_transient(status: reqwest::StatusCode) -> bool {
+    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+// Between half and all of the exponential delay of the attempt so that concurrent requests that
+// failed together do not retry together.
+fn delay(attempt: u64, base_ms: u64, random: u64) -> Duration {
+    let full = base_ms
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_DELAY_MS);
+    Duration::from_millis(full / 2 + random % (full / 2 + 1))
+}
+
+fn random() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| u64::from(x.subsec_nanos()))
+        .unwrap_or_default()
+}
+
+// `request` builds the request again for every attempt. Responses that are not transient failures
+// are returned whatever their status, as is the last response once the retries are used up.
+pub fn send(request: impl Fn() -> reqwest::RequestBuilder) -> Result_<reqwest::Response> {
+    send_to(None, None, request)
+}
+
+// Like send but also keeps to the rate limit and the circuit breaker of `host` and does not retry
+// after `deadline`.
+fn send_to(
+    host: Option<&str>,
+    deadline: Option<Instant>,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> Result_<reqwest::Response> {
+    let host = match host {
+        Some(host) => host,
+        None => return send_with_retries(None, deadline, request),
+    };
+    if let Some(breaker) = BREAKERS.lock().unwrap().get(host) {
+        if !breaker.allows(Instant::now()) {
+            return Err(format!(
+                "skipping the request because the last {} requests to {} failed",
+                breaker.failures, host
+            ))?;
+        }
+    }
+    let result = send_with_retries(Some(host), deadline, request);
+    let succeeded = match &result {
+        Ok(response) => !is_transient(response.status()),
+        Err(_) => false,
+    };
+    BREAKERS
+        .lock()
+        .unwrap()
+        .entry(host.to_string())
+        .or_default()
+        .record(succeeded, Instant::now());
+    result
+}
+
+// Whether a retry after waiting `wait` from `now` would still start before the deadline.
+fn retry_fits(deadline: Option<Instant>, wait: Duration, now: Instant) -> bool {
+    deadline.map_or(true, |deadline| now + wait < deadline)
+}
+
+fn send_with_retries(
+    host: Option<&str>,
+    deadline: Option<Instant>,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> Result_<reqwest::Response> {
+    let retries = RETRIES.load(Ordering::Relaxed);
+    let mut attempt = 0;
+    loop {
+        wait_for_turn(host);
+        let result = request().send();
+        let wait = delay(attempt, BASE_DELAY_MS.load(Ordering::Relaxed), random());
+        // Retrying after the deadline would only fail later, so the attempt counts as the last.
+        let last = attempt == retries || !retry_fits(deadline, wait, Instant::now());
+        let error = match result {
+            Ok(response) => {
+                if !is_transient(response.status()) || last {
+                    return Ok(response);
+                }
+                format!("status code {}", response.status())
+            }
+            Err(err) => {
+                if last {
+                    return Err(err)?;
+                }
+                err.to_string()
+            }
+        };
+        log::warn!(
+            "retrying in {:.1}s after attempt {} of {} failed: {}",
+            wait.as_secs_f64(),
+            attempt + 1,
+            retries + 1,
+            error
+        );
+        std::thread::sleep(wait);
+        attempt += 1;
+    }
+}
+
+pub fn enable_cache(directory: &Path) -> Result_<()> {
+    std::fs::create_dir_all(directory)
+        .map_err(|err| format!("could not create {}: {}", directory.display(), err))?;
+    *CACHE_DIRECTORY.lock().unwrap() = Some(directory.to_path_buf());
+    Ok(())
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+struct Validators {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+// The files of a url are named after the sha1 of the url.
+fn cache_paths(directory: &Path, url: &str) -> (PathBuf, PathBuf) {
+    use sha1::Digest;
+    let name = sha1::Sha1::digest(url.as_bytes())
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<String>();
+    (
+        directory.join(format!("{}.json", name)),
+        directory.join(format!("{}.body", name)),
+    )
+}
+
+// None if the url is not cached or the cache cannot be read.
+fn load(directory: &Path, url: &str) -> Option<(Validators, Vec<u8>)> {
+    let (validators, body) = cache_paths(directory, url);
+    let validators: Validators = serde_json::from_slice(&std::fs::read(validators).ok()?).ok()?;
+    if validators.url != url {
+        return None;
+    }
+    Some((validators, std::fs::read(body).ok()?))
+}
+
+fn store(directory: &Path, validators: &Validators, body: &[u8]) -> Result_<()> {
+    let (validators_path, body_path) = cache_paths(directory, &validators.url);
+    // The body is written first so that validators never point to a missing or older body.
+    std::fs::write(body_path, body)?;
+    std::fs::write(validators_path, serde_json::to_vec(validators)?)?;
+    Ok(())
+}
+
+fn header(response: &reqwest::Response, name: reqwest::header::HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+fn read_body(response: &mut impl std::io::Read, deadline: Option<Instant>) -> Result_<Vec<u8>> {
+    let _timer = profile::timer(profile::Kind::Network);
+    let mut body = vec![];
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        if deadline.map_or(false, |deadline| Instant::now() > deadline) {
+            return Err("the request deadline passed")?;
+        }
+        match response.read(&mut buffer)? {
+            0 => return Ok(body),
+            read => body.extend_from_slice(&buffer[..read]),
+        }
+    }
+}
+
+fn deadline() -> Option<Instant> {
+    match DEADLINE_MS.load(Ordering::Relaxed) {
+        0 => None,
+        ms => Some(Instant::now() + Duration::from_millis(ms)),
+    }
+}
+
+// Sends a GET request built by `request` for `url`, with the validators of the cached response if
+// there is one.
+fn send_get(
+    url: &str,
+    request: impl Fn() -> reqwest::RequestBuilder,
+    deadline: Option<Instant>,
+    cached: Option<&Validators>,
+) -> Result_<reqwest::Response> {
+    let host = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_ascii_lowercase()));
+    let _timer = profile::timer(profile::Kind::Network);
+    send_to(host.as_deref(), deadline, || {
+        let mut builder = request();
+        if let Some(validators) = cached {
+            if let Some(etag) = &validators.etag {
+                builder = builder.header(reqwest::header::IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                builder =
+                    builder.header(reqwest::header::IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+        builder
+    })
+}
+
+// The validators to cache a response with, None if it is not successful or can not be revalidated.
+fn cache_validators(url: &str, response: &reqwest::Response) -> Option<Validators> {
+    let validators = Validators {
+        url: url.to_string(),
+        etag: header(response, reqwest::header::ETAG),
+        last_modified: header(response, reqwest::header::LAST_MODIFIED),
+    };
+    match response.status().is_success()
+        && (validators.etag.is_some() || validators.last_modified.is_some())
+    {
+        true => Some(validators),
+        false => None,
+    }
+}
+
+fn cache(directory: &Path, validators: &Validators, body: &[u8]) {
+    if let Err(err) = store(directory, validators, body) {
+        log::warn!(
+            "failed to cache the response of {}: {}",
+            validators.url,
+            err
+        );
+    }
+}
+
+// Sends a GET request built by `request` for `url` and returns the status and the body. With the
+// cache a 304 Not Modified response is returned as 200 OK with the cached body. Failing to cache
+// does not fail the request.
+pub fn get(
+    url: &str,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> Result_<(reqwest::StatusCode, Vec<u8>)> {
+    let deadline = deadline();
+    let directory = CACHE_DIRECTORY.lock().unwrap().clone();
+    let cached = directory
+        .as_ref()
+        .and_then(|directory| load(directory, url));
+    let mut response = send_get(
+        url,
+        request,
+        deadline,
+        cached.as_ref().map(|(validators, _)| validators),
+    )?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        if let Some((_, body)) = cached {
+            log::info!("not modified: {}", url);
+            return Ok((reqwest::StatusCode::OK, body));
+        }
+    }
+    let body = read_body(&mut response, deadline)
+        .map_err(|err| format!("reading the response of {} failed: {}", url, err))?;
+    if let (Some(directory), Some(validators)) = (&directory, cache_validators(url, &response)) {
+        cache(directory, &validators, &body);
+    }
+    Ok((response.status(), body))
+}
+
+// Called with the whole body once a streamed body was read to the end.
+pub type Keep = Box<dyn FnOnce(&[u8])>;
+
+// A body that is read while it is used. It is only buffered when it is kept, by the cache or the
+// caller, which happens once it was read to the end.
+pub struct Body {
+    reader: Box<dyn std::io::Read>,
+    url: String,
+    deadline: Option<Instant>,
+    kept: Option<(Vec<u8>, Vec<Keep>)>,
+}
+
+impl std::io::Read for Body {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        if self
+            .deadline
+            .map_or(false, |deadline| Instant::now() > deadline)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("the request deadline of {} passed", self.url),
+            ));
+        }
+        let read = {
+            let _timer = profile::timer(profile::Kind::Network);
+            self.reader.read(buffer)?
+        };
+        if read > 0 {
+            if let Some((body, _)) = &mut self.kept {
+                body.extend_from_slice(&buffer[..read]);
+            }
+        } else if !buffer.is_empty() {
+            if let Some((body, keep)) = self.kept.take() {
+                for keep in keep {
+                    keep(&body);
+                }
+            }
+        }
+        Ok(read)
+    }
+}
+
+// Like `get` but the body is read while it is used. `keep`, like archiving it, and the cache get
+// the body once it was read to the end. Without either it is never buffered.
+pub fn get_streamed(
+    url: &str,
+    request: impl Fn() -> reqwest::RequestBuilder,
+    keep: Option<Keep>,
+) -> Result_<(reqwest::StatusCode, Body)> {
+    let deadline = deadline();
+    let directory = CACHE_DIRECTORY.lock().unwrap().clone();
+    let cached = directory
+        .as_ref()
+        .and_then(|directory| load(directory, url));
+    let response = send_get(
+        url,
+        request,
+        deadline,
+        cached.as_ref().map(|(validators, _)| validators),
+    )?;
+    let mut keep = keep.into_iter().collect::<Vec<Keep>>();
+    let (status, reader): (_, Box<dyn std::io::Read>) = match (response.status(), cached) {
+        (reqwest::StatusCode::NOT_MODIFIED, Some((_, body))) => {
+            log::info!("not modified: {}", url);
+            (
+                reqwest::StatusCode::OK,
+                Box::new(std::io::Cursor::new(body)),
+            )
+        }
+        (status, _) => {
+            if let (Some(directory), Some(validators)) =
+                (directory, cache_validators(url, &response))
+            {
+                keep.push(Box::new(move |body: &[u8]| {
+                    cache(&directory, &validators, body)
+                }));
+            }
+            (status, Box::new(response))
+        }
+    };
+    let body = Body {
+        reader,
+        url: url.to_string(),
+        deadline,
+        kept: match keep.is_empty() {
+            true => None,
+            false => Some((vec![], keep)),
+        },
+    };
+    Ok((status, body))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ProxyScheme {
+    All,
+    Http,
+    Https,
+}
+
+// `--proxy` is used for every request. Otherwise HTTPS_PROXY and HTTP_PROXY or their lower case
+// variants are used for the requests with that scheme like curl does.
+fn proxy_settings(
+    flag: Option<&str>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result_<Vec<(ProxyScheme, String)>> {
+    let mut settings = vec![];
+    match flag {
+        Some(url) => settings.push((ProxyScheme::All, url.to_string())),
+        None => {
+            for (scheme, names) in &[
+                (ProxyScheme::Https, ["HTTPS_PROXY", "https_proxy"]),
+                (ProxyScheme::Http, ["HTTP_PROXY", "http_proxy"]),
+            ] {
+                if let Some(url) = names
+                    .iter()
+                    .filter_map(|name| env(name))
+                    .find(|url| !url.is_empty())
+                {
+                    settings.push((*scheme, url));
+                }
+            }
+        }
+    }
+    for (_, url) in settings.iter() {
+        // The http client only speaks SOCKS5, socks5h resolves the host names through the proxy.
+        if url.starts_with("socks")
+            && !url.starts_with("socks5://")
+            && !url.starts_with("socks5h://")
+        {
+            return Err(format!(
+                "proxy {} is not supported, only http, https and socks5 proxies are",
+                url
+            ))?;
+        }
+    }
+    Ok(settings)
+}
+
+// The client of all requests with the proxies of `proxy_settings`. `read_timeout` limits how long
+// the client waits for the response and for every read of its body.
+pub fn client(
+    proxy: Option<&str>,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+) -> Result_<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(connect_timeout)
+        .timeout(read_timeout);
+    for (scheme, url) in proxy_settings(proxy, |name| std::env::var(name).ok())? {
+        builder = builder.proxy(match scheme {
+            ProxyScheme::All => reqwest::Proxy::all(url.as_str())?,
+            ProxyScheme::Http => reqwest::Proxy::http(url.as_str())?,
+            ProxyScheme::Https => reqwest::Proxy::https(url.as_str())?,
+        });
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        assert_eq!(delay(0, 1000, 0), Duration::from_millis(500));
+        assert_eq!(delay(0, 1000, 500), Duration::from_millis(1000));
+        assert_eq!(delay(2, 1000, 0), Duration::from_millis(2000));
+        assert_eq!(delay(2, 1000, 7), Duration::from_millis(2007));
+        assert_eq!(delay(40, 1000, 0), Duration::from_millis(MAX_DELAY_MS / 2));
+        assert_eq!(delay(3, 0, 12345), Duration::from_millis(0));
+        assert!(is_transient(reqwest::StatusCode::BAD_GATEWAY));
+        assert!(is_transient(reqwest::StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_transient(reqwest::StatusCode::NOT_FOUND));
+        let now = Instant::now();
+        let wait = Duration::from_secs(2);
+        assert!(retry_fits(None, wait, now));
+        assert!(retry_fits(Some(now + Duration::from_secs(3)), wait, now));
+        assert!(!retry_fits(Some(now + Duration::from_secs(1)), wait, now));
+    }
+
+    #[test]
+    fn test_reserve() {
+        let now = Instant::now();
+        let interval = Duration::from_millis(250);
+        let mut next = now;
+        assert_eq!(reserve(&mut next, interval, now), now);
+        // Requests right after each other are spaced out.
+        assert_eq!(reserve(&mut next, interval, now), now + interval);
+        assert_eq!(reserve(&mut next, interval, now), now + interval * 2);
+        // After a pause the next request may start right away.
+        let later = now + Duration::from_secs(10);
+        assert_eq!(reserve(&mut next, interval, later), later);
+        // A value instead of the global limits so that other tests are not slowed down.
+        let mut limits = RateLimits::default();
+        assert!(limits.limit_rate(0.0).is_err());
+        assert!(limits.limit_host_rate("api.beatsaver.com").is_err());
+        assert!(limits.limit_host_rate("api.beatsaver.com=fast").is_err());
+        assert!(limits.limit_host_rate("api.beatsaver.com=0").is_err());
+        limits.limit_host_rate("API.beatsaver.com=2").unwrap();
+        assert_eq!(
+            limits.hosts["api.beatsaver.com"].0,
+            Duration::from_millis(500)
+        );
+        let now = Instant::now();
+        let host = Some("api.beatsaver.com");
+        assert_eq!(limits.reserve_turn(host, now), now);
+        assert_eq!(limits.reserve_turn(None, now), now);
+        assert_eq!(
+            limits.reserve_turn(host, now),
+            now + Duration::from_millis(500)
+        );
+        limits.limit_rate(1.0).unwrap();
+        let later = now + Duration::from_secs(10);
+        assert_eq!(limits.reserve_turn(None, later), later);
+        assert_eq!(
+            limits.reserve_turn(host, later),
+            later + Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn test_proxy_settings() {
+        let env = |name: &str| match name {
+            "https_proxy" => Some("http://proxy:3128".to_string()),
+            "HTTP_PROXY" => Some("".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            proxy_settings(None, env).unwrap(),
+            vec![(ProxyScheme::Https, "http://proxy:3128".to_string())]
+        );
+        assert_eq!(
+            proxy_settings(Some("http://other:8080"), env).unwrap(),
+            vec![(ProxyScheme::All, "http://other:8080".to_string())]
+        );
+        assert_eq!(
+            proxy_settings(Some("socks5://localhost:1080"), env).unwrap(),
+            vec![(ProxyScheme::All, "socks5://localhost:1080".to_string())]
+        );
+        assert!(proxy_settings(Some("socks4://localhost:1080"), env).is_err());
+    }
+
+    #[test]
+    fn test_breaker() {
+        let now = Instant::now();
+        let mut breaker = Breaker::default();
+        for _ in 1..BREAKER_FAILURES {
+            breaker.record(false, now);
+        }
+        assert!(breaker.allows(now));
+        breaker.record(false, now);
+        assert!(!breaker.allows(now));
+        assert!(breaker.allows(now + BREAKER_COOLDOWN));
+        // The test request after the cooldown opens it again when it fails.
+        breaker.record(false, now + BREAKER_COOLDOWN);
+        assert!(!breaker.allows(now + BREAKER_COOLDOWN));
+        breaker.record(true, now + BREAKER_COOLDOWN * 2);
+        assert_eq!(breaker, Breaker::default());
+    }
+
+    #[test]
+    fn test_read_body() {
+        let body = vec![7; 200 * 1024];
+        assert_eq!(read_body(&mut &body[..], None).unwrap(), body);
+        let later = Instant::now() + Duration::from_secs(60);
+        assert_eq!(read_body(&mut &body[..], Some(later)).unwrap(), body);
+        let passed = Instant::now() - Duration::from_millis(1);
+        assert!(read_body(&mut &body[..], Some(passed)).is_err());
+    }
+
+    #[test]
+    fn test_cache() {
+        let directory = std::env::temp_dir().join("scoresaber-crawler-test-http-cache");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        let url = "https://scoresaber.com/api.php?function=get-leaderboards&page=1";
+        assert_eq!(load(&directory, url), None);
+        let validators = Validators {
+            url: url.to_string(),
+            etag: Some("\"abc\"".to_string()),
+            last_modified: None,
+        };
+        store(&directory, &validators, b"{}").unwrap();
+        assert_eq!(load(&directory, url), Some((validators, b"{}".to_vec())));
+        assert_eq!(load(&directory, "https://scoresaber.com/other"), None);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_body() {
+        use std::io::Read;
+        let kept = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let new_body = |keep: Vec<Keep>| Body {
+            reader: Box::new(&b"{\"songs\": []}"[..]),
+            url: "https://scoresaber.com/".to_string(),
+            deadline: None,
+            kept: Some((vec![], keep)),
+        };
+        let mut read = vec![];
+        let sink = kept.clone();
+        new_body(vec![Box::new(move |body: &[u8]| {
+            sink.borrow_mut().extend_from_slice(body)
+        })])
+        .read_to_end(&mut read)
+        .unwrap();
+        assert_eq!(read, b"{\"songs\": []}");
+        assert_eq!(*kept.borrow(), read);
+        // A body that was not read to the end is not kept.
+        let sink = kept.clone();
+        let mut body = new_body(vec![Box::new(move |_: &[u8]| sink.borrow_mut().clear())]);
+        body.read_exact(&mut [0; 4]).unwrap();
+        drop(body);
+        assert_eq!(*kept.borrow(), read);
+        let mut body = Body {
+            deadline: Some(Instant::now() - Duration::from_secs(1)),
+            ..new_body(vec![])
+        };
+        assert!(body.read(&mut [0; 4]).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use crate::Result_;
+
+// The parts of a map's info.dat needed to compute its hash.
+#[derive(Debug, serde::Deserialize)]
+struct Info {
+    #[serde(rename = "_difficultyBeatmapSets")]
+    difficulty_beatmap_sets: Vec<BeatmapSet>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BeatmapSet {
+    #[serde(rename = "_difficultyBeatmaps")]
+    difficulty_beatmaps: Vec<Beatmap>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Beatmap {
+    #[serde(rename = "_beatmapFilename")]
+    beatmap_filename: String,
+}
+
+// The hash ScoreSaber and BeatSaver use is the upper case sha1 of the info.dat followed by every
+// difficulty file in the order info.dat lists them.
+fn map_hash(directory: &std::path::Path) -> Result_<String> {
+    use sha1::Digest;
+    let info_path = ["info.dat", "Info.dat"]
+        .iter()
+        .map(|name| directory.join(name))
+        .find(|path| path.exists())
+        .ok_or("no info.dat")?;
+    let info_bytes = std::fs::read(&info_path)?;
+    let info: Info = serde_json::from_slice(&info_bytes)?;
+    let mut hasher = sha1::Sha1::new();
+    hasher.input(&info_bytes);
+    for set in info.difficulty_beatmap_sets.iter() {
+        for beatmap in set.difficulty_beatmaps.iter() {
+            hasher.input(&std::fs::read(directory.join(&beatmap.beatmap_filename))?);
+        }
+    }
+    Ok(hasher
+        .result()
+        .iter()
+        .map(|x| format!("{:02X}", x))
+        .collect())
+}
+
+// Hashes of the maps in a Beat Saber `CustomLevels` directory. Maps that cannot be read are skipped
+// with a warning.
+pub fn installed_hashes(custom_levels: &std::path::Path) -> Result_<Vec<String>> {
+    let mut hashes = vec![];
+    let entries = std::fs::read_dir(custom_levels)
+        .map_err(|err| format!("could not read {}: {}", custom_levels.display(), err))?;
+    for entry in entries {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        match map_hash(&path) {
+            Ok(hash) => hashes.push(hash),
+            Err(err) => log::warn!("skipping map {}: {}", path.display(), err),
+        }
+    }
+    Ok(hashes)
+}
+
+// Where playlists are written into the game. `auto` finds the installation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InstallDir {
+    Auto,
+    Path(std::path::PathBuf),
+}
+
+impl std::str::FromStr for InstallDir {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "auto" => InstallDir::Auto,
+            _ => InstallDir::Path(s.into()),
+        })
+    }
+}
+
+impl InstallDir {
+    // The `Playlists` folder of the installation, created if it does not exist yet.
+    pub fn playlists(&self) -> Result_<std::path::PathBuf> {
+        let install = match self {
+            InstallDir::Auto => find_install_dir(&steam_roots(), &oculus_libraries())?,
+            InstallDir::Path(path) => path.clone(),
+        };
+        if !is_install_dir(&install) {
+            return Err(format!(
+                "{} is not a Beat Saber installation",
+                install.display()
+            ))?;
+        }
+        let playlists = install.join("Playlists");
+        std::fs::create_dir_all(&playlists)?;
+        Ok(playlists)
+    }
+}
+
+fn is_install_dir(path: &std::path::Path) -> bool {
+    path.join("Beat Saber_Data").is_dir()
+}
+
+fn home() -> Option<std::path::PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(Into::into)
+}
+
+// Directories Steam is usually installed in. On Linux the game runs through Proton but is still
+// installed into the native Steam directory.
+fn steam_roots() -> Vec<std::path::PathBuf> {
+    let mut roots: Vec<std::path::PathBuf> = vec![];
+    if cfg!(windows) {
+        for variable in &["ProgramFiles(x86)", "ProgramFiles"] {
+            if let Some(program_files) = std::env::var_os(variable) {
+                roots.push(std::path::Path::new(&program_files).join("Steam"));
+            }
+        }
+    } else if let Some(home) = home() {
+        roots.push(home.join(".steam/steam"));
+        roots.push(home.join(".local/share/Steam"));
+        roots.push(home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam"));
+    }
+    roots
+}
+
+// The Oculus app installs into `Software` directories of its libraries.
+fn oculus_libraries() -> Vec<std::path::PathBuf> {
+    let mut libraries = vec![];
+    if cfg!(windows) {
+        if let Some(program_files) = std::env::var_os("ProgramFiles") {
+            libraries.push(std::path::Path::new(&program_files).join("Oculus"));
+        }
+    }
+    libraries
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
+}
+
+// The library folders listed in Steam's libraryfolders.vdf. Older files list them as `"1" "path"`,
+// newer ones as `"path" "path"` next to numbered app ids.
+fn steam_libraries(vdf: &str) -> Vec<std::path::PathBuf> {
+    vdf.lines()
+        .filter_map(|line| {
+            let parts = line.trim().split('"').collect::<Vec<&str>>();
+            match parts.as_slice() {
+                ["", key, _, value, ""]
+                    if *key == "path" || (is_number(key) && !is_number(value)) =>
+                {
+                    Some(value.replace("\\\\", "\\").into())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn find_install_dir(
+    steam_roots: &[std::path::PathBuf],
+    oculus_libraries: &[std::path::PathBuf],
+) -> Result_<std::path::PathBuf> {
+    let mut candidates = vec![];
+    for root in steam_roots {
+        candidates.push(root.clone());
+        for vdf in &["steamapps/libraryfolders.vdf", "config/libraryfolders.vdf"] {
+            if let Ok(vdf) = std::fs::read_to_string(root.join(vdf)) {
+                candidates.extend(steam_libraries(&vdf));
+            }
+        }
+    }
+    let mut candidates = candidates
+        .iter()
+        .map(|library| library.join("steamapps/common/Beat Saber"))
+        .collect::<Vec<_>>();
+    candidates.extend(
+        oculus_libraries
+            .iter()
+            .map(|library| library.join("Software/hyperbolic-magnetism-beat-saber")),
+    );
+    match candidates.iter().find(|path| is_install_dir(path)) {
+        Some(path) => Ok(path.clone()),
+        None => Err(format!(
+            "could not find the Beat Saber installation, pass its directory with --install-dir <dir> instead. Looked in:\n{}",
+            candidates
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<String>>()
+                .join("\n")
+        ))?,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_installed_hashes() {
+        let custom_levels = std::env::temp_dir().join("scoresaber-crawler-test-custom-levels");
+        let map = custom_levels.join("1a2b (Song - Mapper)");
+        std::fs::create_dir_all(&map).unwrap();
+        std::fs::create_dir_all(custom_levels.join("broken")).unwrap();
+        std::fs::write(
+            map.join("info.dat"),
+            r#"{"_difficultyBeatmapSets":[{"_difficultyBeatmaps":[{"_beatmapFilename":"Expert.dat"}]}]}"#,
+        )
+        .unwrap();
+        std::fs::write(map.join("Expert.dat"), r#"{"_notes":[]}"#).unwrap();
+        assert_eq!(
+            installed_hashes(&custom_levels).unwrap(),
+            ["454B27EBC3F3ACC7894D7BB30932CAA9B1F30B48"]
+        );
+        std::fs::remove_dir_all(&custom_levels).unwrap();
+    }
+
+    #[test]
+    fn test_find_install_dir() {
+        let steam = std::env::temp_dir().join("scoresaber-crawler-test-steam");
+        let library = std::env::temp_dir().join("scoresaber-crawler-test-steam-library");
+        std::fs::create_dir_all(steam.join("steamapps")).unwrap();
+        std::fs::create_dir_all(library.join("steamapps/common/Beat Saber/Beat Saber_Data"))
+            .unwrap();
+        assert!(find_install_dir(&[steam.clone()], &[]).is_err());
+        std::fs::write(
+            steam.join("steamapps/libraryfolders.vdf"),
+            format!(
+                "\"libraryfolders\"\n{{\n\t\"0\"\n\t{{\n\t\t\"path\"\t\t\"{}\"\n\t}}\n}}\n",
+                library.display()
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            find_install_dir(&[steam.clone()], &[]).unwrap(),
+            library.join("steamapps/common/Beat Saber")
+        );
+        assert_eq!(
+            steam_libraries("\"LibraryFolders\"\n{\n\t\"1\"\t\t\"D:\\\\SteamLibrary\"\n}"),
+            [std::path::PathBuf::from("D:\\SteamLibrary")]
+        );
+        std::fs::remove_dir_all(&steam).unwrap();
+        std::fs::remove_dir_all(&library).unwrap();
+    }
+}
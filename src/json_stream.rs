@@ -0,0 +1,307 @@
+// Deserializes the elements of an array inside a json object while the json is read so that a
+// page of 1000 songs is never in memory as a whole. `Elements` hands serde_json only the elements
+// of the array, with the commas between them turned into whitespace, so that a StreamDeserializer
+// returns one element per call on the thread that iterates.
+
+use crate::{profile, Result_};
+use serde::de::DeserializeOwned;
+use std::io::{BufRead, Read};
+
+pub struct Array<T, R: Read> {
+    elements: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<Elements<R>>, T>,
+}
+
+// The elements of the array under `key` of the json object `reader` reads. An error, including a
+// missing key, is the last item.
+pub fn array<T: DeserializeOwned, R: Read>(reader: R, key: &'static str) -> Array<T, R> {
+    let elements = Elements {
+        reader: std::io::BufReader::new(reader),
+        key,
+        state: State::Object,
+    };
+    Array {
+        elements: serde_json::Deserializer::from_reader(elements).into_iter(),
+    }
+}
+
+impl<T: DeserializeOwned, R: Read> Iterator for Array<T, R> {
+    type Item = Result_<T>;
+
+    fn next(&mut self) -> Option<Result_<T>> {
+        let _timer = profile::timer(profile::Kind::Decode);
+        // The stream ends after the first error.
+        Some(self.elements.next()?.map_err(Into::into))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    // Before the array.
+    Object,
+    Array(Framing),
+    // After the array, which has been checked to be followed by the end of the object.
+    Done,
+}
+
+// Where in the array the reader is.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Framing {
+    // The open objects and arrays of the current element.
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    // Whether an element started since the last comma, or since the start of the array.
+    element: bool,
+    // Whether the current element ended. Only a comma or the end of the array may follow.
+    ended: bool,
+    comma: bool,
+}
+
+// Reads the elements of the array under `key` of a json object as whitespace separated values.
+// The framing around them is checked here because serde_json only sees the elements.
+struct Elements<R> {
+    reader: std::io::BufReader<R>,
+    key: &'static str,
+    state: State,
+}
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+impl<R: Read> Elements<R> {
+    fn byte(&mut self) -> std::io::Result<Option<u8>> {
+        let byte = self.reader.fill_buf()?.first().copied();
+        if byte.is_some() {
+            self.reader.consume(1);
+        }
+        Ok(byte)
+    }
+
+    fn expect_byte(&mut self) -> std::io::Result<u8> {
+        match self.byte()? {
+            Some(byte) => Ok(byte),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!(
+                    "the json ended inside the object with the array {}",
+                    self.key
+                ),
+            )),
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> std::io::Result<Option<u8>> {
+        loop {
+            match self.byte()? {
+                Some(byte) if is_whitespace(byte) => (),
+                byte => return Ok(byte),
+            }
+        }
+    }
+
+    // Reads the rest of a string after its opening quote, returning its raw bytes.
+    fn string(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut string = vec![];
+        let mut escaped = false;
+        loop {
+            let byte = self.expect_byte()?;
+            if !escaped && byte == b'"' {
+                return Ok(string);
+            }
+            escaped = !escaped && byte == b'\\';
+            string.push(byte);
+        }
+    }
+
+    // Skips the rest of a value that started with `first` up to the next comma or the end of the
+    // object or array the value is in, which is returned.
+    fn skip_value(&mut self, first: u8) -> std::io::Result<u8> {
+        let mut depth = 0;
+        let mut byte = first;
+        loop {
+            match byte {
+                b'"' => {
+                    self.string()?;
+                }
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' if depth == 0 => return Ok(byte),
+                b'}' | b']' => depth -= 1,
+                b',' if depth == 0 => return Ok(byte),
+                _ => (),
+            }
+            byte = self.expect_byte()?;
+        }
+    }
+
+    // Moves to the start of the array under the key.
+    fn find_array(&mut self) -> std::io::Result<()> {
+        if self.skip_whitespace()? != Some(b'{') {
+            return Err(invalid(format!(
+                "expected an object with the array {}",
+                self.key
+            )));
+        }
+        loop {
+            match self.skip_whitespace()? {
+                Some(b'"') => (),
+                _ => return Err(invalid(format!("missing field `{}`", self.key))),
+            }
+            let name = self.string()?;
+            if self.skip_whitespace()? != Some(b':') {
+                return Err(invalid("expected a colon after a key".to_string()));
+            }
+            let first = match self.skip_whitespace()? {
+                Some(byte) => byte,
+                None => return Err(invalid(format!("missing field `{}`", self.key))),
+            };
+            if name == self.key.as_bytes() {
+                return match first {
+                    b'[' => Ok(()),
+                    _ => Err(invalid(format!("{} is not an array", self.key))),
+                };
+            }
+            if self.skip_value(first)? == b'}' {
+                return Err(invalid(format!("missing field `{}`", self.key)));
+            }
+        }
+    }
+
+    // Checks that the object ends after the array. The keys after it are skipped.
+    fn finish_object(&mut self) -> std::io::Result<()> {
+        let mut next = self.skip_whitespace()?;
+        loop {
+            match next {
+                Some(b'}') => break,
+                Some(b',') => {
+                    let first = self.expect_byte()?;
+                    next = Some(self.skip_value(first)?);
+                }
+                _ => return Err(invalid("expected the end of the object".to_string())),
+            }
+        }
+        match self.skip_whitespace()? {
+            None => Ok(()),
+            Some(_) => Err(invalid("trailing characters after the object".to_string())),
+        }
+    }
+
+    // The next byte of the elements, None after the last one.
+    fn element_byte(&mut self) -> std::io::Result<Option<u8>> {
+        if self.state == State::Object {
+            self.find_array()?;
+            self.state = State::Array(Framing::default());
+        }
+        let mut framing = match self.state {
+            State::Array(framing) => framing,
+            _ => return Ok(None),
+        };
+        let byte = self.expect_byte()?;
+        let mut output = byte;
+        if framing.in_string {
+            if !framing.escaped && byte == b'"' {
+                framing.in_string = false;
+                framing.ended = framing.depth == 0;
+            }
+            framing.escaped = !framing.escaped && byte == b'\\';
+        } else if framing.depth > 0 {
+            match byte {
+                b'"' => framing.in_string = true,
+                b'{' | b'[' => framing.depth += 1,
+                b'}' | b']' => {
+                    framing.depth -= 1;
+                    framing.ended = framing.depth == 0;
+                }
+                _ => (),
+            }
+        } else if is_whitespace(byte) {
+            framing.ended = framing.element;
+        } else if byte == b',' {
+            if !framing.element {
+                return Err(invalid(format!("expected an element of {}", self.key)));
+            }
+            framing.element = false;
+            framing.ended = false;
+            framing.comma = true;
+            output = b' ';
+        } else if byte == b']' {
+            if framing.comma && !framing.element {
+                return Err(invalid(format!("trailing comma in {}", self.key)));
+            }
+            self.finish_object()?;
+            self.state = State::Done;
+            return Ok(None);
+        } else {
+            if framing.ended {
+                return Err(invalid(format!("expected a comma in {}", self.key)));
+            }
+            framing.element = true;
+            match byte {
+                b'"' => framing.in_string = true,
+                b'{' | b'[' => framing.depth += 1,
+                _ => (),
+            }
+        }
+        self.state = State::Array(framing);
+        Ok(Some(output))
+    }
+}
+
+impl<R: Read> Read for Elements<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let mut read = 0;
+        while read < buffer.len() {
+            match self.element_byte()? {
+                Some(byte) => buffer[read] = byte,
+                None => break,
+            }
+            read += 1;
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect<T: DeserializeOwned>(json: &'static [u8]) -> Result_<Vec<T>> {
+        array(json, "values").collect()
+    }
+
+    #[test]
+    fn test_array() {
+        let json = br#" { "page": {"a": [1, "]"]}, "values" : [ [1], [2, 3] ,[] ], "b": {} } "#;
+        assert_eq!(
+            collect::<Vec<u32>>(json).unwrap(),
+            vec![vec![1], vec![2, 3], vec![]]
+        );
+        assert_eq!(
+            collect::<String>(br#"{"values": ["a,]", "\"{"]}"#).unwrap(),
+            vec!["a,]", "\"{"]
+        );
+        assert!(collect::<u32>(br#"{"values": []}"#).unwrap().is_empty());
+        assert!(collect::<u32>(br#"{"other": [1]}"#).is_err());
+        assert!(collect::<u32>(br#"{"values": 1}"#).is_err());
+        assert!(collect::<u32>(br#"{"values": [1, "a"]}"#).is_err());
+        assert!(collect::<u32>(br#"{"values": [1 2]}"#).is_err());
+        assert!(collect::<u32>(br#"{"values": [[1] [2]]}"#).is_err());
+        assert!(collect::<u32>(br#"{"values": [1,]}"#).is_err());
+        assert!(collect::<u32>(br#"{"values": [,1]}"#).is_err());
+        assert!(collect::<u32>(br#"{"values": [1,"#).is_err());
+        assert!(collect::<u32>(br#"{"values": [1], "b": 1"#).is_err());
+        assert!(collect::<u32>(br#"{"values": [1]} 2"#).is_err());
+        // The elements before an error are still returned.
+        let mut values = array::<u32, _>(&br#"{"values": [1, "a"]}"#[..], "values");
+        assert_eq!(values.next().unwrap().unwrap(), 1);
+        assert!(values.next().unwrap().is_err());
+        assert!(values.next().is_none());
+        // Only what the elements need is read.
+        let mut values = array::<u32, _>(&br#"{"values": [1, 2, "#[..], "values");
+        assert_eq!(values.next().unwrap().unwrap(), 1);
+    }
+}
@@ -1,4 +1,37 @@
+mod archive;
+mod beatsaver;
+mod changes;
+mod config;
+mod control;
+mod corpus;
+mod cover;
+mod db;
+mod derived;
+mod export;
+mod http;
+mod installed;
+mod json_stream;
+mod notify;
+mod output;
+mod paging;
+mod player;
+mod playlist;
+mod pool;
+mod pp;
+mod profile;
+mod progress;
+mod provenance;
+mod publish;
+mod reload;
+mod report;
+mod scores;
+mod script;
+mod show;
+mod storage;
+mod workers;
+
 use lazy_static::lazy_static;
+use structopt::StructOpt;
 
 // We use boxes for errors because this is a simple binary where performance does not matter and
 // errors are rare.
@@ -20,12 +53,529 @@ CREATE TABLE IF NOT EXISTS "scoresaber_songs" (
     "bpm" INTEGER NOT NULL,
     "diff" TEXT NOT NULL,
     "stars" REAL NOT NULL,
+    "scores" INTEGER NOT NULL DEFAULT 0,
+    "scores_day" INTEGER NOT NULL DEFAULT 0,
+    -- 'ok', 'repaired' if ScoreSaber's bpm was invalid and BeatSaver's is used instead or 'invalid'.
+    "bpm_status" TEXT NOT NULL DEFAULT 'ok',
+    -- Probable script of the name like 'latin' or 'japanese', see script::Script.
+    "title_script" TEXT,
     PRIMARY KEY("uid")
 );
+CREATE TABLE IF NOT EXISTS "tracked_players" (
+    "id" TEXT NOT NULL UNIQUE,
+    PRIMARY KEY("id")
+);
+CREATE TABLE IF NOT EXISTS "player_history" (
+    "player_id" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "pp" REAL NOT NULL,
+    "rank" INTEGER NOT NULL,
+    "country_rank" INTEGER NOT NULL,
+    "recorded_at" TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS "crawls" (
+    "id" INTEGER NOT NULL,
+    "category" TEXT NOT NULL,
+    "started_at" TEXT NOT NULL,
+    "finished_at" TEXT,
+    "page_sizes" TEXT,
+    PRIMARY KEY("id")
+);
+CREATE TABLE IF NOT EXISTS "ranking_changes" (
+    "uid" INTEGER NOT NULL,
+    "id" TEXT NOT NULL,
+    "old_stars" REAL NOT NULL,
+    "new_stars" REAL NOT NULL,
+    "old_diff" TEXT NOT NULL,
+    "new_diff" TEXT NOT NULL,
+    "changed_at" TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS "beatsaver_maps" (
+    "hash" TEXT NOT NULL UNIQUE,
+    -- NULL if BeatSaver does not know the hash.
+    "key" TEXT,
+    "fetched_at" TEXT NOT NULL,
+    -- NULL for maps fetched before it was stored.
+    "bpm" REAL,
+    PRIMARY KEY("hash")
+);
+CREATE TABLE IF NOT EXISTS "beatsaver_difficulties" (
+    "hash" TEXT NOT NULL,
+    "characteristic" TEXT NOT NULL,
+    "difficulty" TEXT NOT NULL,
+    "notes" INTEGER NOT NULL,
+    "max_score" INTEGER NOT NULL,
+    -- Seconds. NULL for difficulties fetched before it was stored.
+    "length" REAL,
+    -- Note jump speed. NULL for difficulties fetched before it was stored.
+    "njs" REAL,
+    PRIMARY KEY("hash", "characteristic", "difficulty")
+);
+CREATE TABLE IF NOT EXISTS "player_scores" (
+    "player_id" TEXT NOT NULL,
+    "uid" INTEGER NOT NULL,
+    "score_id" INTEGER NOT NULL,
+    "rank" INTEGER NOT NULL,
+    "base_score" INTEGER NOT NULL,
+    "modified_score" INTEGER NOT NULL,
+    -- NULL if the max score of the leaderboard is unknown.
+    "accuracy" REAL,
+    "pp" REAL NOT NULL,
+    "weight" REAL NOT NULL,
+    "modifiers" TEXT NOT NULL,
+    "bad_cuts" INTEGER NOT NULL,
+    "missed_notes" INTEGER NOT NULL,
+    "max_combo" INTEGER NOT NULL,
+    "full_combo" INTEGER NOT NULL,
+    "time_set" TEXT NOT NULL,
+    PRIMARY KEY("player_id", "uid")
+);
+-- The first score of a tracked player on a ranked leaderboard found while polling.
+CREATE TABLE IF NOT EXISTS "first_clears" (
+    "player_id" TEXT NOT NULL,
+    "uid" INTEGER NOT NULL,
+    "accuracy" REAL,
+    "cleared_at" TEXT NOT NULL,
+    PRIMARY KEY("player_id", "uid")
+);
+-- Values of the derived columns from the config for every difficulty. Recomputed on every run.
+CREATE TABLE IF NOT EXISTS "derived_values" (
+    "uid" INTEGER NOT NULL,
+    "name" TEXT NOT NULL,
+    -- NULL if the expression could not be evaluated for the difficulty.
+    "value" REAL,
+    PRIMARY KEY("uid", "name")
+);
+-- What every source last reported for the fields that more than one source writes. The tables
+-- hold the value of the most trusted source.
+CREATE TABLE IF NOT EXISTS "field_sources" (
+    "uid" INTEGER NOT NULL,
+    "field" TEXT NOT NULL,
+    "source" TEXT NOT NULL,
+    -- NULL if the source reported nothing usable.
+    "value" REAL,
+    "recorded_at" TEXT NOT NULL,
+    PRIMARY KEY("uid", "field", "source")
+);
+-- Notifications of batched notifiers waiting to be sent together.
+CREATE TABLE IF NOT EXISTS "pending_notifications" (
+    "id" INTEGER NOT NULL,
+    "channel" TEXT NOT NULL,
+    "title" TEXT NOT NULL,
+    "body" TEXT NOT NULL,
+    "queued_at" TEXT NOT NULL,
+    PRIMARY KEY("id")
+);
+CREATE TABLE IF NOT EXISTS "map_pools" (
+    "name" TEXT NOT NULL UNIQUE,
+    "description" TEXT NOT NULL,
+    "frozen_at" TEXT NOT NULL,
+    PRIMARY KEY("name")
+);
+-- The difficulties of a pool as they were when it was frozen. `position` is the position of the
+-- song in the playlist.
+CREATE TABLE IF NOT EXISTS "map_pool_songs" (
+    "pool" TEXT NOT NULL,
+    "position" INTEGER NOT NULL,
+    "uid" INTEGER NOT NULL,
+    "id" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "diff" TEXT NOT NULL,
+    "stars" REAL NOT NULL,
+    PRIMARY KEY("pool", "uid")
+);
 "#;
 
+// CREATE TABLE IF NOT EXISTS leaves the tables of existing databases alone so columns that were
+// added after a table was created are added here. Together with DATABASE_SCHEMA this is the baseline
+// of the migrations and neither changes anymore.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("scoresaber_songs", "scores", "INTEGER NOT NULL DEFAULT 0"),
+    (
+        "scoresaber_songs",
+        "scores_day",
+        "INTEGER NOT NULL DEFAULT 0",
+    ),
+    ("beatsaver_difficulties", "length", "REAL"),
+    (
+        "scoresaber_songs",
+        "bpm_status",
+        "TEXT NOT NULL DEFAULT 'ok'",
+    ),
+    ("beatsaver_maps", "bpm", "REAL"),
+    ("crawls", "page_sizes", "TEXT"),
+    ("scoresaber_songs", "title_script", "TEXT"),
+    ("beatsaver_difficulties", "njs", "REAL"),
+];
+
+fn has_column(db: &rusqlite::Connection, table: &str, column: &str) -> Result_<bool> {
+    let mut statement = db.prepare(&format!("PRAGMA table_info({})", table))?;
+    let iter = statement.query_map(rusqlite::params![], |row| row.get::<_, String>(1))?;
+    for name in iter {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// How long a statement waits for another process, like a playlist command during a crawl, to
+// release its lock before failing with "database is locked".
+const BUSY_TIMEOUT_SECONDS: u64 = 30;
+
+// The database file and the SQLCipher key it is encrypted with.
+#[derive(Clone, Debug)]
+pub struct DatabaseFile {
+    pub path: std::path::PathBuf,
+    pub key: Option<String>,
+}
+
+impl DatabaseFile {
+    pub fn open(&self) -> Result_<rusqlite::Connection> {
+        open_database(&self.path, self.key.as_ref().map(|x| x.as_str()))
+    }
+}
+
+// Opens the database in WAL mode so that readers do not block the writer and the other way around.
+// With a key the database is encrypted with SQLCipher.
+fn open_database(path: &std::path::Path, key: Option<&str>) -> Result_<rusqlite::Connection> {
+    let db = rusqlite::Connection::open(path)?;
+    if let Some(key) = key {
+        // Plain SQLite ignores the key pragma and would silently write an unencrypted database.
+        if !cfg!(feature = "sqlcipher") {
+            Err("--db-key needs a build with the sqlcipher feature")?;
+        }
+        // The key has to be set before anything else reads the database.
+        db.execute_batch(&format!("PRAGMA key = '{}'", key.replace('\'', "''")))?;
+        db.query_row(
+            "SELECT COUNT(*) FROM sqlite_master",
+            rusqlite::params![],
+            |row| row.get::<_, i64>(0),
+        )
+        .map_err(|err| format!("could not decrypt {}: {}", path.display(), err))?;
+    }
+    db.busy_timeout(std::time::Duration::from_secs(BUSY_TIMEOUT_SECONDS))?;
+    // The pragma returns the new journal mode.
+    let _: String = db.query_row("PRAGMA journal_mode = WAL", rusqlite::params![], |row| {
+        row.get(0)
+    })?;
+    db.execute_batch("PRAGMA foreign_keys = ON")?;
+    Ok(db)
+}
+
+// Schema changes after the baseline in the order they are applied. Append new changes here and
+// never edit released ones. The schema version of a database is the number of applied migrations.
+const MIGRATIONS: &[&str] = &[
+    // Every observed version of the name, difficulty and stars of a leaderboard.
+    r#"
+CREATE TABLE "scoresaber_songs_history" (
+    "uid" INTEGER NOT NULL,
+    "name" TEXT NOT NULL,
+    "diff" TEXT NOT NULL,
+    "stars" REAL NOT NULL,
+    "observed_at" TEXT NOT NULL
+);
+CREATE INDEX "scoresaber_songs_history_uid" ON "scoresaber_songs_history" ("uid", "observed_at");
+INSERT INTO scoresaber_songs_history (uid, name, diff, stars, observed_at)
+    SELECT uid, name, diff, stars, datetime('now') FROM scoresaber_songs;
+"#,
+    // Leaderboards missing from the latest full crawl are kept with ranked = 0.
+    r#"ALTER TABLE "scoresaber_songs" ADD COLUMN "ranked" INTEGER NOT NULL DEFAULT 1;"#,
+    // When the insert path first stored and last wrote a leaderboard. Songs stored before count
+    // as first seen now.
+    r#"
+ALTER TABLE "scoresaber_songs" ADD COLUMN "first_seen" TEXT;
+ALTER TABLE "scoresaber_songs" ADD COLUMN "last_updated" TEXT;
+UPDATE scoresaber_songs SET first_seen = datetime('now'), last_updated = datetime('now');
+"#,
+    // Playlists group by hash and order by stars, the mapper commands look up mappers.
+    r#"
+CREATE INDEX "scoresaber_songs_id" ON "scoresaber_songs" ("id");
+CREATE INDEX "scoresaber_songs_stars" ON "scoresaber_songs" ("stars");
+CREATE INDEX "scoresaber_songs_levelAuthorName" ON "scoresaber_songs" ("levelAuthorName");
+"#,
+    // The songs of playlist files added with `import playlist`. Hashes are uppercase.
+    r#"
+CREATE TABLE "imported_playlists" (
+    "name" TEXT NOT NULL,
+    "hash" TEXT NOT NULL,
+    "song_name" TEXT NOT NULL,
+    PRIMARY KEY("name", "hash")
+);
+"#,
+    // The hardest ranked difficulty of every song, kept up to date by triggers so that unfiltered
+    // playlists do not have to group all difficulties.
+    r#"
+CREATE TABLE "best_difficulties" (
+    "hash" TEXT NOT NULL,
+    "uid" INTEGER NOT NULL,
+    "stars" REAL NOT NULL,
+    PRIMARY KEY("hash")
+);
+CREATE INDEX "best_difficulties_stars" ON "best_difficulties" ("stars");
+INSERT INTO best_difficulties (hash, uid, stars)
+    SELECT id, uid, MAX(stars) FROM scoresaber_songs WHERE ranked = 1 GROUP BY id;
+CREATE TRIGGER "best_difficulties_insert" AFTER INSERT ON "scoresaber_songs" BEGIN
+    DELETE FROM best_difficulties WHERE hash = NEW.id;
+    INSERT INTO best_difficulties (hash, uid, stars)
+        SELECT id, uid, MAX(stars) FROM scoresaber_songs WHERE id = NEW.id AND ranked = 1 GROUP BY id;
+END;
+CREATE TRIGGER "best_difficulties_update" AFTER UPDATE OF "id", "stars", "ranked" ON "scoresaber_songs" BEGIN
+    DELETE FROM best_difficulties WHERE hash IN (OLD.id, NEW.id);
+    INSERT INTO best_difficulties (hash, uid, stars)
+        SELECT id, uid, MAX(stars) FROM scoresaber_songs WHERE id IN (OLD.id, NEW.id) AND ranked = 1 GROUP BY id;
+END;
+CREATE TRIGGER "best_difficulties_delete" AFTER DELETE ON "scoresaber_songs" BEGIN
+    DELETE FROM best_difficulties WHERE hash = OLD.id;
+    INSERT INTO best_difficulties (hash, uid, stars)
+        SELECT id, uid, MAX(stars) FROM scoresaber_songs WHERE id = OLD.id AND ranked = 1 GROUP BY id;
+END;
+"#,
+    // `diff` split into its parts, filled in by update_missing_difficulties.
+    r#"
+ALTER TABLE "scoresaber_songs" ADD COLUMN "difficulty" TEXT;
+ALTER TABLE "scoresaber_songs" ADD COLUMN "characteristic" TEXT;
+CREATE INDEX "scoresaber_songs_difficulty" ON "scoresaber_songs" ("difficulty", "characteristic");
+"#,
+    // When ScoreSaber says the leaderboard was ranked and qualified. NULL if it did not say.
+    r#"
+ALTER TABLE "scoresaber_songs" ADD COLUMN "ranked_at" TEXT;
+ALTER TABLE "scoresaber_songs" ADD COLUMN "qualified_at" TEXT;
+"#,
+    // The changes::ChangeReport of every crawl as json.
+    r#"
+CREATE TABLE "change_reports" (
+    "crawl" INTEGER NOT NULL,
+    "report" TEXT NOT NULL,
+    PRIMARY KEY("crawl")
+);
+"#,
+    // More of the BeatSaver data. The votes change so maps can be fetched again, see
+    // `--beatsaver-refresh-days`.
+    r#"
+ALTER TABLE "beatsaver_maps" ADD COLUMN "uploader" TEXT;
+ALTER TABLE "beatsaver_maps" ADD COLUMN "duration" REAL;
+ALTER TABLE "beatsaver_maps" ADD COLUMN "up_votes" INTEGER;
+ALTER TABLE "beatsaver_maps" ADD COLUMN "down_votes" INTEGER;
+ALTER TABLE "beatsaver_maps" ADD COLUMN "rating" REAL;
+ALTER TABLE "beatsaver_difficulties" ADD COLUMN "nps" REAL;
+UPDATE beatsaver_difficulties SET nps = CAST(notes AS REAL) / length WHERE length > 0;
+CREATE TABLE "beatsaver_tags" (
+    "hash" TEXT NOT NULL,
+    "tag" TEXT NOT NULL,
+    PRIMARY KEY("hash", "tag")
+);
+"#,
+    // How far an unfinished crawl of a category got, for `--resume`. `song_offset` is the number of
+    // songs of the leaderboard list that are stored.
+    r#"
+CREATE TABLE "crawl_state" (
+    "category" TEXT NOT NULL,
+    "crawl" INTEGER NOT NULL,
+    "song_offset" INTEGER NOT NULL,
+    "updated_at" TEXT NOT NULL,
+    PRIMARY KEY("category")
+);
+"#,
+    // Players whose scores were all stored by a crawl of their top scores. Only for them a score
+    // without a stored one on the leaderboard is a first clear.
+    r#"
+CREATE TABLE "full_score_crawls" (
+    "player_id" TEXT NOT NULL,
+    "completed_at" TEXT NOT NULL,
+    PRIMARY KEY("player_id")
+);
+"#,
+    // Enrichment services whose last pass failed for some items so that their data is stale, see
+    // provenance::record_enrichment.
+    r#"
+CREATE TABLE "stale_services" (
+    "service" TEXT NOT NULL,
+    "failed" INTEGER NOT NULL,
+    "since" TEXT NOT NULL,
+    PRIMARY KEY("service")
+);
+"#,
+    // The times a leaderboard was ranked: an episode ends when `prune` unranks it and ranking it
+    // again starts the next one. History rows belong to the episode they were observed in. Songs
+    // stored before were ranked since they were first seen and unranked ones count as unranked now.
+    r#"
+CREATE TABLE "ranked_episodes" (
+    "uid" INTEGER NOT NULL,
+    "episode" INTEGER NOT NULL,
+    "started_at" TEXT NOT NULL,
+    "ended_at" TEXT,
+    PRIMARY KEY("uid", "episode")
+);
+INSERT INTO ranked_episodes (uid, episode, started_at, ended_at)
+    SELECT uid, 1, IFNULL(first_seen, datetime('now')), CASE WHEN ranked = 0 THEN datetime('now') END
+    FROM scoresaber_songs;
+ALTER TABLE "scoresaber_songs_history" ADD COLUMN "episode" INTEGER NOT NULL DEFAULT 1;
+"#,
+    // `db merge` looks up whether a history row is stored by the columns that identify it.
+    r#"
+CREATE INDEX "ranking_changes_uid" ON "ranking_changes" ("uid", "changed_at");
+CREATE INDEX "player_history_player_id" ON "player_history" ("player_id", "recorded_at");
+"#,
+    // Whether the crawl marked the songs missing from it as unranked. Crawls before may have stopped
+    // early so the next crawl sees every page.
+    r#"ALTER TABLE "crawls" ADD COLUMN "pruned" INTEGER NOT NULL DEFAULT 0;"#,
+    // Whether the unfinished crawl was paused, so that it starts paused again after a restart.
+    r#"ALTER TABLE "crawl_state" ADD COLUMN "paused" INTEGER NOT NULL DEFAULT 0;"#,
+    // Views are a stable interface for people querying the database with other tools. They only
+    // show ranked leaderboards. Databases at the baseline had them without that filter.
+    // ScoreSaber assigns increasing uids so the newest leaderboards come first. With a single MAX
+    // the other columns come from the row with the maximum.
+    r#"
+DROP VIEW IF EXISTS "latest_songs";
+DROP VIEW IF EXISTS "per_hash_max_stars";
+DROP VIEW IF EXISTS "recent_reranks";
+DROP VIEW IF EXISTS "player_best_per_map";
+CREATE VIEW "latest_songs" AS
+    SELECT uid, id, name, songSubName, songAuthorName, levelAuthorName, bpm, diff, stars
+    FROM scoresaber_songs WHERE ranked = 1 ORDER BY uid DESC;
+CREATE VIEW "per_hash_max_stars" AS
+    SELECT id, name, MAX(stars) AS max_stars, COUNT(*) AS difficulties
+    FROM scoresaber_songs WHERE ranked = 1 GROUP BY id;
+CREATE VIEW "recent_reranks" AS
+    SELECT c.uid, c.id, s.name, c.old_stars, c.new_stars, c.old_diff, c.new_diff, c.changed_at
+    FROM ranking_changes c JOIN scoresaber_songs s ON s.uid = c.uid
+    WHERE s.ranked = 1 AND c.changed_at >= datetime('now', '-30 days')
+    ORDER BY c.changed_at DESC;
+CREATE VIEW "player_best_per_map" AS
+    SELECT p.player_id, s.id, s.name, p.uid, s.diff, p.accuracy, p.full_combo, MAX(p.pp) AS pp
+    FROM player_scores p JOIN scoresaber_songs s ON s.uid = p.uid
+    WHERE s.ranked = 1
+    GROUP BY p.player_id, s.id;
+"#,
+];
+
+// Brings databases created by any earlier version up to the baseline. Applying it again changes
+// nothing.
+fn create_baseline(db: &rusqlite::Connection) -> Result_<()> {
+    db.execute_batch(DATABASE_SCHEMA)?;
+    for (table, column, definition) in ADDED_COLUMNS {
+        if !has_column(db, table, column)? {
+            db.execute_batch(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                table, column, definition
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+fn apply_migration(db: &rusqlite::Connection, migration: &str, version: usize) -> Result_<()> {
+    db.execute_batch(migration)?;
+    db.execute(
+        "UPDATE schema_version SET version = ?",
+        rusqlite::params![version as i64],
+    )?;
+    Ok(())
+}
+
+// Databases without a `schema_version` are brought up to the baseline first.
+fn migrate(db: &rusqlite::Connection, migrations: &[&str]) -> Result_<()> {
+    use rusqlite::OptionalExtension;
+    db.execute_batch(
+        r#"CREATE TABLE IF NOT EXISTS "schema_version" ("version" INTEGER NOT NULL);"#,
+    )?;
+    let version: Option<i64> = db
+        .query_row(
+            "SELECT version FROM schema_version",
+            rusqlite::params![],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let version = match version {
+        Some(version) => version as usize,
+        None => {
+            create_baseline(db)?;
+            db.execute(
+                "INSERT INTO schema_version (version) VALUES (0)",
+                rusqlite::params![],
+            )?;
+            0
+        }
+    };
+    if version > migrations.len() {
+        return Err(format!(
+            "the database has schema version {} but this program only knows version {}, it was created by a newer version",
+            version,
+            migrations.len()
+        ))?;
+    }
+    for (i, migration) in migrations.iter().enumerate().skip(version) {
+        log::info!("migrating the database to schema version {}", i + 1);
+        // A failed migration leaves the database at the previous version.
+        db.execute_batch("BEGIN")?;
+        let result = apply_migration(db, migration, i + 1);
+        match result {
+            Ok(()) => db.execute_batch("COMMIT")?,
+            Err(_) => db.execute_batch("ROLLBACK")?,
+        }
+        result?;
+    }
+    Ok(())
+}
+
+fn create_schema(db: &rusqlite::Connection) -> Result_<()> {
+    migrate(db, MIGRATIONS)?;
+    script::update_missing(db)?;
+    update_missing_difficulties(db)?;
+    Ok(())
+}
+
 const SCORESABER_API_URL: &str = "https://scoresaber.com/api.php";
 
+// The orderings the leaderboard list can be requested in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Category {
+    Trending,
+    DateRanked,
+    // Most played leaderboards first.
+    ScoresSet,
+    StarDifficulty,
+}
+
+impl Category {
+    // Value of the `cat` query parameter.
+    fn api_value(self) -> u8 {
+        match self {
+            Category::Trending => 0,
+            Category::DateRanked => 1,
+            Category::ScoresSet => 2,
+            Category::StarDifficulty => 3,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Category::Trending => "trending",
+            Category::DateRanked => "date-ranked",
+            Category::ScoresSet => "scores-set",
+            Category::StarDifficulty => "star-difficulty",
+        }
+    }
+}
+
+impl std::str::FromStr for Category {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "trending" => Ok(Category::Trending),
+            "date-ranked" => Ok(Category::DateRanked),
+            "scores-set" | "top-played" => Ok(Category::ScoresSet),
+            "star-difficulty" => Ok(Category::StarDifficulty),
+            _ => Err(format!(
+                "unknown category {}, expected trending, date-ranked, scores-set, top-played or star-difficulty",
+                s
+            )),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, serde::Deserialize)]
 struct ScoreSaberSong {
     uid: ScoreSaberSongId,
@@ -44,68 +594,218 @@ struct ScoreSaberSong {
     difficulty: String,
     #[serde(rename = "stars")]
     star_difficulty: f64,
+    #[serde(rename = "scores", deserialize_with = "deserialize_play_count")]
+    plays: u64,
+    #[serde(rename = "scores_day")]
+    daily_plays: u64,
+    // The legacy get-leaderboards function of api.php never includes the dates so they are only
+    // set for songs from responses that have them, like the leaderboards of the newer /api. See
+    // playlist::RANKED_AT for the fallback.
+    #[serde(rename = "rankedDate", default)]
+    ranked_date: Option<String>,
+    #[serde(rename = "qualifiedDate", default)]
+    qualified_date: Option<String>,
+}
+
+// Splits a `diff` like "_ExpertPlus_SoloStandard" into the difficulty "ExpertPlus" and the
+// characteristic "Standard".
+fn parse_difficulty(diff: &str) -> Option<(&str, &str)> {
+    let mut parts = diff.trim_start_matches('_').splitn(2, '_');
+    let difficulty = parts.next()?;
+    let characteristic = parts.next()?.trim_start_matches("Solo");
+    Some((difficulty, characteristic))
+}
+
+// The typed parts of a `diff` for the `difficulty` and `characteristic` columns. None if the
+// difficulty is unknown.
+fn difficulty_columns(diff: &str) -> Option<(Difficulty, &str)> {
+    let (difficulty, characteristic) = parse_difficulty(diff)?;
+    Some((difficulty.parse().ok()?, characteristic))
+}
+
+// Fills in the difficulty and characteristic of songs that were stored before they had columns.
+fn update_missing_difficulties(db: &rusqlite::Connection) -> Result_<()> {
+    let diffs = db
+        .prepare("SELECT DISTINCT diff FROM scoresaber_songs WHERE difficulty IS NULL")?
+        .query_map(rusqlite::params![], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for diff in diffs {
+        if let Some((difficulty, characteristic)) = difficulty_columns(&diff) {
+            db.execute(
+                "UPDATE scoresaber_songs SET difficulty = ?, characteristic = ? WHERE diff = ?",
+                rusqlite::params![difficulty.name(), characteristic, diff],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+    Expert,
+    ExpertPlus,
+}
+
+impl Difficulty {
+    // As used in `diff`.
+    fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+            Difficulty::Expert => "Expert",
+            Difficulty::ExpertPlus => "ExpertPlus",
+        }
+    }
 }
 
-struct RankedSongsPage<T: Iterator<Item = ScoreSaberSong>> {
+impl std::str::FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "easy" => Ok(Difficulty::Easy),
+            "normal" => Ok(Difficulty::Normal),
+            "hard" => Ok(Difficulty::Hard),
+            "expert" => Ok(Difficulty::Expert),
+            "expertplus" | "expert+" => Ok(Difficulty::ExpertPlus),
+            _ => Err(format!(
+                "unknown difficulty {}, expected Easy, Normal, Hard, Expert or ExpertPlus",
+                s
+            )),
+        }
+    }
+}
+
+// The api formats the total play count with thousands separators like "1,751".
+fn deserialize_play_count<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<u64, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum PlayCount {
+        Number(u64),
+        Text(String),
+    }
+    match <PlayCount as serde::Deserialize>::deserialize(deserializer)? {
+        PlayCount::Number(number) => Ok(number),
+        PlayCount::Text(text) => text
+            .replace(',', "")
+            .parse()
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+struct RankedSongsPage<T: Iterator<Item = Result_<ScoreSaberSong>>> {
     songs: T,
-    last_page: bool,
+    last_page: LastPage,
 }
 
+// A page that was requested with a limit is the last one if it has fewer songs, which a streamed
+// page only knows once all of its songs were read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LastPage {
+    Known(bool),
+    BelowLimit(usize),
+}
+
+// Pages from different sources (the api, an archived corpus) have different iterator types.
+type BoxedSongs = Box<dyn Iterator<Item = Result_<ScoreSaberSong>>>;
+
+fn boxed_page<T: Iterator<Item = Result_<ScoreSaberSong>> + 'static>(
+    page: RankedSongsPage<T>,
+) -> RankedSongsPage<BoxedSongs> {
+    RankedSongsPage {
+        songs: Box::new(page.songs),
+        last_page: page.last_page,
+    }
+}
+
+// The songs are deserialized while they are read.
 fn extract_ranked_songs_page<T: std::io::Read>(
     response: T,
     limit: usize,
-) -> Result_<RankedSongsPage<impl Iterator<Item = ScoreSaberSong>>> {
-    #[derive(Clone, Debug, PartialEq, serde::Deserialize)]
-    struct Songs {
-        songs: Vec<ScoreSaberSong>,
+) -> RankedSongsPage<json_stream::Array<ScoreSaberSong, T>> {
+    RankedSongsPage {
+        songs: json_stream::array(response, "songs"),
+        last_page: LastPage::BelowLimit(limit),
     }
-    let songs: Songs = serde_json::from_reader(response)?;
-    let last = songs.songs.len() < limit;
-    Ok(RankedSongsPage {
-        songs: songs.songs.into_iter(),
-        last_page: last,
-    })
 }
 
-// 1 is first page
-fn get_ranked_songs_page(
-    client: &reqwest::Client,
-    page: u64,
-) -> Result_<RankedSongsPage<impl Iterator<Item = ScoreSaberSong>>> {
-    // cat=1 means sort by date ranked
-    const LIMIT: usize = 1000;
+fn ranked_songs_url(category: Category, limit: usize, page: u64) -> Result_<reqwest::Url> {
     let url = reqwest::Url::parse_with_params(
         SCORESABER_API_URL,
         &[
             ("function", "get-leaderboards"),
             ("ranked", "1"),
-            ("cat", "1"),
-            ("limit", &LIMIT.to_string()),
+            ("cat", &category.api_value().to_string()),
+            ("limit", &limit.to_string()),
             ("page", &page.to_string()),
         ],
     )?;
     log::info!("request: {}", url);
-    let response = client.get(url).send()?;
-    if response.status().is_success() {
-        extract_ranked_songs_page(response, LIMIT)
-    } else {
-        Err(format!(
+    Ok(url)
+}
+
+fn check_status(status: reqwest::StatusCode) -> Result_<()> {
+    match status.is_success() {
+        true => Ok(()),
+        false => Err(format!(
             "response status code does not indiciate success: {}",
-            response.status()
-        ))?
+            status
+        ))?,
     }
 }
 
-fn get_ranked_songs(
+// 1 is first page of `limit` songs. The songs are read from the response while they are used. Only
+// the http cache and the archive make it keep the body.
+fn get_ranked_songs_page(
+    client: &reqwest::Client,
+    category: Category,
+    limit: usize,
+    page: u64,
+) -> Result_<RankedSongsPage<impl Iterator<Item = Result_<ScoreSaberSong>>>> {
+    let url = ranked_songs_url(category, limit, page)?;
+    let keep = match archive::enabled() {
+        true => {
+            let url = url.to_string();
+            Some(Box::new(move |body: &[u8]| archive::record(&url, body)) as http::Keep)
+        }
+        false => None,
+    };
+    let (status, body) = http::get_streamed(url.as_str(), || client.get(url.clone()), keep)?;
+    check_status(status)?;
+    Ok(extract_ranked_songs_page(body, limit))
+}
+
+// The whole body, for the prefetcher which receives the pages on its threads.
+fn get_ranked_songs_body(
     client: &reqwest::Client,
-) -> impl Iterator<Item = Result_<ScoreSaberSong>> + '_ {
+    category: Category,
+    limit: usize,
+    page: u64,
+) -> Result_<Vec<u8>> {
+    let url = ranked_songs_url(category, limit, page)?;
+    let (status, body) = http::get(url.as_str(), || client.get(url.clone()))?;
+    check_status(status)?;
+    archive::record(url.as_str(), &body);
+    Ok(body)
+}
+
+fn get_ranked_songs<'a>(
+    get_page: impl FnMut(u64) -> Result_<RankedSongsPage<BoxedSongs>> + 'a,
+) -> impl Iterator<Item = Result_<ScoreSaberSong>> + 'a {
     struct Iter<'a> {
-        // TODO: this type should be exactly the result of get_ranked_songs_page which is
-        // `impl Iterator`. However we cannot use impl in a struct and I failed to express the same
-        // thing using generics.
-        songs: Box<Iterator<Item = ScoreSaberSong>>,
-        next_page: Option<u64>,
-        client: &'a reqwest::Client,
+        songs: BoxedSongs,
+        // The page the songs are from, whether it is the last one and how many of its songs were
+        // read.
+        page: u64,
+        last_page: LastPage,
+        read: usize,
+        get_page: Box<dyn FnMut(u64) -> Result_<RankedSongsPage<BoxedSongs>> + 'a>,
     }
 
     impl<'a> Iterator for Iter<'a> {
@@ -113,25 +813,39 @@ fn get_ranked_songs(
 
         fn next(&mut self) -> Option<Self::Item> {
             match self.songs.next() {
-                Some(song) => Some(Ok(song)),
+                Some(Ok(song)) => {
+                    self.read += 1;
+                    Some(Ok(song))
+                }
+                // The rest of a page that failed to be read is unknown.
+                Some(Err(err)) => {
+                    self.last_page = LastPage::Known(true);
+                    Some(Err(err))
+                }
                 None => {
-                    match self.next_page {
-                        Some(page) => {
-                            match get_ranked_songs_page(self.client, page) {
-                                Ok(response) => {
-                                    self.songs = Box::new(response.songs);
-                                    // Increment current_page only after adding the songs to the vector. This way if
-                                    // retrieving the response fails, the state is unchanged.
-                                    self.next_page = match response.last_page {
-                                        true => None,
-                                        false => Some(page + 1),
-                                    };
-                                    self.next()
-                                }
-                                Err(err) => Some(Err(err)),
-                            }
+                    let last_page = match self.last_page {
+                        LastPage::Known(last_page) => last_page,
+                        LastPage::BelowLimit(limit) => self.read < limit,
+                    };
+                    if last_page {
+                        return None;
+                    }
+                    let page = self.page + 1;
+                    match (self.get_page)(page) {
+                        Ok(response) => {
+                            progress::emit(progress::Event::PageFetched {
+                                source: "leaderboards",
+                                page,
+                            });
+                            // Move to the page only after retrieving it. This way if retrieving the
+                            // response fails, the state is unchanged.
+                            self.songs = response.songs;
+                            self.page = page;
+                            self.last_page = response.last_page;
+                            self.read = 0;
+                            self.next()
                         }
-                        None => None,
+                        Err(err) => Some(Err(err)),
                     }
                 }
             }
@@ -140,115 +854,1209 @@ fn get_ranked_songs(
 
     Iter {
         songs: Box::new(vec![].into_iter()),
-        next_page: Some(1),
-        client: client,
+        page: 0,
+        last_page: LastPage::Known(false),
+        read: 0,
+        get_page: Box::new(get_page),
     }
 }
 
-fn insert_song_into_db(db: &rusqlite::Connection, song: &ScoreSaberSong) -> Result_<()> {
-    let mut insert_statement = db.prepare("REPLACE INTO scoresaber_songs (uid, id, name, songSubName, songAuthorName, levelAuthorName, bpm, diff, stars) VALUES (?,?,?,?,?,?,?,?,?)")?;
-    let rows_affected = insert_statement.execute(rusqlite::params![
-        song.uid as i64,
-        song.id,
-        song.name,
-        song.sub_name,
-        song.song_author,
-        song.level_author,
-        song.beats_per_minute as i64,
-        song.difficulty,
-        song.star_difficulty
-    ])?;
-    if rows_affected != 1 {
-        return Err("rows_affected is not 1")?;
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct RankingChange {
+    name: String,
+    old_stars: f64,
+    new_stars: f64,
+    old_difficulty: String,
+    new_difficulty: String,
+}
+
+// Compares the song against the stored row with the same uid and records a ranking change if the
+// star difficulty or the difficulty changed. Songs that are not yet stored are not a change.
+fn record_ranking_change(
+    db: &rusqlite::Connection,
+    song: &ScoreSaberSong,
+) -> Result_<Option<RankingChange>> {
+    use rusqlite::OptionalExtension;
+    let stored: Option<(f64, String)> = db
+        .query_row(
+            "SELECT stars, diff FROM scoresaber_songs WHERE uid = ?",
+            rusqlite::params![song.uid as i64],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let (old_stars, old_diff) = match stored {
+        Some(stored) => stored,
+        None => return Ok(None),
+    };
+    if old_stars == song.star_difficulty && old_diff == song.difficulty {
+        return Ok(None);
     }
+    db.execute(
+        "INSERT INTO ranking_changes (uid, id, old_stars, new_stars, old_diff, new_diff, changed_at) VALUES (?,?,?,?,?,?,datetime('now'))",
+        rusqlite::params![
+            song.uid as i64,
+            song.id,
+            old_stars,
+            song.star_difficulty,
+            old_diff,
+            song.difficulty
+        ],
+    )?;
+    Ok(Some(RankingChange {
+        name: song.name.clone(),
+        old_stars,
+        new_stars: song.star_difficulty,
+        old_difficulty: old_diff,
+        new_difficulty: song.difficulty.clone(),
+    }))
+}
+
+// Adds a row to the history of a leaderboard when it is first seen, ranked again or when its name,
+// difficulty or stars differ from the stored row. Must be called before the stored row is updated
+// because a song that is not stored as ranked starts the next episode, see `open_episode`.
+fn record_history(
+    db: &rusqlite::Connection,
+    uid: i64,
+    name: &str,
+    diff: &str,
+    stars: f64,
+) -> Result_<()> {
+    use rusqlite::OptionalExtension;
+    let stored: Option<(String, String, f64, bool)> = db
+        .query_row(
+            "SELECT name, diff, stars, ranked FROM scoresaber_songs WHERE uid = ?",
+            rusqlite::params![uid],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()?;
+    let ranked = match stored {
+        Some((old_name, old_diff, old_stars, ranked)) => {
+            if ranked && old_name == name && old_diff == diff && old_stars == stars {
+                return Ok(());
+            }
+            ranked
+        }
+        None => false,
+    };
+    let episode = last_episode(db, uid)? + if ranked { 0 } else { 1 };
+    db.execute(
+        "INSERT INTO scoresaber_songs_history (uid, name, diff, stars, observed_at, episode) VALUES (?,?,?,?,datetime('now'),?)",
+        rusqlite::params![uid, name, diff, stars, episode],
+    )?;
     Ok(())
 }
 
-fn scrape_all_songs(db: &rusqlite::Connection) -> Result_<()> {
-    let client = reqwest::Client::new();
-    for (i, song_result) in get_ranked_songs(&client).enumerate() {
+// The number of the latest ranked episode of a leaderboard, 0 if it was never stored.
+fn last_episode(db: &rusqlite::Connection, uid: i64) -> Result_<i64> {
+    Ok(db.query_row(
+        "SELECT IFNULL(MAX(episode), 0) FROM ranked_episodes WHERE uid = ?",
+        rusqlite::params![uid],
+        |row| row.get(0),
+    )?)
+}
+
+// Starts the next ranked episode of a new or ranked again leaderboard.
+fn open_episode(db: &rusqlite::Connection, uid: &dyn rusqlite::types::ToSql) -> Result_<()> {
+    db.execute(
+        "INSERT INTO ranked_episodes (uid, episode, started_at) SELECT ?1, IFNULL(MAX(episode), 0) + 1, datetime('now') FROM ranked_episodes WHERE uid = ?1",
+        &[uid],
+    )?;
+    Ok(())
+}
+
+// ScoreSaber occasionally reports a bpm of 0 or an absurd one.
+const MIN_BPM: f64 = 10.0;
+const MAX_BPM: f64 = 1000.0;
+
+fn is_valid_bpm(bpm: f64) -> bool {
+    (MIN_BPM..=MAX_BPM).contains(&bpm)
+}
+
+// Returns the bpm to store and its `bpm_status`. An invalid bpm is replaced by BeatSaver's if the
+// map has been fetched already, otherwise the BeatSaver enrichment repairs it later.
+fn checked_bpm(db: &rusqlite::Connection, hash: &str, bpm: f64) -> Result_<(f64, &'static str)> {
+    use rusqlite::OptionalExtension;
+    if is_valid_bpm(bpm) {
+        return Ok((bpm, "ok"));
+    }
+    let beatsaver_bpm: Option<f64> = db
+        .query_row(
+            "SELECT bpm FROM beatsaver_maps WHERE hash = ? AND bpm IS NOT NULL",
+            rusqlite::params![hash],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match beatsaver_bpm {
+        Some(beatsaver_bpm) if is_valid_bpm(beatsaver_bpm) => Ok((beatsaver_bpm, "repaired")),
+        _ => {
+            log::warn!("song {} has an invalid bpm of {}", hash, bpm);
+            Ok((bpm, "invalid"))
+        }
+    }
+}
+
+fn insert_song_into_db(
+    db: &rusqlite::Connection,
+    song: &ScoreSaberSong,
+) -> Result_<(Stored, Option<RankingChange>)> {
+    let _timer = profile::timer(profile::Kind::Database);
+    let change = record_ranking_change(db, song)?;
+    record_history(
+        db,
+        song.uid as i64,
+        &song.name,
+        &song.difficulty,
+        song.star_difficulty,
+    )?;
+    let (bpm, bpm_status) = checked_bpm(db, &song.id, song.beats_per_minute as f64)?;
+    let difficulty = difficulty_columns(&song.difficulty);
+    provenance::record(
+        db,
+        song.uid as i64,
+        "bpm",
+        "scoresaber",
+        Some(song.beats_per_minute as f64).filter(|x| is_valid_bpm(*x)),
+    )?;
+    let stored = upsert_song(
+        db,
+        rusqlite::params![
+            song.uid as i64,
+            song.id,
+            song.name,
+            song.sub_name,
+            song.song_author,
+            song.level_author,
+            bpm,
+            song.difficulty,
+            song.star_difficulty,
+            song.plays as i64,
+            song.daily_plays as i64,
+            bpm_status,
+            script::detect(&song.name).name(),
+            difficulty.map(|x| x.0.name()),
+            difficulty.map(|x| x.1),
+            song.ranked_date,
+            song.qualified_date
+        ],
+    )?;
+    Ok((stored, change))
+}
+
+// How an upsert changed the stored song.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stored {
+    New,
+    // The song was stored unranked and is ranked again.
+    Reranked,
+    // The stars, diff or ranked date changed.
+    Changed,
+    // Only columns that do not change the ranking like the play counts or the name changed.
+    Refreshed,
+    Identical,
+}
+
+// Inserts a song or updates the columns of the stored one if any of them changed. The parameters
+// are the uid, hash, name, sub name, song author, mapper, bpm, diff, stars, plays, daily plays, bpm
+// status, title script, difficulty, characteristic, ranked date and qualified date. Unlike REPLACE
+// it does not delete the stored row so first_seen and the dates ScoreSaber did not send are kept.
+const UPSERT_SONG: &str = "INSERT INTO scoresaber_songs (uid, id, name, songSubName, songAuthorName, levelAuthorName, bpm, diff, stars, scores, scores_day, bpm_status, title_script, difficulty, characteristic, ranked_at, qualified_at, ranked, first_seen, last_updated) VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,datetime(?16),datetime(?17),1,datetime('now'),datetime('now')) ON CONFLICT(uid) DO UPDATE SET id = excluded.id, name = excluded.name, songSubName = excluded.songSubName, songAuthorName = excluded.songAuthorName, levelAuthorName = excluded.levelAuthorName, bpm = excluded.bpm, diff = excluded.diff, stars = excluded.stars, scores = excluded.scores, scores_day = excluded.scores_day, bpm_status = excluded.bpm_status, title_script = excluded.title_script, difficulty = excluded.difficulty, characteristic = excluded.characteristic, ranked_at = COALESCE(excluded.ranked_at, ranked_at), qualified_at = COALESCE(excluded.qualified_at, qualified_at), ranked = 1, last_updated = datetime('now') WHERE (id, name, songSubName, songAuthorName, levelAuthorName, bpm, diff, stars, scores, scores_day, bpm_status, title_script, difficulty, characteristic, ranked_at, qualified_at, ranked) IS NOT (excluded.id, excluded.name, excluded.songSubName, excluded.songAuthorName, excluded.levelAuthorName, excluded.bpm, excluded.diff, excluded.stars, excluded.scores, excluded.scores_day, excluded.bpm_status, excluded.title_script, excluded.difficulty, excluded.characteristic, COALESCE(excluded.ranked_at, ranked_at), COALESCE(excluded.qualified_at, qualified_at), 1)";
+
+fn upsert_song(
+    db: &rusqlite::Connection,
+    params: &[&dyn rusqlite::types::ToSql],
+) -> Result_<Stored> {
+    use rusqlite::OptionalExtension;
+    let uid = params[0];
+    let ranking =
+        |db: &rusqlite::Connection| -> Result_<Option<(i64, f64, String, Option<String>)>> {
+            Ok(db
+                .query_row(
+                    "SELECT ranked, stars, diff, ranked_at FROM scoresaber_songs WHERE uid = ?",
+                    &[uid],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                )
+                .optional()?)
+        };
+    let stored = match (ranking(db)?, db.execute(UPSERT_SONG, params)?) {
+        (None, _) => Stored::New,
+        (Some((0, ..)), _) => Stored::Reranked,
+        (Some(_), 0) => Stored::Identical,
+        (before, _) if before != ranking(db)? => Stored::Changed,
+        (Some(_), _) => Stored::Refreshed,
+    };
+    if stored == Stored::New || stored == Stored::Reranked {
+        open_episode(db, uid)?;
+    }
+    Ok(stored)
+}
+
+// Returns the newly ranked and reweighted songs and the uids of all songs. `stored_changes` counts
+// the songs so far that were new or whose ranking changed. Play counts change on nearly every crawl
+// so they do not count.
+fn scrape_all_songs(
+    db: &rusqlite::Connection,
+    songs: impl Iterator<Item = Result_<ScoreSaberSong>>,
+    stored_changes: &std::cell::Cell<usize>,
+) -> Result_<(changes::ChangeReport, std::collections::HashSet<i64>)> {
+    let mut report = changes::ChangeReport::default();
+    let mut seen = std::collections::HashSet::new();
+    let (mut new, mut changed, mut identical) = (0, 0, 0);
+    for (i, song_result) in songs.enumerate() {
         let song = song_result?;
+        seen.insert(song.uid as i64);
         println!(
             "handling song number {} with id {} and name {}",
             i, song.uid, song.name
         );
-        insert_song_into_db(db, &song)?;
+        progress::emit(progress::Event::SongInserted {
+            number: i,
+            uid: song.uid,
+            name: &song.name,
+        });
+        let (stored, change) = insert_song_into_db(db, &song)?;
+        match stored {
+            Stored::New => new += 1,
+            Stored::Reranked | Stored::Changed | Stored::Refreshed => changed += 1,
+            Stored::Identical => identical += 1,
+        }
+        if stored != Stored::Identical && stored != Stored::Refreshed {
+            stored_changes.set(stored_changes.get() + 1);
+        }
+        if stored == Stored::New || stored == Stored::Reranked {
+            report.ranked.push(changes::Song {
+                uid: song.uid as i64,
+                name: song.name.clone(),
+                difficulty: song.difficulty.clone(),
+                stars: song.star_difficulty,
+            });
+        }
+        if let Some(change) = change {
+            report.reweighted.push(change);
+        }
+    }
+    println!(
+        "stored {} new, {} changed and {} identical songs",
+        new, changed, identical
+    );
+    Ok((report, seen))
+}
+
+// Marks the ranked songs that are not in `seen` as unranked. ScoreSaber only lists ranked
+// leaderboards so after a full crawl the missing ones were unranked or deleted. They stay in the
+// database because scores and history refer to them and they are ranked again when they reappear.
+// Their ranked episode ends. Returns the newly unranked songs.
+fn prune(
+    db: &rusqlite::Connection,
+    seen: &std::collections::HashSet<i64>,
+) -> Result_<Vec<changes::Song>> {
+    let ranked = db
+        .prepare("SELECT uid, name, diff, stars FROM scoresaber_songs WHERE ranked = 1")?
+        .query_map(rusqlite::params![], |row| {
+            Ok(changes::Song {
+                uid: row.get(0)?,
+                name: row.get(1)?,
+                difficulty: row.get(2)?,
+                stars: row.get(3)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<changes::Song>>>()?;
+    let mut pruned = vec![];
+    for song in ranked {
+        if !seen.contains(&song.uid) {
+            db.execute(
+                "UPDATE scoresaber_songs SET ranked = 0 WHERE uid = ?",
+                rusqlite::params![song.uid],
+            )?;
+            db.execute(
+                "UPDATE ranked_episodes SET ended_at = datetime('now') WHERE uid = ? AND ended_at IS NULL",
+                rusqlite::params![song.uid],
+            )?;
+            pruned.push(song);
+        }
     }
+    Ok(pruned)
+}
+
+// Returns the id of the new crawl.
+fn start_crawl(db: &rusqlite::Connection, category: Category) -> Result_<i64> {
+    db.execute(
+        "INSERT INTO crawls (category, started_at) VALUES (?, datetime('now'))",
+        rusqlite::params![category.name()],
+    )?;
+    Ok(db.last_insert_rowid())
+}
+
+fn finish_crawl(db: &rusqlite::Connection, crawl: i64, pruned: bool) -> Result_<()> {
+    db.execute(
+        "UPDATE crawls SET finished_at = datetime('now'), pruned = ? WHERE id = ?",
+        rusqlite::params![pruned, crawl],
+    )?;
     Ok(())
 }
 
-#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
-struct BeatSaberPlaylistSong {
-    #[serde(rename = "songName")]
-    name: String,
-    #[serde(rename = "hash")]
-    hash: String,
-}
-
-#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
-struct BeatsaberPlaylist {
-    #[serde(rename = "playlistTitle")]
-    title: String,
-    #[serde(rename = "playlistAuthor")]
-    author: String,
-    #[serde(rename = "playlistDescription")]
-    description: String,
-    #[serde(rename = "songs")]
-    songs: Vec<BeatSaberPlaylistSong>,
-}
-
-fn make_beatsaber_playlist(db: &rusqlite::Connection) -> Result_<BeatsaberPlaylist> {
-    const TITLE: &str = "Ranked Songs";
-    const AUTHOR: &str = "Valentin (e00E)";
-    const DESCRIPTION: &str = "Contains all songs that are ranked on Score Saber ordered by star difficulty (roughly equivalent to maximum PP) in descending order.";
-    // GROUP_BY and MAX(stars) are needed because the same hash is part of multiple difficulties of
-    // the same song so we sort by the maximum of all difficulties.
-    let mut statement =
-        db.prepare("SELECT id,name FROM scoresaber_songs GROUP BY id ORDER BY MAX(stars) DESC")?;
-
-    let mut playlist = BeatsaberPlaylist {
-        title: TITLE.to_string(),
-        author: AUTHOR.to_string(),
-        description: DESCRIPTION.to_string(),
-        songs: vec![],
-    };
+// Whether no crawl marked unranked songs in the last `days` days, so that the next crawl has to see
+// every page even when it could stop early.
+fn prune_due(db: &rusqlite::Connection, days: u64) -> Result_<bool> {
+    let pruned: i64 = db.query_row(
+        "SELECT COUNT(*) FROM crawls WHERE pruned = 1 AND finished_at >= datetime('now', ?)",
+        rusqlite::params![format!("-{} days", days)],
+        |row| row.get(0),
+    )?;
+    Ok(pruned == 0)
+}
+
+fn print_latest_changes(db: &rusqlite::Connection) -> Result_<()> {
+    match changes::latest(db)? {
+        Some(ref report) if !report.is_empty() => println!("{}", report.text()),
+        _ => println!("No ranked songs changed."),
+    }
+    Ok(())
+}
+
+// How many songs of the category the interrupted crawl stored, None if it was not interrupted.
+fn load_checkpoint(db: &rusqlite::Connection, category: Category) -> Result_<Option<usize>> {
+    use rusqlite::OptionalExtension;
+    let offset: Option<i64> = db
+        .query_row(
+            "SELECT song_offset FROM crawl_state WHERE category = ?",
+            rusqlite::params![category.name()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(offset.map(|offset| offset as usize))
+}
+
+fn save_checkpoint(
+    db: &rusqlite::Connection,
+    category: Category,
+    crawl: i64,
+    offset: usize,
+) -> Result_<()> {
+    db.execute(
+        "INSERT INTO crawl_state (category, crawl, song_offset, updated_at) VALUES (?,?,?,datetime('now')) ON CONFLICT(category) DO UPDATE SET crawl = excluded.crawl, song_offset = excluded.song_offset, updated_at = excluded.updated_at",
+        rusqlite::params![category.name(), crawl, offset as i64],
+    )?;
+    Ok(())
+}
+
+// Whether the unfinished crawl of the category was paused when its process ended.
+fn crawl_paused(db: &rusqlite::Connection, category: Category) -> Result_<bool> {
+    let paused: i64 = db.query_row(
+        "SELECT COUNT(*) FROM crawl_state WHERE category = ? AND paused = 1",
+        rusqlite::params![category.name()],
+        |row| row.get(0),
+    )?;
+    Ok(paused > 0)
+}
+
+fn save_paused(db: &rusqlite::Connection, category: Category, paused: bool) -> Result_<()> {
+    db.execute(
+        "UPDATE crawl_state SET paused = ? WHERE category = ?",
+        rusqlite::params![paused, category.name()],
+    )?;
+    Ok(())
+}
+
+fn clear_checkpoint(db: &rusqlite::Connection, category: Category) -> Result_<()> {
+    db.execute(
+        "DELETE FROM crawl_state WHERE category = ?",
+        rusqlite::params![category.name()],
+    )?;
+    Ok(())
+}
+
+// `category` is the ordering `get_page` returns the songs in. With `prune_unranked` songs that are
+// no longer ranked are marked as such. `resume_offset` is the number of songs an interrupted crawl
+// already stored. With `stop_when_unchanged` the crawl ends after a page whose songs were all known
+// with an unchanged ranking, which only makes sense when the newest songs come first.
+#[derive(Clone, Copy, Debug)]
+struct CrawlOptions {
+    category: Category,
+    prune_unranked: bool,
+    resume_offset: usize,
+    stop_when_unchanged: bool,
+}
 
-    struct Song {
-        hash: String,
-        name: String,
+// Everything a run does. The sources are parameters so that archived responses can be replayed
+// through the same pipeline. `control` can pause or abort the crawl between pages.
+fn run_crawl(
+    db: &rusqlite::Connection,
+    options: CrawlOptions,
+    control: &control::Control,
+    mut get_page: impl FnMut(u64) -> Result_<RankedSongsPage<BoxedSongs>>,
+    get_player: impl FnMut(&str) -> Result_<player::Player>,
+    notifiers: &[Box<dyn notify::Notifier>],
+    derived_columns: &[derived::DerivedColumn],
+) -> Result_<playlist::BeatsaberPlaylist> {
+    let CrawlOptions {
+        category,
+        prune_unranked,
+        resume_offset,
+        stop_when_unchanged,
+    } = options;
+    progress::stage("crawl");
+    if crawl_paused(db, category)? {
+        println!(
+            "the crawl was paused before the restart, SIGUSR1 resumes it and SIGUSR2 aborts it"
+        );
+        control.pause();
     }
-    let iter = statement.query_map(rusqlite::params![], |row| {
-        Ok(Song {
-            hash: row.get(0)?,
-            name: row.get(1)?,
+    let crawl = start_crawl(db, category)?;
+    let stored: i64 = db.query_row(
+        "SELECT COUNT(*) FROM scoresaber_songs",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    // The checkpoint is saved before fetching a page because by then the songs of the page before
+    // are stored.
+    let mut offset = resume_offset;
+    let stored_changes = std::cell::Cell::new(0);
+    let stopped = std::cell::Cell::new(false);
+    // The songs of the previous page are counted while they are stored so that the pages stay
+    // streamed.
+    let page_songs = std::rc::Rc::new(std::cell::Cell::new(0));
+    // The number of stored changes before the previous page.
+    let mut previous_changes: Option<usize> = None;
+    let checkpointed = |page| -> Result_<RankedSongsPage<BoxedSongs>> {
+        let previous_songs = page_songs.replace(0);
+        offset += previous_songs;
+        save_checkpoint(db, category, crawl, offset)?;
+        control.page_boundary(offset, |paused| save_paused(db, category, paused))?;
+        if let (true, Some(changes)) = (stop_when_unchanged, previous_changes) {
+            if previous_songs > 0 && stored_changes.get() == changes {
+                stopped.set(true);
+                return Ok(RankedSongsPage {
+                    songs: Box::new(vec![].into_iter()),
+                    last_page: LastPage::Known(true),
+                });
+            }
+        }
+        let fetched = get_page(page)?;
+        previous_changes = Some(stored_changes.get());
+        let counter = page_songs.clone();
+        Ok(RankedSongsPage {
+            songs: Box::new(fetched.songs.inspect(move |song| {
+                if song.is_ok() {
+                    counter.set(counter.get() + 1);
+                }
+            })),
+            last_page: fetched.last_page,
         })
-    })?;
-    for song_result in iter {
-        let song = song_result?;
-        playlist.songs.push(BeatSaberPlaylistSong {
-            name: song.name,
-            hash: song.hash,
-        });
+    };
+    let (mut report, seen) = scrape_all_songs(db, get_ranked_songs(checkpointed), &stored_changes)?;
+    clear_checkpoint(db, category)?;
+    if stopped.get() {
+        println!(
+            "stopped after {} songs because a whole page was unchanged, --full crawls everything",
+            seen.len()
+        );
+    }
+    // The first crawl of a database is the state later crawls are compared to, not thousands of
+    // newly ranked songs.
+    if stored == 0 {
+        report.ranked.clear();
     }
-    Ok(playlist)
+    // Only reached when every page was crawled. A resumed crawl has not seen the songs of the
+    // pages before the checkpoint and a stopped one those after it.
+    let pruned = prune_unranked && resume_offset == 0 && !stopped.get();
+    if prune_unranked && stopped.get() {
+        log::warn!("songs that are no longer ranked were not marked because the crawl stopped early, --full or the first crawl after --full-every-days marks them");
+    }
+    if pruned {
+        report.unranked = prune(db, &seen)?;
+        if !report.unranked.is_empty() {
+            println!(
+                "marked {} songs that are no longer ranked as unranked",
+                report.unranked.len()
+            );
+        }
+    }
+    finish_crawl(db, crawl, pruned)?;
+    report.save(db, crawl)?;
+    if !report.is_empty() {
+        notify::notify_all(notifiers, &report.notification());
+    }
+    progress::stage("players");
+    player::update_tracked_players(db, get_player)?;
+    derived::materialize(db, derived_columns)?;
+    progress::stage("playlist");
+    playlist::make_beatsaber_playlist(db, &playlist::PlaylistOptions::default())
 }
 
-fn save_beatsaber_playlist(playlist: BeatsaberPlaylist) -> Result_<()> {
-    let file = std::fs::File::create("ranked_songs.json")?;
-    serde_json::to_writer_pretty(file, &playlist)?;
-    println!("Used {} songs in playlist.", playlist.songs.len());
-    Ok(())
+#[derive(Debug, StructOpt)]
+#[structopt(
+    about = "Extracts all ranked songs from ScoreSaber into a database and creates a playlist from them. Without a subcommand the songs are scraped and the playlist is saved."
+)]
+struct Options {
+    /// Toml configuration file, for example for notifiers.
+    #[structopt(long, parse(from_os_str))]
+    config: Option<std::path::PathBuf>,
+    /// Order in which the ranked songs are crawled: trending, date-ranked, scores-set (also
+    /// top-played) or star-difficulty.
+    #[structopt(long, default_value = "date-ranked")]
+    category: Category,
+    /// Smallest number of songs per leaderboard page, 100 by default. Pages shrink towards it
+    /// when ScoreSaber is slow or failing.
+    #[structopt(long)]
+    min_page_size: Option<usize>,
+    /// Largest number of songs per leaderboard page. Pages grow towards it while ScoreSaber is
+    /// fast.
+    #[structopt(long, default_value = "1000")]
+    max_page_size: usize,
+    /// Fetches this many leaderboard pages of --max-page-size songs at the same time while the
+    /// songs of earlier pages are stored. Above 1 the page size is not tuned so --min-page-size is
+    /// an error.
+    #[structopt(long, default_value = "1")]
+    concurrent_pages: usize,
+    /// How often a request that failed with a connection error or a 5xx or 429 response is retried.
+    #[structopt(long, default_value = "3")]
+    retries: u64,
+    /// Milliseconds before the first retry. Every further retry waits about twice as long.
+    #[structopt(long, default_value = "1000")]
+    retry_delay_ms: u64,
+    /// Spaces out the requests to ScoreSaber and BeatSaver to at most this many per second, also
+    /// across --concurrent-pages.
+    #[structopt(long)]
+    max_requests_per_second: Option<f64>,
+    /// Spaces out the requests to one host, like `api.beatsaver.com=2` for at most 2 requests per
+    /// second. Can be given for several hosts.
+    #[structopt(long)]
+    host_requests_per_second: Vec<String>,
+    /// Fetches the BeatSaver data and covers of this many maps at the same time. A map that fails is
+    /// skipped instead of ending the enrichment.
+    #[structopt(long, default_value = "1")]
+    enrichment_workers: usize,
+    /// Sends all requests through this http, https or socks5 proxy, like `http://proxy:3128` or
+    /// `socks5://localhost:1080`. Without it HTTPS_PROXY and HTTP_PROXY are used.
+    #[structopt(long)]
+    proxy: Option<String>,
+    /// Seconds to wait for a connection to an api.
+    #[structopt(long, default_value = "10")]
+    connect_timeout: u64,
+    /// Seconds to wait for a response and for every read of its body.
+    #[structopt(long, default_value = "30")]
+    read_timeout: u64,
+    /// Seconds an api request including its retries and the whole body may take.
+    #[structopt(long)]
+    request_deadline: Option<u64>,
+    /// Keeps songs that are missing from the crawl ranked. Otherwise they were unranked or deleted
+    /// and are left out of playlists.
+    #[structopt(long)]
+    no_prune: bool,
+    /// Continues an interrupted crawl of the category after the last stored page instead of
+    /// starting at the first page. Songs are not pruned because the crawl is not complete. On Unix
+    /// SIGUSR1 pauses and resumes a running crawl after its current page and SIGUSR2 aborts it. A
+    /// crawl that was paused when its process ended continues paused without `--resume`.
+    #[structopt(long)]
+    resume: bool,
+    /// Crawls every page. Otherwise a crawl in date-ranked order stops after a page of known songs
+    /// whose stars, difficulty and ranked date are unchanged because the older songs are known too.
+    /// Unranked songs are only found by full crawls, see `--full-every-days`.
+    #[structopt(long)]
+    full: bool,
+    /// Crawls every page anyway when no crawl marked unranked songs in this many days so that they
+    /// leave the playlists.
+    #[structopt(long, default_value = "7")]
+    full_every_days: u64,
+    /// Prints the songs that were newly ranked, reweighted or unranked by the crawl.
+    #[structopt(long)]
+    print_changes: bool,
+    /// Also fetch note counts and max scores from BeatSaver for songs that do not have them yet.
+    #[structopt(long)]
+    beatsaver: bool,
+    /// With --beatsaver also fetches maps again that were fetched more than this many days ago.
+    #[structopt(long)]
+    beatsaver_refresh_days: Option<u64>,
+    /// Also store every score of the tracked players.
+    #[structopt(long)]
+    scores: bool,
+    /// Writes progress events as newline delimited json to stderr.
+    #[structopt(long)]
+    progress_json: bool,
+    /// Writes every raw api response gzip compressed into this directory so that it can be parsed
+    /// again later.
+    #[structopt(long, parse(from_os_str))]
+    archive_responses: Option<std::path::PathBuf>,
+    /// Keeps the api responses with their ETag and Last-Modified headers in this directory and
+    /// reuses them when the api answers that they did not change.
+    #[structopt(long, parse(from_os_str))]
+    http_cache: Option<std::path::PathBuf>,
+    /// Prints where the time of the run was spent at the end.
+    #[structopt(long)]
+    profile: bool,
+    /// Database the songs, scores and history are copied into after every crawl, for websites and
+    /// bots: postgres or sqlite for another file. The crawler keeps working on `beatsaber.sqlite`.
+    /// postgres needs a build with the postgres feature.
+    #[structopt(long, default_value = "sqlite")]
+    backend: storage::Backend,
+    /// Connection string of the backend like `postgresql://crawler@localhost/scoresaber`, or the
+    /// path of the SQLite file.
+    #[structopt(long)]
+    dsn: Option<String>,
+    /// Uses an in-memory database instead of `beatsaber.sqlite`, for one-shot runs that only need
+    /// the playlist. Nothing is kept and batched notifiers send right away.
+    #[structopt(long)]
+    ephemeral: bool,
+    /// Opens the database encrypted with SQLCipher using this key. Needs a build with the
+    /// sqlcipher feature.
+    #[structopt(long)]
+    db_key: Option<String>,
+    #[structopt(flatten)]
+    playlist_output: playlist::PlaylistOutput,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Creates a playlist from the songs in the database without crawling.
+    Playlist {
+        #[structopt(flatten)]
+        options: playlist::PlaylistOptions,
+        /// Defaults to `ranked_songs.json` or `ranked_songs.bplist`.
+        #[structopt(long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+        /// Adds the songs of this existing playlist that are not excluded and keeps its title,
+        /// description and image.
+        #[structopt(long, parse(from_os_str))]
+        merge: Option<std::path::PathBuf>,
+        /// Regenerates every playlist defined in the config instead.
+        #[structopt(long, conflicts_with_all = &["output", "merge"])]
+        all: bool,
+        /// With --all, keeps running and regenerates the playlists whose profile changed whenever
+        /// the config file is saved, and the ones that use a file like an exclude_file when it is.
+        #[structopt(long, requires = "all")]
+        watch: bool,
+    },
+    /// Creates one playlist per star bracket without crawling.
+    Buckets {
+        #[structopt(flatten)]
+        options: playlist::PlaylistOptions,
+        /// Comma separated star boundaries of the brackets. The last bracket has no upper limit.
+        #[structopt(long, use_delimiter = true, default_value = "0,3,5,7,9,11")]
+        boundaries: Vec<f64>,
+        /// Directory the playlists are written to as `ranked_songs_<bracket>.json` or `.bplist`.
+        #[structopt(long, parse(from_os_str), default_value = ".")]
+        directory: std::path::PathBuf,
+    },
+    /// Creates one playlist per mapper without crawling.
+    Mappers {
+        #[structopt(flatten)]
+        options: playlist::PlaylistOptions,
+        /// Only mappers with at least this many songs that pass the filters get a playlist.
+        #[structopt(long, default_value = "5")]
+        min_songs: usize,
+        /// Directory the playlists are written to as `ranked_songs_by_<mapper>.json` or `.bplist`.
+        #[structopt(long, parse(from_os_str), default_value = ".")]
+        directory: std::path::PathBuf,
+    },
+    /// Freezes map pools so that their playlists stay the same when ratings change.
+    Pool(pool::PoolCommand),
+    /// Records the pp and rank of a player now and on every following run.
+    Track { player: player::PlayerId },
+    /// Prints the recorded pp and rank progression of a tracked player.
+    History {
+        player: player::PlayerId,
+        /// Writes the progression as json to this file instead of printing it.
+        #[structopt(long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Prints the first clears of ranked songs of a tracked player found while polling.
+    FirstClears { player: player::PlayerId },
+    /// Prints how the accuracy of a tracked player evolves per star bracket.
+    Report {
+        player: player::PlayerId,
+        /// Comma separated star boundaries of the brackets. The last bracket has no upper limit.
+        #[structopt(long, use_delimiter = true, default_value = "0,3,5,7,9,11")]
+        boundaries: Vec<f64>,
+        /// Writes a practice playlist for the next bracket to this file.
+        #[structopt(long, parse(from_os_str))]
+        playlist: Option<std::path::PathBuf>,
+    },
+    /// Creates a playlist of the songs a tracked player has low accuracy on, ordered by the pp to
+    /// gain.
+    Improve {
+        player: player::PlayerId,
+        /// Accuracy in percent below which a difficulty is included.
+        #[structopt(long, default_value = "92")]
+        acc_below: f64,
+        /// Defaults to `improve_<player>.json` or `.bplist`.
+        #[structopt(long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Creates a playlist of the ranked songs on which a tracked player would gain the most pp.
+    Potential {
+        player: player::PlayerId,
+        /// Accuracy in percent the gain is computed for.
+        #[structopt(long, default_value = "95")]
+        accuracy: f64,
+        /// Number of songs in the playlist.
+        #[structopt(long, default_value = "50")]
+        count: usize,
+        /// Defaults to `potential_<player>.json` or `.bplist`.
+        #[structopt(long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Prints everything the database knows about a song: its difficulties with stars and
+    /// estimated pp, BeatSaver data, the scores of tracked players and links.
+    Show {
+        /// Song hash or leaderboard uid.
+        song: String,
+    },
+    /// Creates a playlist of the songs whose star difficulty changed recently, annotated with the
+    /// old and new stars.
+    Reweighted {
+        /// Only changes in this many last days.
+        #[structopt(long, default_value = "30")]
+        days: u64,
+        /// Defaults to `reweighted.json` or `reweighted.bplist`.
+        #[structopt(long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Prints the changes of the crawls of the last days together with a spotlight on the mappers
+    /// whose first ranked map was ranked in that time.
+    Digest {
+        #[structopt(long, default_value = "7")]
+        days: u64,
+        /// Also sends the digest to the notifiers of the config.
+        #[structopt(long)]
+        notify: bool,
+    },
+    /// Creates a playlist of the ranked songs on which `target` has a better score than `player`
+    /// or `player` has not played, ordered by the pp difference. Both need stored scores.
+    Snipe {
+        player: player::PlayerId,
+        target: player::PlayerId,
+        /// Defaults to `snipe_<target>.json` or `.bplist`.
+        #[structopt(long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Creates a playlist of the songs on which the stored score of a player is old, ordered by the
+    /// weighted pp the score contributes.
+    Stale {
+        player: player::PlayerId,
+        /// Scores set more than this many months ago are old.
+        #[structopt(long, default_value = "6")]
+        months: u32,
+        /// Defaults to `stale_<player>.json` or `.bplist`.
+        #[structopt(long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Periodically stores the recent scores of the tracked players. Runs until stopped. SIGHUP
+    /// reloads the notifiers, the interval and the rate limits from the config between polls.
+    Poll {
+        /// Seconds between polls. `interval` under `[poll]` in the config replaces it.
+        #[structopt(long, default_value = "300")]
+        interval: u64,
+    },
+    /// Inspects the database.
+    Db(db::DbCommand),
+    /// Exports data for use outside of Beat Saber.
+    Export(export::ExportCommand),
+    /// Imports data published by other instances.
+    Import(publish::ImportCommand),
+    /// Publishes data for other tools.
+    Publish(publish::PublishCommand),
+    /// Replays archived api responses through the whole pipeline. Notifications are only printed.
+    Simulate {
+        /// Directory with `get-leaderboards-<page>.json` and `player-<id>.json` responses or one
+        /// written by --archive-responses.
+        #[structopt(long, parse(from_os_str))]
+        corpus: std::path::PathBuf,
+        /// Category the corpus was crawled with.
+        #[structopt(long, default_value = "date-ranked")]
+        category: Category,
+        /// Database to run against instead of an empty in-memory one. It is modified so pass a
+        /// copy to keep the original.
+        #[structopt(long, parse(from_os_str))]
+        database: Option<std::path::PathBuf>,
+        /// Defaults to `simulated_ranked_songs.json` or `simulated_ranked_songs.bplist`.
+        #[structopt(long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
 }
 
 fn main() -> Result_<()> {
     env_logger::init();
-    let db = rusqlite::Connection::open(DATABASE_PATH)?;
-    db.execute(DATABASE_SCHEMA, rusqlite::params![])?;
-    scrape_all_songs(&db)?;
-    save_beatsaber_playlist(make_beatsaber_playlist(&db)?)?;
+    let options = Options::from_args();
+    let start = std::time::Instant::now();
+    if options.progress_json {
+        progress::enable();
+    }
+    if options.profile {
+        profile::enable();
+    }
+    http::configure_retries(
+        options.retries,
+        std::time::Duration::from_millis(options.retry_delay_ms),
+    );
+    http::set_rate_limits(
+        options.max_requests_per_second,
+        &options.host_requests_per_second,
+    )?;
+    workers::set_workers(options.enrichment_workers)?;
+    if let Some(directory) = &options.http_cache {
+        http::enable_cache(directory)?;
+    }
+    if let Some(directory) = &options.archive_responses {
+        archive::enable(directory)?;
+    }
+    let config = config::load(options.config.as_ref().map(|x| x.as_path()))?;
+    let database_file = DatabaseFile {
+        path: DATABASE_PATH.into(),
+        key: options.db_key.clone(),
+    };
+    let mut db = match &options.command {
+        Some(Command::Simulate {
+            database: Some(path),
+            ..
+        }) => open_database(path, options.db_key.as_ref().map(|x| x.as_str()))?,
+        Some(Command::Simulate { database: None, .. }) => rusqlite::Connection::open_in_memory()?,
+        _ if options.ephemeral => rusqlite::Connection::open_in_memory()?,
+        _ => database_file.open()?,
+    };
+    let database_file = if options.ephemeral {
+        None
+    } else {
+        Some(&database_file)
+    };
+    create_schema(&db)?;
+    provenance::resolve(&db, &config.source_precedence)?;
+    derived::materialize(&db, &config.derived_columns)?;
+    http::set_deadline(options.request_deadline.map(std::time::Duration::from_secs));
+    let client = http::client(
+        options.proxy.as_ref().map(|x| x.as_str()),
+        std::time::Duration::from_secs(options.connect_timeout),
+        std::time::Duration::from_secs(options.read_timeout),
+    )?;
+    let notifiers = notify::from_config(&config.notifiers, &client, database_file);
+    let mut backend = storage::open(options.backend, options.dsn.as_ref().map(|x| x.as_str()))?;
+    let playlist_output = &playlist::PlaylistOutput {
+        post_process: config.output.playlists.clone(),
+        // Crawling updates the data first. Map pools are frozen so their data can not be stale.
+        stale_warning: match options.command {
+            None | Some(Command::Simulate { .. }) | Some(Command::Pool(_)) => None,
+            _ => playlist::stale_warning(&db, options.playlist_output.stale_after_days)?,
+        },
+        ..options.playlist_output.clone()
+    };
+    match options.command {
+        None => {
+            let category = options.category;
+            if options.concurrent_pages > 1 && options.min_page_size.is_some() {
+                return Err(
+                    "--concurrent-pages fetches every page with --max-page-size songs \
+                    and can not be combined with --min-page-size",
+                )?;
+            }
+            let mut tuner = paging::PageTuner::new(
+                options.min_page_size.unwrap_or(paging::DEFAULT_MIN_SIZE),
+                options.max_page_size,
+            )?;
+            let mut prefetcher = if options.concurrent_pages > 1 {
+                let client = client.clone();
+                Some(paging::Prefetcher::new(
+                    options.max_page_size,
+                    options.concurrent_pages,
+                    move |limit, page| get_ranked_songs_body(&client, category, limit, page),
+                )?)
+            } else {
+                None
+            };
+            // A paused crawl always continues where it was paused.
+            let checkpoint = match options.resume || crawl_paused(&db, category)? {
+                true => load_checkpoint(&db, category)?,
+                false => None,
+            };
+            let resume_offset = match (checkpoint, prefetcher.as_mut()) {
+                (None, _) => 0,
+                (Some(offset), Some(prefetcher)) => prefetcher.resume_at(offset),
+                (Some(offset), None) => tuner.resume_at(offset),
+            };
+            if resume_offset > 0 {
+                println!("resuming the crawl after {} songs", resume_offset);
+            }
+            let playlist = run_crawl(
+                &db,
+                CrawlOptions {
+                    category,
+                    prune_unranked: !options.no_prune,
+                    resume_offset,
+                    stop_when_unchanged: category == Category::DateRanked
+                        && !options.full
+                        && (options.no_prune || !prune_due(&db, options.full_every_days)?),
+                },
+                &control::Control::register()?,
+                |_| match prefetcher.as_mut() {
+                    Some(prefetcher) => prefetcher.next_page().map(|body| {
+                        boxed_page(extract_ranked_songs_page(
+                            std::io::Cursor::new(body),
+                            options.max_page_size,
+                        ))
+                    }),
+                    None => tuner
+                        .next_page(|limit, page| {
+                            get_ranked_songs_page(&client, category, limit, page)
+                        })
+                        .map(boxed_page),
+                },
+                |id| player::get_player(&client, id),
+                &notifiers,
+                &config.derived_columns,
+            )?;
+            if options.print_changes {
+                print_latest_changes(&db)?;
+            }
+            paging::record(
+                &db,
+                match &prefetcher {
+                    Some(prefetcher) => prefetcher.requests(),
+                    None => tuner.requests(),
+                },
+            )?;
+            if options.beatsaver {
+                progress::stage("beatsaver");
+                beatsaver::enrich(&db, &client, options.beatsaver_refresh_days)?;
+                provenance::resolve(&db, &config.source_precedence)?;
+            }
+            if options.scores {
+                progress::stage("scores");
+                scores::update_scores(&db, &client, &player::tracked_players(&db)?, false)?;
+            }
+            if let Some(backend) = backend.as_mut() {
+                progress::stage("storage");
+                storage::copy_tables(&db, backend.as_mut())?;
+            }
+            let path = playlist_output.default_path("ranked_songs");
+            playlist::save_beatsaber_playlist(playlist, &path, playlist_output)?;
+            notify::flush_all(&notifiers);
+        }
+        Some(Command::Playlist {
+            all: true,
+            watch: true,
+            ..
+        }) => {
+            let path = match &options.config {
+                Some(path) => path,
+                None => return Err("--watch needs a --config file to watch")?,
+            };
+            playlist::watch_profile_playlists(&db, path, || {
+                let config = config::load(Some(path.as_path()))?;
+                let output = playlist::PlaylistOutput {
+                    post_process: config.output.playlists.clone(),
+                    ..playlist_output.clone()
+                };
+                Ok((config.playlists, output))
+            })?
+        }
+        Some(Command::Playlist { all: true, .. }) => {
+            playlist::save_profile_playlists(&db, &config.playlists, playlist_output)?
+        }
+        Some(Command::Playlist {
+            options,
+            output,
+            merge,
+            ..
+        }) => {
+            let mut playlist = playlist::make_beatsaber_playlist(&db, &options)?;
+            let playlist_output = match merge {
+                Some(path) => playlist::PlaylistOutput {
+                    merged_image: playlist::merge(&mut playlist, &path, &options)?,
+                    ..playlist_output.clone()
+                },
+                None => playlist_output.clone(),
+            };
+            let output = output.unwrap_or_else(|| playlist_output.default_path("ranked_songs"));
+            playlist::save_beatsaber_playlist(playlist, &output, &playlist_output)?;
+        }
+        Some(Command::Buckets {
+            options,
+            boundaries,
+            directory,
+        }) => {
+            for (name, playlist) in
+                playlist::make_star_bucket_playlists(&db, &options, &boundaries)?
+            {
+                let path =
+                    directory.join(playlist_output.default_path(&format!("ranked_songs_{}", name)));
+                playlist::save_beatsaber_playlist(playlist, &path, playlist_output)?;
+            }
+        }
+        Some(Command::Mappers {
+            options,
+            min_songs,
+            directory,
+        }) => {
+            for (mapper, playlist) in playlist::make_mapper_playlists(&db, &options, min_songs)? {
+                let path = directory
+                    .join(playlist_output.default_path(&playlist::mapper_file_stem(&mapper)));
+                playlist::save_beatsaber_playlist(playlist, &path, playlist_output)?;
+            }
+        }
+        Some(Command::Pool(command)) => pool::run(&db, command, playlist_output)?,
+        Some(Command::Track { player }) => player::track_player(&db, &client, &player)?,
+        Some(Command::History { player, output }) => player::print_history(
+            &db,
+            &player,
+            output.as_ref().map(|x| x.as_path()),
+            &config.output.history,
+        )?,
+        Some(Command::Report {
+            player,
+            boundaries,
+            playlist,
+        }) => report::bucket_report(
+            &db,
+            &player,
+            &boundaries,
+            playlist.as_ref().map(|x| x.as_path()),
+            playlist_output,
+        )?,
+        Some(Command::Improve {
+            player,
+            acc_below,
+            output,
+        }) => {
+            let playlist = report::improvement_playlist(&db, &player, acc_below)?;
+            let output = output
+                .unwrap_or_else(|| playlist_output.default_path(&format!("improve_{}", player)));
+            playlist::save_beatsaber_playlist(playlist, &output, playlist_output)?;
+        }
+        Some(Command::Potential {
+            player,
+            accuracy,
+            count,
+            output,
+        }) => {
+            let playlist = report::potential_playlist(&db, &player, accuracy, count)?;
+            let output = output
+                .unwrap_or_else(|| playlist_output.default_path(&format!("potential_{}", player)));
+            playlist::save_beatsaber_playlist(playlist, &output, playlist_output)?;
+        }
+        Some(Command::Show { song }) => show::run(&db, &song)?,
+        Some(Command::Reweighted { days, output }) => {
+            let playlist = report::reweighted_playlist(&db, days)?;
+            let output = output.unwrap_or_else(|| playlist_output.default_path("reweighted"));
+            playlist::save_beatsaber_playlist(playlist, &output, playlist_output)?;
+        }
+        Some(Command::Digest { days, notify }) => {
+            let digest = changes::digest(&db, days)?;
+            match digest.text().as_str() {
+                "" => println!("nothing changed in the last {} days", days),
+                text => println!("{}", text),
+            }
+            if notify {
+                notify::notify_all(&notifiers, &digest.notification());
+                notify::flush_all(&notifiers);
+            }
+        }
+        Some(Command::Snipe {
+            player,
+            target,
+            output,
+        }) => {
+            let playlist = report::snipe_playlist(&db, &player, &target)?;
+            let output = output
+                .unwrap_or_else(|| playlist_output.default_path(&format!("snipe_{}", target)));
+            playlist::save_beatsaber_playlist(playlist, &output, playlist_output)?;
+        }
+        Some(Command::Stale {
+            player,
+            months,
+            output,
+        }) => {
+            let playlist = report::stale_playlist(&db, &player, months)?;
+            let output = output
+                .unwrap_or_else(|| playlist_output.default_path(&format!("stale_{}", player)));
+            playlist::save_beatsaber_playlist(playlist, &output, playlist_output)?;
+        }
+        Some(Command::FirstClears { player }) => scores::print_first_clears(&db, &player)?,
+        Some(Command::Poll { interval }) => {
+            scores::poll(&db, &client, &reload::Signal::register()?, || {
+                let config = config::load(options.config.as_ref().map(|x| x.as_path()))?;
+                let host_requests_per_second = match config.poll.host_requests_per_second.is_empty()
+                {
+                    true => options.host_requests_per_second.clone(),
+                    false => config.poll.host_requests_per_second,
+                };
+                Ok(scores::PollSettings {
+                    interval: std::time::Duration::from_secs(
+                        config.poll.interval.unwrap_or(interval),
+                    ),
+                    notifiers: notify::from_config(&config.notifiers, &client, database_file),
+                    max_requests_per_second: config
+                        .poll
+                        .max_requests_per_second
+                        .or(options.max_requests_per_second),
+                    host_requests_per_second,
+                })
+            })?
+        }
+        Some(Command::Db(command)) => db::run(&mut db, command, &config.source_precedence)?,
+        Some(Command::Export(command)) => {
+            export::run(&db, &client, command, &config.output.exports)?
+        }
+        Some(Command::Import(command)) => {
+            publish::run_import(&db, &client, command)?;
+            provenance::resolve(&db, &config.source_precedence)?;
+        }
+        Some(Command::Publish(command)) => publish::run(&db, command, &config.output.dataset())?,
+        Some(Command::Simulate {
+            corpus,
+            category,
+            output,
+            ..
+        }) => {
+            let corpus = corpus::Corpus::open(&corpus, category)?;
+            let dry_run: Vec<Box<dyn notify::Notifier>> = vec![Box::new(notify::StdoutNotifier)];
+            let playlist = run_crawl(
+                &db,
+                CrawlOptions {
+                    category,
+                    prune_unranked: !options.no_prune,
+                    resume_offset: 0,
+                    stop_when_unchanged: category == Category::DateRanked
+                        && !options.full
+                        && (options.no_prune || !prune_due(&db, options.full_every_days)?),
+                },
+                &control::Control::default(),
+                |page| corpus.get_ranked_songs_page(page),
+                |id| corpus.get_player(id),
+                &dry_run,
+                &config.derived_columns,
+            )?;
+            if options.print_changes {
+                print_latest_changes(&db)?;
+            }
+            let output =
+                output.unwrap_or_else(|| playlist_output.default_path("simulated_ranked_songs"));
+            playlist::save_beatsaber_playlist(playlist, &output, playlist_output)?;
+        }
+    }
+    profile::print_summary(start.elapsed());
     db.close().map_err(|x| x.1.into())
 }
 
+// The songs of test_data/get-leaderboards.json.
+#[cfg(test)]
+fn test_songs() -> Vec<ScoreSaberSong> {
+    extract_ranked_songs_page(&include_bytes!("../test_data/get-leaderboards.json")[..], 0)
+        .songs
+        .collect::<Result_<_>>()
+        .unwrap()
+}
+
+// An in-memory database with the test songs.
+#[cfg(test)]
+fn test_db() -> rusqlite::Connection {
+    let db = rusqlite::Connection::open_in_memory().unwrap();
+    create_schema(&db).unwrap();
+    for song in test_songs() {
+        insert_song_into_db(&db, &song).unwrap();
+    }
+    db
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -267,6 +2075,10 @@ mod tests {
                 beats_per_minute: 226,
                 difficulty: "_ExpertPlus_SoloStandard".to_string(),
                 star_difficulty: 1.0,
+                plays: 1751,
+                daily_plays: 45,
+                ranked_date: None,
+                qualified_date: None,
             },
             ScoreSaberSong {
                 uid: 101208,
@@ -278,6 +2090,10 @@ mod tests {
                 beats_per_minute: 226,
                 difficulty: "_ExpertPlus_SoloStandard".to_string(),
                 star_difficulty: 9.72,
+                plays: 1751,
+                daily_plays: 45,
+                ranked_date: None,
+                qualified_date: None,
             },
             ScoreSaberSong {
                 uid: 109086,
@@ -289,6 +2105,10 @@ mod tests {
                 beats_per_minute: 255,
                 difficulty: "_ExpertPlus_SoloStandard".to_string(),
                 star_difficulty: 10.08,
+                plays: 954,
+                daily_plays: 39,
+                ranked_date: None,
+                qualified_date: None,
             },
             ScoreSaberSong {
                 uid: 100024,
@@ -300,38 +2120,662 @@ mod tests {
                 beats_per_minute: 199,
                 difficulty: "_ExpertPlus_SoloStandard".to_string(),
                 star_difficulty: 9.38,
+                plays: 1673,
+                daily_plays: 52,
+                ranked_date: None,
+                qualified_date: None,
             },
         ];
     }
 
+    #[test]
+    fn test_migrate() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        let version = |db: &rusqlite::Connection| -> i64 {
+            db.query_row(
+                "SELECT version FROM schema_version",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        let migrations = &[
+            "CREATE TABLE a (x INTEGER);",
+            "ALTER TABLE a ADD COLUMN y INTEGER;",
+        ];
+        migrate(&db, &migrations[..1]).unwrap();
+        assert_eq!(version(&db), 1);
+        migrate(&db, migrations).unwrap();
+        assert_eq!(version(&db), 2);
+        assert!(has_column(&db, "a", "y").unwrap());
+        // Running again applies nothing.
+        migrate(&db, migrations).unwrap();
+        assert!(migrate(&db, &migrations[..1]).is_err());
+        // A failing migration is rolled back.
+        let failing = &[
+            migrations[0],
+            migrations[1],
+            "CREATE TABLE b (x); SELECT * FROM missing;",
+        ];
+        assert!(migrate(&db, failing).is_err());
+        assert_eq!(version(&db), 2);
+        assert!(!has_column(&db, "b", "x").unwrap());
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_views() {
+        let db = test_db();
+        db.execute(
+            "UPDATE scoresaber_songs SET ranked = 0 WHERE uid = ?",
+            rusqlite::params![SONGS[2].uid],
+        )
+        .unwrap();
+        let count = |view: &str| -> i64 {
+            db.query_row(
+                &format!("SELECT COUNT(*) FROM {}", view),
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(count("latest_songs"), 3);
+        assert_eq!(count("per_hash_max_stars"), 2);
+        assert_eq!(count("player_best_per_map"), 0);
+    }
+
     #[test]
     fn test_extract_ranked_songs_page() {
         let result =
-            extract_ranked_songs_page(&include_bytes!("../test_data/get-leaderboards.json")[..], 3)
-                .unwrap();
-        assert_eq!(result.last_page, false);
-        assert_eq!(result.songs.collect::<Vec<ScoreSaberSong>>()[..], SONGS[..]);
+            extract_ranked_songs_page(&include_bytes!("../test_data/get-leaderboards.json")[..], 3);
+        assert_eq!(result.last_page, LastPage::BelowLimit(3));
+        assert_eq!(
+            result
+                .songs
+                .collect::<Result_<Vec<ScoreSaberSong>>>()
+                .unwrap()[..],
+            SONGS[..]
+        );
+    }
+
+    #[test]
+    fn test_get_ranked_songs() {
+        // A full page of 4 songs is followed by `second`.
+        let crawl = |second: &'static [u8]| {
+            let mut pages = 0;
+            let songs = get_ranked_songs(|page| {
+                pages += 1;
+                Ok(boxed_page(match page {
+                    1 => extract_ranked_songs_page(
+                        &include_bytes!("../test_data/get-leaderboards.json")[..],
+                        4,
+                    ),
+                    _ => extract_ranked_songs_page(second, 4),
+                }))
+            })
+            .map(|song| song.is_ok())
+            .collect::<Vec<bool>>();
+            (songs, pages)
+        };
+        assert_eq!(crawl(b"{\"songs\": []}"), (vec![true; 4], 2));
+        // The songs of a page that fails to be read end with the error.
+        assert_eq!(
+            crawl(b"{\"songs\": [1]}"),
+            (vec![true, true, true, true, false], 2)
+        );
     }
 
     #[test]
     fn test_into_database_to_playlist() {
         let db = rusqlite::Connection::open_in_memory().unwrap();
-        db.execute(DATABASE_SCHEMA, rusqlite::params![]).unwrap();
+        create_schema(&db).unwrap();
         for song in SONGS.iter() {
             insert_song_into_db(&db, song).unwrap();
         }
-        let playlist = make_beatsaber_playlist(&db).unwrap();
+        let playlist =
+            playlist::make_beatsaber_playlist(&db, &playlist::PlaylistOptions::default()).unwrap();
         // Remove first song because it is lower difficulty duplicate of second.
         let mut expected_songs = SONGS[1..].to_owned();
         expected_songs.sort_by(|x, y| y.star_difficulty.partial_cmp(&x.star_difficulty).unwrap());
         let expected_playlist = expected_songs
             .iter()
-            .map(|x| BeatSaberPlaylistSong {
-                name: x.name.clone(),
-                hash: x.id.clone(),
+            .map(|x| {
+                let mut difficulties = SONGS.iter().filter(|y| y.id == x.id).collect::<Vec<_>>();
+                difficulties
+                    .sort_by(|y, z| z.star_difficulty.partial_cmp(&y.star_difficulty).unwrap());
+                playlist::BeatSaberPlaylistSong {
+                    name: x.name.clone(),
+                    hash: x.id.clone(),
+                    difficulties: difficulties
+                        .iter()
+                        .map(|y| playlist::PlaylistDifficulty::from_diff(&y.difficulty).unwrap())
+                        .collect(),
+                }
             })
-            .collect::<Vec<BeatSaberPlaylistSong>>();
+            .collect::<Vec<playlist::BeatSaberPlaylistSong>>();
         assert_eq!(playlist.songs, expected_playlist);
         db.close().unwrap();
     }
+
+    #[test]
+    fn test_parse_difficulty() {
+        assert_eq!(
+            parse_difficulty("_ExpertPlus_SoloStandard"),
+            Some(("ExpertPlus", "Standard"))
+        );
+        assert_eq!(
+            parse_difficulty("_Hard_Solo90Degree"),
+            Some(("Hard", "90Degree"))
+        );
+        assert_eq!(parse_difficulty("Hard"), None);
+    }
+
+    #[test]
+    fn test_ranking_changes() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&db).unwrap();
+        let mut song = SONGS[2].clone();
+        insert_song_into_db(&db, &song).unwrap();
+        insert_song_into_db(&db, &song).unwrap();
+        song.star_difficulty = 10.5;
+        insert_song_into_db(&db, &song).unwrap();
+        let changes: Vec<(f64, f64)> = db
+            .prepare("SELECT old_stars, new_stars FROM ranking_changes")
+            .unwrap()
+            .query_map(rusqlite::params![], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(changes, vec![(10.08, 10.5)]);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_song_history() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&db).unwrap();
+        let mut song = SONGS[2].clone();
+        insert_song_into_db(&db, &song).unwrap();
+        insert_song_into_db(&db, &song).unwrap();
+        song.star_difficulty = 10.5;
+        insert_song_into_db(&db, &song).unwrap();
+        song.name = "Milk Crown".to_string();
+        insert_song_into_db(&db, &song).unwrap();
+        let history: Vec<(String, f64)> = db
+            .prepare(
+                "SELECT name, stars FROM scoresaber_songs_history WHERE uid = ? ORDER BY rowid",
+            )
+            .unwrap()
+            .query_map(rusqlite::params![song.uid as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            history,
+            vec![
+                (SONGS[2].name.clone(), 10.08),
+                (SONGS[2].name.clone(), 10.5),
+                ("Milk Crown".to_string(), 10.5),
+            ]
+        );
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_resume() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&db).unwrap();
+        // Pages of two songs starting at `start` that fail at `fail_at`.
+        let pages = |start: usize, fail_at: Option<usize>| {
+            let mut offset = start;
+            move |_: u64| -> Result_<RankedSongsPage<BoxedSongs>> {
+                if Some(offset) == fail_at {
+                    return Err("unavailable")?;
+                }
+                let songs = SONGS[offset.min(SONGS.len())..(offset + 2).min(SONGS.len())].to_vec();
+                offset += 2;
+                Ok(RankedSongsPage {
+                    last_page: LastPage::Known(songs.len() < 2),
+                    songs: Box::new(songs.into_iter().map(Ok)),
+                })
+            }
+        };
+        let no_player = |_: &str| -> Result_<player::Player> { Err("no players")? };
+        let crawl = |resume_offset| CrawlOptions {
+            category: Category::DateRanked,
+            prune_unranked: true,
+            resume_offset,
+            stop_when_unchanged: false,
+        };
+        assert!(run_crawl(
+            &db,
+            crawl(0),
+            &control::Control::default(),
+            pages(0, Some(2)),
+            no_player,
+            &[],
+            &[]
+        )
+        .is_err());
+        assert_eq!(load_checkpoint(&db, Category::DateRanked).unwrap(), Some(2));
+        assert_eq!(load_checkpoint(&db, Category::Trending).unwrap(), None);
+        run_crawl(
+            &db,
+            crawl(2),
+            &control::Control::default(),
+            pages(2, None),
+            no_player,
+            &[],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(load_checkpoint(&db, Category::DateRanked).unwrap(), None);
+        // A crawl that was paused when its process ended starts paused and does not fetch a page
+        // until it is resumed. Aborting it keeps its checkpoint but not the paused state.
+        save_checkpoint(&db, Category::DateRanked, 1, 2).unwrap();
+        save_paused(&db, Category::DateRanked, true).unwrap();
+        let control = control::Control::default();
+        control.abort();
+        let requests = std::cell::Cell::new(0);
+        assert!(run_crawl(
+            &db,
+            crawl(2),
+            &control,
+            |_| -> Result_<RankedSongsPage<BoxedSongs>> {
+                requests.set(requests.get() + 1);
+                Err("unavailable")?
+            },
+            no_player,
+            &[],
+            &[]
+        )
+        .is_err());
+        assert_eq!(requests.get(), 0);
+        assert!(!crawl_paused(&db, Category::DateRanked).unwrap());
+        assert_eq!(load_checkpoint(&db, Category::DateRanked).unwrap(), Some(2));
+        // The songs stored before the interruption are not pruned.
+        let ranked: i64 = db
+            .query_row(
+                "SELECT COUNT(*) FROM scoresaber_songs WHERE ranked = 1",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(ranked, 3);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_stop_when_unchanged() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&db).unwrap();
+        let requests = std::cell::Cell::new(0);
+        let daily_plays = std::cell::Cell::new(0);
+        // Pages of one song. The first song is left out because it shares its uid with the second.
+        let get_page = |page: u64| -> Result_<RankedSongsPage<BoxedSongs>> {
+            requests.set(requests.get() + 1);
+            let songs = SONGS[1..]
+                .iter()
+                .skip(page as usize - 1)
+                .take(1)
+                .map(|song| ScoreSaberSong {
+                    daily_plays: song.daily_plays + daily_plays.get(),
+                    ..song.clone()
+                })
+                .collect::<Vec<_>>();
+            Ok(RankedSongsPage {
+                last_page: LastPage::Known(songs.is_empty()),
+                songs: Box::new(songs.into_iter().map(Ok)),
+            })
+        };
+        let no_player = |_: &str| -> Result_<player::Player> { Err("no players")? };
+        let options = CrawlOptions {
+            category: Category::DateRanked,
+            prune_unranked: true,
+            resume_offset: 0,
+            stop_when_unchanged: true,
+        };
+        run_crawl(
+            &db,
+            options,
+            &control::Control::default(),
+            get_page,
+            no_player,
+            &[],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(requests.get(), 4);
+        assert!(!prune_due(&db, 7).unwrap());
+        requests.set(0);
+        run_crawl(
+            &db,
+            options,
+            &control::Control::default(),
+            get_page,
+            no_player,
+            &[],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(requests.get(), 1);
+        // Songs whose play counts changed are still unchanged.
+        daily_plays.set(10);
+        requests.set(0);
+        run_crawl(
+            &db,
+            options,
+            &control::Control::default(),
+            get_page,
+            no_player,
+            &[],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(requests.get(), 1);
+        // Stopped crawls do not prune the songs they did not see and do not count as pruning.
+        db.execute(
+            "UPDATE crawls SET finished_at = datetime('now', '-8 days') WHERE pruned = 1",
+            rusqlite::params![],
+        )
+        .unwrap();
+        assert!(prune_due(&db, 7).unwrap());
+        let ranked: i64 = db
+            .query_row(
+                "SELECT COUNT(*) FROM scoresaber_songs WHERE ranked = 1",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(ranked, 3);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_prune() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&db).unwrap();
+        for song in SONGS.iter() {
+            insert_song_into_db(&db, song).unwrap();
+        }
+        // The first two songs share a uid.
+        let seen = SONGS[2..]
+            .iter()
+            .map(|song| song.uid as i64)
+            .collect::<std::collections::HashSet<i64>>();
+        assert_eq!(prune(&db, &seen).unwrap().len(), 1);
+        assert_eq!(prune(&db, &seen).unwrap().len(), 0);
+        let unranked: Vec<i64> = db
+            .prepare("SELECT uid FROM scoresaber_songs WHERE ranked = 0")
+            .unwrap()
+            .query_map(rusqlite::params![], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(unranked, vec![SONGS[0].uid as i64]);
+        // Reappearing songs are ranked again.
+        assert_eq!(
+            insert_song_into_db(&db, &SONGS[0]).unwrap().0,
+            Stored::Reranked
+        );
+        assert_eq!(prune(&db, &seen).unwrap().len(), 1);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_first_seen() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&db).unwrap();
+        let song = &SONGS[2];
+        assert_eq!(insert_song_into_db(&db, song).unwrap().0, Stored::New);
+        db.execute(
+            "UPDATE scoresaber_songs SET first_seen = '2019-01-01 00:00:00', last_updated = '2019-01-01 00:00:00'",
+            rusqlite::params![],
+        )
+        .unwrap();
+        let dates = |db: &rusqlite::Connection| -> (String, String) {
+            db.query_row(
+                "SELECT first_seen, last_updated FROM scoresaber_songs WHERE uid = ?",
+                rusqlite::params![song.uid as i64],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+        // Identical songs are not touched.
+        assert_eq!(insert_song_into_db(&db, song).unwrap().0, Stored::Identical);
+        assert_eq!(dates(&db).1, "2019-01-01 00:00:00");
+        let mut played = song.clone();
+        played.plays += 1;
+        assert_eq!(
+            insert_song_into_db(&db, &played).unwrap().0,
+            Stored::Refreshed
+        );
+        let (first_seen, last_updated) = dates(&db);
+        assert_eq!(first_seen, "2019-01-01 00:00:00");
+        assert_ne!(last_updated, "2019-01-01 00:00:00");
+        played.star_difficulty += 1.0;
+        assert_eq!(
+            insert_song_into_db(&db, &played).unwrap().0,
+            Stored::Changed
+        );
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_indexes() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&db).unwrap();
+        let plan: Vec<String> = db
+            .prepare("EXPLAIN QUERY PLAN SELECT * FROM scoresaber_songs WHERE id = ?")
+            .unwrap()
+            .query_map(rusqlite::params!["7719B8DE"], |row| row.get(3))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert!(plan.iter().any(|x| x.contains("scoresaber_songs_id")));
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_open_database() {
+        let path = std::env::temp_dir().join("scoresaber-crawler-test-open.sqlite");
+        let _ = std::fs::remove_file(&path);
+        let db = open_database(&path, None).unwrap();
+        let journal_mode: String = db
+            .query_row("PRAGMA journal_mode", rusqlite::params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+        create_schema(&db).unwrap();
+        // A second connection can read while the first one writes.
+        db.execute_batch("BEGIN; DELETE FROM scoresaber_songs;")
+            .unwrap();
+        let other = open_database(&path, None).unwrap();
+        let songs: i64 = other
+            .query_row(
+                "SELECT COUNT(*) FROM scoresaber_songs",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(songs, 0);
+        db.execute_batch("COMMIT").unwrap();
+        other.close().unwrap();
+        db.close().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_database_key() {
+        let path = std::env::temp_dir().join("scoresaber-crawler-test-key.sqlite");
+        let _ = std::fs::remove_file(&path);
+        if cfg!(feature = "sqlcipher") {
+            let db = open_database(&path, Some("it's secret")).unwrap();
+            create_schema(&db).unwrap();
+            db.close().unwrap();
+            assert!(open_database(&path, Some("it's secret")).is_ok());
+            assert!(open_database(&path, Some("wrong")).is_err());
+        } else {
+            assert!(open_database(&path, Some("it's secret")).is_err());
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_best_difficulties() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&db).unwrap();
+        for song in SONGS.iter() {
+            insert_song_into_db(&db, song).unwrap();
+        }
+        let best = |db: &rusqlite::Connection| -> Vec<(String, f64)> {
+            db.prepare("SELECT hash, stars FROM best_difficulties ORDER BY stars DESC")
+                .unwrap()
+                .query_map(rusqlite::params![], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap()
+        };
+        assert_eq!(
+            best(&db),
+            vec![
+                (SONGS[2].id.clone(), 10.08),
+                (SONGS[1].id.clone(), 9.72),
+                (SONGS[3].id.clone(), 9.38),
+            ]
+        );
+        // The playlist from best_difficulties is the same as the grouped one.
+        let fast =
+            playlist::make_beatsaber_playlist(&db, &playlist::PlaylistOptions::default()).unwrap();
+        let grouped = playlist::make_beatsaber_playlist(
+            &db,
+            &playlist::PlaylistOptions {
+                min_stars: Some(0.0),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(fast.songs, grouped.songs);
+        db.execute(
+            "UPDATE scoresaber_songs SET ranked = 0 WHERE uid = ?",
+            rusqlite::params![SONGS[1].uid as i64],
+        )
+        .unwrap();
+        assert_eq!(
+            best(&db),
+            vec![(SONGS[2].id.clone(), 10.08), (SONGS[3].id.clone(), 9.38)]
+        );
+        db.execute(
+            "DELETE FROM scoresaber_songs WHERE id = ?",
+            rusqlite::params![SONGS[3].id],
+        )
+        .unwrap();
+        assert_eq!(best(&db), vec![(SONGS[2].id.clone(), 10.08)]);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_difficulty_columns() {
+        assert_eq!(
+            difficulty_columns("_ExpertPlus_SoloStandard"),
+            Some((Difficulty::ExpertPlus, "Standard"))
+        );
+        assert_eq!(
+            difficulty_columns("_Hard_Solo90Degree"),
+            Some((Difficulty::Hard, "90Degree"))
+        );
+        assert_eq!(difficulty_columns("_Unknown_SoloStandard"), None);
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&db).unwrap();
+        insert_song_into_db(&db, &SONGS[2]).unwrap();
+        let columns = |db: &rusqlite::Connection| -> (String, String) {
+            db.query_row(
+                "SELECT difficulty, characteristic FROM scoresaber_songs",
+                rusqlite::params![],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+        let expected = ("ExpertPlus".to_string(), "Standard".to_string());
+        assert_eq!(columns(&db), expected);
+        // Songs stored before the columns existed.
+        db.execute_batch("UPDATE scoresaber_songs SET difficulty = NULL, characteristic = NULL")
+            .unwrap();
+        update_missing_difficulties(&db).unwrap();
+        assert_eq!(columns(&db), expected);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_ranked_date() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        create_schema(&db).unwrap();
+        let mut song: ScoreSaberSong = serde_json::from_str(
+            r#"{"uid": 109086, "id": "CFCA2FE00BCC418DC9ECF64D92FC01CEEC52C375", "name": "Milk Crown on Sonnetica", "songSubName": "", "songAuthorName": "nameless", "levelAuthorName": "Hexagonial", "bpm": 255, "diff": "_ExpertPlus_SoloStandard", "scores": "954", "scores_day": 39, "stars": 10.08, "rankedDate": "2019-05-23T11:42:12.000Z", "qualifiedDate": "2019-05-20T08:00:00.000Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            song.ranked_date.as_ref().unwrap(),
+            "2019-05-23T11:42:12.000Z"
+        );
+        insert_song_into_db(&db, &song).unwrap();
+        // A response without the dates keeps the stored ones.
+        song.ranked_date = None;
+        song.qualified_date = None;
+        insert_song_into_db(&db, &song).unwrap();
+        // Songs of the first crawl without a date, the baseline, could have been ranked any time.
+        insert_song_into_db(&db, &SONGS[1]).unwrap();
+        db.execute_batch(
+            "UPDATE scoresaber_songs SET first_seen = datetime('now', '-2 days'); INSERT INTO crawls (category, started_at, finished_at) VALUES ('date-ranked', datetime('now', '-2 days'), datetime('now', '-1 days'));",
+        )
+        .unwrap();
+        insert_song_into_db(&db, &SONGS[3]).unwrap();
+        let dates: (String, String) = db
+            .query_row(
+                "SELECT ranked_at, qualified_at FROM scoresaber_songs WHERE uid = ?",
+                rusqlite::params![song.uid as i64],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            dates,
+            (
+                "2019-05-23 11:42:12".to_string(),
+                "2019-05-20 08:00:00".to_string()
+            )
+        );
+        // Songs without a ranked date that a later crawl found count from when they were first
+        // seen.
+        let options = playlist::PlaylistOptions {
+            ranked_within_days: Some(7),
+            ..Default::default()
+        };
+        let playlist = playlist::make_beatsaber_playlist(&db, &options).unwrap();
+        assert_eq!(playlist.songs.len(), 1);
+        assert_eq!(playlist.songs[0].hash, SONGS[3].id);
+        for (since, songs) in &[("2019-05-01", 2), ("2019-05-23 12:00:00", 1), ("1m", 1)] {
+            let options = playlist::PlaylistOptions {
+                ranked_since: Some(since.parse().unwrap()),
+                ..Default::default()
+            };
+            let playlist = playlist::make_beatsaber_playlist(&db, &options).unwrap();
+            assert_eq!(playlist.songs.len(), *songs, "{}", since);
+        }
+        let options = playlist::PlaylistOptions {
+            sort: "ranked".parse().unwrap(),
+            ..Default::default()
+        };
+        let playlist = playlist::make_beatsaber_playlist(&db, &options).unwrap();
+        let hashes = playlist
+            .songs
+            .iter()
+            .map(|song| song.hash.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            hashes,
+            [SONGS[3].id.as_str(), song.id.as_str(), SONGS[1].id.as_str()]
+        );
+        db.close().unwrap();
+    }
 }
@@ -0,0 +1,338 @@
+use crate::Result_;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+}
+
+pub trait Notifier {
+    fn notify(&self, notification: &Notification) -> Result_<()>;
+
+    // Sends batched notifications that are due. Called at the end of every run.
+    fn flush(&self) -> Result_<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum NotifierConfig {
+    Discord {
+        webhook_url: String,
+    },
+    // Posts the notification as a json object with `title` and `body` fields.
+    Webhook {
+        url: String,
+    },
+    // Hands the mail to a sendmail compatible program so that we do not need to speak smtp.
+    Email {
+        to: String,
+        #[serde(default = "default_sendmail")]
+        sendmail: String,
+    },
+    Stdout,
+    // Collects the notifications for `notifier` in the database and sends them as one summary once
+    // the oldest has waited `window_hours`, for example one Discord message per day. `name`
+    // identifies the collected notifications across runs.
+    Batched {
+        name: String,
+        window_hours: u64,
+        notifier: Box<NotifierConfig>,
+    },
+}
+
+fn default_sendmail() -> String {
+    "sendmail".to_string()
+}
+
+// `database` is where batched notifiers keep their notifications. Without one they send every
+// notification right away.
+pub fn from_config(
+    configs: &[NotifierConfig],
+    client: &reqwest::Client,
+    database: Option<&crate::DatabaseFile>,
+) -> Vec<Box<dyn Notifier>> {
+    configs
+        .iter()
+        .map(|config| notifier(config, client, database))
+        .collect()
+}
+
+fn notifier(
+    config: &NotifierConfig,
+    client: &reqwest::Client,
+    database: Option<&crate::DatabaseFile>,
+) -> Box<dyn Notifier> {
+    match config {
+        NotifierConfig::Discord { webhook_url } => Box::new(DiscordNotifier {
+            client: client.clone(),
+            webhook_url: webhook_url.clone(),
+        }),
+        NotifierConfig::Webhook { url } => Box::new(WebhookNotifier {
+            client: client.clone(),
+            url: url.clone(),
+        }),
+        NotifierConfig::Email { to, sendmail } => Box::new(EmailNotifier {
+            to: to.clone(),
+            sendmail: sendmail.clone(),
+        }),
+        NotifierConfig::Stdout => Box::new(StdoutNotifier),
+        NotifierConfig::Batched {
+            name,
+            window_hours,
+            notifier: inner,
+        } => match database {
+            Some(database) => Box::new(BatchedNotifier {
+                name: name.clone(),
+                window_hours: *window_hours,
+                notifier: notifier(inner, client, Some(database)),
+                database: database.clone(),
+            }),
+            None => notifier(inner, client, None),
+        },
+    }
+}
+
+// A failing notifier must not fail the run that produced the notification so errors are only
+// logged.
+pub fn notify_all(notifiers: &[Box<dyn Notifier>], notification: &Notification) {
+    for notifier in notifiers {
+        if let Err(err) = notifier.notify(notification) {
+            log::warn!("failed to send notification: {}", err);
+        }
+    }
+}
+
+pub fn flush_all(notifiers: &[Box<dyn Notifier>]) {
+    for notifier in notifiers {
+        if let Err(err) = notifier.flush() {
+            log::warn!("failed to send batched notifications: {}", err);
+        }
+    }
+}
+
+fn post_json(client: &reqwest::Client, url: &str, body: &serde_json::Value) -> Result_<()> {
+    let response = crate::http::send(|| client.post(url).json(body))?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "response status code does not indiciate success: {}",
+            response.status()
+        ))?
+    }
+}
+
+pub struct DiscordNotifier {
+    client: reqwest::Client,
+    webhook_url: String,
+}
+
+impl Notifier for DiscordNotifier {
+    fn notify(&self, notification: &Notification) -> Result_<()> {
+        // Discord rejects messages longer than 2000 characters.
+        let content: String = format!("**{}**\n{}", notification.title, notification.body)
+            .chars()
+            .take(2000)
+            .collect();
+        post_json(
+            &self.client,
+            &self.webhook_url,
+            &serde_json::json!({ "content": content }),
+        )
+    }
+}
+
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, notification: &Notification) -> Result_<()> {
+        post_json(
+            &self.client,
+            &self.url,
+            &serde_json::json!({ "title": notification.title, "body": notification.body }),
+        )
+    }
+}
+
+pub struct EmailNotifier {
+    to: String,
+    sendmail: String,
+}
+
+impl Notifier for EmailNotifier {
+    fn notify(&self, notification: &Notification) -> Result_<()> {
+        use std::io::Write;
+        let mut child = std::process::Command::new(&self.sendmail)
+            .arg("-t")
+            .stdin(std::process::Stdio::piped())
+            .spawn()?;
+        {
+            // Dropping stdin closes it which tells sendmail that the mail is complete.
+            let mut stdin = child.stdin.take().ok_or("sendmail has no stdin")?;
+            write!(
+                stdin,
+                "To: {}\nSubject: {}\n\n{}\n",
+                self.to, notification.title, notification.body
+            )?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(format!("sendmail exited with {}", status))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct StdoutNotifier;
+
+impl Notifier for StdoutNotifier {
+    fn notify(&self, notification: &Notification) -> Result_<()> {
+        println!("{}\n{}", notification.title, notification.body);
+        Ok(())
+    }
+}
+
+// Opens its own connection because notifiers are created independently of the database of a
+// command.
+pub struct BatchedNotifier {
+    name: String,
+    window_hours: u64,
+    notifier: Box<dyn Notifier>,
+    database: crate::DatabaseFile,
+}
+
+impl Notifier for BatchedNotifier {
+    fn notify(&self, notification: &Notification) -> Result_<()> {
+        let db = self.database.open()?;
+        queue(&db, &self.name, notification)
+    }
+
+    fn flush(&self) -> Result_<()> {
+        let db = self.database.open()?;
+        flush_batch(&db, &self.name, self.window_hours, self.notifier.as_ref())
+    }
+}
+
+fn queue(db: &rusqlite::Connection, channel: &str, notification: &Notification) -> Result_<()> {
+    db.execute(
+        "INSERT INTO pending_notifications (channel, title, body, queued_at) VALUES (?,?,?,datetime('now'))",
+        rusqlite::params![channel, notification.title, notification.body],
+    )?;
+    Ok(())
+}
+
+// Sends every pending notification of the channel as one if the oldest has waited for the window.
+fn flush_batch(
+    db: &rusqlite::Connection,
+    channel: &str,
+    window_hours: u64,
+    notifier: &dyn Notifier,
+) -> Result_<()> {
+    let due: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM pending_notifications WHERE channel = ? AND queued_at <= datetime('now', ?)",
+        rusqlite::params![channel, format!("-{} hours", window_hours)],
+        |row| row.get(0),
+    )?;
+    if !due {
+        return Ok(());
+    }
+    let mut statement = db.prepare(
+        "SELECT id, title, body FROM pending_notifications WHERE channel = ? ORDER BY id",
+    )?;
+    let pending = statement
+        .query_map(rusqlite::params![channel], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                Notification {
+                    title: row.get(1)?,
+                    body: row.get(2)?,
+                },
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let summary = match &pending[..] {
+        [(_, notification)] => notification.clone(),
+        _ => Notification {
+            title: format!("{} notifications", pending.len()),
+            body: pending
+                .iter()
+                .map(|(_, x)| format!("{}\n{}", x.title, x.body))
+                .collect::<Vec<String>>()
+                .join("\n\n"),
+        },
+    };
+    notifier.notify(&summary)?;
+    // Notifications queued while sending stay for the next batch.
+    let last = pending.last().map(|(id, _)| *id).unwrap_or_default();
+    db.execute(
+        "DELETE FROM pending_notifications WHERE channel = ? AND id <= ?",
+        rusqlite::params![channel, last],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct RecordingNotifier(std::cell::RefCell<Vec<Notification>>);
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, notification: &Notification) -> Result_<()> {
+            self.0.borrow_mut().push(notification.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_flush_batch() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::create_schema(&db).unwrap();
+        let recorder = RecordingNotifier(Default::default());
+        for title in &["a", "b"] {
+            let notification = Notification {
+                title: title.to_string(),
+                body: "body".to_string(),
+            };
+            queue(&db, "daily", &notification).unwrap();
+        }
+        queue(
+            &db,
+            "other",
+            &Notification {
+                title: "c".to_string(),
+                body: String::new(),
+            },
+        )
+        .unwrap();
+        flush_batch(&db, "daily", 24, &recorder).unwrap();
+        assert!(recorder.0.borrow().is_empty());
+        db.execute(
+            "UPDATE pending_notifications SET queued_at = datetime('now', '-25 hours') WHERE title = 'a'",
+            rusqlite::params![],
+        )
+        .unwrap();
+        flush_batch(&db, "daily", 24, &recorder).unwrap();
+        assert_eq!(
+            *recorder.0.borrow(),
+            [Notification {
+                title: "2 notifications".to_string(),
+                body: "a\nbody\n\nb\nbody".to_string(),
+            }]
+        );
+        let remaining: i64 = db
+            .query_row(
+                "SELECT COUNT(*) FROM pending_notifications",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(remaining, 1);
+        db.close().unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+use crate::Result_;
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonStyle {
+    Pretty,
+    Minified,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+// How the files of one output target are written. Unset fields keep the default of the target.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PostProcess {
+    pub json: Option<JsonStyle>,
+    pub compression: Option<Compression>,
+    // True adds a missing trailing newline and false removes trailing newlines. Unset leaves the
+    // file as it was generated.
+    pub trailing_newline: Option<bool>,
+}
+
+// The post processing of every output target, like
+//
+// [output.playlists]
+// json = "minified"
+// trailing_newline = true
+//
+// [output.dataset]
+// compression = "zstd"
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub playlists: PostProcess,
+    pub dataset: PostProcess,
+    // The html setlist and the deck.
+    pub exports: PostProcess,
+    pub history: PostProcess,
+}
+
+impl OutputConfig {
+    // The dataset is minified and gzip compressed unless configured otherwise. Everything else is
+    // pretty and uncompressed.
+    pub fn dataset(&self) -> PostProcess {
+        PostProcess {
+            json: self.dataset.json.or(Some(JsonStyle::Minified)),
+            compression: self.dataset.compression.or(Some(Compression::Gzip)),
+            ..self.dataset.clone()
+        }
+    }
+}
+
+impl PostProcess {
+    pub fn json<T: serde::Serialize>(&self, value: &T) -> Result_<Vec<u8>> {
+        let bytes = match self.json.unwrap_or(JsonStyle::Pretty) {
+            JsonStyle::Pretty => serde_json::to_vec_pretty(value)?,
+            JsonStyle::Minified => serde_json::to_vec(value)?,
+        };
+        self.bytes(bytes)
+    }
+
+    // Applies the newline policy and then the compression.
+    pub fn bytes(&self, mut bytes: Vec<u8>) -> Result_<Vec<u8>> {
+        match self.trailing_newline {
+            Some(true) if !bytes.ends_with(b"\n") => bytes.push(b'\n'),
+            Some(false) => {
+                while bytes.ends_with(b"\n") {
+                    bytes.pop();
+                }
+            }
+            _ => (),
+        }
+        Ok(match self.compression.unwrap_or(Compression::None) {
+            Compression::None => bytes,
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(&bytes)?;
+                encoder.finish()?
+            }
+            Compression::Zstd => zstd::stream::encode_all(&bytes[..], 19)?,
+        })
+    }
+}
+
+// Undoes any of the compressions, recognized by their magic numbers.
+pub fn decompress(bytes: &[u8]) -> Result_<Vec<u8>> {
+    let mut decompressed = vec![];
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(bytes), &mut decompressed)?;
+    } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        decompressed = zstd::stream::decode_all(bytes)?;
+    } else {
+        decompressed.extend_from_slice(bytes);
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_post_process() {
+        let config: OutputConfig = toml::from_str(
+            r#"
+            [playlists]
+            json = "minified"
+            trailing_newline = true
+
+            [dataset]
+            compression = "zstd"
+            "#,
+        )
+        .unwrap();
+        let value = serde_json::json!({"a": [1, 2]});
+        assert_eq!(config.playlists.json(&value).unwrap(), b"{\"a\":[1,2]}\n");
+        assert_eq!(
+            config.history.json(&value).unwrap(),
+            serde_json::to_vec_pretty(&value).unwrap()
+        );
+        let trimmed = PostProcess {
+            trailing_newline: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(trimmed.bytes(b"text\n\n".to_vec()).unwrap(), b"text");
+        for compression in &[Compression::Gzip, Compression::Zstd] {
+            let post_process = PostProcess {
+                compression: Some(*compression),
+                ..config.dataset()
+            };
+            let bytes = post_process.json(&value).unwrap();
+            assert_eq!(decompress(&bytes).unwrap(), b"{\"a\":[1,2]}");
+        }
+        assert_eq!(config.dataset().compression, Some(Compression::Zstd));
+        assert_eq!(
+            OutputConfig::default().dataset().compression,
+            Some(Compression::Gzip)
+        );
+        assert!(toml::from_str::<OutputConfig>("[dataset]\ncompression = \"xz\"").is_err());
+    }
+}
@@ -0,0 +1,327 @@
+use crate::{RankedSongsPage, Result_, ScoreSaberSong};
+
+// Pages that take less than this many seconds double the page size and pages that take longer
+// than SLOW_SECONDS or fail halve it.
+const FAST_SECONDS: f64 = 2.0;
+const SLOW_SECONDS: f64 = 10.0;
+// How often a failed page is retried with a smaller size before the crawl fails.
+const MAX_RETRIES: usize = 3;
+// The smallest page size without --min-page-size.
+pub const DEFAULT_MIN_SIZE: usize = 100;
+
+// Tunes the page size of the leaderboard crawl between `min` and `max` so that ScoreSaber gets
+// smaller requests when it is slow or unstable and larger ones when it is healthy.
+pub struct PageTuner {
+    min: usize,
+    max: usize,
+    // The size the tuner would like to use.
+    target: usize,
+    // Songs fetched so far.
+    offset: usize,
+    // Size and seconds of every request. None for failed requests.
+    requests: Vec<(usize, Option<f64>)>,
+}
+
+impl PageTuner {
+    pub fn new(min: usize, max: usize) -> Result_<Self> {
+        if min == 0 || min > max {
+            return Err(format!(
+                "invalid page sizes {} to {}, the minimum has to be at least 1 and at most the maximum",
+                min, max
+            ))?;
+        }
+        Ok(PageTuner {
+            min,
+            max,
+            target: max,
+            offset: 0,
+            requests: vec![],
+        })
+    }
+
+    // The api pages by number so the next page has to start at the offset. This is the largest
+    // multiple of min up to the target that divides the offset, which min always does.
+    fn size(&self) -> usize {
+        let mut size = self.target - self.target % self.min;
+        while self.offset % size != 0 {
+            size -= self.min;
+        }
+        size
+    }
+
+    fn observe(&mut self, size: usize, seconds: Option<f64>) {
+        self.requests.push((size, seconds));
+        self.target = match seconds {
+            Some(seconds) if seconds < FAST_SECONDS => (self.target * 2).min(self.max),
+            Some(seconds) if seconds <= SLOW_SECONDS => self.target,
+            _ => (self.target / 2).max(self.min),
+        };
+    }
+
+    // Fetches the next page with the tuned size. `get_page` is called with the limit and the 1
+    // based page for that limit.
+    pub fn next_page<T: Iterator<Item = Result_<ScoreSaberSong>>>(
+        &mut self,
+        mut get_page: impl FnMut(usize, u64) -> Result_<RankedSongsPage<T>>,
+    ) -> Result_<RankedSongsPage<T>> {
+        let mut retries = 0;
+        loop {
+            let size = self.size();
+            let start = std::time::Instant::now();
+            match get_page(size, (self.offset / size) as u64 + 1) {
+                Ok(page) => {
+                    self.observe(size, Some(start.elapsed().as_secs_f64()));
+                    self.offset += size;
+                    return Ok(page);
+                }
+                Err(err) => {
+                    self.observe(size, None);
+                    if retries == MAX_RETRIES {
+                        return Err(err);
+                    }
+                    retries += 1;
+                    log::warn!("retrying with a smaller page after: {}", err);
+                }
+            }
+        }
+    }
+
+    // Continues a crawl that stored `offset` songs. Returns the offset the next page starts at which
+    // is rounded down to a multiple of the minimum size.
+    pub fn resume_at(&mut self, offset: usize) -> usize {
+        self.offset = offset - offset % self.min;
+        self.offset
+    }
+
+    pub fn requests(&self) -> &[(usize, Option<f64>)] {
+        &self.requests
+    }
+}
+
+// The result of a request in a background thread. Errors are strings because they have to be sent
+// between threads.
+type Fetched<P> = (Result<P, String>, f64);
+
+// Fetches up to `concurrency` pages of a fixed size at the same time in background threads so that
+// the requests overlap with each other and with storing the songs of the pages before them. Unlike
+// PageTuner the size is not tuned because the page numbers of the requests in flight depend on it.
+//
+// The pages are whatever `fetch` returns, like the bodies of the responses which are only decoded
+// when their songs are stored. Whether a page is the last one is up to the caller, requests for the
+// pages after it are dropped with the prefetcher.
+pub struct Prefetcher<P> {
+    size: usize,
+    concurrency: usize,
+    fetch: std::sync::Arc<dyn Fn(usize, u64) -> Result_<P> + Send + Sync>,
+    // Page numbers and results of the requests in flight in page order.
+    in_flight: std::collections::VecDeque<(u64, std::sync::mpsc::Receiver<Fetched<P>>)>,
+    next_page: u64,
+    // Size and seconds of every request. None for failed requests.
+    requests: Vec<(usize, Option<f64>)>,
+}
+
+impl<P: Send + 'static> Prefetcher<P> {
+    // `fetch` is called with the limit and the 1 based page for that limit.
+    pub fn new(
+        size: usize,
+        concurrency: usize,
+        fetch: impl Fn(usize, u64) -> Result_<P> + Send + Sync + 'static,
+    ) -> Result_<Self> {
+        if size == 0 || concurrency == 0 {
+            return Err("the page size and the number of concurrent pages have to be at least 1")?;
+        }
+        Ok(Prefetcher {
+            size,
+            concurrency,
+            fetch: std::sync::Arc::new(fetch),
+            in_flight: std::collections::VecDeque::new(),
+            next_page: 1,
+            requests: vec![],
+        })
+    }
+
+    // Continues a crawl that stored `offset` songs. Must be called before the first page. Returns
+    // the offset the next page starts at which is rounded down to a multiple of the size.
+    pub fn resume_at(&mut self, offset: usize) -> usize {
+        self.next_page = (offset / self.size) as u64 + 1;
+        offset - offset % self.size
+    }
+
+    fn spawn(&mut self) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let fetch = self.fetch.clone();
+        let (size, page) = (self.size, self.next_page);
+        std::thread::spawn(move || {
+            // The receiver is gone when the crawl ended before this page.
+            let _ = sender.send(fetch_page(fetch.as_ref(), size, page));
+        });
+        self.in_flight.push_back((page, receiver));
+        self.next_page += 1;
+    }
+
+    // Returns the pages in order. A failed page is retried up to MAX_RETRIES times before the
+    // crawl fails.
+    pub fn next_page(&mut self) -> Result_<P> {
+        while self.in_flight.len() < self.concurrency {
+            self.spawn();
+        }
+        let (page, receiver) = match self.in_flight.pop_front() {
+            Some(request) => request,
+            None => return Err("no page in flight")?,
+        };
+        let mut fetched = receiver.recv()?;
+        let mut retries = 0;
+        loop {
+            match fetched {
+                (Ok(page), seconds) => {
+                    self.requests.push((self.size, Some(seconds)));
+                    return Ok(page);
+                }
+                (Err(err), _) => {
+                    self.requests.push((self.size, None));
+                    if retries == MAX_RETRIES {
+                        return Err(err)?;
+                    }
+                    retries += 1;
+                    log::warn!("retrying page {} after: {}", page, err);
+                    fetched = fetch_page(self.fetch.as_ref(), self.size, page);
+                }
+            }
+        }
+    }
+
+    pub fn requests(&self) -> &[(usize, Option<f64>)] {
+        &self.requests
+    }
+}
+
+fn fetch_page<P>(
+    fetch: &(dyn Fn(usize, u64) -> Result_<P> + Send + Sync),
+    size: usize,
+    page: u64,
+) -> Fetched<P> {
+    let start = std::time::Instant::now();
+    let result = fetch(size, page).map_err(|err| err.to_string());
+    (result, start.elapsed().as_secs_f64())
+}
+
+// Like "1000 1.20s, 500 failed".
+fn report(requests: &[(usize, Option<f64>)]) -> String {
+    requests
+        .iter()
+        .map(|(size, seconds)| match seconds {
+            Some(seconds) => format!("{} {:.2}s", size, seconds),
+            None => format!("{} failed", size),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// Stores the requests of the crawl that just finished.
+pub fn record(db: &rusqlite::Connection, requests: &[(usize, Option<f64>)]) -> Result_<()> {
+    db.execute(
+        "UPDATE crawls SET page_sizes = ? WHERE id = (SELECT MAX(id) FROM crawls)",
+        rusqlite::params![report(requests)],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LastPage;
+
+    #[test]
+    fn test_page_tuner() {
+        let mut tuner = PageTuner::new(100, 1000).unwrap();
+        assert_eq!(tuner.size(), 1000);
+        tuner.observe(1000, Some(20.0));
+        assert_eq!(tuner.size(), 500);
+        tuner.offset = 500;
+        tuner.observe(500, None);
+        tuner.observe(250, Some(5.0));
+        // 250 does not divide the offset.
+        assert_eq!(tuner.size(), 100);
+        tuner.observe(100, Some(0.1));
+        assert_eq!(tuner.size(), 500);
+        tuner.observe(500, Some(0.1));
+        tuner.observe(1000, Some(0.1));
+        assert_eq!(tuner.target, 1000);
+        assert_eq!(tuner.size(), 500);
+        assert_eq!(tuner.resume_at(1250), 1200);
+        assert_eq!(tuner.size(), 600);
+        assert!(PageTuner::new(0, 10).is_err());
+        assert!(PageTuner::new(20, 10).is_err());
+    }
+
+    #[test]
+    fn test_retry() {
+        let mut tuner = PageTuner::new(100, 1000).unwrap();
+        let mut requests = vec![];
+        let page = tuner
+            .next_page(|limit, page| {
+                requests.push((limit, page));
+                if requests.len() < 3 {
+                    return Err("unavailable")?;
+                }
+                Ok(RankedSongsPage {
+                    songs: Vec::<Result_<ScoreSaberSong>>::new().into_iter(),
+                    last_page: LastPage::Known(true),
+                })
+            })
+            .unwrap();
+        assert_eq!(page.last_page, LastPage::Known(true));
+        assert_eq!(requests, [(1000, 1), (500, 1), (250, 1)]);
+        assert!(report(tuner.requests()).starts_with("1000 failed, 500 failed, 250 "));
+        let mut tuner = PageTuner::new(100, 100).unwrap();
+        assert!(tuner
+            .next_page(
+                |_, _| -> Result_<RankedSongsPage<std::vec::IntoIter<Result_<ScoreSaberSong>>>> {
+                    Err("unavailable")?
+                }
+            )
+            .is_err());
+        assert_eq!(tuner.requests.len(), MAX_RETRIES + 1);
+    }
+
+    #[test]
+    fn test_prefetcher() {
+        let failures = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = failures.clone();
+        let mut prefetcher = Prefetcher::new(2, 3, move |limit, page| {
+            // The second page fails once.
+            if page == 2 && counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                return Err("unavailable")?;
+            }
+            Ok(crate::test_songs()
+                .into_iter()
+                .skip((page as usize - 1) * limit)
+                .take(limit)
+                .collect::<Vec<_>>())
+        })
+        .unwrap();
+        let mut uids = vec![];
+        loop {
+            let songs = prefetcher.next_page().unwrap();
+            uids.extend(songs.iter().map(|song| song.uid));
+            if songs.len() < 2 {
+                break;
+            }
+        }
+        let expected = crate::test_songs()
+            .into_iter()
+            .map(|song| song.uid)
+            .collect::<Vec<_>>();
+        assert_eq!(uids, expected);
+        assert_eq!(failures.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(
+            prefetcher
+                .requests()
+                .iter()
+                .filter(|(_, seconds)| seconds.is_none())
+                .count(),
+            1
+        );
+        assert!(Prefetcher::new(0, 1, |_, _| Ok(Vec::<u8>::new())).is_err());
+    }
+}
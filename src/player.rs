@@ -0,0 +1,191 @@
+use crate::{archive, http, profile, Result_};
+
+// ScoreSaber player ids are steam or oculus ids. Like the song hash we keep them as opaque strings.
+pub type PlayerId = String;
+
+const SCORESABER_PLAYER_API_URL: &str = "https://scoresaber.com/api/player";
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct Player {
+    pub id: PlayerId,
+    pub name: String,
+    pub pp: f64,
+    pub rank: u64,
+    #[serde(rename = "countryRank")]
+    pub country_rank: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+struct PlayerHistoryEntry {
+    recorded_at: String,
+    name: String,
+    pp: f64,
+    rank: u64,
+    country_rank: u64,
+}
+
+pub fn get_player(client: &reqwest::Client, id: &str) -> Result_<Player> {
+    let url = reqwest::Url::parse(&format!("{}/{}/basic", SCORESABER_PLAYER_API_URL, id))?;
+    log::info!("request: {}", url);
+    let (status, body) = http::get(url.as_str(), || client.get(url.clone()))?;
+    if status.is_success() {
+        archive::record(url.as_str(), &body);
+        let _timer = profile::timer(profile::Kind::Decode);
+        Ok(serde_json::from_slice(&body)?)
+    } else {
+        Err(format!(
+            "response status code does not indiciate success: {}",
+            status
+        ))?
+    }
+}
+
+pub fn tracked_players(db: &rusqlite::Connection) -> Result_<Vec<PlayerId>> {
+    let mut statement = db.prepare("SELECT id FROM tracked_players ORDER BY id")?;
+    let iter = statement.query_map(rusqlite::params![], |row| row.get(0))?;
+    let mut players = vec![];
+    for player in iter {
+        players.push(player?);
+    }
+    Ok(players)
+}
+
+fn record_player_history(db: &rusqlite::Connection, player: &Player) -> Result_<()> {
+    let _timer = profile::timer(profile::Kind::Database);
+    db.execute(
+        "INSERT INTO player_history (player_id, name, pp, rank, country_rank, recorded_at) VALUES (?,?,?,?,?,datetime('now'))",
+        rusqlite::params![
+            player.id,
+            player.name,
+            player.pp,
+            player.rank as i64,
+            player.country_rank as i64
+        ],
+    )?;
+    Ok(())
+}
+
+// Fetching the player first makes sure that we do not track ids that do not exist.
+pub fn track_player(db: &rusqlite::Connection, client: &reqwest::Client, id: &str) -> Result_<()> {
+    let player = get_player(client, id)?;
+    db.execute(
+        "INSERT OR IGNORE INTO tracked_players (id) VALUES (?)",
+        rusqlite::params![player.id],
+    )?;
+    record_player_history(db, &player)?;
+    println!("Tracking player {} ({}).", player.name, player.id);
+    Ok(())
+}
+
+pub fn update_tracked_players(
+    db: &rusqlite::Connection,
+    mut get_player: impl FnMut(&str) -> Result_<Player>,
+) -> Result_<()> {
+    let mut failed = 0;
+    for id in tracked_players(db)? {
+        // A deleted account or an api hiccup should not cost the playlist of the crawl.
+        let player = match get_player(&id) {
+            Ok(player) => player,
+            Err(err) => {
+                log::warn!("failed to update player {}: {}", id, err);
+                failed += 1;
+                continue;
+            }
+        };
+        println!(
+            "player {} has {} pp at rank {}",
+            player.name, player.pp, player.rank
+        );
+        record_player_history(db, &player)?;
+    }
+    crate::provenance::record_enrichment(db, "ScoreSaber player", failed)
+}
+
+// Prints the history as a table or writes it as json to `output`.
+pub fn print_history(
+    db: &rusqlite::Connection,
+    id: &str,
+    output: Option<&std::path::Path>,
+    post_process: &crate::output::PostProcess,
+) -> Result_<()> {
+    let mut statement = db.prepare(
+        "SELECT recorded_at, name, pp, rank, country_rank FROM player_history WHERE player_id = ? ORDER BY recorded_at",
+    )?;
+    let iter = statement.query_map(rusqlite::params![id], |row| {
+        Ok(PlayerHistoryEntry {
+            recorded_at: row.get(0)?,
+            name: row.get(1)?,
+            pp: row.get(2)?,
+            rank: row.get::<_, i64>(3)? as u64,
+            country_rank: row.get::<_, i64>(4)? as u64,
+        })
+    })?;
+    let mut history = vec![];
+    for entry in iter {
+        history.push(entry?);
+    }
+    if history.is_empty() {
+        return Err(format!("no history recorded for player {}", id))?;
+    }
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, post_process.json(&history)?)?;
+        }
+        None => {
+            println!(
+                "{:<19} {:>10} {:>8} {:>12}",
+                "recorded at", "pp", "rank", "country rank"
+            );
+            for entry in history {
+                println!(
+                    "{:<19} {:>10.2} {:>8} {:>12}",
+                    entry.recorded_at, entry.pp, entry.rank, entry.country_rank
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_failing_player() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::create_schema(&db).unwrap();
+        for id in &["1", "2"] {
+            db.execute(
+                "INSERT INTO tracked_players (id) VALUES (?)",
+                rusqlite::params![id],
+            )
+            .unwrap();
+        }
+        update_tracked_players(&db, |id| match id {
+            "1" => Err("not found")?,
+            _ => Ok(Player {
+                id: id.to_string(),
+                name: "player".to_string(),
+                pp: 1000.0,
+                rank: 10,
+                country_rank: 1,
+            }),
+        })
+        .unwrap();
+        let history: Vec<String> = db
+            .prepare("SELECT player_id FROM player_history")
+            .unwrap()
+            .query_map(rusqlite::params![], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(history, ["2"]);
+        assert_eq!(
+            crate::provenance::stale_services(&db).unwrap()[0].0,
+            "ScoreSaber player"
+        );
+        db.close().unwrap();
+    }
+}
@@ -0,0 +1,2433 @@
+use crate::{profile, Result_};
+use structopt::StructOpt;
+
+pub const AUTHOR: &str = "Valentin (e00E)";
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BeatSaberPlaylistSong {
+    #[serde(rename = "songName")]
+    pub name: String,
+    #[serde(rename = "hash")]
+    pub hash: String,
+    // The difficulties that are ranked and pass the filters so that playlist managers highlight
+    // them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub difficulties: Vec<PlaylistDifficulty>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PlaylistDifficulty {
+    pub characteristic: String,
+    pub name: String,
+}
+
+impl PlaylistDifficulty {
+    // `diff` looks like "_ExpertPlus_SoloStandard".
+    pub fn from_diff(diff: &str) -> Option<Self> {
+        let (difficulty, characteristic) = crate::parse_difficulty(diff)?;
+        Some(PlaylistDifficulty {
+            characteristic: characteristic.to_string(),
+            name: difficulty.to_string(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BeatsaberPlaylist {
+    #[serde(rename = "playlistTitle")]
+    pub title: String,
+    #[serde(rename = "playlistAuthor")]
+    pub author: String,
+    #[serde(rename = "playlistDescription")]
+    pub description: String,
+    #[serde(rename = "songs")]
+    pub songs: Vec<BeatSaberPlaylistSong>,
+}
+
+// Filters apply to single difficulties. A song is part of the playlist if any of its difficulties
+// passes.
+#[derive(Clone, Debug, Default, PartialEq, StructOpt)]
+pub struct PlaylistOptions {
+    /// Only include difficulties with at least this many stars.
+    #[structopt(long)]
+    pub min_stars: Option<f64>,
+    /// Only include difficulties with at most this many stars.
+    #[structopt(long)]
+    pub max_stars: Option<f64>,
+    /// Only include difficulties with at least this bpm.
+    #[structopt(long)]
+    pub min_bpm: Option<f64>,
+    /// Only include difficulties with at most this bpm.
+    #[structopt(long)]
+    pub max_bpm: Option<f64>,
+    /// Only include difficulties with at least this many notes per second. Needs BeatSaver data.
+    #[structopt(long)]
+    pub min_nps: Option<f64>,
+    /// Only include difficulties with at most this many notes per second. Needs BeatSaver data.
+    #[structopt(long)]
+    pub max_nps: Option<f64>,
+    /// Only include difficulties with at most this note jump speed. Needs BeatSaver data.
+    #[structopt(long)]
+    pub max_njs: Option<f64>,
+    /// Only include maps that are at least this long, like 2m, 90s or 1m30s. Needs BeatSaver data.
+    #[structopt(long)]
+    pub min_duration: Option<Duration>,
+    /// Only include maps that are at most this long, like 3m. Needs BeatSaver data.
+    #[structopt(long)]
+    pub max_duration: Option<Duration>,
+    /// Only include songs that were ranked in the last this many days, like 7 for the new songs of
+    /// the week. Songs without a ranked date from ScoreSaber count from when a crawl first found
+    /// them, the songs that were already ranked at the first crawl are left out.
+    #[structopt(long)]
+    pub ranked_within_days: Option<u64>,
+    /// Only include songs that were ranked since this UTC date like 2019-05-01 or in the last
+    /// duration like 7d, 2w or 12h, where m is minutes. Songs without a ranked date are handled like
+    /// for --ranked-within-days.
+    #[structopt(long)]
+    pub ranked_since: Option<Since>,
+    /// Keep difficulties whose bpm is invalid, like 0, when filtering by bpm.
+    #[structopt(long)]
+    pub include_invalid_bpm: bool,
+    /// Only include this difficulty: Easy, Normal, Hard, Expert or ExpertPlus. Can be repeated.
+    #[structopt(long = "difficulty")]
+    pub difficulties: Vec<crate::Difficulty>,
+    /// Only include this characteristic like Standard, Lawless, OneSaber or 90Degree. Can be
+    /// repeated.
+    #[structopt(long = "characteristic")]
+    pub characteristics: Vec<String>,
+    /// Only include difficulties whose mapper contains this name, ignoring case. `*` and `?`
+    /// make it a glob that has to match the whole name instead. Can be repeated.
+    #[structopt(long = "mapper")]
+    pub mappers: Vec<String>,
+    /// Only include songs whose title is written in this script: latin, japanese, korean, han
+    /// (Chinese characters without kana), cyrillic or other. Can be repeated.
+    #[structopt(long = "title-script")]
+    pub title_scripts: Vec<crate::script::Script>,
+    /// Only include songs of this playlist added with `import playlist`. Can be repeated.
+    #[structopt(long = "imported")]
+    pub imported: Vec<String>,
+    /// Leave out difficulties this player has a stored score on.
+    #[structopt(long)]
+    pub exclude_played: Option<crate::player::PlayerId>,
+    /// Leave out difficulties this player has a stored full combo on.
+    #[structopt(long)]
+    pub exclude_fc: Option<crate::player::PlayerId>,
+    /// Only include difficulties whose derived column from the config passes a condition like
+    /// `pp95>=300`. Can be repeated.
+    #[structopt(long = "derived")]
+    pub derived: Vec<DerivedCondition>,
+    /// Never include the songs and leaderboards in this file, which contains one song hash or
+    /// leaderboard uid per line. Everything after a `#` is a comment.
+    #[structopt(long, parse(from_os_str))]
+    pub exclude_file: Option<std::path::PathBuf>,
+    /// Leave out maps that are installed in this Beat Saber `CustomLevels` directory, for a
+    /// playlist of the ranked maps that are missing.
+    #[structopt(long, parse(from_os_str))]
+    pub exclude_installed: Option<std::path::PathBuf>,
+    /// Order of the songs: stars, bpm, name, mapper, nps, plays, ranked (newest first), shuffle or
+    /// the name of a derived column.
+    #[structopt(long, default_value = "stars")]
+    pub sort: SortOrder,
+    /// Reverses the order, for example to sort by ascending stars.
+    #[structopt(long)]
+    pub reverse: bool,
+    /// Seed of `--sort shuffle` so that the same songs are shuffled the same way. Defaults to a
+    /// random seed which the description names.
+    #[structopt(long)]
+    pub seed: Option<u64>,
+    // Set for the playlists of one mapper. Unlike `mappers` the name has to match exactly.
+    #[structopt(skip)]
+    pub exact_mapper: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SortOrder {
+    Stars,
+    // Invalid bpms are unknown and come last.
+    Bpm,
+    Name,
+    Mapper,
+    // Notes per second need BeatSaver data. Songs without it come last.
+    Nps,
+    Plays,
+    // Newest first, by RANKED_AT. Songs without a known ranked date come last.
+    Ranked,
+    // Random but reproducible with the seed of the options.
+    Shuffle,
+    // The maximum of a derived column, by default in descending order.
+    Derived(String),
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder::Stars
+    }
+}
+
+impl SortOrder {
+    // Expression over the difficulties of a song and whether it is sorted in descending order
+    // unless reversed.
+    fn expression(&self) -> (String, bool) {
+        let (expression, descending) = match self {
+            SortOrder::Stars => ("MAX(s.stars)", true),
+            SortOrder::Bpm => (
+                "MAX(CASE WHEN s.bpm_status = 'invalid' THEN NULL ELSE s.bpm END)",
+                false,
+            ),
+            SortOrder::Name => ("s.name COLLATE NOCASE", false),
+            SortOrder::Mapper => ("s.levelAuthorName COLLATE NOCASE", false),
+            SortOrder::Nps => ("MAX(d.nps)", false),
+            SortOrder::Plays => ("MAX(s.scores)", true),
+            SortOrder::Ranked => return (format!("MAX({})", RANKED_AT), true),
+            // The order before shuffling, which only depends on the songs.
+            SortOrder::Shuffle => ("s.id", false),
+            // The name is an identifier so it can be part of the sql.
+            SortOrder::Derived(name) => {
+                return (
+                    format!(
+                        "MAX((SELECT value FROM derived_values v WHERE v.uid = s.uid AND v.name = '{}'))",
+                        name
+                    ),
+                    true,
+                )
+            }
+        };
+        (expression.to_string(), descending)
+    }
+
+    fn description(&self) -> String {
+        match self {
+            SortOrder::Stars => "star difficulty (roughly equivalent to maximum PP)".to_string(),
+            SortOrder::Bpm => "BPM".to_string(),
+            SortOrder::Name => "name".to_string(),
+            SortOrder::Mapper => "mapper".to_string(),
+            SortOrder::Nps => "notes per second of the densest difficulty".to_string(),
+            SortOrder::Plays => "play count".to_string(),
+            SortOrder::Ranked => "ranked date".to_string(),
+            SortOrder::Shuffle => "random shuffle".to_string(),
+            SortOrder::Derived(name) => name.clone(),
+        }
+    }
+}
+
+impl std::str::FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "stars" => Ok(SortOrder::Stars),
+            "bpm" => Ok(SortOrder::Bpm),
+            "name" => Ok(SortOrder::Name),
+            "mapper" => Ok(SortOrder::Mapper),
+            "nps" => Ok(SortOrder::Nps),
+            "plays" => Ok(SortOrder::Plays),
+            "ranked" => Ok(SortOrder::Ranked),
+            "shuffle" => Ok(SortOrder::Shuffle),
+            _ if crate::derived::is_identifier(s) => Ok(SortOrder::Derived(s.to_string())),
+            _ => Err(format!(
+                "unknown sort order {}, expected stars, bpm, name, mapper, nps, plays, ranked, shuffle or a derived column",
+                s
+            )),
+        }
+    }
+}
+
+// A map length like "3m", "90s", "1m30s" or "150" for seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Duration {
+    pub seconds: f64,
+}
+
+// The units of map lengths and of times before now with their seconds, longest first.
+const LENGTH_UNITS: &[(char, f64)] = &[('m', 60.0), ('s', 1.0)];
+const AGO_UNITS: &[(char, f64)] = &[
+    ('w', 7.0 * 24.0 * 3600.0),
+    ('d', 24.0 * 3600.0),
+    ('h', 3600.0),
+    ('m', 60.0),
+    ('s', 1.0),
+];
+
+impl Duration {
+    // A number of seconds or numbers followed by one of the units. Negative, infinite and nan
+    // lengths are rejected.
+    fn parse(s: &str, units: &[(char, f64)]) -> Option<Self> {
+        let valid = |seconds: f64| match seconds.is_finite() && seconds >= 0.0 {
+            true => Some(Duration { seconds }),
+            false => None,
+        };
+        if let Ok(seconds) = s.parse::<f64>() {
+            return valid(seconds);
+        }
+        let mut seconds = 0.0;
+        let mut number = String::new();
+        for c in s.chars() {
+            match units.iter().find(|(unit, _)| *unit == c) {
+                Some((_, length)) if !number.is_empty() => {
+                    seconds += number.parse::<f64>().ok()? * length;
+                    number.clear();
+                }
+                _ if c.is_ascii_digit() || c == '.' => number.push(c),
+                _ => return None,
+            }
+        }
+        if !number.is_empty() || s.is_empty() {
+            return None;
+        }
+        valid(seconds)
+    }
+
+    // Whole numbers of every unit but the last, which gets the rest.
+    fn format(self, units: &[(char, f64)]) -> String {
+        let mut text = String::new();
+        let mut rest = self.seconds;
+        for (i, (unit, length)) in units.iter().enumerate() {
+            let last = i + 1 == units.len();
+            let count = if last {
+                rest / length
+            } else {
+                (rest / length).floor()
+            };
+            if count > 0.0 || (last && text.is_empty()) {
+                text.push_str(&format!("{}{}", count, unit));
+                rest -= count * length;
+            }
+        }
+        text
+    }
+}
+
+impl std::str::FromStr for Duration {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Duration::parse(s, LENGTH_UNITS).ok_or_else(|| {
+            format!(
+                "invalid duration {}, expected something like 3m, 90s or 1m30s",
+                s
+            )
+        })
+    }
+}
+
+impl std::fmt::Display for Duration {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.format(LENGTH_UNITS))
+    }
+}
+
+// A point in time, either a UTC date like "2019-05-01" or "2019-05-01 12:00:00", or a Duration
+// before now like "7d", "2w" or "12h". Here "m" is minutes too.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Since {
+    Date(String),
+    Ago(Duration),
+}
+
+impl Since {
+    // SQL expression for the point in time together with its parameter.
+    fn sql(&self) -> (&'static str, String) {
+        match self {
+            Since::Date(date) => ("datetime(?)", date.clone()),
+            Since::Ago(duration) => (
+                "datetime('now', ?)",
+                format!("-{} seconds", duration.seconds),
+            ),
+        }
+    }
+}
+
+// Whether a date with the shape of "2019-05-01" or "2019-05-01 12:00:00" exists. SQLite's datetime
+// would turn the others into NULL which no song is ranked after.
+fn is_valid_date(s: &str) -> bool {
+    let number = |start: usize| s[start..start + 2].parse::<u32>().unwrap_or_default();
+    let year = s[..4].parse::<u32>().unwrap_or_default();
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match number(5) {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => 0,
+    };
+    let day = number(8);
+    let time = s.len() == 10 || (number(11) < 24 && number(14) < 60 && number(17) < 60);
+    (1..=days).contains(&day) && time
+}
+
+impl std::str::FromStr for Since {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        // Digits with the separators at these positions.
+        let shape = |text: &str, separators: &[(usize, char)]| {
+            text.chars().enumerate().all(|(i, c)| {
+                match separators.iter().find(|(position, _)| *position == i) {
+                    Some((_, separator)) => c == *separator,
+                    None => c.is_ascii_digit(),
+                }
+            })
+        };
+        let date = match s.len() {
+            10 => shape(s, &[(4, '-'), (7, '-')]),
+            19 => shape(s, &[(4, '-'), (7, '-'), (10, ' '), (13, ':'), (16, ':')]),
+            _ => false,
+        };
+        if date {
+            if !is_valid_date(s) {
+                return Err(format!("invalid date {}", s));
+            }
+            return Ok(Since::Date(s.to_string()));
+        }
+        Duration::parse(s, AGO_UNITS)
+            .map(Since::Ago)
+            .ok_or_else(|| {
+                format!(
+                "invalid time {}, expected a date like 2019-05-01 or a duration like 7d, 2w or 12h",
+                s
+            )
+            })
+    }
+}
+
+impl std::fmt::Display for Since {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Since::Date(date) => write!(f, "since {}", date),
+            Since::Ago(duration) => write!(f, "in the last {}", duration.format(AGO_UNITS)),
+        }
+    }
+}
+
+// A condition on a derived column like `pp95>=300`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DerivedCondition {
+    pub name: String,
+    pub operator: &'static str,
+    pub value: f64,
+}
+
+impl std::str::FromStr for DerivedCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        // Longer operators first so that `>=` is not taken for `>`.
+        for &operator in &[">=", "<=", "!=", ">", "<", "="] {
+            if let Some(index) = s.find(operator) {
+                let name = s[..index].trim();
+                if !crate::derived::is_identifier(name) {
+                    return Err(format!("invalid derived column name {}", name));
+                }
+                let value = s[index + operator.len()..].trim();
+                let value = value
+                    .parse()
+                    .map_err(|_| format!("invalid number {}", value))?;
+                return Ok(DerivedCondition {
+                    name: name.to_string(),
+                    operator,
+                    value,
+                });
+            }
+        }
+        Err(format!(
+            "invalid condition {}, expected a derived column, an operator like >= and a number",
+            s
+        ))
+    }
+}
+
+impl std::fmt::Display for DerivedCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.name, self.operator, self.value)
+    }
+}
+
+// Every difficulty row `s` with its BeatSaver data `d` if it has been fetched.
+pub const SONGS_FROM: &str = "scoresaber_songs s LEFT JOIN beatsaver_difficulties d ON d.hash = s.id AND d.difficulty = s.difficulty AND d.characteristic = s.characteristic";
+
+// When the song was ranked. ScoreSaber only sometimes says so, see ScoreSaberSong::ranked_date. A
+// song that a later crawl found was ranked between that crawl and the one before so it counts from
+// when it was first seen. The songs of the first crawl that finished after the first songs were
+// stored, the baseline, could have been ranked at any time before so they are NULL.
+pub const RANKED_AT: &str = "COALESCE(s.ranked_at, CASE WHEN s.first_seen > (SELECT MIN(finished_at) FROM crawls WHERE finished_at >= (SELECT MIN(first_seen) FROM scoresaber_songs)) THEN s.first_seen END)";
+
+// SQL conditions on the rows of scoresaber_songs together with their parameters.
+#[derive(Default)]
+pub struct Filter {
+    conditions: Vec<String>,
+    params: Vec<Box<dyn rusqlite::types::ToSql>>,
+}
+
+impl Filter {
+    fn add(&mut self, condition: &str, param: impl rusqlite::types::ToSql + 'static) {
+        self.conditions.push(condition.to_string());
+        self.params.push(Box::new(param));
+    }
+
+    // Passes if any of the params passes the condition.
+    fn add_any(&mut self, condition: &str, params: Vec<String>) {
+        if params.is_empty() {
+            return;
+        }
+        let conditions = vec![condition; params.len()];
+        self.conditions
+            .push(format!("({})", conditions.join(" OR ")));
+        for param in params {
+            self.params.push(Box::new(param));
+        }
+    }
+
+    // Passes if the expression is none of the values. They are bound as a single json array
+    // because SQLite limits a statement to 999 parameters and there can be more values, like the
+    // maps of a large CustomLevels directory.
+    fn exclude<T: serde::Serialize>(&mut self, expression: &str, values: Vec<T>) -> Result_<()> {
+        if values.is_empty() {
+            return Ok(());
+        }
+        self.add(
+            &format!("{} NOT IN (SELECT value FROM json_each(?))", expression),
+            serde_json::to_string(&values)?,
+        );
+        Ok(())
+    }
+
+    // True if nothing but being ranked restricts the songs.
+    fn only_ranked(&self) -> bool {
+        self.conditions.len() == 1 && self.params.is_empty()
+    }
+
+    pub fn where_clause(&self) -> String {
+        if self.conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", self.conditions.join(" AND "))
+        }
+    }
+
+    pub fn params(&self) -> Vec<&dyn rusqlite::types::ToSql> {
+        self.params.iter().map(|x| x.as_ref()).collect()
+    }
+}
+
+// A name without `*` or `?` matches every mapper containing it.
+fn mapper_glob(name: &str) -> String {
+    let name = name.to_lowercase();
+    if name.contains(|c: char| c == '*' || c == '?') {
+        return name;
+    }
+    let mut glob = String::from("*");
+    for c in name.chars() {
+        match c {
+            '[' => glob.push_str("[[]"),
+            _ => glob.push(c),
+        }
+    }
+    glob.push('*');
+    glob
+}
+
+// The hashes and leaderboard uids of an exclusion file.
+#[derive(Debug, Default, PartialEq)]
+struct Exclusions {
+    hashes: Vec<String>,
+    uids: Vec<i64>,
+}
+
+fn read_exclusions(path: &std::path::Path) -> Result_<Exclusions> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    let mut exclusions = Exclusions::default();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Ok(uid) = line.parse() {
+            exclusions.uids.push(uid);
+        } else if line.len() == 40 && line.chars().all(|c| c.is_ascii_hexdigit()) {
+            // ScoreSaber uses upper case hashes and BeatSaver lower case ones.
+            exclusions.hashes.push(line.to_uppercase());
+        } else {
+            return Err(format!(
+                "line {} of {} is neither a song hash nor a leaderboard uid: {}",
+                i + 1,
+                path.display(),
+                line
+            ))?;
+        }
+    }
+    Ok(exclusions)
+}
+
+pub fn filter(options: &PlaylistOptions) -> Result_<Filter> {
+    let mut filter = Filter::default();
+    filter.conditions.push("s.ranked = 1".to_string());
+    if let Some(min_stars) = options.min_stars {
+        filter.add("s.stars >= ?", min_stars);
+    }
+    if let Some(max_stars) = options.max_stars {
+        filter.add("s.stars <= ?", max_stars);
+    }
+    if let Some(min_bpm) = options.min_bpm {
+        filter.add("s.bpm >= ?", min_bpm);
+    }
+    if let Some(max_bpm) = options.max_bpm {
+        filter.add("s.bpm <= ?", max_bpm);
+    }
+    // Difficulties without BeatSaver data have no nps or njs and never pass.
+    if let Some(min_nps) = options.min_nps {
+        filter.add("d.nps >= ?", min_nps);
+    }
+    if let Some(max_nps) = options.max_nps {
+        filter.add("d.nps <= ?", max_nps);
+    }
+    if let Some(max_njs) = options.max_njs {
+        filter.add("d.njs <= ?", max_njs);
+    }
+    if let Some(min_duration) = options.min_duration {
+        filter.add("d.length >= ?", min_duration.seconds);
+    }
+    if let Some(max_duration) = options.max_duration {
+        filter.add("d.length <= ?", max_duration.seconds);
+    }
+    if let Some(days) = options.ranked_within_days {
+        filter.add(
+            &format!("{} >= datetime('now', ?)", RANKED_AT),
+            format!("-{} days", days),
+        );
+    }
+    if let Some(since) = &options.ranked_since {
+        let (time, param) = since.sql();
+        filter.add(&format!("{} >= {}", RANKED_AT, time), param);
+    }
+    if (options.min_bpm.is_some() || options.max_bpm.is_some()) && !options.include_invalid_bpm {
+        filter
+            .conditions
+            .push("s.bpm_status != 'invalid'".to_string());
+    }
+    filter.add_any(
+        "s.difficulty = ?",
+        options
+            .difficulties
+            .iter()
+            .map(|x| x.name().to_string())
+            .collect(),
+    );
+    filter.add_any(
+        "s.characteristic = ? COLLATE NOCASE",
+        options.characteristics.clone(),
+    );
+    filter.add_any(
+        "LOWER(s.levelAuthorName) GLOB ?",
+        options.mappers.iter().map(|x| mapper_glob(x)).collect(),
+    );
+    if let Some(mapper) = &options.exact_mapper {
+        filter.add("s.levelAuthorName = ?", mapper.clone());
+    }
+    filter.add_any(
+        "s.title_script = ?",
+        options
+            .title_scripts
+            .iter()
+            .map(|x| x.name().to_string())
+            .collect(),
+    );
+    filter.add_any(
+        "UPPER(s.id) IN (SELECT hash FROM imported_playlists WHERE name = ?)",
+        options.imported.clone(),
+    );
+    if let Some(player) = &options.exclude_played {
+        filter.add(
+            "s.uid NOT IN (SELECT uid FROM player_scores WHERE player_id = ?)",
+            player.clone(),
+        );
+    }
+    for condition in options.derived.iter() {
+        // The name is an identifier and the operator one of a fixed set.
+        filter.add(
+            &format!(
+                "s.uid IN (SELECT uid FROM derived_values WHERE name = '{}' AND value {} ?)",
+                condition.name, condition.operator
+            ),
+            condition.value,
+        );
+    }
+    if let Some(player) = &options.exclude_fc {
+        filter.add(
+            "s.uid NOT IN (SELECT uid FROM player_scores WHERE player_id = ? AND full_combo)",
+            player.clone(),
+        );
+    }
+    if let Some(path) = &options.exclude_file {
+        let exclusions = read_exclusions(path)?;
+        filter.exclude("UPPER(s.id)", exclusions.hashes)?;
+        filter.exclude("s.uid", exclusions.uids)?;
+    }
+    if let Some(custom_levels) = &options.exclude_installed {
+        filter.exclude(
+            "UPPER(s.id)",
+            crate::installed::installed_hashes(custom_levels)?,
+        )?;
+    }
+    Ok(filter)
+}
+
+// The default title and description describe the playlist of all ranked songs so every filter adds
+// to them.
+fn title_and_description(options: &PlaylistOptions) -> (String, String) {
+    const TITLE: &str = "Ranked Songs";
+    let mut title = TITLE.to_string();
+    let descending = options.sort.expression().1 != options.reverse;
+    let mut description = match (&options.sort, options.seed) {
+        (SortOrder::Shuffle, Some(seed)) => format!(
+            "Contains all songs that are ranked on Score Saber in random order (seed {}).",
+            seed
+        ),
+        _ => format!(
+            "Contains all songs that are ranked on Score Saber ordered by {} in {} order.",
+            options.sort.description(),
+            if descending {
+                "descending"
+            } else {
+                "ascending"
+            }
+        ),
+    };
+    let stars = match (options.min_stars, options.max_stars) {
+        (Some(min), Some(max)) => Some(format!("{} to {} stars", min, max)),
+        (Some(min), None) => Some(format!("at least {} stars", min)),
+        (None, Some(max)) => Some(format!("at most {} stars", max)),
+        (None, None) => None,
+    };
+    let mut restrictions = vec![];
+    if let Some(stars) = stars {
+        description.push_str(&format!(" Only difficulties with {} are included.", stars));
+        restrictions.push(stars);
+    }
+    let bpm = match (options.min_bpm, options.max_bpm) {
+        (Some(min), Some(max)) => Some(format!("{} to {} bpm", min, max)),
+        (Some(min), None) => Some(format!("at least {} bpm", min)),
+        (None, Some(max)) => Some(format!("at most {} bpm", max)),
+        (None, None) => None,
+    };
+    if let Some(bpm) = bpm {
+        description.push_str(&format!(" Only difficulties with {} are included.", bpm));
+        restrictions.push(bpm);
+    }
+    let nps = match (options.min_nps, options.max_nps) {
+        (Some(min), Some(max)) => Some(format!("{} to {} nps", min, max)),
+        (Some(min), None) => Some(format!("at least {} nps", min)),
+        (None, Some(max)) => Some(format!("at most {} nps", max)),
+        (None, None) => None,
+    };
+    if let Some(nps) = nps {
+        description.push_str(&format!(" Only difficulties with {} are included.", nps));
+        restrictions.push(nps);
+    }
+    if let Some(max_njs) = options.max_njs {
+        let njs = format!("at most {} njs", max_njs);
+        description.push_str(&format!(" Only difficulties with {} are included.", njs));
+        restrictions.push(njs);
+    }
+    let duration = match (options.min_duration, options.max_duration) {
+        (Some(min), Some(max)) => Some(format!("{} to {} long", min, max)),
+        (Some(min), None) => Some(format!("at least {} long", min)),
+        (None, Some(max)) => Some(format!("at most {} long", max)),
+        (None, None) => None,
+    };
+    if let Some(duration) = duration {
+        description.push_str(&format!(" Only maps that are {} are included.", duration));
+        restrictions.push(duration);
+    }
+    if let Some(days) = options.ranked_within_days {
+        let ranked = format!("ranked in the last {} days", days);
+        description.push_str(&format!(" Only songs {} are included.", ranked));
+        restrictions.push(ranked);
+    }
+    if let Some(since) = &options.ranked_since {
+        let ranked = format!("ranked {}", since);
+        description.push_str(&format!(" Only songs {} are included.", ranked));
+        restrictions.push(ranked);
+    }
+    if !options.difficulties.is_empty() {
+        let difficulties = options
+            .difficulties
+            .iter()
+            .map(|x| x.name())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        description.push_str(&format!(
+            " Only {} difficulties are included.",
+            difficulties
+        ));
+        restrictions.push(difficulties);
+    }
+    if !options.characteristics.is_empty() {
+        let characteristics = options.characteristics.join(", ");
+        description.push_str(&format!(
+            " Only {} characteristics are included.",
+            characteristics
+        ));
+        restrictions.push(characteristics);
+    }
+    if !options.mappers.is_empty() {
+        let mappers = options.mappers.join(", ");
+        description.push_str(&format!(
+            " Only difficulties mapped by {} are included.",
+            mappers
+        ));
+        restrictions.push(format!("by {}", mappers));
+    }
+    if let Some(mapper) = &options.exact_mapper {
+        description.push_str(&format!(
+            " Only difficulties mapped by {} are included.",
+            mapper
+        ));
+        restrictions.push(format!("by {}", mapper));
+    }
+    if !options.title_scripts.is_empty() {
+        let scripts = options
+            .title_scripts
+            .iter()
+            .map(|x| x.name())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        description.push_str(&format!(
+            " Only songs with {} titles are included.",
+            scripts
+        ));
+        restrictions.push(format!("{} titles", scripts));
+    }
+    if !options.imported.is_empty() {
+        let imported = options.imported.join(", ");
+        description.push_str(&format!(
+            " Only songs of the playlists {} are included.",
+            imported
+        ));
+        restrictions.push(format!("from {}", imported));
+    }
+    for condition in options.derived.iter() {
+        description.push_str(&format!(
+            " Only difficulties with {} are included.",
+            condition
+        ));
+        restrictions.push(condition.to_string());
+    }
+    if let Some(player) = &options.exclude_played {
+        description.push_str(&format!(" Difficulties played by {} are excluded.", player));
+        restrictions.push(format!("unplayed by {}", player));
+    }
+    if let Some(player) = &options.exclude_fc {
+        description.push_str(&format!(
+            " Difficulties full combo'd by {} are excluded.",
+            player
+        ));
+        restrictions.push(format!("no FC by {}", player));
+    }
+    if options.exclude_installed.is_some() {
+        description.push_str(" Maps that are already installed are excluded.");
+        restrictions.push("missing".to_string());
+    }
+    if !restrictions.is_empty() {
+        title.push_str(&format!(" ({})", restrictions.join(", ")));
+    }
+    (title, description)
+}
+
+pub fn make_beatsaber_playlist(
+    db: &rusqlite::Connection,
+    options: &PlaylistOptions,
+) -> Result_<BeatsaberPlaylist> {
+    let _timer = profile::timer(profile::Kind::Playlist);
+    let shuffled;
+    let options = match options.sort {
+        SortOrder::Shuffle => {
+            shuffled = PlaylistOptions {
+                seed: Some(options.seed.unwrap_or_else(random_seed)),
+                ..options.clone()
+            };
+            &shuffled
+        }
+        _ => options,
+    };
+    let filter = filter(options)?;
+    let mut derived = options
+        .derived
+        .iter()
+        .map(|x| x.name.as_str())
+        .collect::<Vec<&str>>();
+    if let SortOrder::Derived(name) = &options.sort {
+        derived.push(name);
+    }
+    for name in derived {
+        let count: i64 = db.query_row(
+            "SELECT COUNT(*) FROM derived_values WHERE name = ?",
+            rusqlite::params![name],
+            |row| row.get(0),
+        )?;
+        if count == 0 {
+            return Err(format!(
+                "there is no derived column {} in the config or no songs",
+                name
+            ))?;
+        }
+    }
+    let (order, descending) = options.sort.expression();
+    let direction = if descending != options.reverse {
+        "DESC"
+    } else {
+        "ASC"
+    };
+    // GROUP_BY and MAX(stars) are needed because the same hash is part of multiple difficulties of
+    // the same song so we sort by the maximum of all difficulties. Songs without a value for the
+    // order come last and ties are broken by stars. Without filters every difficulty counts so the
+    // maximum is already known from best_difficulties.
+    let sql = if filter.only_ranked() && options.sort == SortOrder::Stars {
+        format!(
+            "SELECT b.hash, s.name FROM best_difficulties b JOIN scoresaber_songs s ON s.uid = b.uid ORDER BY b.stars {}",
+            direction
+        )
+    } else {
+        format!(
+            "SELECT s.id, s.name FROM {} {} GROUP BY s.id ORDER BY ({order}) IS NULL, {order} {}, MAX(s.stars) DESC",
+            SONGS_FROM,
+            filter.where_clause(),
+            direction,
+            order = order,
+        )
+    };
+    let mut statement = db.prepare(&sql)?;
+
+    // The difficulties of a song, hardest first.
+    let mut difficulties_statement = db.prepare(&format!(
+        "SELECT s.diff FROM {} {} {} s.id = ? ORDER BY s.stars DESC",
+        SONGS_FROM,
+        filter.where_clause(),
+        if filter.where_clause().is_empty() {
+            "WHERE"
+        } else {
+            "AND"
+        }
+    ))?;
+
+    let (title, description) = title_and_description(options);
+    let mut playlist = BeatsaberPlaylist {
+        title,
+        author: AUTHOR.to_string(),
+        description,
+        songs: vec![],
+    };
+
+    struct Song {
+        hash: String,
+        name: String,
+    }
+    let iter = statement.query_map(&filter.params(), |row| {
+        Ok(Song {
+            hash: row.get(0)?,
+            name: row.get(1)?,
+        })
+    })?;
+    for song_result in iter {
+        let song = song_result?;
+        let mut params = filter.params();
+        params.push(&song.hash);
+        let difficulties = difficulties_statement
+            .query_map(&params, |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        playlist.songs.push(BeatSaberPlaylistSong {
+            difficulties: difficulties
+                .iter()
+                .filter_map(|x| PlaylistDifficulty::from_diff(x))
+                .collect(),
+            name: song.name,
+            hash: song.hash,
+        });
+    }
+    if let (SortOrder::Shuffle, Some(seed)) = (&options.sort, options.seed) {
+        shuffle(&mut playlist.songs, seed);
+    }
+    Ok(playlist)
+}
+
+fn random_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+// Fisher-Yates with splitmix64 which is good enough for playlists and the same everywhere.
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+    for i in (1..items.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+// A playlist file written by this or another tool. bplist files also have an image.
+#[derive(serde::Deserialize)]
+struct ExistingPlaylist {
+    #[serde(flatten)]
+    playlist: BeatsaberPlaylist,
+    image: Option<String>,
+}
+
+fn read_existing(path: &std::path::Path) -> Result_<ExistingPlaylist> {
+    let file = std::fs::File::open(path)
+        .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    Ok(serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|err| format!("{} is not a playlist: {}", path.display(), err))?)
+}
+
+// Reads a json or bplist playlist file.
+pub fn read(path: &std::path::Path) -> Result_<BeatsaberPlaylist> {
+    Ok(read_existing(path)?.playlist)
+}
+
+// Hashes of the songs that the exclusion options leave out.
+fn excluded_hashes(options: &PlaylistOptions) -> Result_<std::collections::HashSet<String>> {
+    let mut hashes = std::collections::HashSet::new();
+    if let Some(path) = &options.exclude_file {
+        hashes.extend(read_exclusions(path)?.hashes);
+    }
+    if let Some(custom_levels) = &options.exclude_installed {
+        hashes.extend(crate::installed::installed_hashes(custom_levels)?);
+    }
+    Ok(hashes)
+}
+
+// Adds the songs of an existing playlist that are not part of the generated one after its songs
+// and takes over the title, author and description of the existing playlist. Songs that are part
+// of both get the difficulties of both. Returns the image of the existing playlist.
+pub fn merge(
+    playlist: &mut BeatsaberPlaylist,
+    path: &std::path::Path,
+    options: &PlaylistOptions,
+) -> Result_<Option<String>> {
+    let existing = read_existing(path)?;
+    let excluded = excluded_hashes(options)?;
+    for song in existing.playlist.songs {
+        let hash = song.hash.to_uppercase();
+        if excluded.contains(&hash) {
+            continue;
+        }
+        match playlist
+            .songs
+            .iter_mut()
+            .find(|x| x.hash.to_uppercase() == hash)
+        {
+            Some(generated) => {
+                for difficulty in song.difficulties {
+                    if !generated.difficulties.contains(&difficulty) {
+                        generated.difficulties.push(difficulty);
+                    }
+                }
+            }
+            None => playlist.songs.push(BeatSaberPlaylistSong { hash, ..song }),
+        }
+    }
+    playlist.title = existing.playlist.title;
+    playlist.author = existing.playlist.author;
+    playlist.description = existing.playlist.description;
+    Ok(existing.image)
+}
+
+// Star brackets between consecutive boundaries like 0-3, 3-5 and 5-7 with their names. The last
+// bracket has no upper limit.
+pub fn star_brackets(boundaries: &[f64]) -> Result_<Vec<(String, f64, Option<f64>)>> {
+    if boundaries.is_empty() || boundaries.windows(2).any(|x| x[0] >= x[1]) {
+        return Err("star bracket boundaries must be increasing")?;
+    }
+    Ok(boundaries
+        .iter()
+        .enumerate()
+        .map(|(i, &min)| {
+            let max = boundaries.get(i + 1).cloned();
+            let name = match max {
+                Some(max) => format!("{}-{}", min, max),
+                None => format!("{}+", min),
+            };
+            (name, min, max)
+        })
+        .collect())
+}
+
+// One playlist per star bracket. A difficulty exactly on a boundary is part of both brackets.
+// Returns the name of every bracket with its playlist and leaves out empty brackets.
+pub fn make_star_bucket_playlists(
+    db: &rusqlite::Connection,
+    options: &PlaylistOptions,
+    boundaries: &[f64],
+) -> Result_<Vec<(String, BeatsaberPlaylist)>> {
+    if options.min_stars.is_some() || options.max_stars.is_some() {
+        return Err("star brackets cannot be combined with --min-stars or --max-stars")?;
+    }
+    let mut playlists = vec![];
+    for (name, min, max) in star_brackets(boundaries)? {
+        let options = PlaylistOptions {
+            min_stars: Some(min),
+            max_stars: max,
+            ..options.clone()
+        };
+        let playlist = make_beatsaber_playlist(db, &options)?;
+        if !playlist.songs.is_empty() {
+            playlists.push((name, playlist));
+        }
+    }
+    Ok(playlists)
+}
+
+// One playlist per mapper with at least `min_songs` songs that pass the filters, most songs first.
+// Returns the mapper of every playlist.
+pub fn make_mapper_playlists(
+    db: &rusqlite::Connection,
+    options: &PlaylistOptions,
+    min_songs: usize,
+) -> Result_<Vec<(String, BeatsaberPlaylist)>> {
+    let filter = filter(options)?;
+    let min_songs = min_songs as i64;
+    let mut params = filter.params();
+    params.push(&min_songs);
+    let mut statement = db.prepare(&format!(
+        "SELECT s.levelAuthorName FROM {} {} GROUP BY s.levelAuthorName HAVING COUNT(DISTINCT s.id) >= ? ORDER BY COUNT(DISTINCT s.id) DESC, s.levelAuthorName COLLATE NOCASE",
+        SONGS_FROM,
+        filter.where_clause()
+    ))?;
+    let mappers = statement
+        .query_map(&params, |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut playlists = vec![];
+    for mapper in mappers {
+        let options = PlaylistOptions {
+            exact_mapper: Some(mapper.clone()),
+            ..options.clone()
+        };
+        playlists.push((mapper, make_beatsaber_playlist(db, &options)?));
+    }
+    Ok(playlists)
+}
+
+// Mapper names can contain anything so only letters and digits are kept for file names.
+pub fn mapper_file_stem(mapper: &str) -> String {
+    let name = mapper
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    format!("ranked_songs_by_{}", name)
+}
+
+// A named playlist from the config that `playlist --all` regenerates, like
+//
+// [[playlists]]
+// name = "practice"
+// title = "Practice"
+// min_stars = 6.0
+// max_stars = 8.0
+// difficulties = ["Expert", "ExpertPlus"]
+// sort = "bpm"
+//
+// The filters are the ones of the command line. `output` defaults to the name with the extension of
+// the playlist format.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlaylistProfile {
+    pub name: String,
+    pub output: Option<std::path::PathBuf>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub image: Option<std::path::PathBuf>,
+    pub min_stars: Option<f64>,
+    pub max_stars: Option<f64>,
+    pub min_bpm: Option<f64>,
+    pub max_bpm: Option<f64>,
+    pub min_nps: Option<f64>,
+    pub max_nps: Option<f64>,
+    pub max_njs: Option<f64>,
+    pub min_duration: Option<String>,
+    pub max_duration: Option<String>,
+    pub ranked_within_days: Option<u64>,
+    pub ranked_since: Option<String>,
+    pub include_invalid_bpm: bool,
+    pub difficulties: Vec<String>,
+    pub characteristics: Vec<String>,
+    pub mappers: Vec<String>,
+    pub title_scripts: Vec<String>,
+    pub imported: Vec<String>,
+    pub exclude_played: Option<crate::player::PlayerId>,
+    pub exclude_fc: Option<crate::player::PlayerId>,
+    pub derived: Vec<String>,
+    pub exclude_file: Option<std::path::PathBuf>,
+    pub exclude_installed: Option<std::path::PathBuf>,
+    pub merge: Option<std::path::PathBuf>,
+    pub sort: Option<String>,
+    pub reverse: bool,
+    pub seed: Option<u64>,
+}
+
+impl PlaylistProfile {
+    fn options(&self) -> Result_<PlaylistOptions> {
+        let error = |err: String| format!("invalid playlist {}: {}", self.name, err);
+        Ok(PlaylistOptions {
+            min_stars: self.min_stars,
+            max_stars: self.max_stars,
+            min_bpm: self.min_bpm,
+            max_bpm: self.max_bpm,
+            min_nps: self.min_nps,
+            max_nps: self.max_nps,
+            max_njs: self.max_njs,
+            min_duration: match &self.min_duration {
+                Some(duration) => Some(duration.parse().map_err(error)?),
+                None => None,
+            },
+            max_duration: match &self.max_duration {
+                Some(duration) => Some(duration.parse().map_err(error)?),
+                None => None,
+            },
+            ranked_within_days: self.ranked_within_days,
+            ranked_since: match &self.ranked_since {
+                Some(since) => Some(since.parse().map_err(error)?),
+                None => None,
+            },
+            include_invalid_bpm: self.include_invalid_bpm,
+            difficulties: self
+                .difficulties
+                .iter()
+                .map(|x| x.parse::<crate::Difficulty>())
+                .collect::<std::result::Result<_, String>>()
+                .map_err(error)?,
+            characteristics: self.characteristics.clone(),
+            mappers: self.mappers.clone(),
+            title_scripts: self
+                .title_scripts
+                .iter()
+                .map(|x| x.parse::<crate::script::Script>())
+                .collect::<std::result::Result<_, String>>()
+                .map_err(error)?,
+            imported: self.imported.clone(),
+            exclude_played: self.exclude_played.clone(),
+            exclude_fc: self.exclude_fc.clone(),
+            derived: self
+                .derived
+                .iter()
+                .map(|x| x.parse::<DerivedCondition>())
+                .collect::<std::result::Result<_, String>>()
+                .map_err(error)?,
+            exclude_file: self.exclude_file.clone(),
+            exclude_installed: self.exclude_installed.clone(),
+            sort: match &self.sort {
+                Some(sort) => sort.parse().map_err(error)?,
+                None => SortOrder::default(),
+            },
+            reverse: self.reverse,
+            seed: self.seed,
+            exact_mapper: None,
+        })
+    }
+
+    // The files besides the config and the database that the playlist is made from.
+    fn inputs(&self, output: &PlaylistOutput) -> Vec<std::path::PathBuf> {
+        vec![
+            self.exclude_file.as_ref(),
+            self.exclude_installed.as_ref(),
+            self.merge.as_ref(),
+            self.image.as_ref().or_else(|| output.image.as_ref()),
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+    }
+
+    // Also returns the image of the merged playlist.
+    fn make_playlist(
+        &self,
+        db: &rusqlite::Connection,
+    ) -> Result_<(BeatsaberPlaylist, Option<String>)> {
+        let options = self.options()?;
+        let mut playlist = make_beatsaber_playlist(db, &options)?;
+        let image = match &self.merge {
+            Some(path) => merge(&mut playlist, path, &options)?,
+            None => None,
+        };
+        if let Some(title) = &self.title {
+            playlist.title = title.clone();
+        }
+        if let Some(description) = &self.description {
+            playlist.description = description.clone();
+        }
+        if let Some(author) = &self.author {
+            playlist.author = author.clone();
+        }
+        Ok((playlist, image))
+    }
+}
+
+// Regenerates every playlist profile. The profiles are checked before anything is written so that
+// a typo in the last one does not leave the others half updated.
+pub fn save_profile_playlists(
+    db: &rusqlite::Connection,
+    profiles: &[PlaylistProfile],
+    output: &PlaylistOutput,
+) -> Result_<()> {
+    check_profiles(profiles)?;
+    for profile in profiles {
+        save_profile_playlist(db, profile, output)?;
+    }
+    Ok(())
+}
+
+fn check_profiles(profiles: &[PlaylistProfile]) -> Result_<()> {
+    if profiles.is_empty() {
+        return Err("the config does not define any playlists")?;
+    }
+    let mut names = std::collections::HashSet::new();
+    for profile in profiles {
+        if profile.name.is_empty() {
+            return Err("every playlist in the config needs a name")?;
+        }
+        if !names.insert(profile.name.as_str()) {
+            return Err(format!(
+                "the config defines playlist {} twice",
+                profile.name
+            ))?;
+        }
+        profile.options()?;
+    }
+    Ok(())
+}
+
+fn save_profile_playlist(
+    db: &rusqlite::Connection,
+    profile: &PlaylistProfile,
+    output: &PlaylistOutput,
+) -> Result_<()> {
+    let (playlist, merged_image) = profile.make_playlist(db)?;
+    let output = PlaylistOutput {
+        image: profile.image.clone().or_else(|| output.image.clone()),
+        merged_image,
+        ..output.clone()
+    };
+    let path = profile
+        .output
+        .clone()
+        .unwrap_or_else(|| output.default_path(&profile.name));
+    save_named_playlist(playlist, &profile.name, &path, &output)
+}
+
+// The profiles whose playlist differs after the config changed from `previous` to `profiles`. A
+// changed output changes every playlist.
+fn changed_profiles<'a>(
+    previous: &[PlaylistProfile],
+    previous_output: &PlaylistOutput,
+    profiles: &'a [PlaylistProfile],
+    output: &PlaylistOutput,
+) -> Vec<&'a PlaylistProfile> {
+    profiles
+        .iter()
+        .filter(|profile| previous_output != output || !previous.contains(profile))
+        .collect()
+}
+
+// How often `watch_profile_playlists` looks at the files.
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+fn modified(path: &std::path::Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+// The modification times of the inputs of every profile, None for the ones that do not exist.
+fn input_times(
+    profiles: &[PlaylistProfile],
+    output: &PlaylistOutput,
+) -> std::collections::HashMap<std::path::PathBuf, Option<std::time::SystemTime>> {
+    profiles
+        .iter()
+        .flat_map(|profile| profile.inputs(output))
+        .map(|path| {
+            let time = modified(&path);
+            (path, time)
+        })
+        .collect()
+}
+
+// Writes every playlist profile and then regenerates the changed ones from the stored songs
+// whenever the modification time of the `config` file or of a file a profile refers to, like its
+// exclude_file, changes, so that tuning a profile shows its playlist right away. `load` reads the
+// profiles and the output from the config. A config that cannot be loaded or checked is reported
+// and the playlists are left as they are until it is fixed. Runs until it is stopped.
+pub fn watch_profile_playlists(
+    db: &rusqlite::Connection,
+    config: &std::path::Path,
+    load: impl Fn() -> Result_<(Vec<PlaylistProfile>, PlaylistOutput)>,
+) -> Result_<()> {
+    let mut last_modified = modified(config);
+    let (mut profiles, mut output) = load()?;
+    save_profile_playlists(db, &profiles, &output)?;
+    let mut last_inputs = input_times(&profiles, &output);
+    println!(
+        "watching {} and the files of its playlists for changes",
+        config.display()
+    );
+    loop {
+        std::thread::sleep(WATCH_INTERVAL);
+        let mut regenerate = vec![];
+        if modified(config) != last_modified {
+            last_modified = modified(config);
+            match load().and_then(|loaded| {
+                check_profiles(&loaded.0)?;
+                Ok(loaded)
+            }) {
+                Ok((reloaded, reloaded_output)) => {
+                    regenerate.extend(
+                        changed_profiles(&profiles, &output, &reloaded, &reloaded_output)
+                            .into_iter()
+                            .cloned(),
+                    );
+                    profiles = reloaded;
+                    output = reloaded_output;
+                }
+                Err(err) => log::error!(
+                    "keeping the playlists because the config cannot be loaded: {}",
+                    err
+                ),
+            }
+        }
+        let inputs = input_times(&profiles, &output);
+        for profile in profiles.iter() {
+            let changed = profile
+                .inputs(&output)
+                .iter()
+                .any(|path| last_inputs.get(path) != inputs.get(path));
+            if changed && !regenerate.iter().any(|x| x.name == profile.name) {
+                regenerate.push(profile.clone());
+            }
+        }
+        for profile in regenerate.iter() {
+            match save_profile_playlist(db, profile, &output) {
+                Ok(()) => println!("regenerated playlist {}", profile.name),
+                Err(err) => log::error!("could not regenerate playlist {}: {}", profile.name, err),
+            }
+        }
+        // After writing so that a profile that merges into its own output is not regenerated
+        // again.
+        last_inputs = match regenerate.is_empty() {
+            true => inputs,
+            false => input_times(&profiles, &output),
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaylistFormat {
+    // The original format understood by every playlist loader.
+    Json,
+    // PlaylistManager's format with the additional fields.
+    Bplist,
+}
+
+impl PlaylistFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            PlaylistFormat::Json => "json",
+            PlaylistFormat::Bplist => "bplist",
+        }
+    }
+}
+
+impl std::str::FromStr for PlaylistFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(PlaylistFormat::Json),
+            "bplist" => Ok(PlaylistFormat::Bplist),
+            _ => Err(format!(
+                "unknown playlist format {}, expected json or bplist",
+                s
+            )),
+        }
+    }
+}
+
+// How playlists are written. Applies to every command that writes playlists.
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct PlaylistOutput {
+    /// Format of written playlists: json or bplist.
+    #[structopt(long = "playlist-format", default_value = "json")]
+    pub format: PlaylistFormat,
+    /// Cover image of bplist playlists.
+    #[structopt(long = "playlist-image", parse(from_os_str))]
+    pub image: Option<std::path::PathBuf>,
+    /// Url of the directory bplist playlists are hosted in. PlaylistManager updates a playlist
+    /// from `<url>/<file name>`.
+    #[structopt(long)]
+    pub sync_url: Option<String>,
+    /// Also writes every playlist into this directory, for example the checkout of the hosted
+    /// copies.
+    #[structopt(long, parse(from_os_str))]
+    pub sync_directory: Option<std::path::PathBuf>,
+    /// Also writes every playlist into the `Playlists` folder of this Beat Saber installation.
+    /// `auto` finds it in the usual Steam and Oculus locations, also for Proton on Linux.
+    #[structopt(long)]
+    pub install_dir: Option<crate::installed::InstallDir>,
+    /// Splits playlists with more songs into numbered parts like `ranked_songs_1.json` because
+    /// Beat Saber and some playlist managers struggle with very long playlists.
+    #[structopt(long)]
+    pub max_songs_per_playlist: Option<usize>,
+    /// Playlists written from data whose last crawl finished more than this many days ago say so
+    /// in their description.
+    #[structopt(long, default_value = "14")]
+    pub stale_after_days: f64,
+    /// Renders a cover with the title, the filters and the song count for bplist playlists
+    /// without `--playlist-image` so that split and bucket playlists are easy to tell apart.
+    #[structopt(long)]
+    pub generate_cover: bool,
+    // Data uri of the image of a merged playlist which is kept unless `image` replaces it.
+    #[structopt(skip)]
+    pub merged_image: Option<String>,
+    // From the config because it is configured per output target.
+    #[structopt(skip)]
+    pub post_process: crate::output::PostProcess,
+    // Appended to the descriptions when the data is outdated, see stale_warning.
+    #[structopt(skip)]
+    pub stale_warning: Option<String>,
+}
+
+impl PlaylistOutput {
+    // Default path of a playlist named `stem` in the current directory.
+    pub fn default_path(&self, stem: &str) -> std::path::PathBuf {
+        format!("{}.{}", stem, self.format.extension()).into()
+    }
+}
+
+// A warning for playlist descriptions if the last finished crawl is older than `days` so that players
+// do not trust outdated star ratings. Databases without a finished crawl, for example imported ones,
+// get none. Enrichment services whose last pass failed for some items are named too.
+pub fn stale_warning(db: &rusqlite::Connection, days: f64) -> Result_<Option<String>> {
+    let age: Option<f64> = db.query_row(
+        "SELECT julianday('now') - julianday(MAX(finished_at)) FROM crawls",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+    let mut warnings = age
+        .filter(|age| *age > days)
+        .map(|age| {
+            format!(
+                "Warning: data may be outdated — last updated {} days ago.",
+                age.floor()
+            )
+        })
+        .into_iter()
+        .collect::<Vec<String>>();
+    for (service, failed, since) in crate::provenance::stale_services(db)? {
+        warnings.push(format!(
+            "Warning: {} data may be outdated — {} items failed to update since {}.",
+            service, failed, since
+        ));
+    }
+    Ok(match warnings.is_empty() {
+        true => None,
+        false => Some(warnings.join(" ")),
+    })
+}
+
+#[derive(serde::Serialize)]
+struct Bplist<'a> {
+    #[serde(flatten)]
+    playlist: &'a BeatsaberPlaylist,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    #[serde(rename = "allowDuplicates")]
+    allow_duplicates: bool,
+    #[serde(rename = "customData")]
+    custom_data: serde_json::Map<String, serde_json::Value>,
+}
+
+// Data uri of the image like PlaylistManager expects it.
+fn image_data(path: &std::path::Path) -> Result_<String> {
+    let mime = match path.extension().and_then(|x| x.to_str()) {
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        _ => "image/png",
+    };
+    let bytes = std::fs::read(path)?;
+    Ok(format!("data:{};base64,{}", mime, base64::encode(&bytes)))
+}
+
+// Playlist managers that key playlists on an id update them in place. The id depends only on the
+// name of the playlist, the profile name for profiles, and the part of a split playlist so it stays
+// the same when the title, the songs or the output path change.
+fn playlist_id(name: &str, part: Option<usize>) -> String {
+    let namespace = uuid::Uuid::new_v5(
+        &uuid::Uuid::NAMESPACE_URL,
+        b"https://github.com/e00E/scoresaber-crawler",
+    );
+    let name = match part {
+        Some(part) => format!("{}#{}", name, part),
+        None => name.to_string(),
+    };
+    uuid::Uuid::new_v5(&namespace, name.as_bytes()).to_string()
+}
+
+fn custom_data(
+    file_name: &str,
+    id: &str,
+    output: &PlaylistOutput,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut custom_data = serde_json::Map::new();
+    custom_data.insert("id".to_string(), id.into());
+    if let Some(sync_url) = &output.sync_url {
+        custom_data.insert(
+            "syncURL".to_string(),
+            format!("{}/{}", sync_url.trim_end_matches('/'), file_name).into(),
+        );
+    }
+    custom_data
+}
+
+// Splits the playlist into parts of at most `max_songs` songs in the same order. The parts are
+// numbered from 1 in their titles and file names. A playlist that fits is not a part.
+fn split(
+    playlist: BeatsaberPlaylist,
+    path: &std::path::Path,
+    max_songs: usize,
+) -> Vec<(BeatsaberPlaylist, std::path::PathBuf, Option<usize>)> {
+    if playlist.songs.len() <= max_songs {
+        return vec![(playlist, path.to_path_buf(), None)];
+    }
+    let stem = path
+        .file_stem()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|x| format!(".{}", x.to_string_lossy()))
+        .unwrap_or_default();
+    let chunks = playlist.songs.chunks(max_songs).collect::<Vec<_>>();
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, songs)| {
+            let part = BeatsaberPlaylist {
+                title: format!("{} (part {} of {})", playlist.title, i + 1, chunks.len()),
+                author: playlist.author.clone(),
+                description: playlist.description.clone(),
+                songs: songs.to_vec(),
+            };
+            let path = path.with_file_name(format!("{}_{}{}", stem, i + 1, extension));
+            (part, path, Some(i + 1))
+        })
+        .collect()
+}
+
+// Playlists that are not from a profile are named after the file they are written to.
+pub fn save_beatsaber_playlist(
+    playlist: BeatsaberPlaylist,
+    path: &std::path::Path,
+    output: &PlaylistOutput,
+) -> Result_<()> {
+    let name = path
+        .file_stem()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    save_named_playlist(playlist, &name, path, output)
+}
+
+fn save_named_playlist(
+    playlist: BeatsaberPlaylist,
+    name: &str,
+    path: &std::path::Path,
+    output: &PlaylistOutput,
+) -> Result_<()> {
+    match output.max_songs_per_playlist {
+        Some(0) => Err("--max-songs-per-playlist must be at least 1")?,
+        Some(max_songs) => {
+            for (part, path, index) in split(playlist, path, max_songs) {
+                write_playlist(part, &playlist_id(name, index), &path, output)?;
+            }
+            Ok(())
+        }
+        None => write_playlist(playlist, &playlist_id(name, None), path, output),
+    }
+}
+
+fn write_playlist(
+    mut playlist: BeatsaberPlaylist,
+    id: &str,
+    path: &std::path::Path,
+    output: &PlaylistOutput,
+) -> Result_<()> {
+    let _timer = profile::timer(profile::Kind::Playlist);
+    if let Some(warning) = &output.stale_warning {
+        playlist.description.push('\n');
+        playlist.description.push_str(warning);
+    }
+    let file_name = match path.file_name().and_then(|x| x.to_str()) {
+        Some(file_name) => file_name,
+        None => return Err(format!("invalid playlist path {}", path.display()))?,
+    };
+    let json = match output.format {
+        PlaylistFormat::Json => {
+            if output.sync_url.is_some() {
+                return Err("--sync-url needs --playlist-format bplist")?;
+            }
+            output.post_process.json(&playlist)?
+        }
+        PlaylistFormat::Bplist => {
+            let image = match (&output.image, &output.merged_image) {
+                (Some(image), _) => Some(image_data(image)?),
+                (None, Some(merged_image)) => Some(merged_image.clone()),
+                (None, None) if output.generate_cover => Some(format!(
+                    "data:image/png;base64,{}",
+                    base64::encode(&crate::cover::render(&playlist)?)
+                )),
+                (None, None) => None,
+            };
+            let bplist = Bplist {
+                playlist: &playlist,
+                image,
+                allow_duplicates: false,
+                custom_data: custom_data(file_name, id, output),
+            };
+            output.post_process.json(&bplist)?
+        }
+    };
+    std::fs::write(path, &json)?;
+    if let Some(directory) = &output.sync_directory {
+        std::fs::write(directory.join(file_name), &json)?;
+    }
+    if let Some(install_dir) = &output.install_dir {
+        std::fs::write(install_dir.playlists()?.join(file_name), &json)?;
+    }
+    println!(
+        "Used {} songs in playlist {}.",
+        playlist.songs.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_star_range() {
+        let db = crate::test_db();
+        let options = PlaylistOptions {
+            min_stars: Some(9.5),
+            max_stars: Some(10.0),
+            ..Default::default()
+        };
+        let playlist = make_beatsaber_playlist(&db, &options).unwrap();
+        assert_eq!(playlist.title, "Ranked Songs (9.5 to 10 stars)");
+        let names = playlist
+            .songs
+            .iter()
+            .map(|song| song.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, ["Happppy song"]);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_characteristic() {
+        let db = crate::test_db();
+        let mut song = crate::test_songs()
+            .into_iter()
+            .find(|song| song.name == "NUCLEAR-STAR")
+            .unwrap();
+        song.difficulty = "_Hard_SoloOneSaber".to_string();
+        crate::insert_song_into_db(&db, &song).unwrap();
+        let options = PlaylistOptions {
+            characteristics: vec!["onesaber".to_string()],
+            ..Default::default()
+        };
+        let playlist = make_beatsaber_playlist(&db, &options).unwrap();
+        assert_eq!(playlist.songs.len(), 1);
+        assert_eq!(playlist.songs[0].name, "NUCLEAR-STAR");
+        assert_eq!(
+            playlist.songs[0].difficulties,
+            [PlaylistDifficulty {
+                characteristic: "OneSaber".to_string(),
+                name: "Hard".to_string(),
+            }]
+        );
+        let options = PlaylistOptions {
+            difficulties: vec![crate::Difficulty::ExpertPlus],
+            ..Default::default()
+        };
+        let playlist = make_beatsaber_playlist(&db, &options).unwrap();
+        assert_eq!(playlist.songs.len(), 2);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_mapper() {
+        assert_eq!(mapper_glob("Hexa"), "*hexa*");
+        assert_eq!(mapper_glob("a[b"), "*a[[]b*");
+        assert_eq!(mapper_glob("Hex*l"), "hex*l");
+        let db = crate::test_db();
+        for (mappers, count) in &[
+            (vec!["hexagon"], 3),
+            (vec!["hex*"], 3),
+            (vec!["hex"], 3),
+            (vec!["hex?"], 0),
+            (vec!["other", "nial"], 3),
+        ] {
+            let options = PlaylistOptions {
+                mappers: mappers.iter().map(|x| x.to_string()).collect(),
+                ..Default::default()
+            };
+            let playlist = make_beatsaber_playlist(&db, &options).unwrap();
+            assert_eq!(playlist.songs.len(), *count, "{:?}", mappers);
+        }
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_sort() {
+        let db = crate::test_db();
+        let options = PlaylistOptions {
+            sort: "bpm".parse().unwrap(),
+            ..Default::default()
+        };
+        let playlist = make_beatsaber_playlist(&db, &options).unwrap();
+        assert_eq!(playlist.songs.len(), 3);
+        assert_eq!(playlist.songs[0].name, "NUCLEAR-STAR");
+        assert_eq!(playlist.songs[2].name, "Milk Crown on Sonnetica");
+        let options = PlaylistOptions {
+            reverse: true,
+            ..Default::default()
+        };
+        let playlist = make_beatsaber_playlist(&db, &options).unwrap();
+        assert_eq!(playlist.songs[0].name, "NUCLEAR-STAR");
+        assert!(playlist.description.ends_with("in ascending order."));
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_profile() {
+        let db = crate::test_db();
+        let profile: PlaylistProfile = toml::from_str(
+            r#"
+            name = "hard"
+            title = "Hard songs"
+            min_stars = 9.5
+            difficulties = ["expertplus"]
+            sort = "name"
+            "#,
+        )
+        .unwrap();
+        let (playlist, _) = profile.make_playlist(&db).unwrap();
+        assert_eq!(playlist.title, "Hard songs");
+        assert_eq!(playlist.author, AUTHOR);
+        assert_eq!(playlist.songs.len(), 2);
+        let profile = PlaylistProfile {
+            sort: Some("?".to_string()),
+            ..profile
+        };
+        assert!(profile.options().is_err());
+        assert!(toml::from_str::<PlaylistProfile>("name = \"a\"\nunknown = 1").is_err());
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_changed_profiles() {
+        let profile = |name: &str, min_stars: f64| PlaylistProfile {
+            name: name.to_string(),
+            min_stars: Some(min_stars),
+            ..PlaylistProfile::default()
+        };
+        let previous = vec![profile("a", 1.0), profile("b", 2.0)];
+        let output = PlaylistOutput::from_iter(&[""]);
+        let profiles = vec![profile("a", 1.0), profile("b", 3.0), profile("c", 1.0)];
+        let names = |changed: Vec<&PlaylistProfile>| {
+            changed
+                .iter()
+                .map(|x| x.name.clone())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            names(changed_profiles(&previous, &output, &profiles, &output)),
+            ["b", "c"]
+        );
+        assert!(changed_profiles(&previous, &output, &previous, &output).is_empty());
+        let bplist = PlaylistOutput {
+            format: PlaylistFormat::Bplist,
+            ..output.clone()
+        };
+        assert_eq!(
+            names(changed_profiles(&previous, &output, &previous, &bplist)),
+            ["a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_profile_inputs() {
+        let output = PlaylistOutput::from_iter(&["", "--playlist-image", "cover.png"]);
+        let profile = PlaylistProfile {
+            name: "a".to_string(),
+            exclude_file: Some("exclusions.txt".into()),
+            merge: Some("favorites.bplist".into()),
+            ..PlaylistProfile::default()
+        };
+        assert_eq!(
+            profile.inputs(&output),
+            [
+                std::path::PathBuf::from("exclusions.txt"),
+                "favorites.bplist".into(),
+                "cover.png".into()
+            ]
+        );
+        let profile = PlaylistProfile {
+            image: Some("own.png".into()),
+            ..profile
+        };
+        assert_eq!(profile.inputs(&output)[2], std::path::Path::new("own.png"));
+    }
+
+    #[test]
+    fn test_star_buckets() {
+        let db = crate::test_db();
+        let playlists =
+            make_star_bucket_playlists(&db, &PlaylistOptions::default(), &[0.0, 3.0, 5.0, 9.5])
+                .unwrap();
+        let buckets = playlists
+            .iter()
+            .map(|(name, playlist)| (name.as_str(), playlist.songs.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(buckets, [("0-3", 1), ("5-9.5", 1), ("9.5+", 2)]);
+        assert_eq!(playlists[2].1.title, "Ranked Songs (at least 9.5 stars)");
+        assert!(make_star_bucket_playlists(&db, &PlaylistOptions::default(), &[3.0, 3.0]).is_err());
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_mapper_playlists() {
+        let db = crate::test_db();
+        db.execute(
+            "UPDATE scoresaber_songs SET levelAuthorName = 'Ra*' WHERE name = 'NUCLEAR-STAR'",
+            rusqlite::params![],
+        )
+        .unwrap();
+        let mappers = |min_songs| {
+            make_mapper_playlists(&db, &PlaylistOptions::default(), min_songs)
+                .unwrap()
+                .into_iter()
+                .map(|(mapper, playlist)| (mapper, playlist.songs.len()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(mappers(2), [("Hexagonial".to_string(), 2)]);
+        assert_eq!(
+            mappers(1),
+            [("Hexagonial".to_string(), 2), ("Ra*".to_string(), 1)]
+        );
+        let playlists = make_mapper_playlists(&db, &PlaylistOptions::default(), 1).unwrap();
+        assert_eq!(playlists[1].1.title, "Ranked Songs (by Ra*)");
+        assert_eq!(mapper_file_stem("Ra*"), "ranked_songs_by_Ra_");
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_bplist() {
+        let playlist = BeatsaberPlaylist {
+            title: "title".to_string(),
+            author: AUTHOR.to_string(),
+            description: "description".to_string(),
+            songs: vec![],
+        };
+        let bplist = Bplist {
+            playlist: &playlist,
+            image: None,
+            allow_duplicates: false,
+            custom_data: serde_json::Map::new(),
+        };
+        let value = serde_json::to_value(&bplist).unwrap();
+        assert_eq!(value["playlistTitle"], "title");
+        assert_eq!(value["allowDuplicates"], false);
+        assert!(value["customData"].is_object());
+        assert!(value.get("image").is_none());
+    }
+
+    #[test]
+    fn test_sync_url() {
+        let output = PlaylistOutput {
+            format: PlaylistFormat::Bplist,
+            image: None,
+            sync_url: Some("https://example.com/playlists/".to_string()),
+            sync_directory: None,
+            install_dir: None,
+            max_songs_per_playlist: None,
+            stale_after_days: 14.0,
+            generate_cover: false,
+            merged_image: None,
+            post_process: Default::default(),
+            stale_warning: None,
+        };
+        let custom_data = custom_data("ranked_songs.bplist", "id", &output);
+        assert_eq!(
+            custom_data["syncURL"],
+            "https://example.com/playlists/ranked_songs.bplist"
+        );
+    }
+
+    #[test]
+    fn test_playlist_id() {
+        assert_eq!(playlist_id("hard", None), playlist_id("hard", None));
+        assert_ne!(playlist_id("hard", None), playlist_id("easy", None));
+        assert_ne!(playlist_id("hard", None), playlist_id("hard", Some(1)));
+        assert_ne!(playlist_id("hard", Some(1)), playlist_id("hard", Some(2)));
+        // The id of a profile playlist does not change with its output path.
+        let db = crate::test_db();
+        let profile = |path: &std::path::Path| PlaylistProfile {
+            name: "hard".to_string(),
+            output: Some(path.to_path_buf()),
+            ..PlaylistProfile::default()
+        };
+        // Two parts.
+        let songs = profile(std::path::Path::new(""))
+            .make_playlist(&db)
+            .unwrap()
+            .0
+            .songs
+            .len();
+        let output = PlaylistOutput {
+            format: PlaylistFormat::Bplist,
+            max_songs_per_playlist: Some((songs + 1) / 2),
+            ..PlaylistOutput::from_iter(&[""])
+        };
+        let ids = |file_name: &str| {
+            let path = std::env::temp_dir().join(file_name);
+            save_profile_playlist(&db, &profile(&path), &output).unwrap();
+            let id = |part: usize| {
+                let path = path.with_file_name(format!(
+                    "{}_{}.bplist",
+                    path.file_stem().unwrap().to_str().unwrap(),
+                    part
+                ));
+                let value: serde_json::Value =
+                    serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+                std::fs::remove_file(&path).unwrap();
+                value["customData"]["id"].as_str().unwrap().to_string()
+            };
+            (id(1), id(2))
+        };
+        let ids_a = ids("scoresaber-crawler-test-id-a.bplist");
+        assert_eq!(ids_a, ids("scoresaber-crawler-test-id-b.bplist"));
+        assert_eq!(ids_a.0, playlist_id("hard", Some(1)));
+        assert_ne!(ids_a.0, ids_a.1);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_exclude_played() {
+        let db = crate::test_db();
+        let milk_crown = crate::test_songs()
+            .into_iter()
+            .find(|song| song.name == "Milk Crown on Sonnetica")
+            .unwrap()
+            .uid;
+        db.execute(
+            "INSERT INTO player_scores (player_id, uid, score_id, rank, base_score, modified_score, pp, weight, modifiers, bad_cuts, missed_notes, max_combo, full_combo, time_set) VALUES ('1', ?, 1, 1, 1, 1, 1, 1, '', 0, 0, 1, 1, '2019-01-01 00:00:00')",
+            rusqlite::params![milk_crown as i64],
+        )
+        .unwrap();
+        for options in &[
+            PlaylistOptions {
+                exclude_played: Some("1".to_string()),
+                ..Default::default()
+            },
+            PlaylistOptions {
+                exclude_fc: Some("1".to_string()),
+                ..Default::default()
+            },
+        ] {
+            let playlist = make_beatsaber_playlist(&db, options).unwrap();
+            assert_eq!(playlist.songs.len(), 2);
+            assert!(playlist
+                .songs
+                .iter()
+                .all(|song| song.name != "Milk Crown on Sonnetica"));
+        }
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_exclude_file() {
+        let db = crate::test_db();
+        let path = std::env::temp_dir().join("scoresaber-crawler-test-exclusions.txt");
+        std::fs::write(
+            &path,
+            "# broken\ncfca2fe00bcc418dc9ecf64d92fc01ceec52c375\n\n100024 # NUCLEAR-STAR\n",
+        )
+        .unwrap();
+        let options = PlaylistOptions {
+            exclude_file: Some(path.clone()),
+            ..Default::default()
+        };
+        let playlist = make_beatsaber_playlist(&db, &options).unwrap();
+        let names = playlist
+            .songs
+            .iter()
+            .map(|song| song.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, ["Happppy song"]);
+        // More entries than SQLite allows parameters.
+        let mut text = String::from("cfca2fe00bcc418dc9ecf64d92fc01ceec52c375\n");
+        for i in 0..1500 {
+            text.push_str(&format!("f{:039x}\n{}\n", i, 1_000_000 + i));
+        }
+        std::fs::write(&path, text).unwrap();
+        let playlist = make_beatsaber_playlist(&db, &options).unwrap();
+        assert_eq!(playlist.songs.len(), 2);
+        std::fs::write(&path, "not a hash\n").unwrap();
+        assert!(make_beatsaber_playlist(&db, &options).is_err());
+        std::fs::remove_file(&path).unwrap();
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_exclude_installed() {
+        let db = crate::test_db();
+        // More maps than SQLite allows parameters.
+        let custom_levels = std::env::temp_dir().join("scoresaber-crawler-test-exclude-installed");
+        for i in 0..1200 {
+            let map = custom_levels.join(i.to_string());
+            std::fs::create_dir_all(&map).unwrap();
+            std::fs::write(
+                map.join("info.dat"),
+                format!(r#"{{"_songName":"{}","_difficultyBeatmapSets":[]}}"#, i),
+            )
+            .unwrap();
+        }
+        // The hash of the first map.
+        db.execute(
+            "UPDATE scoresaber_songs SET id = 'FBD153AD54AA91AF0A86630875C6346EF76A12D5' WHERE name = 'NUCLEAR-STAR'",
+            rusqlite::params![],
+        )
+        .unwrap();
+        let options = PlaylistOptions {
+            exclude_installed: Some(custom_levels.clone()),
+            ..Default::default()
+        };
+        let playlist = make_beatsaber_playlist(&db, &options).unwrap();
+        assert_eq!(playlist.songs.len(), 2);
+        assert!(playlist
+            .songs
+            .iter()
+            .all(|song| song.name != "NUCLEAR-STAR"));
+        assert_eq!(playlist.title, "Ranked Songs (missing)");
+        std::fs::remove_dir_all(&custom_levels).unwrap();
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_derived() {
+        let db = crate::test_db();
+        let columns = [crate::derived::DerivedColumn {
+            name: "pp95".to_string(),
+            expression: "pp(stars, 0.95)".to_string(),
+        }];
+        crate::derived::materialize(&db, &columns).unwrap();
+        let options = PlaylistOptions {
+            derived: vec!["pp95 >= 400".parse().unwrap()],
+            sort: "pp95".parse().unwrap(),
+            reverse: true,
+            ..Default::default()
+        };
+        let playlist = make_beatsaber_playlist(&db, &options).unwrap();
+        let names = playlist
+            .songs
+            .iter()
+            .map(|song| song.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, ["Happppy song", "Milk Crown on Sonnetica"]);
+        assert_eq!(playlist.title, "Ranked Songs (pp95 >= 400)");
+        let options = PlaylistOptions {
+            sort: "pp90".parse().unwrap(),
+            ..Default::default()
+        };
+        assert!(make_beatsaber_playlist(&db, &options).is_err());
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_split() {
+        let song = |name: &str| BeatSaberPlaylistSong {
+            name: name.to_string(),
+            hash: name.to_string(),
+            difficulties: vec![],
+        };
+        let playlist = BeatsaberPlaylist {
+            title: "Ranked Songs".to_string(),
+            author: AUTHOR.to_string(),
+            description: String::new(),
+            songs: vec![song("a"), song("b"), song("c")],
+        };
+        let path = std::path::Path::new("dir/ranked_songs.json");
+        assert_eq!(split(playlist.clone(), path, 3).len(), 1);
+        let parts = split(playlist, path, 2);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].0.title, "Ranked Songs (part 1 of 2)");
+        assert_eq!(parts[0].0.songs.len(), 2);
+        assert_eq!(parts[1].0.songs[0].name, "c");
+        assert_eq!(parts[1].1, std::path::Path::new("dir/ranked_songs_2.json"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let db = crate::test_db();
+        let existing_path = std::env::temp_dir().join("scoresaber-crawler-test-merge.bplist");
+        std::fs::write(
+            &existing_path,
+            r#"{
+                "playlistTitle": "Favorites",
+                "playlistAuthor": "me",
+                "playlistDescription": "",
+                "image": "data:image/png;base64,AAAA",
+                "songs": [
+                    {"songName": "Milk Crown on Sonnetica", "hash": "cfca2fe00bcc418dc9ecf64d92fc01ceec52c375",
+                     "difficulties": [{"characteristic": "Standard", "name": "Expert"}]},
+                    {"songName": "Unranked", "hash": "0000000000000000000000000000000000000001"},
+                    {"songName": "Broken", "hash": "0000000000000000000000000000000000000002"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let exclusions_path =
+            std::env::temp_dir().join("scoresaber-crawler-test-merge-exclusions.txt");
+        std::fs::write(
+            &exclusions_path,
+            "0000000000000000000000000000000000000002\n",
+        )
+        .unwrap();
+        let options = PlaylistOptions {
+            exclude_file: Some(exclusions_path.clone()),
+            ..Default::default()
+        };
+        let mut playlist = make_beatsaber_playlist(&db, &options).unwrap();
+        let image = merge(&mut playlist, &existing_path, &options).unwrap();
+        assert_eq!(image.unwrap(), "data:image/png;base64,AAAA");
+        assert_eq!(playlist.title, "Favorites");
+        assert_eq!(playlist.author, "me");
+        let hashes = playlist
+            .songs
+            .iter()
+            .map(|song| song.hash.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            hashes,
+            [
+                "CFCA2FE00BCC418DC9ECF64D92FC01CEEC52C375",
+                "7719B8DE597CB1BFDFD6048E5FC51656DD5219EE",
+                "762B7BF1C06DBCC7AAB23D955A553E5420FBA6E5",
+                "0000000000000000000000000000000000000001",
+            ]
+        );
+        assert_eq!(playlist.songs[0].difficulties.len(), 2);
+        std::fs::remove_file(&existing_path).unwrap();
+        std::fs::remove_file(&exclusions_path).unwrap();
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_stale_warning() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::create_schema(&db).unwrap();
+        assert_eq!(stale_warning(&db, 14.0).unwrap(), None);
+        db.execute(
+            "INSERT INTO crawls (category, started_at, finished_at) VALUES ('date-ranked', datetime('now', '-20 days'), datetime('now', '-20 days', '+1 hours'))",
+            rusqlite::params![],
+        )
+        .unwrap();
+        assert_eq!(
+            stale_warning(&db, 14.0).unwrap().unwrap(),
+            "Warning: data may be outdated — last updated 19 days ago."
+        );
+        assert_eq!(stale_warning(&db, 30.0).unwrap(), None);
+        crate::provenance::record_enrichment(&db, "BeatSaver", 3).unwrap();
+        db.execute_batch("UPDATE stale_services SET since = '2019-01-01 00:00:00'")
+            .unwrap();
+        crate::provenance::record_enrichment(&db, "BeatSaver", 2).unwrap();
+        assert_eq!(
+            stale_warning(&db, 30.0).unwrap().unwrap(),
+            "Warning: BeatSaver data may be outdated — 2 items failed to update since 2019-01-01 00:00:00."
+        );
+        crate::provenance::record_enrichment(&db, "BeatSaver", 0).unwrap();
+        assert_eq!(stale_warning(&db, 30.0).unwrap(), None);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let shuffled = |seed| {
+            let mut items = (0..20).collect::<Vec<u32>>();
+            shuffle(&mut items, seed);
+            items
+        };
+        assert_eq!(shuffled(7), shuffled(7));
+        assert_ne!(shuffled(7), shuffled(8));
+        let mut sorted = shuffled(7);
+        sorted.sort();
+        assert_eq!(sorted, (0..20).collect::<Vec<u32>>());
+        let db = crate::test_db();
+        let options = PlaylistOptions {
+            sort: SortOrder::Shuffle,
+            seed: Some(42),
+            ..Default::default()
+        };
+        let playlist = make_beatsaber_playlist(&db, &options).unwrap();
+        assert_eq!(playlist, make_beatsaber_playlist(&db, &options).unwrap());
+        assert!(playlist.description.ends_with("in random order (seed 42)."));
+        let options = PlaylistOptions {
+            seed: None,
+            ..options
+        };
+        let playlist = make_beatsaber_playlist(&db, &options).unwrap();
+        assert_eq!(playlist.songs.len(), 3);
+        assert!(playlist.description.contains("(seed "));
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_nps_njs() {
+        let db = crate::test_db();
+        for (hash, notes, njs) in &[
+            ("CFCA2FE00BCC418DC9ECF64D92FC01CEEC52C375", 1000, 18.0),
+            ("7719B8DE597CB1BFDFD6048E5FC51656DD5219EE", 500, 15.0),
+        ] {
+            db.execute(
+                "INSERT INTO beatsaver_difficulties (hash, characteristic, difficulty, notes, max_score, length, njs, nps) VALUES (?, 'Standard', 'ExpertPlus', ?, 0, 100, ?, ? / 100.0)",
+                rusqlite::params![hash, notes, njs, notes],
+            )
+            .unwrap();
+        }
+        for (options, expected) in &[
+            (
+                PlaylistOptions {
+                    max_nps: Some(8.0),
+                    ..Default::default()
+                },
+                vec!["7719B8DE597CB1BFDFD6048E5FC51656DD5219EE"],
+            ),
+            (
+                PlaylistOptions {
+                    min_nps: Some(8.0),
+                    ..Default::default()
+                },
+                vec!["CFCA2FE00BCC418DC9ECF64D92FC01CEEC52C375"],
+            ),
+            (
+                PlaylistOptions {
+                    max_njs: Some(20.0),
+                    ..Default::default()
+                },
+                vec![
+                    "CFCA2FE00BCC418DC9ECF64D92FC01CEEC52C375",
+                    "7719B8DE597CB1BFDFD6048E5FC51656DD5219EE",
+                ],
+            ),
+        ] {
+            let playlist = make_beatsaber_playlist(&db, options).unwrap();
+            let hashes = playlist
+                .songs
+                .iter()
+                .map(|song| song.hash.as_str())
+                .collect::<Vec<&str>>();
+            assert_eq!(&hashes, expected);
+        }
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_duration() {
+        for (text, seconds) in &[
+            ("3m", 180.0),
+            ("90s", 90.0),
+            ("1m30s", 90.0),
+            ("150", 150.0),
+        ] {
+            assert_eq!(
+                text.parse::<Duration>().unwrap().seconds,
+                *seconds,
+                "{}",
+                text
+            );
+        }
+        let overflowing = format!("{}m", "9".repeat(400));
+        for text in &[
+            "",
+            "m",
+            "3h",
+            "3m30",
+            "1.2.3m",
+            "nan",
+            "inf",
+            "-inf",
+            "-5",
+            overflowing.as_str(),
+        ] {
+            assert!(text.parse::<Duration>().is_err(), "{}", text);
+        }
+        assert!("-5m".parse::<Since>().is_err());
+        assert_eq!(Duration { seconds: 90.0 }.to_string(), "1m30s");
+        assert_eq!(Duration { seconds: 180.0 }.to_string(), "3m");
+        assert_eq!(Duration { seconds: 45.0 }.to_string(), "45s");
+        assert_eq!(
+            "2019-05-01".parse::<Since>().unwrap(),
+            Since::Date("2019-05-01".to_string())
+        );
+        assert_eq!(
+            "2019-05-01 12:00:00".parse::<Since>().unwrap().to_string(),
+            "since 2019-05-01 12:00:00"
+        );
+        assert_eq!(
+            "1m30s".parse::<Since>().unwrap(),
+            Since::Ago(Duration { seconds: 90.0 })
+        );
+        assert_eq!(
+            "7d".parse::<Since>().unwrap(),
+            Since::Ago(Duration {
+                seconds: 7.0 * 24.0 * 3600.0
+            })
+        );
+        assert_eq!(
+            "1w12h".parse::<Since>().unwrap().to_string(),
+            "in the last 1w12h"
+        );
+        assert_eq!(
+            "2020-02-29".parse::<Since>().unwrap(),
+            Since::Date("2020-02-29".to_string())
+        );
+        for text in &[
+            "2019-5-1",
+            "2019-05-01T12:00:00",
+            "yesterday",
+            "2019-13-45",
+            "2019-02-29",
+            "2019-04-31",
+            "2019-05-01 24:00:00",
+            "2019-05-01 12:60:00",
+        ] {
+            assert!(text.parse::<Since>().is_err(), "{}", text);
+        }
+        let options = PlaylistOptions {
+            max_duration: Some("3m".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            title_and_description(&options).0,
+            "Ranked Songs (at most 3m long)"
+        );
+    }
+}
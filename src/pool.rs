@@ -0,0 +1,181 @@
+use crate::{
+    playlist::{
+        self, BeatSaberPlaylistSong, BeatsaberPlaylist, PlaylistDifficulty, PlaylistOptions,
+    },
+    Result_,
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub enum PoolCommand {
+    /// Snapshots the songs selected by the filters together with their current stars.
+    Freeze {
+        name: String,
+        #[structopt(flatten)]
+        options: PlaylistOptions,
+    },
+    /// Writes the playlist of a frozen pool. It is the same every time.
+    Emit {
+        name: String,
+        /// Defaults to `<name>.json` or `<name>.bplist`.
+        #[structopt(long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+}
+
+pub fn run(
+    db: &rusqlite::Connection,
+    command: PoolCommand,
+    playlist_output: &playlist::PlaylistOutput,
+) -> Result_<()> {
+    match command {
+        PoolCommand::Freeze { name, options } => freeze(db, &name, &options),
+        PoolCommand::Emit { name, output } => {
+            let output = output.unwrap_or_else(|| playlist_output.default_path(&name));
+            playlist::save_beatsaber_playlist(emit(db, &name)?, &output, playlist_output)
+        }
+    }
+}
+
+fn freeze(db: &rusqlite::Connection, name: &str, options: &PlaylistOptions) -> Result_<()> {
+    db.execute_batch("BEGIN")?;
+    let result = insert_pool(db, name, options);
+    match result {
+        Ok(_) => db.execute_batch("COMMIT")?,
+        Err(_) => db.execute_batch("ROLLBACK")?,
+    }
+    result
+}
+
+fn insert_pool(db: &rusqlite::Connection, name: &str, options: &PlaylistOptions) -> Result_<()> {
+    let exists: i64 = db.query_row(
+        "SELECT COUNT(*) FROM map_pools WHERE name = ?",
+        rusqlite::params![name],
+        |row| row.get(0),
+    )?;
+    if exists > 0 {
+        return Err(format!("map pool {} is already frozen", name))?;
+    }
+    let playlist = playlist::make_beatsaber_playlist(db, options)?;
+    db.execute(
+        "INSERT INTO map_pools (name, description, frozen_at) VALUES (?,?,datetime('now'))",
+        rusqlite::params![name, playlist.description],
+    )?;
+    // The playlist has one entry per song but the pool keeps every difficulty that passed the
+    // filters.
+    let filter = playlist::filter(options)?;
+    let mut statement = db.prepare(&format!(
+        "SELECT s.uid, s.diff, s.stars FROM {} {} {} s.id = ?",
+        playlist::SONGS_FROM,
+        filter.where_clause(),
+        if filter.where_clause().is_empty() {
+            "WHERE"
+        } else {
+            "AND"
+        }
+    ))?;
+    for (position, song) in playlist.songs.iter().enumerate() {
+        let mut params = filter.params();
+        params.push(&song.hash);
+        let difficulties = statement
+            .query_map(&params, |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, f64>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (uid, diff, stars) in difficulties {
+            db.execute(
+                "INSERT INTO map_pool_songs (pool, position, uid, id, name, diff, stars) VALUES (?,?,?,?,?,?,?)",
+                rusqlite::params![name, position as i64, uid, song.hash, song.name, diff, stars],
+            )?;
+        }
+    }
+    println!(
+        "Froze map pool {} with {} songs.",
+        name,
+        playlist.songs.len()
+    );
+    Ok(())
+}
+
+fn emit(db: &rusqlite::Connection, name: &str) -> Result_<BeatsaberPlaylist> {
+    use rusqlite::OptionalExtension;
+    let pool: Option<(String, String)> = db
+        .query_row(
+            "SELECT description, frozen_at FROM map_pools WHERE name = ?",
+            rusqlite::params![name],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let (description, frozen_at) = match pool {
+        Some(pool) => pool,
+        None => return Err(format!("there is no map pool {}", name))?,
+    };
+    let mut statement = db.prepare(
+        "SELECT position, id, name FROM map_pool_songs WHERE pool = ? GROUP BY position ORDER BY position",
+    )?;
+    let rows = statement
+        .query_map(rusqlite::params![name], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut statement = db.prepare(
+        "SELECT diff FROM map_pool_songs WHERE pool = ? AND position = ? ORDER BY stars DESC",
+    )?;
+    let mut songs = vec![];
+    for (position, hash, song_name) in rows {
+        let difficulties = statement
+            .query_map(rusqlite::params![name, position], |row| {
+                row.get::<_, String>(0)
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        songs.push(BeatSaberPlaylistSong {
+            name: song_name,
+            hash,
+            difficulties: difficulties
+                .iter()
+                .filter_map(|x| PlaylistDifficulty::from_diff(x))
+                .collect(),
+        });
+    }
+    Ok(BeatsaberPlaylist {
+        title: name.to_string(),
+        author: playlist::AUTHOR.to_string(),
+        description: format!("Map pool frozen at {}. {}", frozen_at, description),
+        songs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool() {
+        let db = crate::test_db();
+        let options = PlaylistOptions {
+            min_stars: Some(9.5),
+            ..Default::default()
+        };
+        freeze(&db, "pool", &options).unwrap();
+        assert!(freeze(&db, "pool", &options).is_err());
+        let frozen = emit(&db, "pool").unwrap();
+        assert_eq!(frozen.songs.len(), 2);
+        db.execute_batch("UPDATE scoresaber_songs SET stars = stars - 5")
+            .unwrap();
+        assert!(playlist::make_beatsaber_playlist(&db, &options)
+            .unwrap()
+            .songs
+            .is_empty());
+        assert_eq!(emit(&db, "pool").unwrap(), frozen);
+        assert!(emit(&db, "other").is_err());
+        db.close().unwrap();
+    }
+}
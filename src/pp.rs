@@ -0,0 +1,75 @@
+// ScoreSaber's pp formula. The pp of a score is the stars of the difficulty times PP_PER_STAR
+// times the multiplier of the accuracy on the curve.
+const PP_PER_STAR: f64 = 42.117_208_413;
+
+// (accuracy, multiplier) points of the curve. The multiplier is interpolated linearly in between.
+const CURVE: &[(f64, f64)] = &[
+    (0.0, 0.0),
+    (0.6, 0.182_232_336_674_390_6),
+    (0.65, 0.586_601_001_276_757_6),
+    (0.7, 0.612_556_595_911_495_4),
+    (0.75, 0.645_180_821_010_144_3),
+    (0.8, 0.687_226_886_295_028_3),
+    (0.825, 0.715_046_566_345_427_1),
+    (0.85, 0.746_229_066_414_318_5),
+    (0.875, 0.781_693_456_029_604_6),
+    (0.9, 0.825_756_123_560_842),
+    (0.91, 0.848_837_598_812_446_7),
+    (0.92, 0.872_871_034_144_885_1),
+    (0.93, 0.903_999_407_186_573_6),
+    (0.94, 0.941_736_298_058_023_8),
+    (0.95, 1.0),
+    (0.955, 1.038_863_333_141_898_4),
+    (0.96, 1.087_188_357_385_047_8),
+    (0.965, 1.155_212_035_950_103_5),
+    (0.97, 1.248_580_775_995_732_1),
+    (0.9725, 1.309_033_306_505_761_6),
+    (0.975, 1.380_710_274_310_512_6),
+    (0.9775, 1.466_472_639_928_951_2),
+    (0.98, 1.570_241_005_553_223_9),
+    (0.9825, 1.697_536_248_647_543),
+    (0.985, 1.856_388_769_364_710_5),
+    (0.9875, 2.058_947_159_052_738),
+    (0.99, 2.324_506_282_149_922),
+    (0.99125, 2.490_290_579_410_691_3),
+    (0.9925, 2.685_667_856_592_722),
+    (0.99375, 2.919_015_563_925_495_5),
+    (0.995, 3.202_201_759_733_795_5),
+    (0.99625, 3.552_614_533_755_537_3),
+    (0.9975, 3.996_793_606_763_322),
+    (0.99825, 4.325_027_383_589_547),
+    (0.999, 4.715_470_646_416_203),
+    (0.9995, 5.019_543_595_874_787),
+    (1.0, 5.367_394_282_890_631),
+];
+
+fn multiplier(accuracy: f64) -> f64 {
+    let accuracy = accuracy.max(0.0).min(1.0);
+    for window in CURVE.windows(2) {
+        let (a0, m0) = window[0];
+        let (a1, m1) = window[1];
+        if accuracy <= a1 {
+            return m0 + (accuracy - a0) / (a1 - a0) * (m1 - m0);
+        }
+    }
+    CURVE[CURVE.len() - 1].1
+}
+
+// `accuracy` is a fraction like 0.95.
+pub fn pp(stars: f64, accuracy: f64) -> f64 {
+    stars * PP_PER_STAR * multiplier(accuracy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pp() {
+        assert!((pp(1.0, 0.95) - PP_PER_STAR).abs() < 1e-9);
+        assert_eq!(pp(10.0, 0.0), 0.0);
+        assert!(pp(10.0, 0.96) > pp(10.0, 0.95));
+        let halfway = pp(1.0, 0.9525) / PP_PER_STAR;
+        assert!((halfway - (1.0 + 1.038_863_333_141_898_4) / 2.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,105 @@
+// Time spent per kind of work in a run. Enabled with `--profile` which prints the summary at the end
+// so that slow runs can be attributed to the network, decoding, the database or the playlists.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    // Waiting for responses and receiving their bodies.
+    Network,
+    // Parsing json responses.
+    Decode,
+    // Writing to the database.
+    Database,
+    // Querying and writing playlists.
+    Playlist,
+}
+
+const KINDS: [Kind; 4] = [Kind::Network, Kind::Decode, Kind::Database, Kind::Playlist];
+
+// Nanoseconds per kind in the order of KINDS.
+static NANOS: [AtomicU64; 4] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Network => "network",
+            Kind::Decode => "json decode",
+            Kind::Database => "database writes",
+            Kind::Playlist => "playlists",
+        }
+    }
+}
+
+thread_local! {
+    // Nanoseconds the timers nested in the innermost running timer of the thread counted.
+    static NESTED: std::cell::Cell<u64> = std::cell::Cell::new(0);
+}
+
+// Adds the time until it is dropped to its kind. The time of timers nested in it, like reading a
+// streamed response while decoding it, is left to their kinds.
+pub struct Timer {
+    kind: Kind,
+    // When it was started and the nested time of the timer around it.
+    start: Option<(std::time::Instant, u64)>,
+}
+
+pub fn timer(kind: Kind) -> Timer {
+    let start = if ENABLED.load(Ordering::Relaxed) {
+        Some((
+            std::time::Instant::now(),
+            NESTED.with(|nested| nested.replace(0)),
+        ))
+    } else {
+        None
+    };
+    Timer { kind, start }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some((start, outer)) = self.start {
+            let elapsed = start.elapsed().as_nanos() as u64;
+            let nested = NESTED.with(|nested| nested.replace(outer + elapsed));
+            NANOS[self.kind as usize].fetch_add(elapsed.saturating_sub(nested), Ordering::Relaxed);
+        }
+    }
+}
+
+pub fn print_summary(total: std::time::Duration) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let total = total.as_secs_f64();
+    let mut measured = 0.0;
+    println!("{:<16} {:>10} {:>6}", "", "seconds", "share");
+    for &kind in KINDS.iter() {
+        let seconds = NANOS[kind as usize].load(Ordering::Relaxed) as f64 / 1e9;
+        measured += seconds;
+        println!(
+            "{:<16} {:>10.3} {:>5.1}%",
+            kind.name(),
+            seconds,
+            seconds / total * 100.0
+        );
+    }
+    let other = (total - measured).max(0.0);
+    println!(
+        "{:<16} {:>10.3} {:>5.1}%",
+        "other",
+        other,
+        other / total * 100.0
+    );
+    println!("{:<16} {:>10.3}", "total", total);
+}
@@ -0,0 +1,57 @@
+// Machine readable progress for programs that wrap the crawler. Events are written to stderr as
+// one json object per line when enabled.
+
+static ENABLED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, std::sync::atomic::Ordering::Relaxed);
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    StageChanged {
+        stage: &'a str,
+    },
+    // `source` is "leaderboards" or "scores".
+    PageFetched {
+        source: &'a str,
+        page: u64,
+    },
+    SongInserted {
+        number: usize,
+        uid: crate::ScoreSaberSongId,
+        name: &'a str,
+    },
+}
+
+pub fn emit(event: Event) {
+    if !ENABLED.load(std::sync::atomic::Ordering::Relaxed) {
+        return;
+    }
+    match serde_json::to_string(&event) {
+        Ok(line) => eprintln!("{}", line),
+        Err(err) => log::warn!("failed to serialize progress event: {}", err),
+    }
+}
+
+pub fn stage(stage: &str) {
+    emit(Event::StageChanged { stage });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        let event = Event::PageFetched {
+            source: "leaderboards",
+            page: 2,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"page_fetched","source":"leaderboards","page":2}"#
+        );
+    }
+}
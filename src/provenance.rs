@@ -0,0 +1,231 @@
+use crate::{playlist, Result_};
+
+// The sources that write the enrichable fields, most trusted first unless the config sets
+// `source_precedence`.
+pub const SOURCES: &[&str] = &["scoresaber", "beatsaver", "import"];
+
+// Fields more than one source can write. bpm is stored in scoresaber_songs and the others in
+// beatsaver_difficulties.
+pub const FIELDS: &[&str] = &["bpm", "notes", "length", "max_score"];
+
+// Stores what a source reported for a field of a difficulty. None if the source reported nothing
+// usable, like an invalid bpm.
+pub fn record(
+    db: &rusqlite::Connection,
+    uid: i64,
+    field: &str,
+    source: &str,
+    value: Option<f64>,
+) -> Result_<()> {
+    db.execute(
+        "REPLACE INTO field_sources (uid, field, source, value, recorded_at) VALUES (?,?,?,?,datetime('now'))",
+        rusqlite::params![uid, field, source, value],
+    )?;
+    Ok(())
+}
+
+// Marks the data of an enrichment service like "beatsaver" as stale if `failed` items could not be
+// fetched by its last pass, or as up to date if none failed. Stale since the first pass that
+// failed.
+pub fn record_enrichment(db: &rusqlite::Connection, service: &str, failed: usize) -> Result_<()> {
+    if failed == 0 {
+        db.execute(
+            "DELETE FROM stale_services WHERE service = ?",
+            rusqlite::params![service],
+        )?;
+        return Ok(());
+    }
+    db.execute(
+        "INSERT INTO stale_services (service, failed, since) VALUES (?,?,datetime('now')) ON CONFLICT(service) DO UPDATE SET failed = excluded.failed",
+        rusqlite::params![service, failed as i64],
+    )?;
+    Ok(())
+}
+
+// The services with stale data, their number of failed items and since when.
+pub fn stale_services(db: &rusqlite::Connection) -> Result_<Vec<(String, usize, String)>> {
+    let mut statement =
+        db.prepare("SELECT service, failed, since FROM stale_services ORDER BY service")?;
+    let services = statement
+        .query_map(rusqlite::params![], |row| {
+            Ok((row.get(0)?, row.get::<_, i64>(1)? as usize, row.get(2)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(services)
+}
+
+pub fn check_precedence(precedence: &[String]) -> Result_<()> {
+    for (i, source) in precedence.iter().enumerate() {
+        if !SOURCES.contains(&source.as_str()) {
+            return Err(format!(
+                "unknown source {} in source_precedence, expected one of {}",
+                source,
+                SOURCES.join(", ")
+            ))?;
+        }
+        if precedence[..i].contains(source) {
+            return Err(format!("source {} is twice in source_precedence", source))?;
+        }
+    }
+    Ok(())
+}
+
+// Lower is more trusted. Sources missing from the precedence come after it in the default order.
+pub fn rank(precedence: &[String], source: &str) -> usize {
+    match precedence.iter().position(|x| x == source) {
+        Some(position) => position,
+        None => {
+            precedence.len()
+                + SOURCES
+                    .iter()
+                    .position(|x| *x == source)
+                    .unwrap_or(SOURCES.len())
+        }
+    }
+}
+
+// Writes the value of the most trusted source that reported one into the tables.
+pub fn resolve(db: &rusqlite::Connection, precedence: &[String]) -> Result_<()> {
+    check_precedence(precedence)?;
+    let mut statement =
+        db.prepare("SELECT uid, field, source, value FROM field_sources WHERE value IS NOT NULL")?;
+    let rows = statement
+        .query_map(rusqlite::params![], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, f64>(3)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut best = std::collections::BTreeMap::new();
+    for (uid, field, source, value) in rows {
+        let rank = rank(precedence, &source);
+        let entry = best
+            .entry((uid, field))
+            .or_insert((rank, source.clone(), value));
+        if rank < entry.0 {
+            *entry = (rank, source, value);
+        }
+    }
+    // One transaction instead of one per update.
+    db.execute_batch("BEGIN")?;
+    let result = update_fields(db, &best);
+    match result {
+        Ok(()) => db.execute_batch("COMMIT")?,
+        Err(_) => db.execute_batch("ROLLBACK")?,
+    }
+    result
+}
+
+fn update_fields(
+    db: &rusqlite::Connection,
+    best: &std::collections::BTreeMap<(i64, String), (usize, String, f64)>,
+) -> Result_<()> {
+    for ((uid, field), (_, source, value)) in best.iter() {
+        if field == "bpm" {
+            db.execute(
+                "UPDATE scoresaber_songs SET bpm = ?, bpm_status = CASE WHEN ? = 'scoresaber' THEN 'ok' ELSE 'repaired' END WHERE uid = ?",
+                rusqlite::params![value, source, uid],
+            )?;
+        } else if FIELDS.contains(&field.as_str()) {
+            // The field is one of FIELDS so it can be part of the sql.
+            db.execute(
+                &format!(
+                    "UPDATE beatsaver_difficulties SET {} = ? WHERE rowid IN (SELECT d.rowid FROM {} WHERE s.uid = ?)",
+                    field,
+                    playlist::SONGS_FROM
+                ),
+                rusqlite::params![value, uid],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// Prints every enrichable field of the difficulties of a song with the value of every source.
+// `song` is a leaderboard uid or a song hash.
+pub fn lookup(db: &rusqlite::Connection, song: &str, precedence: &[String]) -> Result_<()> {
+    let mut statement = db.prepare(
+        "SELECT uid, name, diff, stars FROM scoresaber_songs WHERE CAST(uid AS TEXT) = ? OR UPPER(id) = UPPER(?) ORDER BY stars DESC",
+    )?;
+    let difficulties = statement
+        .query_map(rusqlite::params![song, song], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, f64>(3)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if difficulties.is_empty() {
+        return Err(format!("there is no leaderboard uid or song hash {}", song))?;
+    }
+    let mut sources_statement = db.prepare(
+        "SELECT source, value, recorded_at FROM field_sources WHERE uid = ? AND field = ?",
+    )?;
+    for (uid, name, diff, stars) in difficulties {
+        println!("{} {} {} {:.2}★", uid, name, diff, stars);
+        for field in FIELDS {
+            let mut sources = sources_statement
+                .query_map(rusqlite::params![uid, field], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<f64>>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            if sources.is_empty() {
+                continue;
+            }
+            sources.sort_by_key(|(source, _, _)| rank(precedence, source));
+            println!("  {}", field);
+            // The first source with a value is the one that is used.
+            let mut used = false;
+            for (source, value, recorded_at) in sources {
+                let value = match value {
+                    Some(value) => value.to_string(),
+                    None => "-".to_string(),
+                };
+                let marker = if !used && value != "-" {
+                    used = true;
+                    "*"
+                } else {
+                    " "
+                };
+                println!("   {} {:<10} {:>10} {}", marker, source, value, recorded_at);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let db = crate::test_db();
+        record(&db, 109_086, "bpm", "beatsaver", Some(200.0)).unwrap();
+        let bpm = |db: &rusqlite::Connection| -> (f64, String) {
+            db.query_row(
+                "SELECT bpm, bpm_status FROM scoresaber_songs WHERE uid = 109086",
+                rusqlite::params![],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+        resolve(&db, &[]).unwrap();
+        assert_eq!(bpm(&db), (255.0, "ok".to_string()));
+        resolve(&db, &["beatsaver".to_string()]).unwrap();
+        assert_eq!(bpm(&db), (200.0, "repaired".to_string()));
+        assert!(resolve(&db, &["beatleader".to_string()]).is_err());
+        assert!(check_precedence(&["import".to_string(), "import".to_string()]).is_err());
+        assert_eq!(rank(&["import".to_string()], "scoresaber"), 1);
+        db.close().unwrap();
+    }
+}
@@ -0,0 +1,456 @@
+use crate::{output, playlist, profile, Result_};
+use structopt::StructOpt;
+
+// Increased whenever fields of the dataset change so that consumers can detect it.
+const DATASET_VERSION: u32 = 1;
+
+#[derive(Debug, StructOpt)]
+pub enum PublishCommand {
+    /// Writes every ranked difficulty with its BeatSaver data into one json file, gzip compressed
+    /// unless the config says otherwise, for other tools so they do not have to crawl ScoreSaber
+    /// themselves. The sha256 checksum is written next to it.
+    Dataset {
+        #[structopt(long, parse(from_os_str), default_value = "dataset.json.gz")]
+        output: std::path::PathBuf,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ImportCommand {
+    /// Seeds the database with a dataset published by `publish dataset`, for example to skip
+    /// fetching every map from BeatSaver on the first run. The checksum is verified if the
+    /// `.sha256` file is next to the dataset.
+    Dataset {
+        #[structopt(parse(from_os_str))]
+        file: std::path::PathBuf,
+    },
+    /// Adds the songs of a hand curated playlist file so that `--imported <name>` restricts
+    /// playlists to them. Maps that are not ranked are fetched from BeatSaver. Importing a
+    /// playlist with the same name again replaces its songs.
+    Playlist {
+        #[structopt(parse(from_os_str))]
+        file: std::path::PathBuf,
+        /// Defaults to the file name without extension.
+        #[structopt(long)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Dataset {
+    version: u32,
+    generated_at: String,
+    songs: Vec<DatasetSong>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct DatasetSong {
+    uid: i64,
+    hash: String,
+    name: String,
+    sub_name: String,
+    song_author: String,
+    level_author: String,
+    bpm: f64,
+    // Like "_ExpertPlus_SoloStandard".
+    diff: String,
+    stars: f64,
+    plays: i64,
+    daily_plays: i64,
+    // The BeatSaver data is missing for maps that have not been fetched.
+    beatsaver_key: Option<String>,
+    notes: Option<i64>,
+    length: Option<f64>,
+    max_score: Option<i64>,
+}
+
+pub fn run(
+    db: &rusqlite::Connection,
+    command: PublishCommand,
+    post_process: &output::PostProcess,
+) -> Result_<()> {
+    match command {
+        PublishCommand::Dataset { output } => {
+            let dataset = dataset(db)?;
+            let checksum = write_compressed(&dataset, &output, post_process)?;
+            let file_name = output
+                .file_name()
+                .and_then(|x| x.to_str())
+                .unwrap_or_default();
+            // The format of sha256sum so that `sha256sum -c` verifies it.
+            std::fs::write(
+                checksum_path(&output),
+                format!("{}  {}\n", checksum, file_name),
+            )?;
+            println!(
+                "Published {} difficulties with checksum {}.",
+                dataset.songs.len(),
+                checksum
+            );
+            Ok(())
+        }
+    }
+}
+
+fn checksum_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut checksum_path = path.to_path_buf().into_os_string();
+    checksum_path.push(".sha256");
+    checksum_path.into()
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    sha2::Sha256::digest(bytes)
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect()
+}
+
+fn dataset(db: &rusqlite::Connection) -> Result_<Dataset> {
+    let generated_at: String =
+        db.query_row("SELECT datetime('now')", rusqlite::params![], |row| {
+            row.get(0)
+        })?;
+    let mut statement = db.prepare(&format!(
+        "SELECT s.uid, s.id, s.name, s.songSubName, s.songAuthorName, s.levelAuthorName, s.bpm, s.diff, s.stars, s.scores, s.scores_day, m.key, d.notes, d.length, d.max_score FROM {} LEFT JOIN beatsaver_maps m ON m.hash = s.id WHERE s.ranked = 1 ORDER BY s.uid",
+        playlist::SONGS_FROM
+    ))?;
+    let songs = statement
+        .query_map(rusqlite::params![], |row| {
+            Ok(DatasetSong {
+                uid: row.get(0)?,
+                hash: row.get(1)?,
+                name: row.get(2)?,
+                sub_name: row.get(3)?,
+                song_author: row.get(4)?,
+                level_author: row.get(5)?,
+                bpm: row.get(6)?,
+                diff: row.get(7)?,
+                stars: row.get(8)?,
+                plays: row.get(9)?,
+                daily_plays: row.get(10)?,
+                beatsaver_key: row.get(11)?,
+                notes: row.get(12)?,
+                length: row.get(13)?,
+                max_score: row.get(14)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(Dataset {
+        version: DATASET_VERSION,
+        generated_at,
+        songs,
+    })
+}
+
+// Returns the hex encoded sha256 of the written file.
+fn write_compressed(
+    dataset: &Dataset,
+    path: &std::path::Path,
+    post_process: &output::PostProcess,
+) -> Result_<String> {
+    let bytes = post_process.json(dataset)?;
+    std::fs::write(path, &bytes)?;
+    Ok(sha256(&bytes))
+}
+
+pub fn run_import(
+    db: &rusqlite::Connection,
+    client: &reqwest::Client,
+    command: ImportCommand,
+) -> Result_<()> {
+    match command {
+        ImportCommand::Dataset { file } => {
+            let dataset = read_compressed(&file)?;
+            import(db, &dataset)?;
+            println!(
+                "Imported {} difficulties generated at {}.",
+                dataset.songs.len(),
+                dataset.generated_at
+            );
+            Ok(())
+        }
+        ImportCommand::Playlist { file, name } => {
+            let name = match name {
+                Some(name) => name,
+                None => match file.file_stem() {
+                    Some(stem) => stem.to_string_lossy().into_owned(),
+                    None => return Err(format!("{} has no file name", file.display()))?,
+                },
+            };
+            let playlist = playlist::read(&file)?;
+            let ranked = import_playlist(db, &name, &playlist)?;
+            println!(
+                "Imported {} songs as {} of which {} are ranked.",
+                playlist.songs.len(),
+                name,
+                ranked
+            );
+            crate::beatsaver::enrich_imported(db, client)
+        }
+    }
+}
+
+// Returns how many of the songs are ranked.
+fn import_playlist(
+    db: &rusqlite::Connection,
+    name: &str,
+    playlist: &playlist::BeatsaberPlaylist,
+) -> Result_<usize> {
+    db.execute_batch("BEGIN")?;
+    let result = insert_playlist(db, name, playlist);
+    match result {
+        Ok(_) => db.execute_batch("COMMIT")?,
+        Err(_) => db.execute_batch("ROLLBACK")?,
+    }
+    result
+}
+
+fn insert_playlist(
+    db: &rusqlite::Connection,
+    name: &str,
+    playlist: &playlist::BeatsaberPlaylist,
+) -> Result_<usize> {
+    db.execute(
+        "DELETE FROM imported_playlists WHERE name = ?",
+        rusqlite::params![name],
+    )?;
+    for song in &playlist.songs {
+        db.execute(
+            "INSERT OR IGNORE INTO imported_playlists (name, hash, song_name) VALUES (?,?,?)",
+            rusqlite::params![name, song.hash.to_uppercase(), song.name],
+        )?;
+    }
+    let ranked: i64 = db.query_row(
+        "SELECT COUNT(DISTINCT hash) FROM imported_playlists WHERE name = ? AND hash IN (SELECT UPPER(id) FROM scoresaber_songs WHERE ranked = 1)",
+        rusqlite::params![name],
+        |row| row.get(0),
+    )?;
+    Ok(ranked as usize)
+}
+
+fn read_compressed(path: &std::path::Path) -> Result_<Dataset> {
+    let bytes = std::fs::read(path)?;
+    let checksum_path = checksum_path(path);
+    if checksum_path.exists() {
+        let expected = std::fs::read_to_string(&checksum_path)?;
+        let expected = expected.split_whitespace().next().unwrap_or_default();
+        if expected != sha256(&bytes) {
+            return Err(format!("checksum of {} does not match", path.display()))?;
+        }
+    }
+    // Datasets can be published with any compression.
+    let dataset: Dataset = serde_json::from_slice(&output::decompress(&bytes)?)?;
+    if dataset.version > DATASET_VERSION {
+        return Err(format!(
+            "dataset version {} is newer than the supported version {}",
+            dataset.version, DATASET_VERSION
+        ))?;
+    }
+    Ok(dataset)
+}
+
+// Stores the songs and the BeatSaver data of the dataset. Maps without BeatSaver data are fetched by
+// the next run with `--beatsaver`.
+fn import(db: &rusqlite::Connection, dataset: &Dataset) -> Result_<()> {
+    let _timer = profile::timer(profile::Kind::Database);
+    // One transaction instead of one per insert.
+    db.execute_batch("BEGIN")?;
+    let result = insert_songs(db, &dataset.songs);
+    match result {
+        Ok(()) => db.execute_batch("COMMIT")?,
+        Err(_) => db.execute_batch("ROLLBACK")?,
+    }
+    result
+}
+
+fn insert_songs(db: &rusqlite::Connection, songs: &[DatasetSong]) -> Result_<()> {
+    for song in songs {
+        let (bpm, bpm_status) = crate::checked_bpm(db, &song.hash, song.bpm)?;
+        let difficulty = crate::difficulty_columns(&song.diff);
+        crate::record_history(db, song.uid, &song.name, &song.diff, song.stars)?;
+        crate::upsert_song(
+            db,
+            rusqlite::params![
+                song.uid,
+                song.hash,
+                song.name,
+                song.sub_name,
+                song.song_author,
+                song.level_author,
+                bpm,
+                song.diff,
+                song.stars,
+                song.plays,
+                song.daily_plays,
+                bpm_status,
+                crate::script::detect(&song.name).name(),
+                difficulty.map(|x| x.0.name()),
+                difficulty.map(|x| x.1),
+                // Datasets do not have the dates, the stored ones are kept.
+                None::<String>,
+                None::<String>
+            ],
+        )?;
+        for (field, value) in &[
+            ("bpm", Some(song.bpm).filter(|x| crate::is_valid_bpm(*x))),
+            ("notes", song.notes.map(|x| x as f64)),
+            ("length", song.length),
+            ("max_score", song.max_score.map(|x| x as f64)),
+        ] {
+            crate::provenance::record(db, song.uid, field, "import", *value)?;
+        }
+        if song.beatsaver_key.is_none() {
+            continue;
+        }
+        // Maps that are already stored keep their metadata and when it was fetched, only the key of
+        // the dataset is taken over.
+        db.execute(
+            "INSERT INTO beatsaver_maps (hash, key, fetched_at) VALUES (?,?,datetime('now')) ON CONFLICT(hash) DO UPDATE SET key = excluded.key",
+            rusqlite::params![song.hash, song.beatsaver_key],
+        )?;
+        if let (Some((difficulty, characteristic)), Some(notes), Some(max_score)) = (
+            crate::parse_difficulty(&song.diff),
+            song.notes,
+            song.max_score,
+        ) {
+            db.execute(
+                "INSERT OR IGNORE INTO beatsaver_difficulties (hash, characteristic, difficulty, notes, max_score, length) VALUES (?,?,?,?,?,?)",
+                rusqlite::params![song.hash, characteristic, difficulty, notes, max_score, song.length],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dataset() {
+        let db = crate::test_db();
+        let dataset = dataset(&db).unwrap();
+        assert_eq!(dataset.songs.len(), 4);
+        assert_eq!(dataset.songs[0].beatsaver_key, None);
+        let path = std::env::temp_dir().join("scoresaber-crawler-test-dataset.json.gz");
+        let checksum =
+            write_compressed(&dataset, &path, &output::OutputConfig::default().dataset()).unwrap();
+        assert_eq!(checksum.len(), 64);
+        let decoder = flate2::read::GzDecoder::new(std::fs::File::open(&path).unwrap());
+        let value: serde_json::Value = serde_json::from_reader(decoder).unwrap();
+        assert_eq!(value["version"], DATASET_VERSION);
+        assert_eq!(value["songs"].as_array().unwrap().len(), 4);
+        std::fs::remove_file(&path).unwrap();
+        // Unranked songs are not published so that an import does not rank them again.
+        db.execute(
+            "UPDATE scoresaber_songs SET ranked = 0 WHERE uid = ?",
+            rusqlite::params![dataset.songs[0].uid],
+        )
+        .unwrap();
+        assert_eq!(super::dataset(&db).unwrap().songs.len(), 3);
+    }
+
+    #[test]
+    fn test_import() {
+        let db = crate::test_db();
+        let path = std::env::temp_dir().join("scoresaber-crawler-test-import.json.gz");
+        let checksum = write_compressed(
+            &dataset(&db).unwrap(),
+            &path,
+            &output::OutputConfig::default().dataset(),
+        )
+        .unwrap();
+        db.close().unwrap();
+
+        std::fs::write(checksum_path(&path), "0  other\n").unwrap();
+        assert!(read_compressed(&path).is_err());
+        std::fs::write(checksum_path(&path), format!("{}  file\n", checksum)).unwrap();
+        let dataset = read_compressed(&path).unwrap();
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::create_schema(&db).unwrap();
+        import(&db, &dataset).unwrap();
+        let count: i64 = db
+            .query_row(
+                "SELECT COUNT(*) FROM scoresaber_songs",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 4);
+        // Importing into a database with BeatSaver data leaves it alone.
+        let hash = dataset.songs[0].hash.clone();
+        db.execute(
+            "INSERT INTO beatsaver_maps (hash, key, fetched_at, bpm) VALUES (?, 'old', '2019-01-01 00:00:00', 120)",
+            rusqlite::params![hash],
+        )
+        .unwrap();
+        db.execute(
+            "INSERT INTO beatsaver_difficulties (hash, characteristic, difficulty, notes, max_score, length, njs) VALUES (?, 'Standard', 'ExpertPlus', 1, 1, 100, 18)",
+            rusqlite::params![hash],
+        )
+        .unwrap();
+        let mut dataset = dataset;
+        dataset.songs[0].beatsaver_key = Some("new".to_string());
+        dataset.songs[0].notes = Some(2);
+        dataset.songs[0].max_score = Some(2);
+        import(&db, &dataset).unwrap();
+        let map: (String, String, f64) = db
+            .query_row(
+                "SELECT key, fetched_at, bpm FROM beatsaver_maps WHERE hash = ?",
+                rusqlite::params![hash],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            map,
+            ("new".to_string(), "2019-01-01 00:00:00".to_string(), 120.0)
+        );
+        let difficulty: (i64, f64) = db
+            .query_row(
+                "SELECT notes, njs FROM beatsaver_difficulties WHERE hash = ?",
+                rusqlite::params![hash],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(difficulty, (1, 18.0));
+        db.close().unwrap();
+        std::fs::remove_file(checksum_path(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_import_playlist() {
+        let db = crate::test_db();
+        let song = |name: &str, hash: &str| playlist::BeatSaberPlaylistSong {
+            name: name.to_string(),
+            hash: hash.to_string(),
+            difficulties: vec![],
+        };
+        let curated = playlist::BeatsaberPlaylist {
+            title: "Curated".to_string(),
+            author: "someone".to_string(),
+            description: String::new(),
+            songs: vec![
+                song("NUCLEAR-STAR", "762b7bf1c06dbcc7aab23d955a553e5420fba6e5"),
+                song("Unranked", "0000000000000000000000000000000000000000"),
+            ],
+        };
+        assert_eq!(import_playlist(&db, "curated", &curated).unwrap(), 1);
+        // Importing again replaces the songs.
+        assert_eq!(import_playlist(&db, "curated", &curated).unwrap(), 1);
+        let options = playlist::PlaylistOptions {
+            imported: vec!["curated".to_string()],
+            ..Default::default()
+        };
+        let generated = playlist::make_beatsaber_playlist(&db, &options).unwrap();
+        assert_eq!(
+            generated
+                .songs
+                .iter()
+                .map(|x| x.hash.as_str())
+                .collect::<Vec<&str>>(),
+            ["762B7BF1C06DBCC7AAB23D955A553E5420FBA6E5"]
+        );
+    }
+}
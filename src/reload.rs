@@ -0,0 +1,37 @@
+use crate::Result_;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Set by SIGHUP so that long running commands reload the config at their next safe point instead of
+// in the middle of an update.
+#[derive(Clone, Default)]
+pub struct Signal(Arc<AtomicBool>);
+
+impl Signal {
+    pub fn register() -> Result_<Self> {
+        let signal = Signal::default();
+        // There is no SIGHUP on Windows.
+        #[cfg(unix)]
+        signal_hook::flag::register(signal_hook::SIGHUP, signal.0.clone())?;
+        Ok(signal)
+    }
+
+    // Whether a reload was requested since the last call.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal() {
+        let signal = Signal::default();
+        assert!(!signal.take());
+        signal.0.store(true, Ordering::SeqCst);
+        assert!(signal.take());
+        assert!(!signal.take());
+    }
+}
@@ -0,0 +1,605 @@
+use crate::{playlist, Result_};
+
+// Average accuracy of every star bracket at every history snapshot of a player. Only the best
+// score of a map is stored so a snapshot averages the scores set before it as they are now. The
+// brackets are half open here so that no score is counted twice.
+struct Progression {
+    brackets: Vec<(String, f64, Option<f64>)>,
+    // Snapshot time and the average accuracy of every bracket, None without scores.
+    snapshots: Vec<(String, Vec<Option<f64>>)>,
+}
+
+fn progression(
+    db: &rusqlite::Connection,
+    player: &str,
+    boundaries: &[f64],
+) -> Result_<Progression> {
+    let brackets = playlist::star_brackets(boundaries)?;
+    let mut statement = db.prepare(
+        "SELECT DISTINCT recorded_at FROM player_history WHERE player_id = ? ORDER BY recorded_at",
+    )?;
+    let times = statement
+        .query_map(rusqlite::params![player], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    if times.is_empty() {
+        return Err(format!("no history recorded for player {}", player))?;
+    }
+    let mut snapshots = vec![];
+    for time in times {
+        let mut accuracies = vec![];
+        for &(_, min, max) in brackets.iter() {
+            accuracies.push(db.query_row(
+                "SELECT AVG(p.accuracy) FROM player_scores p JOIN scoresaber_songs s ON s.uid = p.uid WHERE p.player_id = ? AND p.accuracy IS NOT NULL AND p.time_set <= ? AND s.stars >= ? AND (? IS NULL OR s.stars < ?)",
+                rusqlite::params![player, time, min, max, max],
+                |row| row.get(0),
+            )?);
+        }
+        snapshots.push((time, accuracies));
+    }
+    Ok(Progression {
+        brackets,
+        snapshots,
+    })
+}
+
+// Index of the bracket whose accuracy rose the most between the last two snapshots.
+fn fastest_improving(progression: &Progression) -> Option<(usize, f64)> {
+    let len = progression.snapshots.len();
+    if len < 2 {
+        return None;
+    }
+    let before = &progression.snapshots[len - 2].1;
+    let after = &progression.snapshots[len - 1].1;
+    before
+        .iter()
+        .zip(after.iter())
+        .enumerate()
+        .filter_map(|(i, (before, after))| match (before, after) {
+            (Some(before), Some(after)) if after > before => Some((i, after - before)),
+            _ => None,
+        })
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+}
+
+// Prints the progression and writes a practice playlist of the bracket above the one the player is
+// improving fastest in, or above the highest one they have scores in.
+pub fn bucket_report(
+    db: &rusqlite::Connection,
+    player: &str,
+    boundaries: &[f64],
+    playlist_path: Option<&std::path::Path>,
+    playlist_output: &playlist::PlaylistOutput,
+) -> Result_<()> {
+    let progression = progression(db, player, boundaries)?;
+    print!("{:<19}", "recorded at");
+    for (name, _, _) in progression.brackets.iter() {
+        print!(" {:>8}", name);
+    }
+    println!();
+    for (time, accuracies) in progression.snapshots.iter() {
+        print!("{:<19}", time);
+        for accuracy in accuracies {
+            match accuracy {
+                Some(accuracy) => print!(" {:>7.2}%", accuracy * 100.0),
+                None => print!(" {:>8}", "-"),
+            }
+        }
+        println!();
+    }
+
+    let current = match fastest_improving(&progression) {
+        Some((i, change)) => {
+            println!(
+                "Improving fastest in the {} stars bracket (+{:.2}%).",
+                progression.brackets[i].0,
+                change * 100.0
+            );
+            Some(i)
+        }
+        None => progression
+            .snapshots
+            .last()
+            .and_then(|(_, accuracies)| accuracies.iter().rposition(Option::is_some)),
+    };
+    let target = match current {
+        Some(i) => i + 1,
+        None => 0,
+    };
+    let (name, min, max) = match progression.brackets.get(target) {
+        Some(bracket) => bracket,
+        None => {
+            println!("There is no bracket above to practice.");
+            return Ok(());
+        }
+    };
+    if let Some(path) = playlist_path {
+        // Easiest songs first.
+        let options = playlist::PlaylistOptions {
+            min_stars: Some(*min),
+            max_stars: *max,
+            reverse: true,
+            ..Default::default()
+        };
+        let mut practice = playlist::make_beatsaber_playlist(db, &options)?;
+        practice.title = format!("Practice {} stars", name);
+        println!("Practice playlist for the {} stars bracket:", name);
+        playlist::save_beatsaber_playlist(practice, path, playlist_output)?;
+    }
+    Ok(())
+}
+
+// Difficulties as (hash, name, diff, stars, current pp) grouped into songs ordered by the pp
+// gained by reaching `accuracy` on the difficulty with the most to gain. Difficulties without gain
+// are left out.
+fn songs_by_gain(
+    rows: Vec<(String, String, String, f64, f64)>,
+    accuracy: f64,
+) -> Vec<playlist::BeatSaberPlaylistSong> {
+    songs_by_largest(
+        rows.into_iter()
+            .map(|(hash, name, diff, stars, pp)| {
+                (hash, name, diff, crate::pp::pp(stars, accuracy) - pp)
+            })
+            .filter(|row| row.3 > 0.0)
+            .collect(),
+    )
+}
+
+// Groups rows of hash, name, diff and a value into songs ordered by the largest value of their
+// difficulties.
+fn songs_by_largest(
+    rows: Vec<(String, String, String, f64)>,
+) -> Vec<playlist::BeatSaberPlaylistSong> {
+    // Value and song with its difficulties. `positions` maps hashes to their song.
+    let mut songs: Vec<(f64, playlist::BeatSaberPlaylistSong)> = vec![];
+    let mut positions = std::collections::HashMap::new();
+    for (hash, name, diff, gain) in rows {
+        let position = *positions.entry(hash.clone()).or_insert_with(|| {
+            songs.push((
+                gain,
+                playlist::BeatSaberPlaylistSong {
+                    name,
+                    hash,
+                    difficulties: vec![],
+                },
+            ));
+            songs.len() - 1
+        });
+        let song = &mut songs[position];
+        song.0 = song.0.max(gain);
+        song.1
+            .difficulties
+            .extend(playlist::PlaylistDifficulty::from_diff(&diff));
+    }
+    songs.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+    songs.into_iter().map(|(_, song)| song).collect()
+}
+
+fn gain_rows(
+    db: &rusqlite::Connection,
+    sql: &str,
+    params: &[&dyn rusqlite::types::ToSql],
+) -> Result_<Vec<(String, String, String, f64, f64)>> {
+    let mut statement = db.prepare(sql)?;
+    let rows = statement
+        .query_map(params, |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+// Songs on which the player has difficulties with less than `acc_below` accuracy, ordered by the
+// pp they would gain by reaching it.
+pub fn improvement_playlist(
+    db: &rusqlite::Connection,
+    player: &str,
+    acc_below: f64,
+) -> Result_<playlist::BeatsaberPlaylist> {
+    let rows = gain_rows(
+        db,
+        "SELECT s.id, s.name, s.diff, s.stars, p.pp FROM scoresaber_songs s JOIN player_scores p ON p.uid = s.uid WHERE s.ranked = 1 AND p.player_id = ? AND p.accuracy < ?",
+        &[&player, &(acc_below / 100.0)],
+    )?;
+    if rows.is_empty() {
+        return Err(format!(
+            "player {} has no stored scores below {}% accuracy",
+            player, acc_below
+        ))?;
+    }
+    Ok(playlist::BeatsaberPlaylist {
+        title: format!("Improve {} (below {}% accuracy)", player, acc_below),
+        author: playlist::AUTHOR.to_string(),
+        description: format!(
+            "Contains the songs on which {} has less than {}% accuracy ordered by the pp gained by reaching it.",
+            player, acc_below
+        ),
+        songs: songs_by_gain(rows, acc_below / 100.0),
+    })
+}
+
+// The `count` ranked songs on which the player would gain the most pp by playing them at
+// `accuracy` compared to their stored score, or no score at all.
+pub fn potential_playlist(
+    db: &rusqlite::Connection,
+    player: &str,
+    accuracy: f64,
+    count: usize,
+) -> Result_<playlist::BeatsaberPlaylist> {
+    let stored: i64 = db.query_row(
+        "SELECT COUNT(*) FROM player_scores WHERE player_id = ?",
+        rusqlite::params![player],
+        |row| row.get(0),
+    )?;
+    if stored == 0 {
+        return Err(format!(
+            "player {} has no stored scores, they are stored with --scores",
+            player
+        ))?;
+    }
+    let rows = gain_rows(
+        db,
+        "SELECT s.id, s.name, s.diff, s.stars, IFNULL(p.pp, 0) FROM scoresaber_songs s LEFT JOIN player_scores p ON p.uid = s.uid AND p.player_id = ? WHERE s.ranked = 1",
+        &[&player],
+    )?;
+    let mut songs = songs_by_gain(rows, accuracy / 100.0);
+    songs.truncate(count);
+    Ok(playlist::BeatsaberPlaylist {
+        title: format!("Potential of {} at {}% accuracy", player, accuracy),
+        author: playlist::AUTHOR.to_string(),
+        description: format!(
+            "Contains the {} songs on which {} would gain the most pp by reaching {}% accuracy.",
+            songs.len(),
+            player,
+            accuracy
+        ),
+        songs,
+    })
+}
+
+// Songs with a difficulty whose stars changed in the last `days` days, most recent change first.
+// The names are annotated with the change of every difficulty like "Song (9.50★ → 10.10★)".
+pub fn reweighted_playlist(
+    db: &rusqlite::Connection,
+    days: u64,
+) -> Result_<playlist::BeatsaberPlaylist> {
+    let mut statement = db.prepare(
+        "SELECT c.uid, c.id, s.name, c.new_diff, c.old_stars, c.new_stars FROM ranking_changes c JOIN scoresaber_songs s ON s.uid = c.uid WHERE c.changed_at >= datetime('now', ?) ORDER BY c.changed_at DESC, c.rowid DESC",
+    )?;
+    let rows = statement
+        .query_map(rusqlite::params![format!("-{} days", days)], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, f64>(4)?,
+                row.get::<_, f64>(5)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    // Newest first so the first change of a difficulty has its current stars and the last one its
+    // stars before the window.
+    let mut changes: Vec<(i64, String, String, String, f64, f64)> = vec![];
+    for row in rows {
+        match changes.iter_mut().find(|change| change.0 == row.0) {
+            Some(change) => change.4 = row.4,
+            None => changes.push(row),
+        }
+    }
+    let mut songs: Vec<(playlist::BeatSaberPlaylistSong, Vec<String>)> = vec![];
+    let mut positions = std::collections::HashMap::new();
+    for (_, hash, name, diff, old_stars, new_stars) in changes {
+        let position = *positions.entry(hash.clone()).or_insert_with(|| {
+            songs.push((
+                playlist::BeatSaberPlaylistSong {
+                    name,
+                    hash,
+                    difficulties: vec![],
+                },
+                vec![],
+            ));
+            songs.len() - 1
+        });
+        let (song, annotations) = &mut songs[position];
+        song.difficulties
+            .extend(playlist::PlaylistDifficulty::from_diff(&diff));
+        annotations.push(format!("{:.2}★ → {:.2}★", old_stars, new_stars));
+    }
+    let songs = songs
+        .into_iter()
+        .map(|(song, annotations)| playlist::BeatSaberPlaylistSong {
+            name: format!("{} ({})", song.name, annotations.join(", ")),
+            ..song
+        })
+        .collect::<Vec<_>>();
+    Ok(playlist::BeatsaberPlaylist {
+        title: format!("Reweighted in the last {} days", days),
+        author: playlist::AUTHOR.to_string(),
+        description: format!(
+            "Contains the {} ranked songs whose star difficulty changed in the last {} days, most recent change first.",
+            songs.len(),
+            days
+        ),
+        songs,
+    })
+}
+
+// Songs on which the score of `target` beats the score of `player` or `player` has no score, ordered
+// by how much more pp `target` has.
+pub fn snipe_playlist(
+    db: &rusqlite::Connection,
+    player: &str,
+    target: &str,
+) -> Result_<playlist::BeatsaberPlaylist> {
+    for id in &[player, target] {
+        let stored: i64 = db.query_row(
+            "SELECT COUNT(*) FROM player_scores WHERE player_id = ?",
+            rusqlite::params![id],
+            |row| row.get(0),
+        )?;
+        if stored == 0 {
+            return Err(format!(
+                "player {} has no stored scores, they are stored with --scores",
+                id
+            ))?;
+        }
+    }
+    let mut statement = db.prepare(
+        "SELECT s.id, s.name, s.diff, t.pp - IFNULL(p.pp, 0) FROM player_scores t JOIN scoresaber_songs s ON s.uid = t.uid LEFT JOIN player_scores p ON p.uid = t.uid AND p.player_id = ? WHERE s.ranked = 1 AND t.player_id = ? AND (p.uid IS NULL OR t.modified_score > p.modified_score)",
+    )?;
+    let rows = statement
+        .query_map(rusqlite::params![player, target], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let songs = songs_by_largest(rows);
+    Ok(playlist::BeatsaberPlaylist {
+        title: format!("Snipe {} for {}", target, player),
+        author: playlist::AUTHOR.to_string(),
+        description: format!(
+            "Contains the {} songs on which {} has a better score than {} or {} has not played, ordered by the pp difference.",
+            songs.len(),
+            target,
+            player,
+            player
+        ),
+        songs,
+    })
+}
+
+// Songs on which the stored score of the player was set more than `months` ago, ordered by the
+// weighted pp the score currently contributes so that the old scores that matter most come first.
+pub fn stale_playlist(
+    db: &rusqlite::Connection,
+    player: &str,
+    months: u32,
+) -> Result_<playlist::BeatsaberPlaylist> {
+    let mut statement = db.prepare(
+        "SELECT s.id, s.name, s.diff, p.pp * p.weight FROM player_scores p JOIN scoresaber_songs s ON s.uid = p.uid WHERE s.ranked = 1 AND p.player_id = ? AND p.time_set < datetime('now', ?)",
+    )?;
+    let rows = statement
+        .query_map(
+            rusqlite::params![player, format!("-{} months", months)],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if rows.is_empty() {
+        return Err(format!(
+            "player {} has no stored scores older than {} months",
+            player, months
+        ))?;
+    }
+    let songs = songs_by_largest(rows);
+    Ok(playlist::BeatsaberPlaylist {
+        title: format!("Refresh {} (older than {} months)", player, months),
+        author: playlist::AUTHOR.to_string(),
+        description: format!(
+            "Contains the {} songs on which the score of {} is older than {} months ordered by the weighted pp it contributes.",
+            songs.len(),
+            player,
+            months
+        ),
+        songs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fastest_improving() {
+        let progression = Progression {
+            brackets: playlist::star_brackets(&[0.0, 3.0, 5.0]).unwrap(),
+            snapshots: vec![
+                (
+                    "2019-06-01 00:00:00".to_string(),
+                    vec![Some(0.9), Some(0.8), None],
+                ),
+                (
+                    "2019-07-01 00:00:00".to_string(),
+                    vec![Some(0.91), Some(0.85), Some(0.7)],
+                ),
+            ],
+        };
+        let (i, change) = fastest_improving(&progression).unwrap();
+        assert_eq!(i, 1);
+        assert!((change - 0.05).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_improvement_playlist() {
+        let db = crate::test_db();
+        let songs = crate::test_songs();
+        // The same pp on a harder difficulty leaves more to gain.
+        for (i, accuracy) in &[(1, 0.9), (2, 0.9), (3, 0.95)] {
+            db.execute(
+                "INSERT INTO player_scores (player_id, uid, score_id, rank, base_score, modified_score, accuracy, pp, weight, modifiers, bad_cuts, missed_notes, max_combo, full_combo, time_set) VALUES ('1', ?, 1, 1, 1, 1, ?, 300, 1, '', 0, 0, 1, 0, '2019-01-01 00:00:00')",
+                rusqlite::params![songs[*i].uid as i64, *accuracy],
+            )
+            .unwrap();
+        }
+        let playlist = improvement_playlist(&db, "1", 92.0).unwrap();
+        let names = playlist
+            .songs
+            .iter()
+            .map(|song| song.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, ["Milk Crown on Sonnetica", "Happppy song"]);
+        assert!(improvement_playlist(&db, "1", 50.0).is_err());
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_reweighted_playlist() {
+        let db = crate::test_db();
+        for (uid, old_stars, new_stars, days_ago) in &[
+            (109_086, 9.5, 9.8, 10),
+            (109_086, 9.8, 10.08, 2),
+            (100_024, 9.0, 9.38, 5),
+            (101_208, 9.0, 9.72, 60),
+        ] {
+            db.execute(
+                "INSERT INTO ranking_changes (uid, id, old_stars, new_stars, old_diff, new_diff, changed_at) SELECT uid, id, ?, ?, diff, diff, datetime('now', ?) FROM scoresaber_songs WHERE uid = ?",
+                rusqlite::params![*old_stars, *new_stars, format!("-{} days", days_ago), *uid],
+            )
+            .unwrap();
+        }
+        let playlist = reweighted_playlist(&db, 30).unwrap();
+        let names = playlist
+            .songs
+            .iter()
+            .map(|song| song.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            names,
+            [
+                "Milk Crown on Sonnetica (9.50★ → 10.08★)",
+                "NUCLEAR-STAR (9.00★ → 9.38★)"
+            ]
+        );
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_snipe_playlist() {
+        let db = crate::test_db();
+        let songs = crate::test_songs();
+        assert!(snipe_playlist(&db, "1", "2").is_err());
+        for (player, i, score, pp) in &[
+            ("1", 2, 100, 300.0),
+            ("1", 3, 200, 250.0),
+            ("2", 1, 50, 100.0),
+            ("2", 2, 150, 350.0),
+            ("2", 3, 100, 200.0),
+        ] {
+            db.execute(
+                "INSERT INTO player_scores (player_id, uid, score_id, rank, base_score, modified_score, accuracy, pp, weight, modifiers, bad_cuts, missed_notes, max_combo, full_combo, time_set) VALUES (?, ?, 1, 1, ?, ?, 0.9, ?, 1, '', 0, 0, 1, 0, '2019-01-01 00:00:00')",
+                rusqlite::params![*player, songs[*i].uid as i64, *score, *score, *pp],
+            )
+            .unwrap();
+        }
+        let playlist = snipe_playlist(&db, "1", "2").unwrap();
+        let names = playlist
+            .songs
+            .iter()
+            .map(|song| song.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, ["Happppy song", "Milk Crown on Sonnetica"]);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_stale_playlist() {
+        let db = crate::test_db();
+        let songs = crate::test_songs();
+        assert!(stale_playlist(&db, "1", 6).is_err());
+        for (i, pp, weight, age) in &[
+            (1, 300.0, 0.9, "-1 years"),
+            (2, 350.0, 1.0, "-1 days"),
+            (3, 250.0, 1.0, "-8 months"),
+        ] {
+            db.execute(
+                "INSERT INTO player_scores (player_id, uid, score_id, rank, base_score, modified_score, accuracy, pp, weight, modifiers, bad_cuts, missed_notes, max_combo, full_combo, time_set) VALUES ('1', ?, 1, 1, 1, 1, 0.9, ?, ?, '', 0, 0, 1, 0, datetime('now', ?))",
+                rusqlite::params![songs[*i].uid as i64, *pp, *weight, *age],
+            )
+            .unwrap();
+        }
+        let playlist = stale_playlist(&db, "1", 6).unwrap();
+        let names = playlist
+            .songs
+            .iter()
+            .map(|song| song.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, ["Happppy song", "NUCLEAR-STAR"]);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_unranked_songs_left_out() {
+        let db = crate::test_db();
+        let songs = crate::test_songs();
+        for (player, i, score, pp) in &[("1", 2, 100, 300.0), ("2", 3, 200, 350.0)] {
+            db.execute(
+                "INSERT INTO player_scores (player_id, uid, score_id, rank, base_score, modified_score, accuracy, pp, weight, modifiers, bad_cuts, missed_notes, max_combo, full_combo, time_set) VALUES (?, ?, 1, 1, ?, ?, 0.9, ?, 1, '', 0, 0, 1, 0, '2019-01-01 00:00:00')",
+                rusqlite::params![*player, songs[*i].uid as i64, *score, *score, *pp],
+            )
+            .unwrap();
+        }
+        db.execute(
+            "UPDATE scoresaber_songs SET ranked = 0 WHERE uid IN (?, ?)",
+            rusqlite::params![songs[2].uid as i64, songs[3].uid as i64],
+        )
+        .unwrap();
+        let names = |playlist: playlist::BeatsaberPlaylist| {
+            playlist
+                .songs
+                .into_iter()
+                .map(|song| song.name)
+                .collect::<Vec<String>>()
+        };
+        assert!(improvement_playlist(&db, "1", 95.0).is_err());
+        assert!(stale_playlist(&db, "1", 6).is_err());
+        assert!(names(snipe_playlist(&db, "1", "2").unwrap()).is_empty());
+        let potential = names(potential_playlist(&db, "1", 95.0, 10).unwrap());
+        assert_eq!(potential.len(), 1);
+        assert!(!potential.contains(&songs[2].name));
+        assert!(!potential.contains(&songs[3].name));
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_songs_by_gain() {
+        let row = |hash: &str, diff: &str, stars: f64, pp: f64| {
+            (
+                hash.to_string(),
+                hash.to_string(),
+                diff.to_string(),
+                stars,
+                pp,
+            )
+        };
+        let songs = songs_by_gain(
+            vec![
+                row("a", "_Expert_SoloStandard", 5.0, 0.0),
+                row("a", "_ExpertPlus_SoloStandard", 8.0, 0.0),
+                row("b", "_ExpertPlus_SoloStandard", 10.0, 0.0),
+                row("c", "_ExpertPlus_SoloStandard", 10.0, 1000.0),
+            ],
+            0.95,
+        );
+        let hashes = songs
+            .iter()
+            .map(|song| song.hash.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(hashes, ["b", "a"]);
+        assert_eq!(songs[1].difficulties.len(), 2);
+    }
+}
@@ -0,0 +1,512 @@
+use crate::{archive, http, player::PlayerId, profile, Result_, ScoreSaberSongId};
+
+const SCORESABER_PLAYER_API_URL: &str = "https://scoresaber.com/api/player";
+const SCORES_PAGE_LIMIT: u64 = 100;
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Score {
+    pub id: u64,
+    pub rank: u64,
+    pub base_score: u64,
+    pub modified_score: u64,
+    pub pp: f64,
+    pub weight: f64,
+    // Comma separated like "DA,FS".
+    pub modifiers: String,
+    pub bad_cuts: u64,
+    pub missed_notes: u64,
+    pub max_combo: u64,
+    pub full_combo: bool,
+    pub time_set: String,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Leaderboard {
+    pub id: ScoreSaberSongId,
+    // 0 when ScoreSaber does not know it.
+    pub max_score: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct PlayerScore {
+    pub score: Score,
+    pub leaderboard: Leaderboard,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoresPageMetadata {
+    pub total: u64,
+    pub page: u64,
+    pub items_per_page: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoresPage {
+    pub player_scores: Vec<PlayerScore>,
+    pub metadata: ScoresPageMetadata,
+}
+
+impl ScoresPage {
+    pub fn last_page(&self) -> bool {
+        self.metadata.page * self.metadata.items_per_page >= self.metadata.total
+    }
+}
+
+// 1 is first page. `sort` is "top" or "recent".
+pub fn get_scores_page(
+    client: &reqwest::Client,
+    player: &str,
+    sort: &str,
+    page: u64,
+) -> Result_<ScoresPage> {
+    let url = reqwest::Url::parse_with_params(
+        &format!("{}/{}/scores", SCORESABER_PLAYER_API_URL, player),
+        &[
+            ("sort", sort),
+            ("limit", &SCORES_PAGE_LIMIT.to_string()),
+            ("page", &page.to_string()),
+        ],
+    )?;
+    log::info!("request: {}", url);
+    let (status, body) = http::get(url.as_str(), || client.get(url.clone()))?;
+    if status.is_success() {
+        archive::record(url.as_str(), &body);
+        let _timer = profile::timer(profile::Kind::Decode);
+        Ok(serde_json::from_slice(&body)?)
+    } else {
+        Err(format!(
+            "response status code does not indiciate success: {}",
+            status
+        ))?
+    }
+}
+
+// ScoreSaber does not know the max score of every leaderboard. BeatSaver's note count fills the
+// gap if it has been fetched.
+fn max_score(db: &rusqlite::Connection, score: &PlayerScore) -> Result_<Option<u64>> {
+    use rusqlite::OptionalExtension;
+    if score.leaderboard.max_score > 0 {
+        return Ok(Some(score.leaderboard.max_score));
+    }
+    let max_score: Option<i64> = db
+        .query_row(
+            "SELECT d.max_score FROM scoresaber_songs s JOIN beatsaver_difficulties d ON d.hash = s.id AND d.difficulty = s.difficulty AND d.characteristic = s.characteristic WHERE s.uid = ?",
+            rusqlite::params![score.leaderboard.id as i64],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(max_score.map(|x| x as u64))
+}
+
+// ScoreSaber only reports the best score of a player on a leaderboard, so the latest one is also the
+// best. A score that was set before the stored one, for example from a stale page, is ignored while
+// the stored score is refreshed, like its pp after a rerank.
+pub fn insert_score_into_db(
+    db: &rusqlite::Connection,
+    player: &str,
+    score: &PlayerScore,
+) -> Result_<()> {
+    let _timer = profile::timer(profile::Kind::Database);
+    let accuracy = max_score(db, score)?.map(|max| score.score.base_score as f64 / max as f64);
+    db.execute(
+        "REPLACE INTO player_scores (player_id, uid, score_id, rank, base_score, modified_score, accuracy, pp, weight, modifiers, bad_cuts, missed_notes, max_combo, full_combo, time_set) SELECT ?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,datetime(?15) WHERE NOT EXISTS (SELECT 1 FROM player_scores WHERE player_id = ?1 AND uid = ?2 AND time_set > datetime(?15))",
+        rusqlite::params![
+            player,
+            score.leaderboard.id as i64,
+            score.score.id as i64,
+            score.score.rank as i64,
+            score.score.base_score as i64,
+            score.score.modified_score as i64,
+            accuracy,
+            score.score.pp,
+            score.score.weight,
+            score.score.modifiers,
+            score.score.bad_cuts as i64,
+            score.score.missed_notes as i64,
+            score.score.max_combo as i64,
+            score.score.full_combo,
+            score.score.time_set
+        ],
+    )?;
+    Ok(())
+}
+
+fn is_stored(db: &rusqlite::Connection, player: &str, score: &PlayerScore) -> Result_<bool> {
+    let count: i64 = db.query_row(
+        "SELECT COUNT(*) FROM player_scores WHERE player_id = ? AND score_id = ?",
+        rusqlite::params![player, score.score.id as i64],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FirstClear {
+    pub name: String,
+    pub diff: String,
+    pub accuracy: Option<f64>,
+}
+
+// Whether the modifiers, like "DA,NF", include No Fail. Such a score is not a clear.
+fn is_no_fail(modifiers: &str) -> bool {
+    modifiers.split(',').any(|modifier| modifier.trim() == "NF")
+}
+
+// Records the score as a first clear if the leaderboard is ranked and the player has no stored
+// score without No Fail on it yet. Has to be called before the score is stored and only makes sense
+// for players with a full crawl, see has_full_crawl.
+fn record_first_clear(
+    db: &rusqlite::Connection,
+    player: &str,
+    score: &PlayerScore,
+) -> Result_<Option<FirstClear>> {
+    use rusqlite::OptionalExtension;
+    if is_no_fail(&score.score.modifiers) {
+        return Ok(None);
+    }
+    let played: i64 = db.query_row(
+        "SELECT COUNT(*) FROM player_scores WHERE player_id = ? AND uid = ? AND ',' || modifiers || ',' NOT LIKE '%,NF,%'",
+        rusqlite::params![player, score.leaderboard.id as i64],
+        |row| row.get(0),
+    )?;
+    if played > 0 {
+        return Ok(None);
+    }
+    let song: Option<(String, String)> = db
+        .query_row(
+            "SELECT name, diff FROM scoresaber_songs WHERE uid = ? AND ranked = 1",
+            rusqlite::params![score.leaderboard.id as i64],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let (name, diff) = match song {
+        Some(song) => song,
+        None => return Ok(None),
+    };
+    let accuracy = max_score(db, score)?.map(|max| score.score.base_score as f64 / max as f64);
+    db.execute(
+        "INSERT OR IGNORE INTO first_clears (player_id, uid, accuracy, cleared_at) VALUES (?,?,?,datetime(?))",
+        rusqlite::params![
+            player,
+            score.leaderboard.id as i64,
+            accuracy,
+            score.score.time_set
+        ],
+    )?;
+    Ok(Some(FirstClear {
+        name,
+        diff,
+        accuracy,
+    }))
+}
+
+fn has_full_crawl(db: &rusqlite::Connection, player: &str) -> Result_<bool> {
+    let count: i64 = db.query_row(
+        "SELECT COUNT(*) FROM full_score_crawls WHERE player_id = ?",
+        rusqlite::params![player],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+fn record_full_crawl(db: &rusqlite::Connection, player: &str) -> Result_<()> {
+    db.execute(
+        "REPLACE INTO full_score_crawls (player_id, completed_at) VALUES (?, datetime('now'))",
+        rusqlite::params![player],
+    )?;
+    Ok(())
+}
+
+struct ScoreCrawl {
+    player: PlayerId,
+    next_page: u64,
+    // Recent crawls go through the recent scores until reaching one that is already stored.
+    recent: bool,
+    // A recent crawl of a player without stored scores has nothing to catch up to so it stops after
+    // the first page. The full archive is built by a crawl that is not recent.
+    had_scores: bool,
+    // Whether a crawl of the top scores of the player went through all pages before. Only then
+    // recent crawls look for first clears because before a score could be missing just because it
+    // was not fetched yet.
+    full_crawl: bool,
+    new_scores: usize,
+    first_clears: Vec<FirstClear>,
+}
+
+pub struct ScoreUpdate {
+    pub player: PlayerId,
+    pub new_scores: usize,
+    pub first_clears: Vec<FirstClear>,
+}
+
+// Stores the next page of the crawl. Returns whether there are more pages.
+fn crawl_page(
+    db: &rusqlite::Connection,
+    client: &reqwest::Client,
+    crawl: &mut ScoreCrawl,
+) -> Result_<bool> {
+    let sort = if crawl.recent { "recent" } else { "top" };
+    let scores = get_scores_page(client, &crawl.player, sort, crawl.next_page)?;
+    crate::progress::emit(crate::progress::Event::PageFetched {
+        source: "scores",
+        page: crawl.next_page,
+    });
+    println!(
+        "storing {} scores page {} of player {} with {} scores in total",
+        sort, crawl.next_page, crawl.player, scores.metadata.total
+    );
+    for score in scores.player_scores.iter() {
+        if crawl.recent && is_stored(db, &crawl.player, score)? {
+            return Ok(false);
+        }
+        if crawl.recent && crawl.full_crawl {
+            if let Some(clear) = record_first_clear(db, &crawl.player, score)? {
+                crawl.first_clears.push(clear);
+            }
+        }
+        insert_score_into_db(db, &crawl.player, score)?;
+        crawl.new_scores += 1;
+    }
+    crawl.next_page += 1;
+    Ok(!(scores.player_scores.is_empty()
+        || scores.last_page()
+        || (crawl.recent && !crawl.had_scores)))
+}
+
+// Crawls the scores of all players. One page is fetched per player in turn so that a player with
+// many scores does not hold up the updates of the others. A failing player is skipped.
+// With `recent` only the scores set since the newest stored score are fetched. Returns the update
+// of every player that did not fail.
+pub fn update_scores(
+    db: &rusqlite::Connection,
+    client: &reqwest::Client,
+    players: &[PlayerId],
+    recent: bool,
+) -> Result_<Vec<ScoreUpdate>> {
+    let mut queue = std::collections::VecDeque::new();
+    for player in players {
+        let stored: i64 = db.query_row(
+            "SELECT COUNT(*) FROM player_scores WHERE player_id = ?",
+            rusqlite::params![player],
+            |row| row.get(0),
+        )?;
+        queue.push_back(ScoreCrawl {
+            player: player.clone(),
+            next_page: 1,
+            recent,
+            had_scores: stored > 0,
+            full_crawl: has_full_crawl(db, player)?,
+            new_scores: 0,
+            first_clears: vec![],
+        });
+    }
+    let mut done = vec![];
+    while let Some(mut crawl) = queue.pop_front() {
+        match crawl_page(db, client, &mut crawl) {
+            Ok(true) => queue.push_back(crawl),
+            Ok(false) => {
+                if !crawl.recent {
+                    record_full_crawl(db, &crawl.player)?;
+                }
+                done.push(ScoreUpdate {
+                    player: crawl.player,
+                    new_scores: crawl.new_scores,
+                    first_clears: crawl.first_clears,
+                })
+            }
+            Err(err) => log::warn!("failed to crawl scores of player {}: {}", crawl.player, err),
+        }
+    }
+    Ok(done)
+}
+
+fn first_clears_notification(update: &ScoreUpdate) -> crate::notify::Notification {
+    let body = update
+        .first_clears
+        .iter()
+        .map(|clear| match clear.accuracy {
+            Some(accuracy) => format!("{} ({}): {:.2}%", clear.name, clear.diff, accuracy * 100.0),
+            None => format!("{} ({})", clear.name, clear.diff),
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    crate::notify::Notification {
+        title: format!(
+            "Player {} cleared {} ranked songs for the first time",
+            update.player,
+            update.first_clears.len()
+        ),
+        body,
+    }
+}
+
+// What `poll` reloads from the config.
+pub struct PollSettings {
+    pub interval: std::time::Duration,
+    pub notifiers: Vec<Box<dyn crate::notify::Notifier>>,
+    pub max_requests_per_second: Option<f64>,
+    pub host_requests_per_second: Vec<String>,
+}
+
+fn apply_rate_limits(settings: &PollSettings) -> Result_<()> {
+    crate::http::set_rate_limits(
+        settings.max_requests_per_second,
+        &settings.host_requests_per_second,
+    )
+}
+
+// Runs until an error that is not specific to a single player occurs.
+// Loads the settings with `load` at the start and again when `reload` is signaled. This happens
+// between updates so that a reload never interrupts one and a broken config keeps the old settings.
+pub fn poll(
+    db: &rusqlite::Connection,
+    client: &reqwest::Client,
+    reload: &crate::reload::Signal,
+    load: impl Fn() -> Result_<PollSettings>,
+) -> Result_<()> {
+    let mut settings = load()?;
+    apply_rate_limits(&settings)?;
+    loop {
+        if reload.take() {
+            match load().and_then(|reloaded| {
+                apply_rate_limits(&reloaded)?;
+                Ok(reloaded)
+            }) {
+                Ok(reloaded) => {
+                    crate::notify::flush_all(&settings.notifiers);
+                    settings = reloaded;
+                    println!("reloaded the config");
+                }
+                Err(err) => log::error!(
+                    "keeping the old config because it cannot be reloaded: {}",
+                    err
+                ),
+            }
+        }
+        let players = crate::player::tracked_players(db)?;
+        for update in update_scores(db, client, &players, true)? {
+            if update.new_scores > 0 {
+                println!(
+                    "stored {} new scores of player {}",
+                    update.new_scores, update.player
+                );
+            }
+            if !update.first_clears.is_empty() {
+                crate::notify::notify_all(&settings.notifiers, &first_clears_notification(&update));
+            }
+        }
+        crate::notify::flush_all(&settings.notifiers);
+        std::thread::sleep(settings.interval);
+    }
+}
+
+pub fn print_first_clears(db: &rusqlite::Connection, player: &str) -> Result_<()> {
+    let mut statement = db.prepare(
+        "SELECT f.cleared_at, s.name, s.diff, f.accuracy FROM first_clears f JOIN scoresaber_songs s ON s.uid = f.uid WHERE f.player_id = ? ORDER BY f.cleared_at",
+    )?;
+    let iter = statement.query_map(rusqlite::params![player], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, Option<f64>>(3)?,
+        ))
+    })?;
+    for clear in iter {
+        let (cleared_at, name, diff, accuracy) = clear?;
+        let accuracy = match accuracy {
+            Some(accuracy) => format!("{:.2}%", accuracy * 100.0),
+            None => "-".to_string(),
+        };
+        println!("{:<19} {:>7} {} ({})", cleared_at, accuracy, name, diff);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scores_page() {
+        let page: ScoresPage =
+            serde_json::from_slice(include_bytes!("../test_data/player-scores.json")).unwrap();
+        assert!(page.last_page());
+        assert_eq!(page.player_scores.len(), 2);
+        let score = &page.player_scores[0];
+        assert_eq!(score.leaderboard.id, 109086);
+        assert_eq!(score.score.modifiers, "FS");
+        assert_eq!(score.score.missed_notes, 1);
+        assert!(!score.score.full_combo);
+        assert!(page.player_scores[1].score.full_combo);
+    }
+
+    #[test]
+    fn test_first_clear() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::create_schema(&db).unwrap();
+        let page: ScoresPage =
+            serde_json::from_slice(include_bytes!("../test_data/player-scores.json")).unwrap();
+        let score = &page.player_scores[0];
+        assert_eq!(record_first_clear(&db, "1", score).unwrap(), None);
+        db.execute(
+            "INSERT INTO scoresaber_songs (uid, id, name, songSubName, songAuthorName, levelAuthorName, bpm, diff, stars) VALUES (?, 'hash', 'song', '', '', '', 120, '_Expert_SoloStandard', 5)",
+            rusqlite::params![score.leaderboard.id as i64],
+        )
+        .unwrap();
+        let clear = record_first_clear(&db, "1", score).unwrap().unwrap();
+        assert_eq!(clear.name, "song");
+        insert_score_into_db(&db, "1", score).unwrap();
+        assert_eq!(record_first_clear(&db, "1", score).unwrap(), None);
+        // No Fail scores are not clears and do not count as one.
+        let mut no_fail = score.clone();
+        no_fail.score.modifiers = "DA,NF".to_string();
+        assert_eq!(record_first_clear(&db, "2", &no_fail).unwrap(), None);
+        insert_score_into_db(&db, "2", &no_fail).unwrap();
+        assert!(record_first_clear(&db, "2", score).unwrap().is_some());
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_full_crawl() {
+        let db = crate::test_db();
+        assert!(!has_full_crawl(&db, "1").unwrap());
+        record_full_crawl(&db, "1").unwrap();
+        record_full_crawl(&db, "1").unwrap();
+        assert!(has_full_crawl(&db, "1").unwrap());
+        assert!(!has_full_crawl(&db, "2").unwrap());
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_keep_latest_score() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        crate::create_schema(&db).unwrap();
+        let page: ScoresPage =
+            serde_json::from_slice(include_bytes!("../test_data/player-scores.json")).unwrap();
+        let latest = page.player_scores[0].clone();
+        let mut older = latest.clone();
+        older.score.id += 1;
+        older.score.time_set = "2000-01-01T00:00:00.000Z".to_string();
+        let mut refreshed = latest.clone();
+        refreshed.score.pp += 1.0;
+        let stored = |db: &rusqlite::Connection| -> (i64, f64) {
+            db.query_row(
+                "SELECT score_id, pp FROM player_scores WHERE player_id = '1'",
+                rusqlite::params![],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+        insert_score_into_db(&db, "1", &latest).unwrap();
+        insert_score_into_db(&db, "1", &older).unwrap();
+        assert_eq!(stored(&db), (latest.score.id as i64, latest.score.pp));
+        insert_score_into_db(&db, "1", &refreshed).unwrap();
+        assert_eq!(stored(&db), (latest.score.id as i64, refreshed.score.pp));
+        db.close().unwrap();
+    }
+}
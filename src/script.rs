@@ -0,0 +1,112 @@
+use crate::Result_;
+
+// The probable script of a song title, for example for playlists that a stream or karaoke audience
+// can read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Script {
+    Latin,
+    // Any kana, usually together with kanji.
+    Japanese,
+    Korean,
+    // Chinese characters without kana, which can be Chinese or Japanese.
+    Han,
+    Cyrillic,
+    Other,
+}
+
+impl Script {
+    // As stored in `title_script`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Script::Latin => "latin",
+            Script::Japanese => "japanese",
+            Script::Korean => "korean",
+            Script::Han => "han",
+            Script::Cyrillic => "cyrillic",
+            Script::Other => "other",
+        }
+    }
+}
+
+impl std::str::FromStr for Script {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "latin" => Ok(Script::Latin),
+            "japanese" => Ok(Script::Japanese),
+            "korean" => Ok(Script::Korean),
+            "han" => Ok(Script::Han),
+            "cyrillic" => Ok(Script::Cyrillic),
+            "other" => Ok(Script::Other),
+            _ => Err(format!(
+                "unknown script {}, expected latin, japanese, korean, han, cyrillic or other",
+                s
+            )),
+        }
+    }
+}
+
+// The most specific script any letter of the title belongs to so that a Latin title with a
+// Japanese subtitle counts as Japanese. Titles without letters count as Latin.
+pub fn detect(title: &str) -> Script {
+    let mut scripts = vec![];
+    for c in title.chars().filter(|c| c.is_alphabetic()) {
+        let script = match c as u32 {
+            0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => Script::Korean,
+            0x3040..=0x30FF | 0x31F0..=0x31FF | 0xFF66..=0xFF9F => Script::Japanese,
+            0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF => Script::Han,
+            0x0400..=0x04FF => Script::Cyrillic,
+            0x0000..=0x024F | 0x1E00..=0x1EFF | 0xFF21..=0xFF5A => Script::Latin,
+            _ => Script::Other,
+        };
+        scripts.push(script);
+    }
+    [
+        Script::Korean,
+        Script::Japanese,
+        Script::Han,
+        Script::Cyrillic,
+        Script::Other,
+    ]
+    .iter()
+    .cloned()
+    .find(|script| scripts.contains(script))
+    .unwrap_or(Script::Latin)
+}
+
+// Fills in the script of songs that were stored before it was detected.
+pub fn update_missing(db: &rusqlite::Connection) -> Result_<()> {
+    let mut statement =
+        db.prepare("SELECT DISTINCT name FROM scoresaber_songs WHERE title_script IS NULL")?;
+    let names = statement
+        .query_map(rusqlite::params![], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for name in names {
+        db.execute(
+            "UPDATE scoresaber_songs SET title_script = ? WHERE name = ? AND title_script IS NULL",
+            rusqlite::params![detect(&name).name(), name],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect("Milk Crown on Sonnetica"), Script::Latin);
+        assert_eq!(detect("Pokémon"), Script::Latin);
+        assert_eq!(detect("1234"), Script::Latin);
+        assert_eq!(detect("Happppy song (ハッピーソング)"), Script::Japanese);
+        assert_eq!(detect("千本桜"), Script::Han);
+        assert_eq!(detect("夜に駆ける"), Script::Japanese);
+        assert_eq!(detect("뱅뱅뱅"), Script::Korean);
+        assert_eq!(detect("Катюша"), Script::Cyrillic);
+        assert_eq!(detect("Ελλάδα"), Script::Other);
+        assert_eq!("Latin".parse::<Script>(), Ok(Script::Latin));
+        assert!("klingon".parse::<Script>().is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{playlist, Result_};
+
+const LEADERBOARD_URL: &str = "https://scoresaber.com/leaderboard";
+const BEATSAVER_MAP_URL: &str = "https://beatsaver.com/beatmap";
+
+// Accuracy the estimated pp of a difficulty are shown for.
+const ESTIMATE_ACCURACY: f64 = 0.95;
+
+// The hash of a song given its hash or the uid of one of its leaderboards.
+fn song_hash(db: &rusqlite::Connection, song: &str) -> Result_<String> {
+    use rusqlite::OptionalExtension;
+    let hash: Option<String> = db
+        .query_row(
+            "SELECT id FROM scoresaber_songs WHERE CAST(uid AS TEXT) = ? OR UPPER(id) = UPPER(?) LIMIT 1",
+            rusqlite::params![song, song],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(hash.ok_or_else(|| format!("there is no leaderboard uid or song hash {}", song))?)
+}
+
+struct CardDifficulty {
+    uid: i64,
+    diff: String,
+    stars: f64,
+    plays: i64,
+    notes: Option<i64>,
+    length: Option<f64>,
+}
+
+// A time a leaderboard was ranked with the stars it last had then.
+struct Episode {
+    started_at: String,
+    ended_at: Option<String>,
+    stars: Option<f64>,
+}
+
+fn episodes(db: &rusqlite::Connection, uid: i64) -> Result_<Vec<Episode>> {
+    let mut statement = db.prepare(
+        "SELECT e.started_at, e.ended_at, (SELECT h.stars FROM scoresaber_songs_history h WHERE h.uid = e.uid AND h.episode = e.episode ORDER BY h.rowid DESC LIMIT 1) FROM ranked_episodes e WHERE e.uid = ? ORDER BY e.episode",
+    )?;
+    let episodes = statement
+        .query_map(rusqlite::params![uid], |row| {
+            Ok(Episode {
+                started_at: row.get(0)?,
+                ended_at: row.get(1)?,
+                stars: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(episodes)
+}
+
+// The day of a datetime column.
+fn day(datetime: &str) -> &str {
+    datetime.get(..10).unwrap_or(datetime)
+}
+
+fn format_length(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+// Builds the card of a song from every table that knows something about it.
+fn card(db: &rusqlite::Connection, hash: &str) -> Result_<String> {
+    use std::fmt::Write;
+    let (name, sub_name, song_author, mapper, bpm, bpm_status): (
+        String,
+        String,
+        String,
+        String,
+        f64,
+        String,
+    ) = db.query_row(
+        "SELECT name, songSubName, songAuthorName, levelAuthorName, bpm, bpm_status FROM scoresaber_songs WHERE id = ? ORDER BY stars DESC LIMIT 1",
+        rusqlite::params![hash],
+        |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+            ))
+        },
+    )?;
+    let key: Option<String> = {
+        use rusqlite::OptionalExtension;
+        db.query_row(
+            "SELECT key FROM beatsaver_maps WHERE hash = ?",
+            rusqlite::params![hash],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or_default()
+    };
+    let mut statement = db.prepare(&format!(
+        "SELECT s.uid, s.diff, s.stars, s.scores, d.notes, d.length FROM {} WHERE s.id = ? ORDER BY s.stars DESC",
+        playlist::SONGS_FROM
+    ))?;
+    let difficulties = statement
+        .query_map(rusqlite::params![hash], |row| {
+            Ok(CardDifficulty {
+                uid: row.get(0)?,
+                diff: row.get(1)?,
+                stars: row.get(2)?,
+                plays: row.get(3)?,
+                notes: row.get(4)?,
+                length: row.get(5)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut scores_statement = db.prepare(
+        "SELECT player_id, accuracy, pp, full_combo FROM player_scores WHERE uid = ? ORDER BY pp DESC",
+    )?;
+
+    let mut card = String::new();
+    if sub_name.is_empty() {
+        writeln!(card, "{}", name)?;
+    } else {
+        writeln!(card, "{} {}", name, sub_name)?;
+    }
+    writeln!(card, "by {}, mapped by {}", song_author, mapper)?;
+    let mut facts = vec![if bpm_status == "invalid" {
+        "BPM unknown".to_string()
+    } else {
+        format!("{} BPM", bpm)
+    }];
+    if let Some(length) = difficulties.iter().find_map(|x| x.length) {
+        facts.push(format_length(length));
+    }
+    writeln!(card, "{}", facts.join(", "))?;
+    writeln!(card)?;
+    for difficulty in difficulties.iter() {
+        let name = match crate::parse_difficulty(&difficulty.diff) {
+            Some((name, "Standard")) => name.to_string(),
+            Some((name, characteristic)) => format!("{} {}", characteristic, name),
+            None => difficulty.diff.clone(),
+        };
+        let mut line = format!(
+            "  {:<20} {:>6.2}★  {:>6.1}pp at {}%  {} plays",
+            name,
+            difficulty.stars,
+            crate::pp::pp(difficulty.stars, ESTIMATE_ACCURACY),
+            ESTIMATE_ACCURACY * 100.0,
+            difficulty.plays
+        );
+        if let Some(notes) = difficulty.notes {
+            line.push_str(&format!("  {} notes", notes));
+            if let Some(length) = difficulty.length.filter(|x| *x > 0.0) {
+                line.push_str(&format!("  {:.2} nps", notes as f64 / length));
+            }
+        }
+        writeln!(card, "{}", line)?;
+        // Only leaderboards that were unranked at some point have a history worth showing.
+        let episodes = episodes(db, difficulty.uid)?;
+        if episodes.len() > 1 || episodes.iter().any(|x| x.ended_at.is_some()) {
+            for episode in episodes.iter() {
+                let mut line = format!("    ranked {}", day(&episode.started_at));
+                match &episode.ended_at {
+                    Some(ended_at) => line.push_str(&format!(" to {}", day(ended_at))),
+                    None => line.push_str(" until now"),
+                }
+                if let Some(stars) = episode.stars {
+                    line.push_str(&format!(" at {:.2}★", stars));
+                }
+                writeln!(card, "{}", line)?;
+            }
+        }
+        let scores = scores_statement
+            .query_map(rusqlite::params![difficulty.uid], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<f64>>(1)?,
+                    row.get::<_, f64>(2)?,
+                    row.get::<_, bool>(3)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (player, accuracy, pp, full_combo) in scores {
+            let accuracy = match accuracy {
+                Some(accuracy) => format!("{:.2}%", accuracy * 100.0),
+                None => "-".to_string(),
+            };
+            writeln!(
+                card,
+                "    {}: {} {:.1}pp{}",
+                player,
+                accuracy,
+                pp,
+                if full_combo { " FC" } else { "" }
+            )?;
+        }
+    }
+    writeln!(card)?;
+    for difficulty in difficulties.iter() {
+        writeln!(card, "{}/{}", LEADERBOARD_URL, difficulty.uid)?;
+    }
+    if let Some(key) = key {
+        writeln!(card, "{}/{}", BEATSAVER_MAP_URL, key)?;
+    }
+    Ok(card)
+}
+
+pub fn run(db: &rusqlite::Connection, song: &str) -> Result_<()> {
+    let hash = song_hash(db, song)?;
+    print!("{}", card(db, &hash)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_card() {
+        let db = crate::test_db();
+        db.execute(
+            "INSERT INTO player_scores (player_id, uid, score_id, rank, base_score, modified_score, accuracy, pp, weight, modifiers, bad_cuts, missed_notes, max_combo, full_combo, time_set) VALUES ('1', 109086, 1, 1, 1, 1, 0.9, 300, 1, '', 0, 0, 1, 1, '2019-01-01 00:00:00')",
+            rusqlite::params![],
+        )
+        .unwrap();
+        let hash = song_hash(&db, "109086").unwrap();
+        assert_eq!(hash, "CFCA2FE00BCC418DC9ECF64D92FC01CEEC52C375");
+        assert_eq!(song_hash(&db, &hash.to_lowercase()).unwrap(), hash);
+        assert!(song_hash(&db, "1").is_err());
+        let card = card(&db, &hash).unwrap();
+        assert!(card.starts_with("Milk Crown on Sonnetica\nby "));
+        assert!(card.contains("255 BPM"));
+        assert!(card.contains("    1: 90.00% 300.0pp FC\n"));
+        assert!(card.contains("https://scoresaber.com/leaderboard/109086\n"));
+        assert_eq!(format_length(125.4), "2:05");
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_episodes() {
+        let db = crate::test_db();
+        let hash = song_hash(&db, "109086").unwrap();
+        assert!(!card(&db, &hash).unwrap().contains("    ranked "));
+        let seen = crate::test_songs()
+            .iter()
+            .map(|song| song.uid as i64)
+            .filter(|uid| *uid != 109086)
+            .collect::<std::collections::HashSet<i64>>();
+        assert_eq!(crate::prune(&db, &seen).unwrap().len(), 1);
+        db.execute(
+            "UPDATE ranked_episodes SET started_at = '2019-01-01 00:00:00', ended_at = '2019-05-01 00:00:00' WHERE uid = 109086",
+            rusqlite::params![],
+        )
+        .unwrap();
+        assert!(card(&db, &hash)
+            .unwrap()
+            .contains("    ranked 2019-01-01 to 2019-05-01 at 10.08★\n"));
+        // Ranking it again keeps the first episode and starts a second one with the new stars.
+        let mut song = crate::test_songs()
+            .into_iter()
+            .find(|song| song.uid == 109086)
+            .unwrap();
+        song.star_difficulty = 11.0;
+        crate::insert_song_into_db(&db, &song).unwrap();
+        let card = card(&db, &hash).unwrap();
+        assert!(card.contains("    ranked 2019-01-01 to 2019-05-01 at 10.08★\n"));
+        assert!(card.contains(" until now at 11.00★\n"));
+        let history: Vec<(f64, i64)> = db
+            .prepare("SELECT stars, episode FROM scoresaber_songs_history WHERE uid = 109086 ORDER BY rowid")
+            .unwrap()
+            .query_map(rusqlite::params![], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(history, vec![(10.08, 1), (11.0, 2)]);
+        db.close().unwrap();
+    }
+}
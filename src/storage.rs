@@ -0,0 +1,317 @@
+use crate::Result_;
+use rusqlite::types::Value;
+
+// Databases the crawled tables are copied into after every crawl. The crawler itself always works
+// on SQLite, which keeps the checkpoints, caches and history it reads back. Websites and bots on a
+// server query the copy instead, either in PostgreSQL or in another SQLite file that the crawl does
+// not lock while it runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sqlite" => Ok(Backend::Sqlite),
+            "postgres" => Ok(Backend::Postgres),
+            _ => Err(format!(
+                "unknown backend {}, expected sqlite or postgres",
+                s
+            )),
+        }
+    }
+}
+
+// The tables other programs read. Caches, checkpoints and the data only the crawler needs are left
+// out.
+pub const TABLES: &[&str] = &[
+    "scoresaber_songs",
+    "ranking_changes",
+    "ranked_episodes",
+    "crawls",
+    "change_reports",
+    "beatsaver_maps",
+    "beatsaver_difficulties",
+    "tracked_players",
+    "player_history",
+    "player_scores",
+    "first_clears",
+];
+
+// The type of a column by SQLite's affinity rules. Numeric columns count as real.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnType {
+    Integer,
+    Real,
+    Text,
+    Blob,
+}
+
+impl ColumnType {
+    fn from_declared(declared: &str) -> Self {
+        let declared = declared.to_uppercase();
+        if declared.contains("INT") {
+            ColumnType::Integer
+        } else if ["CHAR", "CLOB", "TEXT"]
+            .iter()
+            .any(|x| declared.contains(x))
+        {
+            ColumnType::Text
+        } else if declared.is_empty() || declared.contains("BLOB") {
+            ColumnType::Blob
+        } else {
+            ColumnType::Real
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub primary_key: bool,
+}
+
+// A database that holds copies of tables.
+pub trait Storage {
+    // Replaces the table with one of `columns` holding `rows` in one transaction so that readers
+    // see either the old or the new copy. Recreating it keeps up with columns added by migrations.
+    fn replace_table(
+        &mut self,
+        table: &str,
+        columns: &[Column],
+        rows: &[Vec<Value>],
+    ) -> Result_<()>;
+}
+
+// Opens the storage of `--backend` and `--dsn`. SQLite without a dsn is the working database alone.
+pub fn open(backend: Backend, dsn: Option<&str>) -> Result_<Option<Box<dyn Storage>>> {
+    match (backend, dsn) {
+        (Backend::Sqlite, None) => Ok(None),
+        (Backend::Sqlite, Some(path)) => Ok(Some(Box::new(SqliteStorage {
+            db: rusqlite::Connection::open(path)?,
+        }))),
+        (Backend::Postgres, None) => Err("--backend postgres needs --dsn")?,
+        (Backend::Postgres, Some(dsn)) => connect_postgres(dsn).map(Some),
+    }
+}
+
+// Copies TABLES from the working database into `storage`.
+pub fn copy_tables(db: &rusqlite::Connection, storage: &mut dyn Storage) -> Result_<()> {
+    for table in TABLES {
+        let columns = columns(db, table)?;
+        let names = columns
+            .iter()
+            .map(|column| quote(&column.name))
+            .collect::<Vec<String>>();
+        let mut statement = db.prepare(&format!("SELECT {} FROM {}", names.join(", "), table))?;
+        let rows = statement
+            .query_map(rusqlite::params![], |row| {
+                (0..columns.len()).map(|i| row.get(i)).collect()
+            })?
+            .collect::<rusqlite::Result<Vec<Vec<Value>>>>()?;
+        storage.replace_table(table, &columns, &rows)?;
+        log::info!("copied {} rows of {}", rows.len(), table);
+    }
+    Ok(())
+}
+
+fn columns(db: &rusqlite::Connection, table: &str) -> Result_<Vec<Column>> {
+    let mut statement = db.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = statement
+        .query_map(rusqlite::params![], |row| {
+            let declared: String = row.get(2)?;
+            let primary_key: i64 = row.get(5)?;
+            Ok(Column {
+                name: row.get(1)?,
+                column_type: ColumnType::from_declared(&declared),
+                primary_key: primary_key > 0,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<Column>>>()?;
+    Ok(columns)
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// The column definitions of a CREATE TABLE with the type names of the backend.
+fn definitions(columns: &[Column], type_name: impl Fn(ColumnType) -> &'static str) -> String {
+    let mut definitions = columns
+        .iter()
+        .map(|column| format!("{} {}", quote(&column.name), type_name(column.column_type)))
+        .collect::<Vec<String>>();
+    let key = columns
+        .iter()
+        .filter(|column| column.primary_key)
+        .map(|column| quote(&column.name))
+        .collect::<Vec<String>>();
+    if !key.is_empty() {
+        definitions.push(format!("PRIMARY KEY({})", key.join(", ")));
+    }
+    definitions.join(", ")
+}
+
+pub struct SqliteStorage {
+    db: rusqlite::Connection,
+}
+
+impl Storage for SqliteStorage {
+    fn replace_table(
+        &mut self,
+        table: &str,
+        columns: &[Column],
+        rows: &[Vec<Value>],
+    ) -> Result_<()> {
+        let transaction = self.db.transaction()?;
+        transaction.execute_batch(&format!(
+            "DROP TABLE IF EXISTS {0}; CREATE TABLE {0} ({1});",
+            quote(table),
+            definitions(columns, |column_type| match column_type {
+                ColumnType::Integer => "INTEGER",
+                ColumnType::Real => "REAL",
+                ColumnType::Text => "TEXT",
+                ColumnType::Blob => "BLOB",
+            })
+        ))?;
+        {
+            let mut insert = transaction.prepare(&format!(
+                "INSERT INTO {} VALUES ({})",
+                quote(table),
+                vec!["?"; columns.len()].join(", ")
+            ))?;
+            for row in rows {
+                insert.execute(row)?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "postgres")]
+struct PostgresStorage {
+    client: postgres::Client,
+}
+
+#[cfg(feature = "postgres")]
+fn connect_postgres(dsn: &str) -> Result_<Box<dyn Storage>> {
+    Ok(Box::new(PostgresStorage {
+        client: postgres::Client::connect(dsn, postgres::NoTls)?,
+    }))
+}
+
+#[cfg(not(feature = "postgres"))]
+fn connect_postgres(_dsn: &str) -> Result_<Box<dyn Storage>> {
+    Err("--backend postgres needs a build with the postgres feature")?
+}
+
+// SQLite columns are loosely typed so a value that does not fit the declared type of its column
+// fails instead of being converted silently. Integers fit real columns and numbers text columns.
+#[cfg(feature = "postgres")]
+fn postgres_value(
+    value: &Value,
+    column: &Column,
+) -> Result_<Box<dyn postgres::types::ToSql + Sync>> {
+    let value: Box<dyn postgres::types::ToSql + Sync> = match (column.column_type, value) {
+        (ColumnType::Integer, Value::Integer(x)) => Box::new(Some(*x)),
+        (ColumnType::Integer, Value::Null) => Box::new(None::<i64>),
+        (ColumnType::Real, Value::Integer(x)) => Box::new(Some(*x as f64)),
+        (ColumnType::Real, Value::Real(x)) => Box::new(Some(*x)),
+        (ColumnType::Real, Value::Null) => Box::new(None::<f64>),
+        (ColumnType::Text, Value::Text(x)) => Box::new(Some(x.clone())),
+        (ColumnType::Text, Value::Integer(x)) => Box::new(Some(x.to_string())),
+        (ColumnType::Text, Value::Real(x)) => Box::new(Some(x.to_string())),
+        (ColumnType::Text, Value::Null) => Box::new(None::<String>),
+        (ColumnType::Blob, Value::Blob(x)) => Box::new(Some(x.clone())),
+        (ColumnType::Blob, Value::Null) => Box::new(None::<Vec<u8>>),
+        (column_type, value) => Err(format!(
+            "column {} has the type {:?} but holds {:?}",
+            column.name, column_type, value
+        ))?,
+    };
+    Ok(value)
+}
+
+#[cfg(feature = "postgres")]
+impl Storage for PostgresStorage {
+    fn replace_table(
+        &mut self,
+        table: &str,
+        columns: &[Column],
+        rows: &[Vec<Value>],
+    ) -> Result_<()> {
+        let mut transaction = self.client.transaction()?;
+        transaction.batch_execute(&format!(
+            "DROP TABLE IF EXISTS {0}; CREATE TABLE {0} ({1});",
+            quote(table),
+            definitions(columns, |column_type| match column_type {
+                ColumnType::Integer => "BIGINT",
+                ColumnType::Real => "DOUBLE PRECISION",
+                ColumnType::Text => "TEXT",
+                ColumnType::Blob => "BYTEA",
+            })
+        ))?;
+        let placeholders = (1..=columns.len())
+            .map(|i| format!("${}", i))
+            .collect::<Vec<String>>();
+        let insert = transaction.prepare(&format!(
+            "INSERT INTO {} VALUES ({})",
+            quote(table),
+            placeholders.join(", ")
+        ))?;
+        for row in rows {
+            let values = row
+                .iter()
+                .zip(columns)
+                .map(|(value, column)| postgres_value(value, column))
+                .collect::<Result_<Vec<_>>>()?;
+            let params = values
+                .iter()
+                .map(|value| value.as_ref() as &(dyn postgres::types::ToSql + Sync))
+                .collect::<Vec<_>>();
+            transaction.execute(&insert, &params)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_tables() {
+        let db = crate::test_db();
+        let mut storage = SqliteStorage {
+            db: rusqlite::Connection::open_in_memory().unwrap(),
+        };
+        // Copying again replaces the rows.
+        copy_tables(&db, &mut storage).unwrap();
+        copy_tables(&db, &mut storage).unwrap();
+        let count = |db: &rusqlite::Connection| -> i64 {
+            db.query_row(
+                "SELECT COUNT(*) FROM scoresaber_songs",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(count(&storage.db), count(&db));
+        let copied = columns(&storage.db, "scoresaber_songs").unwrap();
+        assert_eq!(copied, columns(&db, "scoresaber_songs").unwrap());
+        assert!(copied
+            .iter()
+            .any(|column| column.name == "uid" && column.primary_key));
+        assert_eq!(ColumnType::from_declared("REAL"), ColumnType::Real);
+        assert_eq!(ColumnType::from_declared("varchar(10)"), ColumnType::Text);
+        assert!(open(Backend::Postgres, None).is_err());
+        assert!(open(Backend::Sqlite, None).unwrap().is_none());
+    }
+}
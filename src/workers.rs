@@ -0,0 +1,174 @@
+// Runs per-map work like BeatSaver lookups and cover downloads on a bounded number of threads. The
+// results are handled on the calling thread because the database can not be shared between
+// threads. The threads take the next item when they are done with one so a slow map does not hold
+// up the others.
+//
+// A failing or panicking item is logged and skipped instead of ending the pass. Only a run of
+// MAX_CONSECUTIVE_FAILURES failures stops it early because then the api is most likely down.
+
+use crate::Result_;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+static WORKERS: AtomicUsize = AtomicUsize::new(1);
+const MAX_CONSECUTIVE_FAILURES: usize = 10;
+
+pub fn set_workers(workers: usize) -> Result_<()> {
+    if workers == 0 {
+        return Err("the number of workers has to be at least 1")?;
+    }
+    WORKERS.store(workers, Ordering::Relaxed);
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub succeeded: usize,
+    pub failed: usize,
+    // Whether the pass ended early because of too many failures in a row.
+    pub stopped: bool,
+}
+
+// The result of an item in a worker thread. Errors are strings because they have to be sent
+// between threads.
+type Done<R> = (usize, Result<R, String>);
+
+// Calls `work` for every item on the worker threads and `handle` with the item and the result of
+// each successful one on this thread in the order they finish. An error of `handle` ends the pass.
+pub fn run<T, R>(
+    items: Vec<T>,
+    work: impl Fn(&T) -> Result_<R> + Send + Sync + 'static,
+    handle: impl FnMut(&T, R) -> Result_<()>,
+) -> Result_<Summary>
+where
+    T: Send + Sync + 'static,
+    R: Send + 'static,
+{
+    run_with(WORKERS.load(Ordering::Relaxed), items, work, handle)
+}
+
+fn run_with<T, R>(
+    workers: usize,
+    items: Vec<T>,
+    work: impl Fn(&T) -> Result_<R> + Send + Sync + 'static,
+    mut handle: impl FnMut(&T, R) -> Result_<()>,
+) -> Result_<Summary>
+where
+    T: Send + Sync + 'static,
+    R: Send + 'static,
+{
+    let items = Arc::new(items);
+    let work = Arc::new(work);
+    let next = Arc::new(AtomicUsize::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = std::sync::mpsc::channel::<Done<R>>();
+    let workers = workers.min(items.len());
+    for _ in 0..workers {
+        let (items, work, next, stop, sender) = (
+            items.clone(),
+            work.clone(),
+            next.clone(),
+            stop.clone(),
+            sender.clone(),
+        );
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let item = match items.get(index) {
+                    Some(item) => item,
+                    None => break,
+                };
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| work(item)))
+                    .unwrap_or_else(|_| Err("the worker panicked".into()))
+                    .map_err(|err| err.to_string());
+                // The receiver is gone when the pass was stopped.
+                if sender.send((index, result)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(sender);
+    let result = collect(&items, &receiver, &mut handle);
+    stop.store(true, Ordering::Relaxed);
+    result
+}
+
+fn collect<T, R>(
+    items: &[T],
+    receiver: &std::sync::mpsc::Receiver<Done<R>>,
+    handle: &mut impl FnMut(&T, R) -> Result_<()>,
+) -> Result_<Summary> {
+    let mut summary = Summary::default();
+    let mut consecutive_failures = 0;
+    for (index, result) in receiver.iter() {
+        match result {
+            Ok(value) => {
+                consecutive_failures = 0;
+                summary.succeeded += 1;
+                handle(&items[index], value)?;
+            }
+            Err(err) => {
+                consecutive_failures += 1;
+                summary.failed += 1;
+                log::warn!("skipping item {} of {}: {}", index + 1, items.len(), err);
+                if consecutive_failures == MAX_CONSECUTIVE_FAILURES {
+                    log::warn!(
+                        "stopping after {} failures in a row",
+                        MAX_CONSECUTIVE_FAILURES
+                    );
+                    summary.stopped = true;
+                    break;
+                }
+            }
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let mut handled = vec![];
+        let summary = run_with(
+            3,
+            (0..20).collect(),
+            |x: &u32| -> Result_<u32> {
+                match x {
+                    3 => Err("bad map")?,
+                    5 => panic!("very bad map"),
+                    _ => Ok(x * 2),
+                }
+            },
+            |x, doubled| {
+                handled.push((*x, doubled));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                succeeded: 18,
+                failed: 2,
+                stopped: false,
+            }
+        );
+        handled.sort();
+        assert_eq!(handled.len(), 18);
+        assert!(handled.iter().all(|(x, doubled)| x * 2 == *doubled));
+        let summary = run_with(
+            3,
+            (0..100).collect(),
+            |_: &u32| -> Result_<()> { Err("api down")? },
+            |_, _| Ok(()),
+        )
+        .unwrap();
+        assert!(summary.stopped);
+        assert_eq!(summary.failed, MAX_CONSECUTIVE_FAILURES);
+        assert!(set_workers(0).is_err());
+    }
+}